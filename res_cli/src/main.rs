@@ -0,0 +1,198 @@
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use argh::FromArgs;
+use res_emulator::cpu::Cpu;
+use res_emulator::cpu::CpuBus;
+use res_emulator::ppu::RenderMode;
+use res_emulator::util::write_wav;
+use res_emulator::System;
+
+const SAMPLE_RATE: usize = 44100;
+
+/// Runs a ROM without user interface, e.g. for CI or scripted testing.
+#[derive(FromArgs)]
+struct ResCliArgs {
+    /// rom file to load in iNES, NES 2.0, UNIF or FDS format, or music file in NSF/NSFe format
+    #[argh(positional)]
+    rom: PathBuf,
+
+    /// FDS BIOS, required to load FDS disk images
+    #[argh(option)]
+    fds_bios: Option<PathBuf>,
+
+    /// track of the NSF file to play, starting at 0 (default: starting track of the file)
+    #[argh(option)]
+    track: Option<usize>,
+
+    /// replay joypad inputs from a recording in JSON format
+    #[argh(option)]
+    playback: Option<PathBuf>,
+
+    /// number of frames to run (default: 60)
+    #[argh(option, default = "60")]
+    frames: usize,
+
+    /// stop once the program counter reaches this address (hex)
+    #[argh(option, from_str_fn(parse_address))]
+    until_pc: Option<u16>,
+
+    /// stop once memory contains a value, given as ADDR=VALUE in hex (e.g. 6000=00)
+    #[argh(option, from_str_fn(parse_memory_condition))]
+    until_memory: Option<(u16, u8)>,
+
+    /// save a screenshot when reaching this frame number, can be repeated
+    #[argh(option)]
+    screenshot_at: Vec<usize>,
+
+    /// save a screenshot of the last frame
+    #[argh(switch)]
+    screenshot: bool,
+
+    /// directory to save screenshots to (default: current directory)
+    #[argh(option, default = "PathBuf::from(\".\")")]
+    screenshot_dir: PathBuf,
+
+    /// write the audio output to a WAV file
+    #[argh(option)]
+    audio: Option<PathBuf>,
+
+    /// write a snapshot of the final state to this file
+    #[argh(option)]
+    snapshot: Option<PathBuf>,
+
+    /// use the cycle accurate dot based renderer instead of the scanline based renderer
+    #[argh(switch)]
+    dot_renderer: bool,
+}
+
+impl ResCliArgs {
+    fn has_condition(&self) -> bool {
+        self.until_pc.is_some() || self.until_memory.is_some()
+    }
+
+    fn condition_met(&self, cpu: &Cpu) -> bool {
+        if let Some(pc) = self.until_pc {
+            if cpu.program_counter == pc {
+                return true;
+            }
+        }
+        if let Some((addr, value)) = self.until_memory {
+            if cpu.bus.peek(addr) == Some(value) {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+fn parse_address(value: &str) -> Result<u16, String> {
+    u16::from_str_radix(value.trim_start_matches('$'), 16)
+        .map_err(|e| format!("Invalid address '{value}': {e}"))
+}
+
+fn parse_memory_condition(value: &str) -> Result<(u16, u8), String> {
+    let (addr, byte) = value
+        .split_once('=')
+        .ok_or_else(|| format!("Expected ADDR=VALUE, got '{value}'"))?;
+    let byte = u8::from_str_radix(byte.trim_start_matches('$'), 16)
+        .map_err(|e| format!("Invalid value '{byte}': {e}"))?;
+    Ok((parse_address(addr)?, byte))
+}
+
+fn main() -> Result<()> {
+    let args: ResCliArgs = argh::from_env();
+
+    let rom = fs::read(&args.rom).with_context(|| format!("Cannot read {:?}", args.rom))?;
+    let fds_bios = match &args.fds_bios {
+        Some(path) => Some(fs::read(path).with_context(|| format!("Cannot read {path:?}"))?),
+        None => None,
+    };
+    let mut system = System::with_rom_bytes(&rom, None, fds_bios.as_deref())?;
+    if let Some(title) = &system.cartridge().borrow().title {
+        println!("{title}");
+    }
+    if let Some(header) = system.cartridge().borrow().header {
+        println!("{header}");
+    }
+    if let Some(header) = &system.cartridge().borrow().nsf_header {
+        println!("{header}");
+    }
+    if let Some(track) = args.track {
+        system.select_track(track)?;
+    }
+    if args.dot_renderer {
+        system.cpu.bus.ppu.render_mode = RenderMode::Dot;
+    }
+    if let Some(playback) = &args.playback {
+        system.playback_from_file(playback);
+    }
+    if args.audio.is_some() {
+        system.cpu.bus.apu.audio_sample_rate = SAMPLE_RATE;
+    }
+
+    let name = args
+        .rom
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_else(|| "screenshot".to_string());
+    let mut audio = Vec::new();
+    let mut condition_met = false;
+    while system.ppu().frame < args.frames {
+        system.update_buttons([false; 8]);
+        let current_frame = system.ppu().frame;
+        system.execute_until(|cpu| cpu.bus.ppu.frame > current_frame || args.condition_met(cpu))?;
+        audio.append(&mut system.cpu.bus.apu.audio_buffer);
+
+        if args.condition_met(&system.cpu) {
+            condition_met = true;
+            break;
+        }
+        if args.screenshot_at.contains(&system.ppu().frame) {
+            save_screenshot(&system, &args.screenshot_dir, &name)?;
+        }
+    }
+    println!(
+        "Stopped at frame {} (PC {:04X}, cycle {})",
+        system.ppu().frame,
+        system.cpu.program_counter,
+        system.cpu.cycle
+    );
+
+    if args.screenshot {
+        save_screenshot(&system, &args.screenshot_dir, &name)?;
+    }
+    if let Some(path) = &args.audio {
+        write_wav(path, audio, SAMPLE_RATE)?;
+    }
+    if let Some(path) = &args.snapshot {
+        fs::write(path, system.snapshot())
+            .with_context(|| format!("Cannot write snapshot to {path:?}"))?;
+    }
+
+    if args.has_condition() && !condition_met {
+        return Err(anyhow!(
+            "Condition not met after {} frames",
+            system.ppu().frame
+        ));
+    }
+    Ok(())
+}
+
+/// Saves the current frame as `{name}-{frame}.png`, which matches the naming of the golden
+/// files of the e2e tests.
+fn save_screenshot(system: &System, dir: &Path, name: &str) -> Result<()> {
+    let path = dir.join(format!("{name}-{}.png", system.ppu().frame));
+    system
+        .ppu()
+        .framebuffer
+        .as_rgba_image()
+        .save(&path)
+        .with_context(|| format!("Cannot write screenshot to {path:?}"))?;
+    println!("Saved {path:?}");
+    Ok(())
+}
@@ -441,6 +441,12 @@ impl DebuggerUi {
             } else {
                 ui.label("Disabled");
             }
+            ui.label(RichText::new("DMC:").strong());
+            if emulator.cpu.bus.apu.status.dmc_enable {
+                ui.label(emulator.cpu.bus.apu.dmc.pretty_print());
+            } else {
+                ui.label("Disabled");
+            }
         });
     }
 
@@ -117,6 +117,7 @@ pub struct EmulatorApp {
     debugger_ui: DebuggerUi,
     audio_engine: AudioEngine,
     gilrs: Gilrs,
+    disable_sprite_limit: bool,
 }
 
 impl EmulatorApp {
@@ -135,6 +136,7 @@ impl EmulatorApp {
             debugger_ui: DebuggerUi::new(cc),
             audio_engine: AudioEngine::new(),
             gilrs: Gilrs::new().unwrap(),
+            disable_sprite_limit: false,
         };
 
         if let Some(rom) = rom {
@@ -145,8 +147,9 @@ impl EmulatorApp {
 
     fn load_rom(&mut self, rom: Rom) {
         self.emulator =
-            System::with_ines_bytes(&rom.ines_data, rom.persistent_data.as_deref()).unwrap();
+            System::with_rom_bytes(&rom.ines_data, rom.persistent_data.as_deref(), None).unwrap();
         self.emulator.cpu.bus.apu.audio_sample_rate = self.audio_engine.sample_rate;
+        self.emulator.cpu.bus.ppu.disable_sprite_limit = self.disable_sprite_limit;
         self.loaded_rom = Some(rom);
     }
 
@@ -158,7 +161,7 @@ impl EmulatorApp {
                     let record: Record = serde_json::from_str(&data).unwrap();
                     self.emulator.playback_from = Some(record);
                 }
-                Some("nes") => {
+                Some("nes" | "unf" | "nsf" | "nsfe") => {
                     self.load_rom(Rom::load_from_file(path));
                 }
                 _ => {
@@ -231,6 +234,12 @@ impl EmulatorApp {
                 if ui.button("Debug").clicked() {
                     self.debug_mode = !self.debug_mode;
                 }
+                if ui
+                    .checkbox(&mut self.disable_sprite_limit, "No Sprite Limit")
+                    .changed()
+                {
+                    self.emulator.cpu.bus.ppu.disable_sprite_limit = self.disable_sprite_limit;
+                }
                 if let Some(record) = &mut self.emulator.record_to {
                     if ui.button("Save Recording").clicked() {
                         std::fs::write(
@@ -243,6 +252,9 @@ impl EmulatorApp {
                 } else if ui.button("Record").clicked() {
                     self.emulator.record_to = Some(Record::default());
                 }
+                if let Some(title) = &self.emulator.cartridge().borrow().title {
+                    ui.label(title);
+                }
             });
         });
     }
@@ -0,0 +1,132 @@
+#!/usr/bin/env python3
+"""Generates src/cartridge/database.txt from the NES 2.0 XML database (nes20db.xml).
+
+The XML database is published on the nesdev forums. Usage:
+
+    python3 scripts/gen_database.py nes20db.xml
+
+Entries below the "# Hand-maintained entries" line of database.txt are kept, unless the XML
+database describes the same ROM.
+"""
+
+import argparse
+import pathlib
+import sys
+import xml.etree.ElementTree as ET
+
+DATABASE = pathlib.Path(__file__).parent.parent / "src" / "cartridge" / "database.txt"
+LOCAL_MARKER = "# Hand-maintained entries"
+
+HEADER = """\
+# Known ROMs, identified by the CRC32 and SHA-1 of their PRG-ROM followed by CHR-ROM.
+# Generated by scripts/gen_database.py from the NES 2.0 XML database, do not edit above the
+# hand-maintained entries.
+#
+# Columns: crc32 sha1 mapper.submapper mirroring prg_ram prg_nvram chr_ram chr_nvram timing
+# expansion_device title
+#
+# Mirroring is H, V or 4 (four-screen). RAM sizes are in KiB. Timing is NTSC, PAL, MULTI or
+# DENDY. The expansion device uses the NES 2.0 numbering (1 = standard controllers).
+"""
+
+# Mirroring of the pcb element. Mapper controlled mirroring ignores the header, so it is
+# stored as H.
+MIRRORING = {"H": "H", "V": "V", "4": "4"}
+TIMING = {"0": "NTSC", "1": "PAL", "2": "MULTI", "3": "DENDY"}
+
+
+def kib(game, tag):
+    element = game.find(tag)
+    if element is None:
+        return 0
+    return int(element.get("size", "0")) // 1024
+
+
+def convert_game(game, title):
+    """Returns the database line of a game element, or None if it cannot be described."""
+    rom = game.find("rom")
+    pcb = game.find("pcb")
+    if rom is None or pcb is None:
+        return None
+    # The rom hash covers all data after the header, which only matches the PRG+CHR hash used
+    # by the emulator if there is nothing else.
+    if game.find("trainer") is not None or game.find("miscrom") is not None:
+        return None
+    console = game.find("console")
+    region = console.get("region", "0") if console is not None else "0"
+    expansion = game.find("expansion")
+    expansion_device = expansion.get("type", "1") if expansion is not None else "1"
+    return " ".join(
+        [
+            rom.get("crc32").upper(),
+            rom.get("sha1").upper(),
+            f"{pcb.get('mapper', '0')}.{pcb.get('submapper', '0')}",
+            MIRRORING.get(pcb.get("mirroring", "H"), "H"),
+            str(kib(game, "prgram")),
+            str(kib(game, "prgnvram")),
+            str(kib(game, "chrram")),
+            str(kib(game, "chrnvram")),
+            TIMING.get(region, "NTSC"),
+            expansion_device,
+            title,
+        ]
+    )
+
+
+def title_from_comment(comment):
+    """The comment before each game is its path, e.g. "Licensed\\Nintendo\\Ice Climber.nes"."""
+    name = comment.strip().replace("\\", "/").split("/")[-1]
+    if name.lower().endswith(".nes"):
+        name = name[:-4]
+    return " ".join(name.split())
+
+
+def convert(xml_path):
+    parser = ET.XMLParser(target=ET.TreeBuilder(insert_comments=True))
+    root = ET.parse(xml_path, parser).getroot()
+    lines = []
+    skipped = 0
+    for game in root.iter("game"):
+        comments = [child.text for child in game if child.tag is ET.Comment]
+        title = title_from_comment(comments[0]) if comments else "Unknown"
+        line = convert_game(game, title)
+        if line is None:
+            skipped += 1
+        else:
+            lines.append(line)
+    print(f"Converted {len(lines)} games, skipped {skipped}.", file=sys.stderr)
+    return lines
+
+
+def sha1_of(line):
+    columns = line.split(" ")
+    if line.startswith("#") or len(columns) < 2:
+        return None
+    return columns[1]
+
+
+def local_entries():
+    if not DATABASE.exists():
+        return [LOCAL_MARKER]
+    text = DATABASE.read_text()
+    index = text.find(LOCAL_MARKER)
+    if index < 0:
+        return [LOCAL_MARKER]
+    return text[index:].rstrip("\n").split("\n")
+
+
+def main():
+    parser = argparse.ArgumentParser(description=__doc__.splitlines()[0])
+    parser.add_argument("xml", type=pathlib.Path, help="path of nes20db.xml")
+    parser.add_argument("--output", type=pathlib.Path, default=DATABASE)
+    args = parser.parse_args()
+
+    lines = sorted(set(convert(args.xml)), key=lambda line: line.split(" ", 10)[10])
+    # Hand-maintained entries of ROMs in the XML database are replaced by its data.
+    hashes = {line.split(" ")[1] for line in lines}
+    local = [line for line in local_entries() if sha1_of(line) not in hashes]
+    args.output.write_text(HEADER + "\n".join(lines + [""] + local) + "\n")
+
+
+if __name__ == "__main__":
+    main()
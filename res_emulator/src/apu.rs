@@ -1,3 +1,4 @@
+mod dmc;
 mod frame_counter;
 mod noise;
 mod pulse;
@@ -8,9 +9,11 @@ use bincode::Decode;
 use bincode::Encode;
 use packed_struct::prelude::PackedStruct;
 
+use self::dmc::DmcChannel;
+pub use self::dmc::DMA_STALL_CYCLES;
 use self::frame_counter::FrameCounter;
 use self::noise::NoiseChannel;
-use self::pulse::PulseChannel;
+pub use self::pulse::PulseChannel;
 use self::triangle::TriangleChannel;
 
 #[derive(Default, bincode::Encode, bincode::Decode, Clone)]
@@ -25,6 +28,27 @@ pub struct Apu {
     pub pulse1: PulseChannel,
     pub triangle: TriangleChannel,
     pub noise: NoiseChannel,
+    pub dmc: DmcChannel,
+    /// Output of the cartridge expansion audio, mixed into each sample.
+    pub expansion_audio: f32,
+    /// Frame counter clocks that happened between APU cycles, which are passed on to the
+    /// pulse and noise channels on the next APU cycle.
+    pending_half_frame: bool,
+    pending_quarter_frame: bool,
+}
+
+/// Value of $4015 when read.
+#[derive(PackedStruct, Encode, Decode, Clone, Debug, Default, Copy, PartialEq, Eq)]
+#[packed_struct(bit_numbering = "msb0", size_bytes = "1")]
+pub struct ChannelStatus {
+    pub dmc_interrupt: bool,
+    pub frame_interrupt: bool,
+    pub _unused: bool,
+    pub dmc_active: bool,
+    pub noise_active: bool,
+    pub triangle_active: bool,
+    pub pulse1_active: bool,
+    pub pulse0_active: bool,
 }
 
 #[derive(PackedStruct, Encode, Decode, Clone, Debug, Default, Copy, PartialEq, Eq)]
@@ -51,9 +75,19 @@ impl Apu {
             pulse1: PulseChannel::default(),
             triangle: TriangleChannel::default(),
             noise: NoiseChannel::default(),
+            dmc: DmcChannel::default(),
+            expansion_audio: 0.0,
+            pending_half_frame: false,
+            pending_quarter_frame: false,
         }
     }
 
+    /// Called when the reset button is pressed. Silences all channels as if $4015 was
+    /// written with 0.
+    pub fn reset(&mut self) {
+        self.cpu_bus_write(0x4015, 0);
+    }
+
     pub fn advance_clock(&mut self, cycles: usize) -> Result<()> {
         let samples_per_frame = self.audio_sample_rate as f64 / 60.0;
         let cycles_per_frame = 29268.67105 + 512.0;
@@ -67,19 +101,18 @@ impl Apu {
                 self.frame_counter.half_frame,
                 self.frame_counter.quarter_frame,
             );
+            self.dmc.tick();
+            self.pending_half_frame |= self.frame_counter.half_frame;
+            self.pending_quarter_frame |= self.frame_counter.quarter_frame;
             if self.cycle % 2 == 0 {
-                self.pulse0.tick(
-                    self.frame_counter.half_frame,
-                    self.frame_counter.quarter_frame,
-                );
-                self.pulse1.tick(
-                    self.frame_counter.half_frame,
-                    self.frame_counter.quarter_frame,
-                );
-                self.noise.tick(
-                    self.frame_counter.half_frame,
-                    self.frame_counter.quarter_frame,
-                );
+                self.pulse0
+                    .tick(self.pending_half_frame, self.pending_quarter_frame);
+                self.pulse1
+                    .tick(self.pending_half_frame, self.pending_quarter_frame);
+                self.noise
+                    .tick(self.pending_half_frame, self.pending_quarter_frame);
+                self.pending_half_frame = false;
+                self.pending_quarter_frame = false;
             }
 
             self.cycles_since_last_sample += 1.0;
@@ -114,19 +147,46 @@ impl Apu {
         } else {
             0.0
         };
-        0.1128 * (pulse0 + pulse1) + 0.12765 * triangle + 0.0741 * noise
+        // The DMC output level is kept while the channel is disabled.
+        let dmc = self.dmc.value();
+        0.1128 * (pulse0 + pulse1)
+            + 0.12765 * triangle
+            + 0.0741 * noise
+            + 0.42545 * dmc
+            + self.expansion_audio
     }
 
     pub fn tick(&mut self) -> Result<()> {
         Ok(())
     }
 
-    pub fn cpu_bus_peek(&self, _addr: u16) -> u8 {
-        0
+    pub fn channel_status(&self) -> ChannelStatus {
+        ChannelStatus {
+            dmc_interrupt: self.dmc.irq_active(),
+            frame_interrupt: self.frame_counter.interrupt,
+            _unused: false,
+            dmc_active: self.dmc.active(),
+            noise_active: self.noise.active(),
+            triangle_active: self.triangle.active(),
+            pulse1_active: self.pulse1.active(),
+            pulse0_active: self.pulse0.active(),
+        }
+    }
+
+    pub fn cpu_bus_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x4015 => self.channel_status().pack().unwrap()[0],
+            _ => 0,
+        }
     }
 
     pub fn cpu_bus_read(&mut self, addr: u16) -> u8 {
-        self.cpu_bus_peek(addr)
+        let value = self.cpu_bus_peek(addr);
+        if addr == 0x4015 {
+            // Reading the status acknowledges the frame interrupt
+            self.frame_counter.interrupt = false;
+        }
+        value
     }
 
     pub fn cpu_bus_write(&mut self, addr: u16, value: u8) {
@@ -137,8 +197,18 @@ impl Apu {
                 .triangle
                 .write_register((addr - 0x4008) as usize, value),
             0x400C..=0x400F => self.noise.write_register((addr - 0x400C) as usize, value),
-            0x4015 => self.status = StatusRegister::unpack(&[value]).unwrap(),
-            0x4017 => self.frame_counter.write_register(value),
+            0x4010..=0x4013 => self.dmc.write_register((addr - 0x4010) as usize, value),
+            0x4015 => {
+                self.status = StatusRegister::unpack(&[value]).unwrap();
+                self.pulse0.set_enabled(self.status.pulse0_enable);
+                self.pulse1.set_enabled(self.status.pulse1_enable);
+                self.triangle.set_enabled(self.status.triangle_enable);
+                self.noise.set_enabled(self.status.noise_enable);
+                self.dmc.set_enabled(self.status.dmc_enable);
+            }
+            0x4017 => self
+                .frame_counter
+                .write_register(value, self.cycle % 2 == 0),
             _ => {}
         }
     }
@@ -0,0 +1,284 @@
+use std::fmt::Display;
+use std::fmt::Formatter;
+
+use bincode::Decode;
+use bincode::Encode;
+use intbits::Bits;
+use itertools::Itertools;
+use packed_struct::prelude::PackedStruct;
+
+#[derive(PackedStruct, Encode, Decode, Clone, Debug, Default, Copy, PartialEq, Eq)]
+#[packed_struct(bit_numbering = "msb0", size_bytes = "1")]
+pub struct DmcRegister0 {
+    irq_enable: bool,
+    loop_flag: bool,
+    #[packed_field(size_bits = "2")]
+    _unused: u8,
+    #[packed_field(size_bits = "4")]
+    rate_index: u8,
+}
+
+#[derive(PackedStruct, Encode, Decode, Clone, Debug, Default, Copy, PartialEq, Eq)]
+#[packed_struct(bit_numbering = "msb0", size_bytes = "1")]
+pub struct DmcRegister1 {
+    _unused: bool,
+    #[packed_field(size_bits = "7")]
+    direct_load: u8,
+}
+
+type DmcRegister2 = u8;
+type DmcRegister3 = u8;
+
+#[derive(Debug, Default, Encode, Decode, Clone)]
+pub struct DmcChannel {
+    register0: DmcRegister0,
+    register1: DmcRegister1,
+    register2: DmcRegister2,
+    register3: DmcRegister3,
+
+    cycle: u16,
+    output_level: u8,
+    shift_register: u8,
+    bits_remaining: u8,
+    silence: bool,
+    sample_buffer: Option<u8>,
+    current_address: u16,
+    bytes_remaining: u16,
+    irq_flag: bool,
+}
+
+impl Display for DmcChannel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "DMC {:02X} {:02X} {:02X} {:02X}",
+            self.register0.pack().unwrap()[0],
+            self.register1.pack().unwrap()[0],
+            self.register2,
+            self.register3,
+        )
+    }
+}
+
+/// Timer periods in CPU cycles (NTSC).
+const RATES: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+
+/// Number of CPU cycles the CPU is stalled for while the DMC reads a sample byte.
+pub const DMA_STALL_CYCLES: usize = 4;
+
+impl DmcChannel {
+    // Samples are located at $C000-$FFFF in steps of 64 bytes.
+    fn sample_address(&self) -> u16 {
+        0xC000 + self.register2 as u16 * 64
+    }
+
+    // Samples have a length of 1-4081 bytes in steps of 16 bytes.
+    fn sample_length(&self) -> u16 {
+        self.register3 as u16 * 16 + 1
+    }
+
+    pub fn pretty_print(&self) -> String {
+        let mut lines: Vec<String> = Vec::new();
+        lines.push(format!(
+            "Rate({:X}) {}{}",
+            self.register0.rate_index,
+            if self.register0.loop_flag { "L" } else { " " },
+            if self.register0.irq_enable { "I" } else { " " },
+        ));
+        lines.push(format!(
+            "Sample: {:04X} ({} bytes)",
+            self.sample_address(),
+            self.sample_length(),
+        ));
+        lines.push(format!(
+            "Reader: {:04X} ({} left)",
+            self.current_address, self.bytes_remaining,
+        ));
+        lines.push(format!(
+            "Value: {} (Cy {}, Bits {})",
+            self.output_level, self.cycle, self.bits_remaining
+        ));
+        lines.iter().join("\n")
+    }
+
+    fn restart_sample(&mut self) {
+        self.current_address = self.sample_address();
+        self.bytes_remaining = self.sample_length();
+    }
+
+    /// Called on writes to $4015. Disabling the channel stops the sample, enabling it restarts
+    /// the sample only if it has finished playing.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.irq_flag = false;
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart_sample();
+        }
+    }
+
+    /// True while there are bytes of the sample left to play.
+    pub fn active(&self) -> bool {
+        self.bytes_remaining > 0
+    }
+
+    pub fn irq_active(&self) -> bool {
+        self.irq_flag
+    }
+
+    /// Returns the address of the next sample byte if the sample buffer needs to be refilled.
+    /// The byte is read by the CPU bus and passed to `load_sample_byte`.
+    pub fn dma_request(&self) -> Option<u16> {
+        if self.sample_buffer.is_none() && self.bytes_remaining > 0 {
+            Some(self.current_address)
+        } else {
+            None
+        }
+    }
+
+    pub fn load_sample_byte(&mut self, value: u8) {
+        self.sample_buffer = Some(value);
+        self.current_address = if self.current_address == 0xFFFF {
+            0x8000
+        } else {
+            self.current_address + 1
+        };
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.register0.loop_flag {
+                self.restart_sample();
+            } else if self.register0.irq_enable {
+                self.irq_flag = true;
+            }
+        }
+    }
+
+    pub fn tick(&mut self) {
+        if self.cycle > 0 {
+            self.cycle -= 1;
+            return;
+        }
+        self.cycle = RATES[self.register0.rate_index as usize] - 1;
+
+        // Start a new output cycle with the next byte from the sample buffer.
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.sample_buffer.take() {
+                Some(sample) => {
+                    self.silence = false;
+                    self.shift_register = sample;
+                }
+                None => self.silence = true,
+            }
+        }
+
+        if !self.silence {
+            if self.shift_register.bit(0) {
+                if self.output_level <= 125 {
+                    self.output_level += 2;
+                }
+            } else if self.output_level >= 2 {
+                self.output_level -= 2;
+            }
+        }
+        self.shift_register >>= 1;
+        self.bits_remaining -= 1;
+    }
+
+    pub fn write_register(&mut self, idx: usize, value: u8) {
+        match idx {
+            0 => {
+                self.register0 = DmcRegister0::unpack(&[value]).unwrap();
+                if !self.register0.irq_enable {
+                    self.irq_flag = false;
+                }
+            }
+            1 => {
+                self.register1 = DmcRegister1::unpack(&[value]).unwrap();
+                self.output_level = self.register1.direct_load;
+            }
+            2 => self.register2 = value,
+            3 => self.register3 = value,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn value(&self) -> f32 {
+        self.output_level as f32 / 127.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample_playback() {
+        let mut dmc = DmcChannel::default();
+        dmc.write_register(0, 0x0F); // Fastest rate, no loop, no irq
+        dmc.write_register(1, 0x40);
+        dmc.write_register(2, 0x01); // $C040
+        dmc.write_register(3, 0x00); // 1 byte
+        dmc.set_enabled(true);
+        assert!(dmc.active());
+
+        assert_eq!(dmc.dma_request(), Some(0xC040));
+        dmc.load_sample_byte(0b0000_0011);
+        assert_eq!(dmc.dma_request(), None);
+        assert!(!dmc.active());
+        assert!(!dmc.irq_active());
+
+        // Each bit of the sample moves the output level up or down by 2.
+        dmc.tick();
+        assert_eq!(dmc.output_level, 0x42);
+        for _ in 0..RATES[0xF] {
+            dmc.tick();
+        }
+        assert_eq!(dmc.output_level, 0x44);
+        for _ in 0..RATES[0xF] {
+            dmc.tick();
+        }
+        assert_eq!(dmc.output_level, 0x42);
+
+        // The channel is silent once the sample buffer runs empty.
+        for _ in 0..RATES[0xF] * 8 {
+            dmc.tick();
+        }
+        assert!(dmc.silence);
+        assert_eq!(dmc.output_level, 0x38);
+    }
+
+    #[test]
+    fn test_loop_and_irq() {
+        let mut dmc = DmcChannel::default();
+        dmc.write_register(0, 0x40); // Loop
+        dmc.write_register(2, 0xFF); // $FFC0
+        dmc.write_register(3, 0x04); // 65 bytes
+        dmc.set_enabled(true);
+        for _ in 0..65 {
+            let addr = dmc.dma_request().unwrap();
+            dmc.load_sample_byte(0);
+            dmc.sample_buffer = None;
+            if addr == 0xFFFF {
+                assert_eq!(dmc.current_address, 0x8000);
+            }
+        }
+        // Looping restarts the sample without raising an IRQ
+        assert_eq!(dmc.dma_request(), Some(0xFFC0));
+        assert!(!dmc.irq_active());
+
+        dmc.write_register(0, 0x80); // IRQ, no loop
+        dmc.write_register(3, 0x00); // 1 byte
+        dmc.set_enabled(false);
+        dmc.set_enabled(true);
+        dmc.load_sample_byte(0);
+        assert!(dmc.irq_active());
+        assert!(!dmc.active());
+
+        // Writes to $4015 acknowledge the IRQ.
+        dmc.set_enabled(true);
+        assert!(!dmc.irq_active());
+    }
+}
@@ -9,42 +9,137 @@ pub struct FrameCounterRegister {
     pub irq_inhibit: bool,
 }
 
+/// CPU cycles after the start of the sequence at which each step clocks the channels.
+const STEP_1: usize = 7457;
+const STEP_2: usize = 14913;
+const STEP_3: usize = 22371;
+const STEP_4: usize = 29829;
+const STEP_5: usize = 37281;
+
 #[derive(Default, bincode::Encode, bincode::Decode, Clone, Debug)]
 pub struct FrameCounter {
     register: FrameCounterRegister,
-    cycle: usize,
+    /// CPU cycles since the start of the sequence.
     cpu_cycles: usize,
+    /// CPU cycles until a write to $4017 restarts the sequence.
+    reset_delay: usize,
 
     pub half_frame: bool,
     pub quarter_frame: bool,
     pub irq_frame: bool,
+    /// Set at the end of each 4-step sequence unless `irq_inhibit` is set. Asserts the CPU
+    /// IRQ line until acknowledged by reading $4015.
+    pub interrupt: bool,
 }
 
 impl FrameCounter {
     pub fn tick(&mut self) {
-        self.cpu_cycles += 1;
-        if self.cpu_cycles > 7457 {
-            self.cpu_cycles -= 7457;
+        self.half_frame = false;
+        self.quarter_frame = false;
+        self.irq_frame = false;
 
-            if self.register.mode {
-                self.cycle = (self.cycle + 1) % 5;
-            } else {
-                self.cycle = (self.cycle + 1) % 4;
+        if self.reset_delay > 0 {
+            self.reset_delay -= 1;
+            if self.reset_delay == 0 {
+                self.cpu_cycles = 0;
+                // Restarting in 5-step mode clocks the quarter and half frame units right away.
+                self.half_frame = self.register.mode;
+                self.quarter_frame = self.register.mode;
+                return;
             }
+        }
 
-            let last_frame = if self.register.mode { 4 } else { 3 };
-            self.irq_frame = !self.register.mode && self.cycle == last_frame;
-            self.half_frame = self.cycle == 1 || self.cycle == last_frame;
-            self.quarter_frame =
-                self.cycle == 0 || self.cycle == 1 || self.cycle == 2 || self.cycle == last_frame;
-        } else {
-            self.half_frame = false;
-            self.quarter_frame = false;
-            self.irq_frame = false;
+        self.cpu_cycles += 1;
+        match (self.register.mode, self.cpu_cycles) {
+            (_, STEP_1 | STEP_3) => self.quarter_frame = true,
+            (_, STEP_2) | (false, STEP_4) | (true, STEP_5) => {
+                self.quarter_frame = true;
+                self.half_frame = true;
+            }
+            _ => (),
+        }
+        // The interrupt flag is set during the last 3 cycles of the 4-step sequence, the last
+        // of which is also the first cycle of the next sequence.
+        if !self.register.mode && (STEP_4 - 1..=STEP_4 + 1).contains(&self.cpu_cycles) {
+            self.irq_frame = true;
+            if !self.register.irq_inhibit {
+                self.interrupt = true;
+            }
+        }
+        let sequence_length = if self.register.mode { STEP_5 } else { STEP_4 } + 1;
+        if self.cpu_cycles == sequence_length {
+            self.cpu_cycles = 0;
         }
     }
 
-    pub fn write_register(&mut self, value: u8) {
+    /// Writes $4017. The sequence restarts 3 CPU cycles after the write if it happens during
+    /// an APU cycle, or 4 cycles after it if it happens between APU cycles.
+    pub fn write_register(&mut self, value: u8, during_apu_cycle: bool) {
         self.register = FrameCounterRegister::unpack(&[value]).unwrap();
+        if self.register.irq_inhibit {
+            self.interrupt = false;
+        }
+        self.reset_delay = if during_apu_cycle { 3 } else { 4 };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the cycles of the quarter frame clocks with whether they also clock the half
+    /// frame, and the cycles at which the IRQ flag is set.
+    fn run(frame_counter: &mut FrameCounter, cycles: usize) -> (Vec<(usize, bool)>, Vec<usize>) {
+        let mut clocks = Vec::new();
+        let mut irqs = Vec::new();
+        for cycle in 1..=cycles {
+            frame_counter.tick();
+            if frame_counter.quarter_frame {
+                clocks.push((cycle, frame_counter.half_frame));
+            }
+            if frame_counter.irq_frame {
+                irqs.push(cycle);
+            }
+        }
+        (clocks, irqs)
+    }
+
+    #[test]
+    pub fn test_four_step_sequence() {
+        let mut frame_counter = FrameCounter::default();
+        frame_counter.write_register(0x00, true);
+        let (clocks, irqs) = run(&mut frame_counter, 3 + 29830 + 7457);
+        assert_eq!(
+            clocks,
+            [
+                (3 + 7457, false),
+                (3 + 14913, true),
+                (3 + 22371, false),
+                (3 + 29829, true),
+                (3 + 29830 + 7457, false),
+            ]
+        );
+        assert_eq!(irqs, [3 + 29828, 3 + 29829, 3 + 29830]);
+        assert!(frame_counter.interrupt);
+    }
+
+    #[test]
+    pub fn test_five_step_sequence() {
+        let mut frame_counter = FrameCounter::default();
+        frame_counter.write_register(0x80, false);
+        let (clocks, irqs) = run(&mut frame_counter, 4 + 37282 + 7457);
+        assert_eq!(
+            clocks,
+            [
+                (4, true),
+                (4 + 7457, false),
+                (4 + 14913, true),
+                (4 + 22371, false),
+                (4 + 37281, true),
+                (4 + 37282 + 7457, false),
+            ]
+        );
+        assert!(irqs.is_empty());
+        assert!(!frame_counter.interrupt);
     }
 }
@@ -45,6 +45,8 @@ pub struct NoiseChannel {
 
     cycle: u16,
     length_counter: u8,
+    /// Set by $4015. The length counter is only loaded while the channel is enabled.
+    enabled: bool,
     decay_level: u8,
     quarter_frames: usize,
     shift_register: u16,
@@ -136,6 +138,17 @@ impl NoiseChannel {
         }
     }
 
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.length_counter = 0;
+        }
+    }
+
+    pub fn active(&self) -> bool {
+        self.length_counter > 0
+    }
+
     pub fn write_register(&mut self, idx: usize, value: u8) {
         match idx {
             0 => self.register0 = NoiseRegister0::unpack(&[value]).unwrap(),
@@ -146,7 +159,7 @@ impl NoiseChannel {
         }
         self.shift_register = 1;
         self.decay_level = 15;
-        if idx == 3 {
+        if idx == 3 && self.enabled {
             self.length_counter = NOTE_LENGTHS[self.register3.length_counter_load as usize];
         }
     }
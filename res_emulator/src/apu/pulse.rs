@@ -50,6 +50,8 @@ pub struct PulseChannel {
     counter: u8,
     cycle: u16,
     length_counter: u8,
+    /// Set by $4015. The length counter is only loaded while the channel is enabled.
+    enabled: bool,
     decay_level: u8,
     quarter_frames: usize,
 }
@@ -163,6 +165,17 @@ impl PulseChannel {
         }
     }
 
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.length_counter = 0;
+        }
+    }
+
+    pub fn active(&self) -> bool {
+        self.length_counter > 0
+    }
+
     pub fn write_register(&mut self, idx: usize, value: u8) {
         match idx {
             0 => self.register0 = PulseRegister0::unpack(&[value]).unwrap(),
@@ -172,7 +185,7 @@ impl PulseChannel {
             _ => unreachable!(),
         }
         self.decay_level = 15;
-        if idx == 3 {
+        if idx == 3 && self.enabled {
             self.length_counter = NOTE_LENGTHS[self.register3.length_counter_load as usize];
         }
     }
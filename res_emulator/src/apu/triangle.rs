@@ -35,6 +35,8 @@ pub struct TriangleChannel {
     counter: u8,
     cycle: u16,
     length_counter: u8,
+    /// Set by $4015. The length counter is only loaded while the channel is enabled.
+    enabled: bool,
     linear_counter: u8,
     linear_counter_reload: bool,
 }
@@ -131,6 +133,17 @@ impl TriangleChannel {
         }
     }
 
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.length_counter = 0;
+        }
+    }
+
+    pub fn active(&self) -> bool {
+        self.length_counter > 0
+    }
+
     pub fn write_register(&mut self, idx: usize, value: u8) {
         match idx {
             0 => self.register0 = TriangleRegister0::unpack(&[value]).unwrap(),
@@ -140,7 +153,9 @@ impl TriangleChannel {
             _ => unreachable!(),
         }
         if idx == 3 {
-            self.length_counter = NOTE_LENGTHS[self.register3.length_counter_load as usize] / 2;
+            if self.enabled {
+                self.length_counter = NOTE_LENGTHS[self.register3.length_counter_load as usize];
+            }
             self.linear_counter_reload = true;
         }
     }
@@ -1,6 +1,25 @@
+mod axrom;
+mod bnrom;
+mod cnrom;
+mod database;
+mod fds;
+mod fme7;
+mod gxrom;
+mod ines;
 mod mmc1;
+mod mmc2;
+mod mmc3;
+mod mmc5;
+mod namco163;
 mod nrom;
+mod nsf;
+mod registry;
+mod single_chip;
+mod unif;
 mod uxrom;
+mod vrc24;
+mod vrc6;
+mod vrc_irq;
 
 use std::fmt::Formatter;
 
@@ -8,12 +27,48 @@ use anyhow::anyhow;
 use anyhow::Result;
 use bincode::Decode;
 use bincode::Encode;
+use intbits::Bits;
 use nrom::NromMapper;
-use packed_struct::prelude::*;
 use thiserror::Error;
 
+use self::axrom::AxRomMapper;
+use self::bnrom::BnRomMapper;
+use self::bnrom::Mapper34Board;
+use self::cnrom::CnRomMapper;
+pub use self::fds::is_fds_image;
+use self::fds::FdsMapper;
+use self::fme7::Fme7Mapper;
+use self::gxrom::GxRomMapper;
+pub use self::ines::ConsoleType;
+pub use self::ines::HeaderFormat;
+pub use self::ines::InesHeader;
+pub use self::ines::TimingMode;
 use self::mmc1::Mmc1Mapper;
+use self::mmc2::Mmc2Mapper;
+use self::mmc2::Mmc2Variant;
+use self::mmc3::Mmc3Mapper;
+use self::mmc5::Mmc5Mapper;
+use self::namco163::Namco163Mapper;
+pub use self::nsf::is_nsf;
+pub use self::nsf::NsfExpansion;
+pub use self::nsf::NsfFormat;
+pub use self::nsf::NsfHeader;
+use self::nsf::NsfMapper;
+pub use self::registry::decode_mapper;
+pub use self::registry::register_mapper;
+pub use self::registry::DecodeMapperResult;
+pub use self::registry::MapperRegistration;
+pub use self::registry::MapperState;
+pub use self::registry::RomData;
+use self::registry::TaggedMapper;
+use self::single_chip::SingleChipMapper;
+pub use self::unif::is_unif;
 use self::uxrom::UxRomMapper;
+use self::vrc24::Vrc24Mapper;
+use self::vrc24::Vrc24Variant;
+use self::vrc24::VrcChip;
+use self::vrc6::Vrc6Mapper;
+use self::vrc6::Vrc6Variant;
 
 #[derive(Error)]
 pub enum CartridgeError {
@@ -31,20 +86,110 @@ impl std::fmt::Debug for CartridgeError {
 
 pub type CartridgeResult<T> = std::result::Result<T, CartridgeError>;
 
-trait Mapper: Encode + Decode + Clone + Default {
+/// Interface of the mapper hardware on a cartridge.
+///
+/// Mappers that implement `Encode`, `Decode` and `Clone` can be added with `register_mapper`.
+pub trait Mapper: MapperState {
     fn get_mirroring_mode(&self) -> MirroringMode;
     fn persistent_data(&self) -> Vec<u8>;
+    /// Restores data previously returned by `persistent_data`, e.g. battery backed RAM that
+    /// a frontend loads after the cartridge was created. Data of the wrong size is ignored.
+    fn load_persistent_data(&mut self, _data: &[u8]) {}
 
     fn cpu_bus_peek(&self, addr: u16) -> Option<u8>;
     fn cpu_bus_read(&mut self, addr: u16) -> CartridgeResult<u8>;
     fn cpu_bus_write(&mut self, addr: u16, value: u8) -> CartridgeResult<()>;
 
     fn ppu_bus_peek(&self, addr: u16) -> Option<u8>;
+    /// Called for pattern fetches of the renderer, while `ppu_bus_peek` is used for all other
+    /// accesses. Allows mappers to react to the fetched tiles (e.g. the MMC2 CHR latches).
     fn ppu_bus_read(&mut self, addr: u16) -> CartridgeResult<u8>;
     fn ppu_bus_write(&mut self, addr: u16, value: u8) -> CartridgeResult<()>;
+
+    /// Called whenever the PPU puts a new address on its address bus, for rendering fetches as
+    /// well as accesses through $2006/$2007. The scanline renderer does not perform real
+    /// rendering fetches, so only the dot renderer reports their addresses.
+    fn ppu_address_changed(&mut self, _addr: u16) {}
+
+    /// Called by the PPU when A12 of the PPU address bus rises after having been low for a
+    /// while. Used by mappers that count scanlines (e.g. MMC3).
+    fn ppu_a12_rising_edge(&mut self) {}
+
+    /// State of the cartridge IRQ line. Returns true while the mapper asserts an IRQ.
+    fn irq_active(&self) -> bool {
+        false
+    }
+
+    /// Allows the cartridge to provide nametable data at $2000-$2FFF instead of the internal
+    /// VRAM of the PPU. Returns None to read from VRAM according to the mirroring mode.
+    fn nametable_peek(&self, _addr: u16) -> Option<u8> {
+        None
+    }
+
+    /// Called for nametable and attribute fetches of the renderer, while `nametable_peek` is
+    /// used for all other accesses.
+    fn nametable_fetch(&mut self, addr: u16) -> Option<u8> {
+        self.nametable_peek(addr)
+    }
+
+    /// Returns true if the write was handled by the cartridge instead of the internal VRAM.
+    fn nametable_write(&mut self, _addr: u16, _value: u8) -> bool {
+        false
+    }
+
+    /// Called by the PPU when switching between background and sprite pattern fetches.
+    /// `large_sprites` is set in 8x16 sprite mode.
+    fn ppu_fetch_phase(&mut self, _phase: PpuFetchPhase, _large_sprites: bool) {}
+
+    /// Called by the PPU at the first dot of each scanline.
+    fn ppu_scanline_start(&mut self, _scanline: usize, _rendering: bool) {}
+
+    /// Called once per CPU cycle.
+    fn cpu_tick(&mut self) {}
+
+    /// Output of the expansion audio on the cartridge, on the same scale as the output of the
+    /// APU it is mixed into.
+    fn audio_output(&self) -> f32 {
+        0.0
+    }
+
+    /// Number of disk sides of disk based systems (e.g. the Famicom Disk System).
+    fn disk_side_count(&self) -> usize {
+        0
+    }
+
+    fn inserted_disk_side(&self) -> Option<usize> {
+        None
+    }
+
+    /// Inserts the given disk side, or ejects the disk if None.
+    fn insert_disk_side(&mut self, _side: Option<usize>) {}
+
+    /// Number of tracks of music players (e.g. NSF files).
+    fn track_count(&self) -> usize {
+        0
+    }
+
+    fn current_track(&self) -> Option<usize> {
+        None
+    }
+
+    /// Selects the track that is started by the next reset.
+    fn select_track(&mut self, _track: usize) {}
 }
 
-#[derive(Default, Encode, Decode, Clone, Copy)]
+/// The pattern fetches the PPU is about to make.
+#[derive(Debug, Encode, Decode, Clone, Copy, PartialEq, Eq)]
+pub enum PpuFetchPhase {
+    /// Background tiles of the scanline. The first two tiles are fetched at the end of the
+    /// previous scanline.
+    Background {
+        scanline: usize,
+    },
+    Sprites,
+}
+
+#[derive(Default, Debug, Encode, Decode, Clone, Copy, PartialEq, Eq)]
 pub enum MirroringMode {
     #[default]
     Horizontal,
@@ -52,181 +197,733 @@ pub enum MirroringMode {
     FourScreen,
     SingleLower,
     SingleUpper,
+    /// Each of the four nametables is mapped to the given 1k page (0 or 1) of the PPU VRAM.
+    Custom([u8; 4]),
 }
 
-#[derive(PackedStruct, Default, Debug, Copy, Clone)]
-#[packed_struct(bit_numbering = "msb0", size_bytes = "11")]
-pub struct InesHeader {
-    magic: [u8; 4],
-    prg_size: u8,
-    chr_size: u8,
-    #[packed_field(size_bits = "4")]
-    lower_mapper: u8,
-    four_screen: bool,
-    trainer: bool,
-    has_battery_ram: bool,
-    mirroring: bool,
-    #[packed_field(size_bits = "4")]
-    upper_mapper: u8,
-    #[packed_field(size_bits = "2")]
-    format: u8,
-    playchoice10: bool,
-    vs_unisystem: bool,
-    _flags8: u8,
-    _flags9: u8,
-    _flags10: u8,
+/// File formats that can be loaded by `Cartridge::load`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RomFormat {
+    Ines,
+    Nes20,
+    Unif,
+    Fds,
+    /// NSF or NSFe music files.
+    Nsf,
 }
 
-/// Enum of all supported Mappers.
-/// This is used in place of Box<Mapper> since Encode/Decode do not support trait objects.
-/// TODO: Consider using serde and https://github.com/dtolnay/typetag
-#[derive(Encode, Decode, Clone)]
-enum MapperEnum {
-    Nrom(NromMapper),
-    Mmc1(Mmc1Mapper),
-    UxRom(UxRomMapper),
+impl RomFormat {
+    /// Detects the format from the contents of a file.
+    pub fn detect(raw: &[u8]) -> Option<RomFormat> {
+        if let Ok(header) = InesHeader::parse(raw) {
+            if header.format == HeaderFormat::Nes20 {
+                Some(RomFormat::Nes20)
+            } else {
+                Some(RomFormat::Ines)
+            }
+        } else if is_unif(raw) {
+            Some(RomFormat::Unif)
+        } else if is_fds_image(raw) {
+            Some(RomFormat::Fds)
+        } else if is_nsf(raw) {
+            Some(RomFormat::Nsf)
+        } else {
+            None
+        }
+    }
 }
 
 #[derive(Encode, Decode, Clone)]
 pub struct Cartridge {
-    mapper: MapperEnum,
+    mapper: TaggedMapper,
+    /// Additional 2k VRAM of four-screen boards, which provides the upper two nametables.
+    four_screen_vram: Vec<u8>,
     pub has_persistent_data: bool,
+    /// Header of the loaded iNES file, if the cartridge was loaded from one.
+    pub header: Option<InesHeader>,
+    /// Metadata of the loaded NSF file, if the cartridge was loaded from one.
+    pub nsf_header: Option<NsfHeader>,
+    /// Title of the game, if the ROM was identified by the game database.
+    pub title: Option<String>,
 }
 
 impl Cartridge {
     #[allow(clippy::new_without_default)]
     pub fn new() -> Self {
         Self {
-            mapper: MapperEnum::Nrom(NromMapper::default()),
+            mapper: TaggedMapper {
+                tag: "nrom".to_string(),
+                mapper: Box::new(NromMapper::default()),
+            },
+            four_screen_vram: vec![],
             has_persistent_data: false,
+            header: None,
+            nsf_header: None,
+            title: None,
         }
     }
 
     pub fn load_nrom_with_data(&mut self, prg: &[u8], chr: &[u8]) {
-        self.mapper = MapperEnum::Nrom(NromMapper::new(prg, chr, MirroringMode::Horizontal, None));
+        self.mapper = TaggedMapper {
+            tag: "nrom".to_string(),
+            mapper: Box::new(NromMapper::new(prg, chr, MirroringMode::Horizontal, None)),
+        };
+        self.four_screen_vram = vec![];
     }
 
-    pub fn load_ines(&mut self, raw: &[u8], persistent_data: Option<&[u8]>) -> Result<()> {
-        let header = InesHeader::unpack_from_slice(&raw[0..11])?;
-        if header.magic != [78, 69, 83, 26] {
-            return Err(anyhow!("Expected NES header."));
+    /// Loads a Famicom Disk System image, which requires the FDS BIOS. Writes to the disk are
+    /// returned as persistent data.
+    pub fn load_fds(
+        &mut self,
+        raw: &[u8],
+        bios: &[u8],
+        persistent_data: Option<&[u8]>,
+    ) -> Result<()> {
+        self.mapper = TaggedMapper {
+            tag: "fds".to_string(),
+            mapper: Box::new(FdsMapper::new(raw, bios, persistent_data)?),
+        };
+        self.four_screen_vram = vec![];
+        self.has_persistent_data = true;
+        self.header = None;
+        self.nsf_header = None;
+        self.title = None;
+        Ok(())
+    }
+
+    /// Loads an NSF or NSFe music file. The track to play is chosen with `select_track`.
+    pub fn load_nsf(&mut self, raw: &[u8]) -> Result<()> {
+        let (nsf_header, data) = nsf::parse_nsf(raw)?;
+        self.mapper = TaggedMapper {
+            tag: "nsf".to_string(),
+            mapper: Box::new(NsfMapper::new(&nsf_header, &data)?),
+        };
+        self.four_screen_vram = vec![];
+        self.has_persistent_data = false;
+        self.header = None;
+        self.title = None;
+        self.nsf_header = Some(nsf_header);
+        Ok(())
+    }
+
+    /// Loads a ROM in any of the supported formats, which is detected from the contents of
+    /// the file. FDS images require the FDS BIOS.
+    pub fn load(
+        &mut self,
+        raw: &[u8],
+        persistent_data: Option<&[u8]>,
+        fds_bios: Option<&[u8]>,
+    ) -> Result<RomFormat> {
+        let format = RomFormat::detect(raw).ok_or_else(|| anyhow!("Unknown ROM format."))?;
+        match format {
+            RomFormat::Ines | RomFormat::Nes20 => self.load_ines(raw, persistent_data)?,
+            RomFormat::Unif => self.load_unif(raw, persistent_data)?,
+            RomFormat::Fds => {
+                let bios = fds_bios.ok_or_else(|| anyhow!("FDS images require the FDS BIOS."))?;
+                self.load_fds(raw, bios, persistent_data)?
+            }
+            RomFormat::Nsf => self.load_nsf(raw)?,
         }
-        let mapper_id = header.lower_mapper | (header.upper_mapper << 4);
-        //println!("Loading iNES (mapper {:}): {:#?}", mapper_id, header);
-        let prg_len = header.prg_size as usize * 16 * 1024;
-        let chr_len = header.chr_size as usize * 8 * 1024;
-        let prg_start = 16;
-        let prg_end = prg_start + prg_len;
-        let chr_end = prg_end + chr_len;
-
-        if chr_end != raw.len() {
+        Ok(format)
+    }
+
+    pub fn load_ines(&mut self, raw: &[u8], persistent_data: Option<&[u8]>) -> Result<()> {
+        let header = InesHeader::parse(raw)?;
+        let prg_start = header.prg_rom_offset();
+        let prg_end = header.chr_rom_offset();
+        let chr_end = header.expected_file_size();
+        if chr_end > raw.len() {
             return Err(anyhow!(
-                "Expected rom size to be {}, but it is {}",
+                "Expected rom size to be at least {}, but it is {}",
                 chr_end,
                 raw.len()
             ));
         }
 
-        self.has_persistent_data = header.has_battery_ram;
+        // The trainer is loaded into PRG-RAM at $7000-$71FF.
+        let mut initial_ram = persistent_data.map(|data| data.to_vec());
+        if header.has_trainer {
+            let ram = initial_ram.get_or_insert_with(|| vec![0; 0x2000]);
+            ram.resize(ram.len().max(0x2000), 0);
+            ram[0x1000..0x1200].copy_from_slice(&raw[InesHeader::SIZE..prg_start]);
+        }
+        self.load_rom(
+            header,
+            &raw[prg_start..prg_end],
+            &raw[prg_end..chr_end],
+            initial_ram.as_deref(),
+        )
+    }
 
-        let mirroring_mode = if header.four_screen {
-            MirroringMode::FourScreen
-        } else if header.mirroring {
-            MirroringMode::Vertical
-        } else {
-            MirroringMode::Horizontal
-        };
+    /// Loads a UNIF file, whose board is emulated by the mapper of the equivalent iNES header.
+    pub fn load_unif(&mut self, raw: &[u8], persistent_data: Option<&[u8]>) -> Result<()> {
+        let rom = unif::parse_unif(raw)?;
+        self.load_rom(rom.header, &rom.prg, &rom.chr, persistent_data)
+    }
 
-        match mapper_id {
-            0 => {
-                self.mapper = MapperEnum::Nrom(NromMapper::new(
-                    &raw[prg_start..prg_end],
-                    &raw[prg_end..chr_end],
-                    mirroring_mode,
-                    persistent_data,
-                ))
+    /// Creates the mapper registered for the mapper number of the header. Headers other than
+    /// NES 2.0 are corrected by the game database if the ROM is known.
+    fn load_rom(
+        &mut self,
+        mut header: InesHeader,
+        prg: &[u8],
+        chr: &[u8],
+        persistent_data: Option<&[u8]>,
+    ) -> Result<()> {
+        let game = database::lookup(prg, chr);
+        if let Some(game) = game {
+            if header.format != HeaderFormat::Nes20 {
+                game.apply(&mut header);
             }
-            1 => {
-                self.mapper = MapperEnum::Mmc1(Mmc1Mapper::new(
-                    &raw[prg_start..prg_end],
-                    &raw[prg_end..chr_end],
-                    persistent_data,
-                ))
-            }
-            2 => {
-                self.mapper = MapperEnum::UxRom(UxRomMapper::new(
-                    &raw[prg_start..prg_end],
-                    &raw[prg_end..chr_end],
-                    mirroring_mode,
-                    persistent_data,
-                ))
-            }
-            _ => return Err(anyhow!("Unsupported mapper {mapper_id}")),
+        }
+        let registration = registry::find_by_number(header.mapper)?;
+        let rom = RomData {
+            header: &header,
+            prg,
+            chr,
+            persistent_data,
+        };
+        self.mapper = TaggedMapper {
+            tag: registration.tag.to_string(),
+            mapper: (registration.create)(&rom)?,
         };
+        self.four_screen_vram = if header.mirroring == MirroringMode::FourScreen {
+            vec![0; 0x800]
+        } else {
+            vec![]
+        };
+        self.has_persistent_data = header.has_battery;
+        self.header = Some(header);
+        self.nsf_header = None;
+        self.title = game.map(|game| game.title.clone());
         Ok(())
     }
 
     pub fn persistent_data(&self) -> Vec<u8> {
-        match &self.mapper {
-            MapperEnum::Nrom(mapper) => mapper.persistent_data(),
-            MapperEnum::Mmc1(mapper) => mapper.persistent_data(),
-            MapperEnum::UxRom(mapper) => mapper.persistent_data(),
-        }
+        self.mapper.mapper.persistent_data()
+    }
+
+    pub fn load_persistent_data(&mut self, data: &[u8]) {
+        self.mapper.mapper.load_persistent_data(data);
     }
 
     pub fn get_mirroring_mode(&self) -> MirroringMode {
-        match &self.mapper {
-            MapperEnum::Nrom(mapper) => mapper.get_mirroring_mode(),
-            MapperEnum::Mmc1(mapper) => mapper.get_mirroring_mode(),
-            MapperEnum::UxRom(mapper) => mapper.get_mirroring_mode(),
-        }
+        self.mapper.mapper.get_mirroring_mode()
     }
 
     pub fn cpu_bus_peek(&self, addr: u16) -> Option<u8> {
-        match &self.mapper {
-            MapperEnum::Nrom(mapper) => mapper.cpu_bus_peek(addr),
-            MapperEnum::Mmc1(mapper) => mapper.cpu_bus_peek(addr),
-            MapperEnum::UxRom(mapper) => mapper.cpu_bus_peek(addr),
-        }
+        self.mapper.mapper.cpu_bus_peek(addr)
     }
 
     pub fn cpu_bus_read(&mut self, addr: u16) -> CartridgeResult<u8> {
-        match &mut self.mapper {
-            MapperEnum::Nrom(mapper) => mapper.cpu_bus_read(addr),
-            MapperEnum::Mmc1(mapper) => mapper.cpu_bus_read(addr),
-            MapperEnum::UxRom(mapper) => mapper.cpu_bus_read(addr),
-        }
+        self.mapper.mapper.cpu_bus_read(addr)
     }
 
     pub fn cpu_bus_write(&mut self, addr: u16, value: u8) -> CartridgeResult<()> {
-        match &mut self.mapper {
-            MapperEnum::Nrom(mapper) => mapper.cpu_bus_write(addr, value),
-            MapperEnum::Mmc1(mapper) => mapper.cpu_bus_write(addr, value),
-            MapperEnum::UxRom(mapper) => mapper.cpu_bus_write(addr, value),
-        }
+        self.mapper.mapper.cpu_bus_write(addr, value)
     }
 
     pub fn ppu_bus_peek(&self, addr: u16) -> Option<u8> {
-        match &self.mapper {
-            MapperEnum::Nrom(mapper) => mapper.ppu_bus_peek(addr),
-            MapperEnum::Mmc1(mapper) => mapper.ppu_bus_peek(addr),
-            MapperEnum::UxRom(mapper) => mapper.ppu_bus_peek(addr),
-        }
+        self.mapper.mapper.ppu_bus_peek(addr)
     }
 
     pub fn ppu_bus_read(&mut self, addr: u16) -> CartridgeResult<u8> {
-        match &mut self.mapper {
-            MapperEnum::Nrom(mapper) => mapper.ppu_bus_read(addr),
-            MapperEnum::Mmc1(mapper) => mapper.ppu_bus_read(addr),
-            MapperEnum::UxRom(mapper) => mapper.ppu_bus_read(addr),
-        }
+        self.mapper.mapper.ppu_bus_read(addr)
     }
 
     pub fn ppu_bus_write(&mut self, addr: u16, value: u8) -> CartridgeResult<()> {
-        match &mut self.mapper {
-            MapperEnum::Nrom(mapper) => mapper.ppu_bus_write(addr, value),
-            MapperEnum::Mmc1(mapper) => mapper.ppu_bus_write(addr, value),
-            MapperEnum::UxRom(mapper) => mapper.ppu_bus_write(addr, value),
+        self.mapper.mapper.ppu_bus_write(addr, value)
+    }
+
+    pub fn ppu_address_changed(&mut self, addr: u16) {
+        self.mapper.mapper.ppu_address_changed(addr)
+    }
+
+    pub fn ppu_a12_rising_edge(&mut self) {
+        self.mapper.mapper.ppu_a12_rising_edge()
+    }
+
+    pub fn irq_active(&self) -> bool {
+        self.mapper.mapper.irq_active()
+    }
+
+    /// Returns the nametable data at $2000-$2FFF if it is provided by the cartridge instead of
+    /// the VRAM of the PPU.
+    pub fn nametable_peek(&self, addr: u16) -> Option<u8> {
+        self.mapper
+            .mapper
+            .nametable_peek(addr)
+            .or_else(|| Some(self.four_screen_vram[self.four_screen_vram_index(addr)?]))
+    }
+
+    pub fn nametable_fetch(&mut self, addr: u16) -> Option<u8> {
+        self.mapper
+            .mapper
+            .nametable_fetch(addr)
+            .or_else(|| Some(self.four_screen_vram[self.four_screen_vram_index(addr)?]))
+    }
+
+    /// Returns true if the write was handled by the cartridge.
+    pub fn nametable_write(&mut self, addr: u16, value: u8) -> bool {
+        if self.mapper.mapper.nametable_write(addr, value) {
+            return true;
+        }
+        match self.four_screen_vram_index(addr) {
+            Some(index) => {
+                self.four_screen_vram[index] = value;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The upper two nametables of four-screen boards are mapped to VRAM on the cartridge.
+    fn four_screen_vram_index(&self, addr: u16) -> Option<usize> {
+        if !self.four_screen_vram.is_empty()
+            && addr.bit(11)
+            && self.get_mirroring_mode() == MirroringMode::FourScreen
+        {
+            Some(addr as usize % 0x800)
+        } else {
+            None
         }
     }
+
+    pub fn ppu_fetch_phase(&mut self, phase: PpuFetchPhase, large_sprites: bool) {
+        self.mapper.mapper.ppu_fetch_phase(phase, large_sprites)
+    }
+
+    pub fn ppu_scanline_start(&mut self, scanline: usize, rendering: bool) {
+        self.mapper.mapper.ppu_scanline_start(scanline, rendering)
+    }
+
+    pub fn cpu_tick(&mut self) {
+        self.mapper.mapper.cpu_tick()
+    }
+
+    pub fn audio_output(&self) -> f32 {
+        self.mapper.mapper.audio_output()
+    }
+
+    pub fn disk_side_count(&self) -> usize {
+        self.mapper.mapper.disk_side_count()
+    }
+
+    pub fn inserted_disk_side(&self) -> Option<usize> {
+        self.mapper.mapper.inserted_disk_side()
+    }
+
+    /// Swaps the disk of disk based systems. None ejects the disk.
+    pub fn insert_disk_side(&mut self, side: Option<usize>) {
+        self.mapper.mapper.insert_disk_side(side)
+    }
+
+    pub fn track_count(&self) -> usize {
+        self.mapper.mapper.track_count()
+    }
+
+    pub fn current_track(&self) -> Option<usize> {
+        self.mapper.mapper.current_track()
+    }
+
+    /// Selects the track of music players that is started by the next reset.
+    pub fn select_track(&mut self, track: usize) {
+        self.mapper.mapper.select_track(track)
+    }
+}
+
+/// Allocates `size` bytes of PRG-RAM, restored from `persistent_data` if it has the same size.
+/// Saves of a different size are ignored, like by `Mapper::load_persistent_data`.
+fn persistent_ram(persistent_data: Option<&[u8]>, size: usize) -> Vec<u8> {
+    match persistent_data {
+        Some(data) if data.len() == size => data.to_vec(),
+        _ => vec![0; size],
+    }
+}
+
+/// Registrations of the mappers included in this crate.
+fn builtin_mappers() -> Vec<MapperRegistration> {
+    vec![
+        MapperRegistration {
+            tag: "nrom",
+            mapper_numbers: &[0],
+            create: |rom| {
+                Ok(Box::new(NromMapper::new(
+                    rom.prg,
+                    rom.chr,
+                    rom.header.mirroring,
+                    rom.persistent_data,
+                )))
+            },
+            decode: decode_mapper::<NromMapper>,
+        },
+        MapperRegistration {
+            tag: "mmc1",
+            mapper_numbers: &[1],
+            create: |rom| {
+                Ok(Box::new(Mmc1Mapper::new(
+                    rom.prg,
+                    rom.chr,
+                    rom.persistent_data,
+                )))
+            },
+            decode: decode_mapper::<Mmc1Mapper>,
+        },
+        MapperRegistration {
+            tag: "uxrom",
+            mapper_numbers: &[2],
+            create: |rom| {
+                Ok(Box::new(UxRomMapper::new(
+                    rom.prg,
+                    rom.chr,
+                    rom.header.mirroring,
+                    rom.persistent_data,
+                )))
+            },
+            decode: decode_mapper::<UxRomMapper>,
+        },
+        MapperRegistration {
+            tag: "cnrom",
+            mapper_numbers: &[3],
+            create: |rom| {
+                // NES 2.0 submapper 1 is used for boards without bus conflicts.
+                Ok(Box::new(CnRomMapper::new(
+                    rom.prg,
+                    rom.chr,
+                    rom.header.mirroring,
+                    rom.header.submapper != 1,
+                    rom.persistent_data,
+                )))
+            },
+            decode: decode_mapper::<CnRomMapper>,
+        },
+        MapperRegistration {
+            tag: "mmc3",
+            mapper_numbers: &[4],
+            create: |rom| {
+                Ok(Box::new(Mmc3Mapper::new(
+                    rom.prg,
+                    rom.chr,
+                    rom.header.mirroring,
+                    rom.persistent_data,
+                )))
+            },
+            decode: decode_mapper::<Mmc3Mapper>,
+        },
+        MapperRegistration {
+            tag: "mmc5",
+            mapper_numbers: &[5],
+            create: |rom| {
+                Ok(Box::new(Mmc5Mapper::new(
+                    rom.prg,
+                    rom.chr,
+                    rom.persistent_data,
+                )))
+            },
+            decode: decode_mapper::<Mmc5Mapper>,
+        },
+        MapperRegistration {
+            tag: "axrom",
+            mapper_numbers: &[7],
+            create: |rom| {
+                // Only AMROM has bus conflicts, which is identified by NES 2.0 submapper 2.
+                Ok(Box::new(AxRomMapper::new(
+                    rom.prg,
+                    rom.chr,
+                    rom.header.submapper == 2,
+                )))
+            },
+            decode: decode_mapper::<AxRomMapper>,
+        },
+        MapperRegistration {
+            tag: "mmc2",
+            mapper_numbers: &[9, 10],
+            create: |rom| {
+                let variant = if rom.header.mapper == 9 {
+                    Mmc2Variant::Mmc2
+                } else {
+                    Mmc2Variant::Mmc4
+                };
+                Ok(Box::new(Mmc2Mapper::new(
+                    variant,
+                    rom.prg,
+                    rom.chr,
+                    rom.persistent_data,
+                )))
+            },
+            decode: decode_mapper::<Mmc2Mapper>,
+        },
+        MapperRegistration {
+            tag: "namco163",
+            mapper_numbers: &[19],
+            create: |rom| {
+                Ok(Box::new(Namco163Mapper::new(
+                    rom.prg,
+                    rom.chr,
+                    rom.persistent_data,
+                )))
+            },
+            decode: decode_mapper::<Namco163Mapper>,
+        },
+        MapperRegistration {
+            tag: "vrc2_vrc4",
+            mapper_numbers: &[21, 22, 23, 25],
+            create: |rom| {
+                let variant = Vrc24Variant::from_header(rom.header.mapper, rom.header.submapper);
+                // iNES 1.0 headers always claim 8k PRG-RAM, so VRC2 boards are only assumed to
+                // have it with a battery. The others have the microwire latch instead.
+                let has_ram = if rom.header.has_exact_ram_sizes() {
+                    rom.header.prg_ram_size + rom.header.prg_nvram_size > 0
+                } else {
+                    rom.header.has_battery || variant.chip == VrcChip::Vrc4
+                };
+                Ok(Box::new(Vrc24Mapper::new(
+                    variant,
+                    rom.prg,
+                    rom.chr,
+                    has_ram,
+                    rom.persistent_data,
+                )))
+            },
+            decode: decode_mapper::<Vrc24Mapper>,
+        },
+        MapperRegistration {
+            tag: "vrc6",
+            mapper_numbers: &[24, 26],
+            create: |rom| {
+                let variant = if rom.header.mapper == 24 {
+                    Vrc6Variant::Vrc6a
+                } else {
+                    Vrc6Variant::Vrc6b
+                };
+                Ok(Box::new(Vrc6Mapper::new(
+                    variant,
+                    rom.prg,
+                    rom.chr,
+                    rom.persistent_data,
+                )))
+            },
+            decode: decode_mapper::<Vrc6Mapper>,
+        },
+        MapperRegistration {
+            tag: "bnrom",
+            mapper_numbers: &[34],
+            create: |rom| {
+                // Use the NES 2.0 submapper if available, otherwise only NINA-001 has CHR-ROM.
+                let board = match rom.header.submapper {
+                    1 => Mapper34Board::Nina001,
+                    2 => Mapper34Board::BnRom,
+                    _ if rom.header.chr_rom_size > 0 => Mapper34Board::Nina001,
+                    _ => Mapper34Board::BnRom,
+                };
+                Ok(Box::new(BnRomMapper::new(
+                    board,
+                    rom.prg,
+                    rom.chr,
+                    rom.header.mirroring,
+                    rom.persistent_data,
+                )))
+            },
+            decode: decode_mapper::<BnRomMapper>,
+        },
+        MapperRegistration {
+            tag: "gxrom",
+            mapper_numbers: &[66],
+            create: |rom| {
+                Ok(Box::new(GxRomMapper::new(
+                    rom.prg,
+                    rom.chr,
+                    rom.header.mirroring,
+                )))
+            },
+            decode: decode_mapper::<GxRomMapper>,
+        },
+        MapperRegistration {
+            tag: "fme7",
+            mapper_numbers: &[69],
+            create: |rom| {
+                Ok(Box::new(Fme7Mapper::new(
+                    rom.prg,
+                    rom.chr,
+                    rom.persistent_data,
+                )))
+            },
+            decode: decode_mapper::<Fme7Mapper>,
+        },
+        MapperRegistration {
+            tag: "fds",
+            // FDS images are loaded with `load_fds`.
+            mapper_numbers: &[],
+            create: |_| Err(anyhow!("FDS images are not loaded from iNES files")),
+            decode: decode_mapper::<FdsMapper>,
+        },
+        MapperRegistration {
+            tag: "nsf",
+            // NSF files are loaded with `load_nsf`.
+            mapper_numbers: &[],
+            create: |_| Err(anyhow!("NSF files are not loaded from iNES files")),
+            decode: decode_mapper::<NsfMapper>,
+        },
+        MapperRegistration {
+            tag: "single_chip",
+            mapper_numbers: &[218],
+            create: |rom| {
+                Ok(Box::new(SingleChipMapper::new(
+                    rom.prg,
+                    rom.header.mirroring == MirroringMode::FourScreen,
+                    rom.header.mirroring_bit,
+                )))
+            },
+            decode: decode_mapper::<SingleChipMapper>,
+        },
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_load_ines_with_trainer_and_trailing_data() {
+        let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x00, 0x04, 0x00];
+        rom.resize(16, 0);
+        rom.extend((0..InesHeader::TRAINER_SIZE).map(|i| i as u8));
+        rom.extend([0x42; 16 * 1024]);
+        rom.extend(b"Trailing title");
+
+        let mut cartridge = Cartridge::new();
+        cartridge.load_ines(&rom, None).unwrap();
+        assert!(cartridge.header.unwrap().has_trainer);
+        assert_eq!(cartridge.cpu_bus_peek(0x7000), Some(0x00));
+        assert_eq!(cartridge.cpu_bus_peek(0x71FF), Some(0xFF));
+        assert_eq!(cartridge.cpu_bus_peek(0x8000), Some(0x42));
+
+        // Files that are too short are rejected.
+        assert!(cartridge.load_ines(&rom[..16 * 1024], None).is_err());
+    }
+
+    #[test]
+    pub fn test_load_persistent_data() {
+        // MMC1 with battery backed PRG-RAM.
+        let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, 0x02, 0x01, 0x12, 0x00];
+        rom.resize(16 + 40 * 1024, 0);
+        let mut cartridge = Cartridge::new();
+        cartridge.load_ines(&rom, None).unwrap();
+        assert!(cartridge.has_persistent_data);
+
+        let mut save = vec![0; 8 * 1024];
+        save[0] = 0x42;
+        cartridge.load_persistent_data(&save);
+        assert_eq!(cartridge.cpu_bus_peek(0x6000), Some(0x42));
+        assert_eq!(cartridge.persistent_data(), save);
+
+        // Data of the wrong size is ignored.
+        cartridge.load_persistent_data(&[0x12]);
+        assert_eq!(cartridge.cpu_bus_peek(0x6000), Some(0x42));
+    }
+
+    #[test]
+    pub fn test_vrc2_ram_detection() {
+        let load = |flags6: u8, flags7: u8| {
+            // VRC2 (mapper 22) with 16k PRG-ROM and 8k CHR-ROM.
+            let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, flags6, flags7];
+            rom.resize(16 + 24 * 1024, 0);
+            let mut cartridge = Cartridge::new();
+            cartridge.load_ines(&rom, None).unwrap();
+            cartridge.cpu_bus_write(0x6000, 0xFF).unwrap();
+            cartridge.cpu_bus_peek(0x6000)
+        };
+        // iNES 1.0 without battery has the microwire latch, which only stores bit 0.
+        assert_eq!(load(0x60, 0x10), Some(0x01));
+        // iNES 1.0 with battery has PRG-RAM.
+        assert_eq!(load(0x62, 0x10), Some(0xFF));
+        // NES 2.0 without PRG-RAM has the microwire latch.
+        assert_eq!(load(0x60, 0x18), Some(0x01));
+    }
+
+    #[test]
+    pub fn test_four_screen_vram() {
+        let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x08, 0x00];
+        rom.resize(16 + 24 * 1024, 0);
+
+        let mut cartridge = Cartridge::new();
+        cartridge.load_ines(&rom, None).unwrap();
+        assert_eq!(cartridge.get_mirroring_mode(), MirroringMode::FourScreen);
+        // The lower two nametables are left to the PPU.
+        assert!(!cartridge.nametable_write(0x2400, 0x12));
+        assert!(cartridge.nametable_write(0x2800, 0x34));
+        assert!(cartridge.nametable_write(0x2C00, 0x56));
+        assert_eq!(cartridge.nametable_peek(0x2400), None);
+        assert_eq!(cartridge.nametable_peek(0x2800), Some(0x34));
+        assert_eq!(cartridge.nametable_fetch(0x2C00), Some(0x56));
+    }
+
+    #[test]
+    pub fn test_load_detects_format() {
+        let mut cartridge = Cartridge::new();
+        let mut ines = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x00, 0x00, 0x08];
+        ines.resize(16, 0);
+        ines.resize(16 + 16 * 1024, 0x42);
+        assert_eq!(cartridge.load(&ines, None, None).unwrap(), RomFormat::Nes20);
+        ines[7] = 0x00;
+        assert_eq!(cartridge.load(&ines, None, None).unwrap(), RomFormat::Ines);
+
+        let mut unif = b"UNIF".to_vec();
+        unif.resize(32, 0);
+        unif.extend(b"MAPR\x0A\0\0\0NES-UNROM\0");
+        unif.extend(b"PRG0\x00\x40\0\0");
+        unif.extend([0x17; 16 * 1024]);
+        assert_eq!(cartridge.load(&unif, None, None).unwrap(), RomFormat::Unif);
+        assert_eq!(cartridge.header.unwrap().mapper, 2);
+        assert_eq!(cartridge.cpu_bus_peek(0xC000), Some(0x17));
+
+        let mut fds = b"\x01*NINTENDO-HVC*".to_vec();
+        fds.resize(65500, 0);
+        assert!(cartridge.load(&fds, None, None).is_err());
+        assert_eq!(
+            cartridge.load(&fds, None, Some(&[0; 8 * 1024])).unwrap(),
+            RomFormat::Fds
+        );
+        assert_eq!(cartridge.disk_side_count(), 1);
+
+        assert!(cartridge.load(b"Unknown", None, None).is_err());
+    }
+
+    #[test]
+    pub fn test_database_corrects_header() {
+        // Claim CNROM with vertical mirroring and battery backed PRG-RAM.
+        let mut rom = std::fs::read("tests/e2e/ice_climber.nes").unwrap();
+        rom[6] = 0x33;
+        let mut cartridge = Cartridge::new();
+        cartridge.load_ines(&rom, None).unwrap();
+        let header = cartridge.header.unwrap();
+        assert!(cartridge.title.as_ref().unwrap().starts_with("Ice Climber"));
+        assert_eq!(cartridge.mapper.tag, "nrom");
+        assert_eq!(header.mapper, 0);
+        assert_eq!(header.mirroring, MirroringMode::Horizontal);
+        assert_eq!(cartridge.get_mirroring_mode(), MirroringMode::Horizontal);
+        assert_eq!(header.prg_ram_size + header.prg_nvram_size, 0);
+        assert!(!header.has_battery);
+        assert!(!cartridge.has_persistent_data);
+
+        // NES 2.0 headers are trusted, but the game is still identified.
+        rom[7] = 0x08;
+        cartridge.load_ines(&rom, None).unwrap();
+        assert_eq!(cartridge.header.unwrap().mapper, 3);
+        assert_eq!(cartridge.mapper.tag, "cnrom");
+        assert!(cartridge.title.as_ref().unwrap().starts_with("Ice Climber"));
+
+        // Claim NROM with vertical mirroring for the MMC1 board with 8k PRG-RAM and CHR-RAM.
+        let mut rom = std::fs::read("../roms/programs/instr_test_v5.nes").unwrap();
+        rom[6] = 0x01;
+        cartridge.load_ines(&rom, None).unwrap();
+        let header = cartridge.header.unwrap();
+        assert_eq!(cartridge.mapper.tag, "mmc1");
+        assert_eq!(header.mapper, 1);
+        assert_eq!(header.mirroring, MirroringMode::Horizontal);
+        assert_eq!(header.prg_ram_size, 8 * 1024);
+        assert_eq!(header.chr_ram_size, 8 * 1024);
+        cartridge.cpu_bus_write(0x6000, 0x42).unwrap();
+        assert_eq!(cartridge.cpu_bus_peek(0x6000), Some(0x42));
+    }
 }
@@ -0,0 +1,148 @@
+use bincode::Decode;
+use bincode::Encode;
+use intbits::Bits;
+
+use super::CartridgeError;
+use super::CartridgeResult;
+use super::Mapper;
+use super::MirroringMode;
+
+const PRG_BANK_SIZE: usize = 32 * 1024;
+
+/// AxROM (mapper 7): Switchable 32k PRG-ROM bank, CHR-RAM and single screen mirroring.
+#[derive(Encode, Decode, Clone)]
+pub struct AxRomMapper {
+    pub prg: Vec<u8>,
+    pub chr: Vec<u8>,
+    pub chr_is_ram: bool,
+    pub control_register: u8,
+    pub bus_conflicts: bool,
+}
+
+impl AxRomMapper {
+    pub fn new(prg: &[u8], chr: &[u8], bus_conflicts: bool) -> AxRomMapper {
+        AxRomMapper {
+            prg: prg.to_vec(),
+            chr: if chr.is_empty() {
+                vec![0; 8 * 1024]
+            } else {
+                chr.to_vec()
+            },
+            chr_is_ram: chr.is_empty(),
+            control_register: 0,
+            bus_conflicts,
+        }
+    }
+
+    fn get_prg_index(&self, addr: u16) -> usize {
+        let bank_count = (self.prg.len() / PRG_BANK_SIZE).max(1);
+        let bank = self.control_register.bits(0..=3) as usize % bank_count;
+        bank * PRG_BANK_SIZE + (addr as usize - 0x8000)
+    }
+}
+
+impl Default for AxRomMapper {
+    fn default() -> Self {
+        Self::new(&[], &[], false)
+    }
+}
+
+impl Mapper for AxRomMapper {
+    fn cpu_bus_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x8000..=0xFFFF => self.prg.get(self.get_prg_index(addr)).copied(),
+            _ => None,
+        }
+    }
+
+    fn cpu_bus_read(&mut self, addr: u16) -> CartridgeResult<u8> {
+        Ok(self.cpu_bus_peek(addr).unwrap_or_default())
+    }
+
+    fn cpu_bus_write(&mut self, addr: u16, value: u8) -> CartridgeResult<()> {
+        match addr {
+            0x8000..=0xFFFF => {
+                // With bus conflicts the ROM drives the data bus at the same time as the CPU.
+                self.control_register = if self.bus_conflicts {
+                    value & self.cpu_bus_peek(addr).unwrap_or(0xFF)
+                } else {
+                    value
+                };
+                Ok(())
+            }
+            _ => Err(CartridgeError::InvalidWrite(addr)),
+        }
+    }
+
+    fn ppu_bus_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x0000..=0x1FFF => Some(self.chr[addr as usize]),
+            _ => None,
+        }
+    }
+
+    fn ppu_bus_read(&mut self, addr: u16) -> CartridgeResult<u8> {
+        self.ppu_bus_peek(addr)
+            .ok_or(CartridgeError::InvalidRead(addr))
+    }
+
+    fn ppu_bus_write(&mut self, addr: u16, value: u8) -> CartridgeResult<()> {
+        if let 0x0000..=0x1FFF = addr {
+            if self.chr_is_ram {
+                self.chr[addr as usize] = value;
+            }
+        }
+        Ok(())
+    }
+
+    fn get_mirroring_mode(&self) -> MirroringMode {
+        if self.control_register.bit(4) {
+            MirroringMode::SingleUpper
+        } else {
+            MirroringMode::SingleLower
+        }
+    }
+
+    fn persistent_data(&self) -> Vec<u8> {
+        Vec::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AxRomMapper;
+    use super::PRG_BANK_SIZE;
+    use crate::cartridge::Mapper;
+    use crate::cartridge::MirroringMode;
+
+    fn test_mapper() -> AxRomMapper {
+        // Create a mapper with the bank number in the first byte of each bank to allow us to
+        // identify them after mapping.
+        let mut prg = vec![0xFF_u8; 4 * PRG_BANK_SIZE];
+        for bank in 0..4 {
+            prg[bank * PRG_BANK_SIZE] = bank as u8;
+        }
+        AxRomMapper::new(&prg, &[], false)
+    }
+
+    #[test]
+    pub fn test_prg_mapping_and_mirroring() {
+        let mut mapper = test_mapper();
+        assert_eq!(mapper.cpu_bus_peek(0x8000), Some(0));
+        assert!(matches!(
+            mapper.get_mirroring_mode(),
+            MirroringMode::SingleLower
+        ));
+
+        mapper.cpu_bus_write(0xFFFF, 0b0001_0010).unwrap();
+        assert_eq!(mapper.cpu_bus_peek(0x8000), Some(2));
+        assert!(matches!(
+            mapper.get_mirroring_mode(),
+            MirroringMode::SingleUpper
+        ));
+
+        // CHR-RAM is writable
+        mapper.ppu_bus_write(0x1234, 0x42).unwrap();
+        assert_eq!(mapper.ppu_bus_peek(0x1234), Some(0x42));
+    }
+}
@@ -0,0 +1,236 @@
+use bincode::Decode;
+use bincode::Encode;
+use intbits::Bits;
+
+use super::persistent_ram;
+use super::CartridgeError;
+use super::CartridgeResult;
+use super::Mapper;
+use super::MirroringMode;
+
+const PRG_BANK_SIZE: usize = 32 * 1024;
+const CHR_BANK_SIZE: usize = 4 * 1024;
+
+/// Mapper 34 is used by two different boards.
+#[derive(Encode, Decode, Clone, Copy, PartialEq, Eq)]
+pub enum Mapper34Board {
+    /// Switchable 32k PRG-ROM bank selected by writes to $8000-$FFFF, CHR-RAM and bus
+    /// conflicts.
+    BnRom,
+    /// Switchable 32k PRG-ROM bank and two 4k CHR-ROM banks selected by writes to
+    /// $7FFD-$7FFF.
+    Nina001,
+}
+
+/// BNROM and NINA-001 (mapper 34)
+#[derive(Encode, Decode, Clone)]
+pub struct BnRomMapper {
+    pub board: Mapper34Board,
+    pub prg: Vec<u8>,
+    pub chr: Vec<u8>,
+    pub ram: Vec<u8>,
+    pub chr_is_ram: bool,
+    pub prg_bank: u8,
+    pub chr_banks: [u8; 2],
+    pub mirroring_mode: MirroringMode,
+}
+
+impl BnRomMapper {
+    const RAM_SIZE: usize = 8 * 1024;
+
+    pub fn new(
+        board: Mapper34Board,
+        prg: &[u8],
+        chr: &[u8],
+        mirroring_mode: MirroringMode,
+        persistent_data: Option<&[u8]>,
+    ) -> BnRomMapper {
+        BnRomMapper {
+            board,
+            prg: prg.to_vec(),
+            chr: if chr.is_empty() {
+                vec![0; 8 * 1024]
+            } else {
+                chr.to_vec()
+            },
+            ram: persistent_ram(persistent_data, BnRomMapper::RAM_SIZE),
+            chr_is_ram: chr.is_empty(),
+            prg_bank: 0,
+            chr_banks: [0, 1],
+            mirroring_mode,
+        }
+    }
+
+    fn get_prg_index(&self, addr: u16) -> usize {
+        let bank_count = (self.prg.len() / PRG_BANK_SIZE).max(1);
+        (self.prg_bank as usize % bank_count) * PRG_BANK_SIZE + (addr as usize - 0x8000)
+    }
+
+    fn get_chr_index(&self, addr: u16) -> usize {
+        match self.board {
+            Mapper34Board::BnRom => addr as usize,
+            Mapper34Board::Nina001 => {
+                let bank_count = (self.chr.len() / CHR_BANK_SIZE).max(1);
+                let bank = self.chr_banks[addr as usize / CHR_BANK_SIZE] as usize % bank_count;
+                bank * CHR_BANK_SIZE + (addr as usize % CHR_BANK_SIZE)
+            }
+        }
+    }
+}
+
+impl Default for BnRomMapper {
+    fn default() -> Self {
+        Self::new(
+            Mapper34Board::BnRom,
+            &[],
+            &[],
+            MirroringMode::Horizontal,
+            None,
+        )
+    }
+}
+
+impl Mapper for BnRomMapper {
+    fn cpu_bus_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7FFF => Some(self.ram[(addr as usize - 0x6000) % BnRomMapper::RAM_SIZE]),
+            0x8000..=0xFFFF => self.prg.get(self.get_prg_index(addr)).copied(),
+            _ => None,
+        }
+    }
+
+    fn cpu_bus_read(&mut self, addr: u16) -> CartridgeResult<u8> {
+        Ok(self.cpu_bus_peek(addr).unwrap_or_default())
+    }
+
+    fn cpu_bus_write(&mut self, addr: u16, value: u8) -> CartridgeResult<()> {
+        match (self.board, addr) {
+            (Mapper34Board::Nina001, 0x7FFD..=0x7FFF) => {
+                // Registers are written to RAM as well.
+                self.ram[(addr as usize - 0x6000) % BnRomMapper::RAM_SIZE] = value;
+                match addr {
+                    0x7FFD => self.prg_bank = value.bit(0) as u8,
+                    0x7FFE => self.chr_banks[0] = value.bits(0..=3),
+                    _ => self.chr_banks[1] = value.bits(0..=3),
+                }
+                Ok(())
+            }
+            (_, 0x6000..=0x7FFF) => {
+                self.ram[(addr as usize - 0x6000) % BnRomMapper::RAM_SIZE] = value;
+                Ok(())
+            }
+            (Mapper34Board::BnRom, 0x8000..=0xFFFF) => {
+                // The board has bus conflicts, the ROM drives the data bus at the same time
+                // as the CPU.
+                self.prg_bank = value & self.cpu_bus_peek(addr).unwrap_or(0xFF);
+                Ok(())
+            }
+            (Mapper34Board::Nina001, 0x8000..=0xFFFF) => Ok(()),
+            _ => Err(CartridgeError::InvalidWrite(addr)),
+        }
+    }
+
+    fn ppu_bus_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x0000..=0x1FFF => Some(self.chr[self.get_chr_index(addr)]),
+            _ => None,
+        }
+    }
+
+    fn ppu_bus_read(&mut self, addr: u16) -> CartridgeResult<u8> {
+        self.ppu_bus_peek(addr)
+            .ok_or(CartridgeError::InvalidRead(addr))
+    }
+
+    fn ppu_bus_write(&mut self, addr: u16, value: u8) -> CartridgeResult<()> {
+        if let 0x0000..=0x1FFF = addr {
+            if self.chr_is_ram {
+                let idx = self.get_chr_index(addr);
+                self.chr[idx] = value;
+            }
+        }
+        Ok(())
+    }
+
+    fn get_mirroring_mode(&self) -> MirroringMode {
+        self.mirroring_mode
+    }
+
+    fn persistent_data(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn load_persistent_data(&mut self, data: &[u8]) {
+        if data.len() == self.ram.len() {
+            self.ram.copy_from_slice(data);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BnRomMapper;
+    use super::Mapper34Board;
+    use super::CHR_BANK_SIZE;
+    use super::PRG_BANK_SIZE;
+    use crate::cartridge::Mapper;
+    use crate::cartridge::MirroringMode;
+
+    fn test_prg() -> Vec<u8> {
+        // Bank number in the first byte of each bank. All other bytes are 0xFF to avoid bus
+        // conflicts.
+        let mut prg = vec![0xFF_u8; 4 * PRG_BANK_SIZE];
+        for bank in 0..4 {
+            prg[bank * PRG_BANK_SIZE] = bank as u8;
+        }
+        prg
+    }
+
+    #[test]
+    pub fn test_bnrom() {
+        let mut mapper = BnRomMapper::new(
+            Mapper34Board::BnRom,
+            &test_prg(),
+            &[],
+            MirroringMode::Vertical,
+            None,
+        );
+        mapper.cpu_bus_write(0x8001, 3).unwrap();
+        assert_eq!(mapper.cpu_bus_peek(0x8000), Some(3));
+
+        // Bus conflict with the 0x03 stored at the start of bank 3.
+        mapper.cpu_bus_write(0x8000, 2).unwrap();
+        assert_eq!(mapper.cpu_bus_peek(0x8000), Some(2));
+        mapper.cpu_bus_write(0x8000, 1).unwrap();
+        assert_eq!(mapper.cpu_bus_peek(0x8000), Some(0));
+    }
+
+    #[test]
+    pub fn test_nina001() {
+        let mut chr = vec![0x00_u8; 4 * CHR_BANK_SIZE];
+        for bank in 0..4 {
+            chr[bank * CHR_BANK_SIZE] = bank as u8;
+        }
+        let mut mapper = BnRomMapper::new(
+            Mapper34Board::Nina001,
+            &test_prg(),
+            &chr,
+            MirroringMode::Vertical,
+            None,
+        );
+        assert_eq!(mapper.ppu_bus_peek(0x0000), Some(0));
+        assert_eq!(mapper.ppu_bus_peek(0x1000), Some(1));
+
+        mapper.cpu_bus_write(0x7FFD, 1).unwrap();
+        mapper.cpu_bus_write(0x7FFE, 3).unwrap();
+        mapper.cpu_bus_write(0x7FFF, 2).unwrap();
+        assert_eq!(mapper.cpu_bus_peek(0x8000), Some(1));
+        assert_eq!(mapper.ppu_bus_peek(0x0000), Some(3));
+        assert_eq!(mapper.ppu_bus_peek(0x1000), Some(2));
+        assert_eq!(mapper.cpu_bus_peek(0x7FFF), Some(2));
+
+        // Writes to ROM do not switch banks.
+        mapper.cpu_bus_write(0x8000, 0).unwrap();
+        assert_eq!(mapper.cpu_bus_peek(0x8000), Some(1));
+    }
+}
@@ -0,0 +1,168 @@
+use bincode::Decode;
+use bincode::Encode;
+
+use super::persistent_ram;
+use super::CartridgeError;
+use super::CartridgeResult;
+use super::Mapper;
+use super::MirroringMode;
+
+const CHR_BANK_SIZE: usize = 8 * 1024;
+
+/// CNROM (mapper 3): Fixed PRG-ROM with switchable 8k CHR-ROM banks.
+#[derive(Encode, Decode, Clone)]
+pub struct CnRomMapper {
+    pub prg: Vec<u8>,
+    pub chr: Vec<u8>,
+    pub ram: Vec<u8>,
+    pub chr_bank: u8,
+    pub bus_conflicts: bool,
+    pub mirroring_mode: MirroringMode,
+}
+
+impl CnRomMapper {
+    const RAM_SIZE: usize = 8 * 1024;
+
+    pub fn new(
+        prg: &[u8],
+        chr: &[u8],
+        mirroring_mode: MirroringMode,
+        bus_conflicts: bool,
+        persistent_data: Option<&[u8]>,
+    ) -> CnRomMapper {
+        CnRomMapper {
+            prg: prg.to_vec(),
+            chr: if chr.is_empty() {
+                vec![0; CHR_BANK_SIZE]
+            } else {
+                chr.to_vec()
+            },
+            ram: persistent_ram(persistent_data, CnRomMapper::RAM_SIZE),
+            chr_bank: 0,
+            bus_conflicts,
+            mirroring_mode,
+        }
+    }
+
+    fn get_chr_index(&self, addr: u16) -> usize {
+        let bank_count = (self.chr.len() / CHR_BANK_SIZE).max(1);
+        (self.chr_bank as usize % bank_count) * CHR_BANK_SIZE + addr as usize
+    }
+}
+
+impl Default for CnRomMapper {
+    fn default() -> Self {
+        Self::new(&[], &[], MirroringMode::Horizontal, false, None)
+    }
+}
+
+impl Mapper for CnRomMapper {
+    fn cpu_bus_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7FFF => Some(self.ram[(addr as usize - 0x6000) % CnRomMapper::RAM_SIZE]),
+            0x8000..=0xFFFF => {
+                if !self.prg.is_empty() {
+                    Some(self.prg[(addr as usize - 0x8000) % self.prg.len()])
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn cpu_bus_read(&mut self, addr: u16) -> CartridgeResult<u8> {
+        Ok(self.cpu_bus_peek(addr).unwrap_or_default())
+    }
+
+    fn cpu_bus_write(&mut self, addr: u16, value: u8) -> CartridgeResult<()> {
+        match addr {
+            0x6000..=0x7FFF => {
+                self.ram[(addr as usize - 0x6000) % CnRomMapper::RAM_SIZE] = value;
+                Ok(())
+            }
+            0x8000..=0xFFFF => {
+                // With bus conflicts the ROM drives the data bus at the same time as the CPU.
+                self.chr_bank = if self.bus_conflicts {
+                    value & self.cpu_bus_peek(addr).unwrap_or(0xFF)
+                } else {
+                    value
+                };
+                Ok(())
+            }
+            _ => Err(CartridgeError::InvalidWrite(addr)),
+        }
+    }
+
+    fn ppu_bus_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x0000..=0x1FFF => Some(self.chr[self.get_chr_index(addr)]),
+            _ => None,
+        }
+    }
+
+    fn ppu_bus_read(&mut self, addr: u16) -> CartridgeResult<u8> {
+        self.ppu_bus_peek(addr)
+            .ok_or(CartridgeError::InvalidRead(addr))
+    }
+
+    fn ppu_bus_write(&mut self, _addr: u16, _value: u8) -> CartridgeResult<()> {
+        // Some games will try to write to character ROM and expect it to NOOP.
+        Ok(())
+    }
+
+    fn get_mirroring_mode(&self) -> MirroringMode {
+        self.mirroring_mode
+    }
+
+    fn persistent_data(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn load_persistent_data(&mut self, data: &[u8]) {
+        if data.len() == self.ram.len() {
+            self.ram.copy_from_slice(data);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CnRomMapper;
+    use super::CHR_BANK_SIZE;
+    use crate::cartridge::Mapper;
+    use crate::cartridge::MirroringMode;
+
+    fn test_mapper(bus_conflicts: bool) -> CnRomMapper {
+        // PRG-ROM contains 0xFF except for 0x8000, which is 0x01 to test bus conflicts.
+        let mut prg = vec![0xFF_u8; 32 * 1024];
+        prg[0] = 0x01;
+        let mut chr = vec![0x00_u8; 4 * CHR_BANK_SIZE];
+        for bank in 0..4 {
+            chr[bank * CHR_BANK_SIZE] = bank as u8;
+        }
+        CnRomMapper::new(&prg, &chr, MirroringMode::Vertical, bus_conflicts, None)
+    }
+
+    #[test]
+    pub fn test_chr_mapping() {
+        let mut mapper = test_mapper(true);
+        assert_eq!(mapper.ppu_bus_peek(0x0000), Some(0));
+        mapper.cpu_bus_write(0xC000, 2).unwrap();
+        assert_eq!(mapper.ppu_bus_peek(0x0000), Some(2));
+        mapper.cpu_bus_write(0xC000, 3).unwrap();
+        assert_eq!(mapper.ppu_bus_peek(0x0000), Some(3));
+    }
+
+    #[test]
+    pub fn test_bus_conflicts() {
+        // The written value is ANDed with the ROM value at the written address.
+        let mut mapper = test_mapper(true);
+        mapper.cpu_bus_write(0x8000, 3).unwrap();
+        assert_eq!(mapper.ppu_bus_peek(0x0000), Some(1));
+
+        let mut mapper = test_mapper(false);
+        mapper.cpu_bus_write(0x8000, 3).unwrap();
+        assert_eq!(mapper.ppu_bus_peek(0x0000), Some(3));
+    }
+}
@@ -0,0 +1,178 @@
+use anyhow::anyhow;
+use anyhow::Result;
+use lazy_static::lazy_static;
+
+use super::InesHeader;
+use super::MirroringMode;
+use super::TimingMode;
+
+lazy_static! {
+    static ref GAMES: Vec<GameInfo> =
+        parse_database(include_str!("database.txt")).expect("Embedded game database is invalid");
+}
+
+/// Board and title of a known ROM, used to correct the often inaccurate iNES 1.0 headers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GameInfo {
+    pub crc32: u32,
+    pub sha1: [u8; 20],
+    pub mapper: u16,
+    pub submapper: u8,
+    pub mirroring: MirroringMode,
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+    pub timing: TimingMode,
+    pub expansion_device: u8,
+    pub title: String,
+}
+
+impl GameInfo {
+    /// Replaces the board description of the header. The ROM sizes are kept, since they
+    /// were already used to find the entry.
+    pub fn apply(&self, header: &mut InesHeader) {
+        header.mapper = self.mapper;
+        header.submapper = self.submapper;
+        header.mirroring = self.mirroring;
+        header.mirroring_bit = self.mirroring == MirroringMode::Vertical;
+        header.prg_ram_size = self.prg_ram_size;
+        header.prg_nvram_size = self.prg_nvram_size;
+        header.chr_ram_size = self.chr_ram_size;
+        header.chr_nvram_size = self.chr_nvram_size;
+        header.has_battery = self.prg_nvram_size + self.chr_nvram_size > 0;
+        header.timing = self.timing;
+        header.expansion_device = self.expansion_device;
+        header.from_database = true;
+    }
+}
+
+/// Finds the database entry of a ROM by the CRC32 and SHA-1 of its PRG-ROM followed by its
+/// CHR-ROM.
+pub fn lookup(prg: &[u8], chr: &[u8]) -> Option<&'static GameInfo> {
+    lookup_in(&GAMES, prg, chr)
+}
+
+fn lookup_in<'a>(games: &'a [GameInfo], prg: &[u8], chr: &[u8]) -> Option<&'a GameInfo> {
+    let mut crc32 = crc32fast::Hasher::new();
+    crc32.update(prg);
+    crc32.update(chr);
+    let crc32 = crc32.finalize();
+    let mut candidates = games.iter().filter(|game| game.crc32 == crc32).peekable();
+    // Only hash with SHA-1 if the CRC32 matches, which rules out most ROMs cheaply.
+    candidates.peek()?;
+    let mut sha1 = sha1_smol::Sha1::new();
+    sha1.update(prg);
+    sha1.update(chr);
+    let sha1 = sha1.digest().bytes();
+    candidates.find(|game| game.sha1 == sha1)
+}
+
+/// Parses the lines of the database, see database.txt for the format.
+fn parse_database(text: &str) -> Result<Vec<GameInfo>> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| parse_entry(line).map_err(|e| anyhow!("Invalid database entry {line}: {e}")))
+        .collect()
+}
+
+fn parse_entry(line: &str) -> Result<GameInfo> {
+    let mut columns = line.splitn(11, ' ');
+    let mut next = || columns.next().ok_or_else(|| anyhow!("Missing column"));
+    let crc32 = u32::from_str_radix(next()?, 16)?;
+    let sha1 = parse_sha1(next()?)?;
+    let (mapper, submapper) = next()?
+        .split_once('.')
+        .ok_or_else(|| anyhow!("Expected mapper.submapper"))?;
+    let mirroring = match next()? {
+        "H" => MirroringMode::Horizontal,
+        "V" => MirroringMode::Vertical,
+        "4" => MirroringMode::FourScreen,
+        other => return Err(anyhow!("Unknown mirroring {other}")),
+    };
+    let mut ram_size = || -> Result<usize> { Ok(next()?.parse::<usize>()? * 1024) };
+    let prg_ram_size = ram_size()?;
+    let prg_nvram_size = ram_size()?;
+    let chr_ram_size = ram_size()?;
+    let chr_nvram_size = ram_size()?;
+    let timing = match next()? {
+        "NTSC" => TimingMode::Ntsc,
+        "PAL" => TimingMode::Pal,
+        "MULTI" => TimingMode::MultiRegion,
+        "DENDY" => TimingMode::Dendy,
+        other => return Err(anyhow!("Unknown timing {other}")),
+    };
+    Ok(GameInfo {
+        crc32,
+        sha1,
+        mapper: mapper.parse()?,
+        submapper: submapper.parse()?,
+        mirroring,
+        prg_ram_size,
+        prg_nvram_size,
+        chr_ram_size,
+        chr_nvram_size,
+        timing,
+        expansion_device: next()?.parse()?,
+        title: next()?.to_string(),
+    })
+}
+
+fn parse_sha1(hex: &str) -> Result<[u8; 20]> {
+    let mut sha1 = [0; 20];
+    if hex.len() != 40 || !hex.is_ascii() {
+        return Err(anyhow!("Expected 40 hex digits in SHA-1 {hex}"));
+    }
+    for (i, byte) in sha1.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)?;
+    }
+    Ok(sha1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_embedded_database() {
+        assert!(parse_database(include_str!("database.txt")).is_ok());
+        assert!(GAMES
+            .iter()
+            .any(|game| game.title.starts_with("Ice Climber")));
+    }
+
+    #[test]
+    pub fn test_lookup() {
+        // CRC32 and SHA-1 of "abc" split between PRG and CHR.
+        let games = parse_database(
+            "# Comment\n\
+             352441C2 A9993E364706816ABA3E25717850C26C9CD0D89D 4.1 4 8 8 0 0 PAL 2 Test Game\n",
+        )
+        .unwrap();
+        let game = lookup_in(&games, b"ab", b"c").unwrap();
+        assert_eq!(game.title, "Test Game");
+        assert_eq!(game.mapper, 4);
+        assert_eq!(game.submapper, 1);
+        assert_eq!(game.prg_nvram_size, 8 * 1024);
+        assert_eq!(game.timing, TimingMode::Pal);
+        assert!(lookup_in(&games, b"abd", b"").is_none());
+
+        // A CRC32 match is not enough to identify a ROM.
+        let mut collision = games[0].clone();
+        collision.sha1 = [0; 20];
+        assert!(lookup_in(&[collision], b"abc", b"").is_none());
+
+        let mut header = InesHeader::default();
+        game.apply(&mut header);
+        assert_eq!(header.mirroring, MirroringMode::FourScreen);
+        assert!(header.has_battery);
+        assert_eq!(header.expansion_device, 2);
+    }
+
+    #[test]
+    pub fn test_invalid_entries() {
+        assert!(parse_database("352441C2 A999 0.0 H 0 0 0 0 NTSC 1 Short SHA-1").is_err());
+        assert!(parse_database("352441C2 A9993E364706816ABA3E25717850C26C9CD0D89D 0 H").is_err());
+    }
+}
@@ -0,0 +1,829 @@
+use anyhow::anyhow;
+use anyhow::Result;
+use bincode::Decode;
+use bincode::Encode;
+use intbits::Bits;
+
+use super::CartridgeError;
+use super::CartridgeResult;
+use super::Mapper;
+use super::MirroringMode;
+
+/// Size of a disk side in the .fds format, which does not contain gaps or CRCs.
+pub const FDS_SIDE_SIZE: usize = 65500;
+const FDS_HEADER_SIZE: usize = 16;
+const FDS_MAGIC: [u8; 4] = [0x46, 0x44, 0x53, 0x1A];
+const DISK_INFO_MAGIC: &[u8] = b"\x01*NINTENDO-HVC*";
+const BIOS_SIZE: usize = 8 * 1024;
+const RAM_SIZE: usize = 32 * 1024;
+
+/// Gaps between the blocks on the disk, in bytes.
+const LEADING_GAP: usize = 28300 / 8;
+const BLOCK_GAP: usize = 976 / 8;
+/// CPU cycles it takes to transfer a byte, and to move the head back to the start of the disk.
+const BYTE_TRANSFER_CYCLES: u32 = 150;
+const HEAD_RETURN_CYCLES: u32 = 50000;
+
+/// Returns true if the file is an FDS disk image, with or without the fwNES header.
+pub fn is_fds_image(raw: &[u8]) -> bool {
+    raw.starts_with(&FDS_MAGIC) || raw.starts_with(DISK_INFO_MAGIC)
+}
+
+/// Parses a .fds image, with or without the fwNES header, into its disk sides.
+pub fn parse_fds_image(raw: &[u8]) -> Result<Vec<Vec<u8>>> {
+    let data = if raw.starts_with(&FDS_MAGIC) {
+        &raw[FDS_HEADER_SIZE.min(raw.len())..]
+    } else {
+        raw
+    };
+    let sides: Vec<Vec<u8>> = data
+        .chunks(FDS_SIDE_SIZE)
+        .filter(|side| side.starts_with(DISK_INFO_MAGIC))
+        .map(|side| {
+            let mut side = side.to_vec();
+            side.resize(FDS_SIDE_SIZE, 0);
+            side
+        })
+        .collect();
+    if sides.is_empty() {
+        return Err(anyhow!("Expected FDS disk image."));
+    }
+    Ok(sides)
+}
+
+/// Length of the block starting with `block`. The length of file data blocks is stored in the
+/// preceding file header block.
+fn block_length(block: &[u8], file_size: usize) -> Option<usize> {
+    match block.first()? {
+        1 => Some(56),
+        2 => Some(2),
+        3 => Some(16),
+        4 => Some(1 + file_size),
+        _ => None,
+    }
+}
+
+/// Returns the file size if `block` is a file header block.
+fn file_size(block: &[u8]) -> Option<usize> {
+    match block {
+        [3, ..] if block.len() >= 16 => Some(block[13] as usize | (block[14] as usize) << 8),
+        _ => None,
+    }
+}
+
+/// Converts a disk side of the .fds format into the layout on disk, with gaps, block start
+/// marks and CRCs, which is streamed by the disk drive.
+fn side_to_raw(side: &[u8]) -> Vec<u8> {
+    let mut raw = vec![0; LEADING_GAP];
+    let mut pos = 0;
+    let mut size = 0;
+    while let Some(block) = block_length(&side[pos..], size).and_then(|n| side.get(pos..pos + n)) {
+        raw.push(0x80);
+        raw.extend(block);
+        // The BIOS does not verify the CRC during emulation, so a placeholder is used.
+        raw.extend([0x4D, 0x62]);
+        raw.extend([0; BLOCK_GAP]);
+        size = file_size(block).unwrap_or(size);
+        pos += block.len();
+    }
+    raw.resize(raw.len().max(LEADING_GAP + FDS_SIDE_SIZE), 0);
+    raw
+}
+
+/// Converts the raw layout of a disk side back into the .fds format.
+fn raw_to_side(raw: &[u8]) -> Vec<u8> {
+    let mut side = Vec::with_capacity(FDS_SIDE_SIZE);
+    let mut pos = 0;
+    let mut size = 0;
+    // Each block starts after the first non-zero byte following the gap.
+    while let Some(mark) = raw[pos..].iter().position(|value| *value != 0) {
+        let start = pos + mark + 1;
+        let block = match block_length(&raw[start..], size).and_then(|n| raw.get(start..start + n))
+        {
+            Some(block) if side.len() + block.len() <= FDS_SIDE_SIZE => block,
+            _ => break,
+        };
+        side.extend(block);
+        size = file_size(block).unwrap_or(size);
+        // Skip the CRC
+        pos = (start + block.len() + 2).min(raw.len());
+    }
+    side.resize(FDS_SIDE_SIZE, 0);
+    side
+}
+
+/// Famicom Disk System
+///
+/// The RAM adapter provides 32k PRG-RAM, 8k CHR-RAM, a timer IRQ, the disk drive interface
+/// and a wavetable audio channel. The BIOS is mapped at $E000-$FFFF.
+#[derive(Encode, Decode, Clone)]
+pub struct FdsMapper {
+    pub bios: Vec<u8>,
+    pub ram: Vec<u8>,
+    pub chr: Vec<u8>,
+    /// Disk sides in their raw layout including gaps.
+    pub disk_sides: Vec<Vec<u8>>,
+    pub inserted_side: Option<usize>,
+
+    pub disk_registers_enabled: bool,
+    pub sound_registers_enabled: bool,
+    pub horizontal_mirroring: bool,
+
+    pub irq_reload: u16,
+    pub irq_counter: u16,
+    pub irq_enabled: bool,
+    pub irq_repeat: bool,
+    pub timer_irq: bool,
+
+    pub motor_on: bool,
+    pub reset_transfer: bool,
+    pub read_mode: bool,
+    pub crc_control: bool,
+    pub disk_ready: bool,
+    pub disk_irq_enabled: bool,
+    pub disk_irq: bool,
+    pub read_data: u8,
+    pub write_data: u8,
+    pub transfer_complete: bool,
+    pub end_of_head: bool,
+    pub scanning_disk: bool,
+    pub gap_ended: bool,
+    pub disk_position: usize,
+    pub delay: u32,
+
+    pub audio: FdsAudio,
+}
+
+impl FdsMapper {
+    /// The persistent data contains the disk sides in .fds format, including all writes
+    /// to the disk. If available, it is used instead of the disk image.
+    pub fn new(disk: &[u8], bios: &[u8], persistent_data: Option<&[u8]>) -> Result<FdsMapper> {
+        if bios.len() != BIOS_SIZE {
+            return Err(anyhow!(
+                "Expected FDS BIOS size to be {BIOS_SIZE}, but it is {}",
+                bios.len()
+            ));
+        }
+        let mut sides = parse_fds_image(disk)?;
+        if let Some(data) = persistent_data {
+            if data.len() == sides.len() * FDS_SIDE_SIZE {
+                sides = parse_fds_image(data)?;
+            }
+        }
+        Ok(FdsMapper {
+            bios: bios.to_vec(),
+            ram: vec![0; RAM_SIZE],
+            chr: vec![0; 8 * 1024],
+            disk_sides: sides.iter().map(|side| side_to_raw(side)).collect(),
+            inserted_side: Some(0),
+            disk_registers_enabled: true,
+            sound_registers_enabled: true,
+            horizontal_mirroring: false,
+            irq_reload: 0,
+            irq_counter: 0,
+            irq_enabled: false,
+            irq_repeat: false,
+            timer_irq: false,
+            motor_on: false,
+            reset_transfer: false,
+            read_mode: true,
+            crc_control: false,
+            disk_ready: false,
+            disk_irq_enabled: false,
+            disk_irq: false,
+            read_data: 0,
+            write_data: 0,
+            transfer_complete: false,
+            end_of_head: true,
+            scanning_disk: false,
+            gap_ended: false,
+            disk_position: 0,
+            delay: 0,
+            audio: FdsAudio::default(),
+        })
+    }
+
+    fn tick_timer(&mut self) {
+        if !self.irq_enabled {
+            return;
+        }
+        if self.irq_counter == 0 {
+            self.timer_irq = true;
+            self.irq_counter = self.irq_reload;
+            if !self.irq_repeat {
+                self.irq_enabled = false;
+            }
+        } else {
+            self.irq_counter -= 1;
+        }
+    }
+
+    /// Streams one byte from or to the disk every `BYTE_TRANSFER_CYCLES` while the motor is
+    /// on. Reaching the end of the disk stops the motor and returns the head to the start.
+    fn tick_disk_drive(&mut self) {
+        let side = match self.inserted_side {
+            Some(side) if self.motor_on => side,
+            _ => {
+                self.end_of_head = true;
+                self.scanning_disk = false;
+                return;
+            }
+        };
+        if self.reset_transfer && !self.scanning_disk {
+            return;
+        }
+        if self.end_of_head {
+            self.delay = HEAD_RETURN_CYCLES;
+            self.end_of_head = false;
+            self.disk_position = 0;
+            self.gap_ended = false;
+            return;
+        }
+        if self.delay > 0 {
+            self.delay -= 1;
+            return;
+        }
+
+        self.scanning_disk = true;
+        let mut raise_irq = self.disk_irq_enabled;
+        if self.read_mode {
+            let value = self.disk_sides[side][self.disk_position];
+            if !self.disk_ready {
+                self.gap_ended = false;
+            } else if value != 0 && !self.gap_ended {
+                // The block start mark ends the gap, but is not transferred.
+                self.gap_ended = true;
+                raise_irq = false;
+            }
+            if self.gap_ended {
+                self.transfer_complete = true;
+                self.read_data = value;
+                self.disk_irq |= raise_irq;
+            }
+        } else {
+            if !self.crc_control {
+                self.transfer_complete = true;
+                self.disk_irq |= raise_irq;
+            }
+            let value = if self.disk_ready { self.write_data } else { 0 };
+            // The write head is located 2 bytes behind the read head.
+            if self.disk_position >= 2 {
+                self.disk_sides[side][self.disk_position - 2] = value;
+            }
+            self.gap_ended = false;
+        }
+
+        self.disk_position += 1;
+        if self.disk_position >= self.disk_sides[side].len() {
+            self.motor_on = false;
+            self.end_of_head = true;
+        } else {
+            self.delay = BYTE_TRANSFER_CYCLES;
+        }
+    }
+
+    fn disk_status(&self) -> u8 {
+        (self.timer_irq as u8) | (self.transfer_complete as u8) << 1 | (self.end_of_head as u8) << 6
+    }
+
+    fn drive_status(&self) -> u8 {
+        let inserted = self.inserted_side.is_some();
+        (!inserted as u8) | ((!inserted || !self.scanning_disk) as u8) << 1 | (!inserted as u8) << 2
+    }
+}
+
+impl Default for FdsMapper {
+    fn default() -> Self {
+        Self::new(DISK_INFO_MAGIC, &[0; BIOS_SIZE], None).unwrap()
+    }
+}
+
+impl Mapper for FdsMapper {
+    fn cpu_bus_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x4030 if self.disk_registers_enabled => Some(self.disk_status()),
+            0x4031 if self.disk_registers_enabled => Some(self.read_data),
+            0x4032 if self.disk_registers_enabled => Some(self.drive_status()),
+            // Battery good
+            0x4033 if self.disk_registers_enabled => Some(0x80),
+            0x4040..=0x4092 if self.sound_registers_enabled => self.audio.peek(addr),
+            0x6000..=0xDFFF => Some(self.ram[addr as usize - 0x6000]),
+            0xE000..=0xFFFF => Some(self.bios[addr as usize - 0xE000]),
+            _ => None,
+        }
+    }
+
+    fn cpu_bus_read(&mut self, addr: u16) -> CartridgeResult<u8> {
+        let value = self.cpu_bus_peek(addr).unwrap_or_default();
+        if self.disk_registers_enabled {
+            match addr {
+                0x4030 => {
+                    self.transfer_complete = false;
+                    self.timer_irq = false;
+                    self.disk_irq = false;
+                }
+                0x4031 => {
+                    self.transfer_complete = false;
+                    self.disk_irq = false;
+                }
+                _ => (),
+            }
+        }
+        Ok(value)
+    }
+
+    fn cpu_bus_write(&mut self, addr: u16, value: u8) -> CartridgeResult<()> {
+        match addr {
+            0x4020 => self.irq_reload = (self.irq_reload & 0xFF00) | value as u16,
+            0x4021 => self.irq_reload = (self.irq_reload & 0x00FF) | (value as u16) << 8,
+            0x4022 => {
+                self.irq_repeat = value.bit(0);
+                self.irq_enabled = value.bit(1) && self.disk_registers_enabled;
+                if self.irq_enabled {
+                    self.irq_counter = self.irq_reload;
+                } else {
+                    self.timer_irq = false;
+                }
+            }
+            0x4023 => {
+                self.disk_registers_enabled = value.bit(0);
+                self.sound_registers_enabled = value.bit(1);
+                if !self.disk_registers_enabled {
+                    self.irq_enabled = false;
+                    self.timer_irq = false;
+                    self.disk_irq = false;
+                }
+            }
+            0x4024..=0x4026 if !self.disk_registers_enabled => (),
+            0x4024 => {
+                self.write_data = value;
+                self.transfer_complete = false;
+                self.disk_irq = false;
+            }
+            0x4025 => {
+                self.motor_on = value.bit(0);
+                self.reset_transfer = value.bit(1);
+                self.read_mode = value.bit(2);
+                self.horizontal_mirroring = value.bit(3);
+                self.crc_control = value.bit(4);
+                self.disk_ready = value.bit(6);
+                self.disk_irq_enabled = value.bit(7);
+                self.disk_irq = false;
+            }
+            // External connector
+            0x4026 => (),
+            0x4040..=0x4092 => {
+                if self.sound_registers_enabled {
+                    self.audio.write(addr, value);
+                }
+            }
+            0x6000..=0xDFFF => self.ram[addr as usize - 0x6000] = value,
+            0x4027..=0x403F | 0xE000..=0xFFFF => (),
+            _ => return Err(CartridgeError::InvalidWrite(addr)),
+        }
+        Ok(())
+    }
+
+    fn ppu_bus_peek(&self, addr: u16) -> Option<u8> {
+        self.chr.get(addr as usize).copied()
+    }
+
+    fn ppu_bus_read(&mut self, addr: u16) -> CartridgeResult<u8> {
+        self.ppu_bus_peek(addr)
+            .ok_or(CartridgeError::InvalidRead(addr))
+    }
+
+    fn ppu_bus_write(&mut self, addr: u16, value: u8) -> CartridgeResult<()> {
+        if let Some(byte) = self.chr.get_mut(addr as usize) {
+            *byte = value;
+        }
+        Ok(())
+    }
+
+    fn irq_active(&self) -> bool {
+        self.timer_irq || self.disk_irq
+    }
+
+    fn cpu_tick(&mut self) {
+        self.tick_timer();
+        self.tick_disk_drive();
+        self.audio.tick();
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    fn disk_side_count(&self) -> usize {
+        self.disk_sides.len()
+    }
+
+    fn inserted_disk_side(&self) -> Option<usize> {
+        self.inserted_side
+    }
+
+    fn insert_disk_side(&mut self, side: Option<usize>) {
+        self.inserted_side = side.filter(|side| *side < self.disk_sides.len());
+        self.scanning_disk = false;
+        self.end_of_head = true;
+    }
+
+    fn get_mirroring_mode(&self) -> MirroringMode {
+        if self.horizontal_mirroring {
+            MirroringMode::Horizontal
+        } else {
+            MirroringMode::Vertical
+        }
+    }
+
+    fn persistent_data(&self) -> Vec<u8> {
+        self.disk_sides
+            .iter()
+            .flat_map(|side| raw_to_side(side))
+            .collect()
+    }
+
+    fn load_persistent_data(&mut self, data: &[u8]) {
+        if data.len() == self.disk_sides.len() * FDS_SIDE_SIZE {
+            if let Ok(sides) = parse_fds_image(data) {
+                self.disk_sides = sides.iter().map(|side| side_to_raw(side)).collect();
+            }
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// FdsAudio
+
+/// Adjustments of the modulation counter for each entry of the modulation table. The value
+/// 4 resets the counter instead.
+const MODULATION_ADJUSTMENTS: [i8; 8] = [0, 1, 2, 4, 0, -4, -2, -1];
+
+/// Master volume of 2/2, 2/3, 2/4 and 2/5.
+const MASTER_VOLUME: [f32; 4] = [1.0, 2.0 / 3.0, 2.0 / 4.0, 2.0 / 5.0];
+
+/// Volume or modulation envelope of the FDS audio channel.
+#[derive(Encode, Decode, Clone, Default)]
+pub struct FdsEnvelope {
+    pub speed: u8,
+    pub gain: u8,
+    pub increase: bool,
+    pub disabled: bool,
+    timer: u32,
+}
+
+impl FdsEnvelope {
+    fn write(&mut self, value: u8) {
+        self.speed = value.bits(0..=5);
+        self.increase = value.bit(6);
+        self.disabled = value.bit(7);
+        if self.disabled {
+            self.gain = self.speed;
+        }
+        self.timer = 0;
+    }
+
+    /// The gain moves towards 0 or 32 every 8 * (speed + 1) * master speed CPU cycles.
+    fn tick(&mut self, master_speed: u8) {
+        if self.disabled {
+            return;
+        }
+        self.timer += 1;
+        if self.timer >= 8 * (self.speed as u32 + 1) * master_speed as u32 {
+            self.timer = 0;
+            if self.increase && self.gain < 32 {
+                self.gain += 1;
+            } else if !self.increase && self.gain > 0 {
+                self.gain -= 1;
+            }
+        }
+    }
+}
+
+/// The FDS audio channel plays a 64 step waveform of 6 bit samples. Its pitch is modulated
+/// by a counter driven by a table of 64 adjustments.
+#[derive(Encode, Decode, Clone)]
+pub struct FdsAudio {
+    pub wave_table: Vec<u8>,
+    pub wave_frequency: u16,
+    pub wave_halted: bool,
+    pub wave_write_enabled: bool,
+    pub envelopes_halted: bool,
+    pub master_volume: u8,
+    pub envelope_speed: u8,
+    pub volume_envelope: FdsEnvelope,
+
+    pub mod_table: Vec<u8>,
+    pub mod_frequency: u16,
+    pub mod_halted: bool,
+    pub mod_counter: i8,
+    pub mod_position: u8,
+    pub mod_envelope: FdsEnvelope,
+
+    wave_accumulator: u32,
+    mod_accumulator: u32,
+    /// Output level from 0 to 63 * 32.
+    level: u16,
+}
+
+impl Default for FdsAudio {
+    fn default() -> Self {
+        FdsAudio {
+            wave_table: vec![0; 64],
+            wave_frequency: 0,
+            wave_halted: true,
+            wave_write_enabled: false,
+            envelopes_halted: false,
+            master_volume: 0,
+            envelope_speed: 0xE8,
+            volume_envelope: FdsEnvelope::default(),
+            mod_table: vec![0; 64],
+            mod_frequency: 0,
+            mod_halted: true,
+            mod_counter: 0,
+            mod_position: 0,
+            mod_envelope: FdsEnvelope::default(),
+            wave_accumulator: 0,
+            mod_accumulator: 0,
+            level: 0,
+        }
+    }
+}
+
+impl FdsAudio {
+    pub fn peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x4040..=0x407F => Some(self.wave_table[addr as usize - 0x4040]),
+            0x4090 => Some(self.volume_envelope.gain),
+            0x4092 => Some(self.mod_envelope.gain),
+            _ => None,
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x4040..=0x407F => {
+                if self.wave_write_enabled {
+                    self.wave_table[addr as usize - 0x4040] = value.bits(0..=5);
+                }
+            }
+            0x4080 => self.volume_envelope.write(value),
+            0x4082 => self.wave_frequency = (self.wave_frequency & 0xF00) | value as u16,
+            0x4083 => {
+                self.wave_frequency =
+                    (self.wave_frequency & 0x0FF) | (value.bits(0..=3) as u16) << 8;
+                self.envelopes_halted = value.bit(6);
+                self.wave_halted = value.bit(7);
+                if self.wave_halted {
+                    self.wave_accumulator = 0;
+                }
+            }
+            0x4084 => self.mod_envelope.write(value),
+            // 7 bit signed counter
+            0x4085 => self.mod_counter = ((value << 1) as i8) >> 1,
+            0x4086 => self.mod_frequency = (self.mod_frequency & 0xF00) | value as u16,
+            0x4087 => {
+                self.mod_frequency = (self.mod_frequency & 0x0FF) | (value.bits(0..=3) as u16) << 8;
+                self.mod_halted = value.bit(7);
+                if self.mod_halted {
+                    self.mod_accumulator = 0;
+                }
+            }
+            0x4088 => {
+                // Each write fills two entries of the table.
+                if self.mod_halted {
+                    let position = self.mod_position as usize;
+                    self.mod_table[position] = value.bits(0..=2);
+                    self.mod_table[position + 1] = value.bits(0..=2);
+                    self.mod_position = (self.mod_position + 2) % 64;
+                }
+            }
+            0x4089 => {
+                self.master_volume = value.bits(0..=1);
+                self.wave_write_enabled = value.bit(7);
+            }
+            0x408A => self.envelope_speed = value,
+            _ => (),
+        }
+    }
+
+    pub fn tick(&mut self) {
+        if !self.envelopes_halted && !self.wave_halted && self.envelope_speed > 0 {
+            self.volume_envelope.tick(self.envelope_speed);
+            self.mod_envelope.tick(self.envelope_speed);
+        }
+
+        if !self.mod_halted {
+            self.mod_accumulator += self.mod_frequency as u32;
+            if self.mod_accumulator >= 0x10000 {
+                self.mod_accumulator -= 0x10000;
+                self.step_modulator();
+            }
+        }
+
+        if !self.wave_halted {
+            self.wave_accumulator =
+                (self.wave_accumulator + self.modulated_frequency()) % (64 << 16);
+        }
+        // The output is held while the wave table is written.
+        if !self.wave_write_enabled {
+            let sample = self.wave_table[(self.wave_accumulator >> 16) as usize];
+            self.level = sample as u16 * self.volume_envelope.gain.min(32) as u16;
+        }
+    }
+
+    fn step_modulator(&mut self) {
+        let entry = self.mod_table[self.mod_position as usize];
+        self.mod_position = (self.mod_position + 1) % 64;
+        self.mod_counter = if entry == 4 {
+            0
+        } else {
+            // Wrap around within the 7 bit range.
+            let counter = self.mod_counter + MODULATION_ADJUSTMENTS[entry as usize];
+            ((counter << 1) as i8) >> 1
+        };
+    }
+
+    /// Applies the modulation to the wave frequency, following
+    /// https://www.nesdev.org/wiki/FDS_audio#Frequency_calculation
+    fn modulated_frequency(&self) -> u32 {
+        let pitch = self.wave_frequency as i32;
+        let counter = self.mod_counter as i32;
+        let mut temp = counter * self.mod_envelope.gain as i32;
+        let remainder = temp & 0x0F;
+        temp >>= 4;
+        if remainder > 0 && temp & 0x80 == 0 {
+            temp += if counter < 0 { -1 } else { 2 };
+        }
+        if temp >= 192 {
+            temp -= 256;
+        } else if temp < -64 {
+            temp += 256;
+        }
+        temp *= pitch;
+        let remainder = temp & 0x3F;
+        temp >>= 6;
+        if remainder >= 32 {
+            temp += 1;
+        }
+        (pitch + temp).max(0) as u32
+    }
+
+    /// At full volume the channel is about 2.4 times as loud as an APU pulse channel.
+    pub fn output(&self) -> f32 {
+        let level = self.level as f32 / (63.0 * 32.0);
+        0.1128 * 2.4 * level * MASTER_VOLUME[self.master_volume as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates a disk side with the disk info block, a file amount block and one file with
+    /// the given contents.
+    fn test_side(contents: &[u8]) -> Vec<u8> {
+        let mut side = DISK_INFO_MAGIC.to_vec();
+        side.resize(56, 0);
+        side.extend([2, 1]);
+        let mut file_header = vec![3, 0, 0, b'T', b'E', b'S', b'T', 0, 0, 0, 0, 0, 0];
+        file_header.extend([contents.len() as u8, 0, 0]);
+        side.extend(file_header);
+        side.push(4);
+        side.extend(contents);
+        side.resize(FDS_SIDE_SIZE, 0);
+        side
+    }
+
+    fn test_mapper() -> FdsMapper {
+        let mut image = FDS_MAGIC.to_vec();
+        image.push(2);
+        image.resize(FDS_HEADER_SIZE, 0);
+        image.extend(test_side(&[0x11, 0x22, 0x33]));
+        image.extend(test_side(&[0x44]));
+        FdsMapper::new(&image, &[0; BIOS_SIZE], None).unwrap()
+    }
+
+    /// Runs the drive until the next byte is transferred and returns it.
+    fn read_next_byte(mapper: &mut FdsMapper) -> u8 {
+        for _ in 0..HEAD_RETURN_CYCLES * 20 {
+            mapper.cpu_tick();
+            if mapper.cpu_bus_peek(0x4030).unwrap().bit(1) {
+                return mapper.cpu_bus_read(0x4031).unwrap();
+            }
+        }
+        panic!("No byte transferred");
+    }
+
+    #[test]
+    pub fn test_parse_image() {
+        let mapper = test_mapper();
+        assert_eq!(mapper.disk_side_count(), 2);
+        // Leading gap, followed by the start mark of the disk info block.
+        let raw = &mapper.disk_sides[0];
+        assert_eq!(raw[LEADING_GAP - 1], 0);
+        assert_eq!(&raw[LEADING_GAP..LEADING_GAP + 2], &[0x80, 0x01]);
+        assert_eq!(raw_to_side(raw), test_side(&[0x11, 0x22, 0x33]));
+
+        assert!(parse_fds_image(&[0; 100]).is_err());
+        assert!(FdsMapper::new(&test_side(&[]), &[0; 100], None).is_err());
+    }
+
+    #[test]
+    pub fn test_read_disk() {
+        let mut mapper = test_mapper();
+        // Start the motor in read mode and wait for the first block.
+        mapper.cpu_bus_write(0x4025, 0b0100_0101).unwrap();
+        assert_eq!(read_next_byte(&mut mapper), 0x80);
+        assert_eq!(read_next_byte(&mut mapper), 0x01);
+        assert_eq!(read_next_byte(&mut mapper), b'*');
+        assert_eq!(mapper.cpu_bus_peek(0x4032), Some(0x00));
+
+        // Ejecting the disk
+        mapper.insert_disk_side(None);
+        mapper.cpu_tick();
+        assert_eq!(mapper.cpu_bus_peek(0x4032), Some(0x07));
+        assert_eq!(mapper.inserted_disk_side(), None);
+    }
+
+    #[test]
+    pub fn test_write_disk_and_persistence() {
+        let mut mapper = test_mapper();
+        mapper.insert_disk_side(Some(1));
+        // Overwrite the file contents in the raw layout, like the drive would, and make sure
+        // it is persisted.
+        let data_block = mapper.disk_sides[1]
+            .windows(2)
+            .position(|window| window == [0x80, 0x04])
+            .unwrap();
+        mapper.disk_sides[1][data_block + 2] = 0x55;
+        let data = mapper.persistent_data();
+        assert_eq!(data.len(), 2 * FDS_SIDE_SIZE);
+        assert_eq!(&data[FDS_SIDE_SIZE..], &test_side(&[0x55]));
+
+        let image = [test_side(&[0x11, 0x22, 0x33]), test_side(&[0x44])].concat();
+        let restored = FdsMapper::new(&image, &[0; BIOS_SIZE], Some(&data)).unwrap();
+        assert_eq!(restored.persistent_data(), data);
+
+        // Writes through the drive land 2 bytes behind the read head.
+        mapper.cpu_bus_write(0x4025, 0b0100_0001).unwrap();
+        mapper.cpu_bus_write(0x4024, 0x99).unwrap();
+        while mapper.disk_position < 4 {
+            mapper.cpu_tick();
+        }
+        assert_eq!(&mapper.disk_sides[1][0..2], &[0x99, 0x99]);
+    }
+
+    #[test]
+    pub fn test_timer_irq() {
+        let mut mapper = test_mapper();
+        mapper.cpu_bus_write(0x4020, 0x02).unwrap();
+        mapper.cpu_bus_write(0x4021, 0x00).unwrap();
+        mapper.cpu_bus_write(0x4022, 0x03).unwrap();
+        mapper.cpu_tick();
+        mapper.cpu_tick();
+        assert!(!mapper.irq_active());
+        mapper.cpu_tick();
+        assert!(mapper.irq_active());
+        assert_eq!(mapper.cpu_bus_read(0x4030).unwrap() & 0x01, 0x01);
+        assert!(!mapper.irq_active());
+
+        // Repeats after reloading the counter.
+        for _ in 0..3 {
+            mapper.cpu_tick();
+        }
+        assert!(mapper.irq_active());
+    }
+
+    #[test]
+    pub fn test_audio() {
+        let mut mapper = test_mapper();
+        // Square wave at full volume.
+        mapper.cpu_bus_write(0x4089, 0x80).unwrap();
+        for i in 0..64 {
+            mapper
+                .cpu_bus_write(0x4040 + i, if i < 32 { 63 } else { 0 })
+                .unwrap();
+        }
+        mapper.cpu_bus_write(0x4089, 0x00).unwrap();
+        mapper.cpu_bus_write(0x4080, 0x80 | 32).unwrap();
+        mapper.cpu_bus_write(0x4082, 0x00).unwrap();
+        mapper.cpu_bus_write(0x4083, 0x04).unwrap();
+        assert_eq!(mapper.cpu_bus_peek(0x4090), Some(32));
+
+        mapper.cpu_tick();
+        assert!((mapper.audio_output() - 0.1128 * 2.4).abs() < 1e-6);
+        // A step takes 0x10000 / 0x400 = 64 cycles.
+        for _ in 0..32 * 64 {
+            mapper.cpu_tick();
+        }
+        assert_eq!(mapper.audio_output(), 0.0);
+
+        // Modulation changes the pitch
+        mapper.cpu_bus_write(0x4084, 0x80 | 32).unwrap();
+        mapper.cpu_bus_write(0x4085, 0x10).unwrap();
+        assert!(mapper.audio.modulated_frequency() > 0x400);
+        mapper.cpu_bus_write(0x4085, 0x70).unwrap();
+        assert!(mapper.audio.modulated_frequency() < 0x400);
+    }
+}
@@ -0,0 +1,497 @@
+use bincode::Decode;
+use bincode::Encode;
+use intbits::Bits;
+
+use super::persistent_ram;
+use super::CartridgeError;
+use super::CartridgeResult;
+use super::Mapper;
+use super::MirroringMode;
+
+const PRG_BANK_SIZE: usize = 8 * 1024;
+const CHR_BANK_SIZE: usize = 1024;
+
+/// Sunsoft FME-7 and 5B (mapper 69)
+///
+/// Four switchable 8k PRG banks, one of which can be mapped to RAM at $6000, eight 1k CHR
+/// banks and a 16 bit IRQ counter decremented every CPU cycle. The 5B variant adds a
+/// YM2149 compatible sound chip.
+#[derive(Encode, Decode, Clone)]
+pub struct Fme7Mapper {
+    pub prg: Vec<u8>,
+    pub chr: Vec<u8>,
+    pub ram: Vec<u8>,
+    pub chr_is_ram: bool,
+
+    pub command: u8,
+    pub chr_banks: [u8; 8],
+    /// Bank at $6000 followed by the banks at $8000, $A000 and $C000.
+    pub prg_banks: [u8; 4],
+    pub mirroring_register: u8,
+
+    pub irq_enabled: bool,
+    pub irq_counter_enabled: bool,
+    pub irq_counter: u16,
+    pub irq_pending: bool,
+
+    pub audio: Sunsoft5b,
+}
+
+impl Fme7Mapper {
+    const RAM_SIZE: usize = 8 * 1024;
+
+    pub fn new(prg: &[u8], chr: &[u8], persistent_data: Option<&[u8]>) -> Fme7Mapper {
+        Fme7Mapper {
+            prg: prg.to_vec(),
+            chr: if chr.is_empty() {
+                vec![0; 8 * 1024]
+            } else {
+                chr.to_vec()
+            },
+            ram: persistent_ram(persistent_data, Fme7Mapper::RAM_SIZE),
+            chr_is_ram: chr.is_empty(),
+            command: 0,
+            chr_banks: [0; 8],
+            prg_banks: [0; 4],
+            mirroring_register: 0,
+            irq_enabled: false,
+            irq_counter_enabled: false,
+            irq_counter: 0,
+            irq_pending: false,
+            audio: Sunsoft5b::default(),
+        }
+    }
+
+    fn get_prg_index(&self, addr: u16) -> usize {
+        let bank_count = (self.prg.len() / PRG_BANK_SIZE).max(1);
+        let bank = match addr {
+            0x6000..=0xDFFF => self.prg_banks[(addr as usize - 0x6000) / PRG_BANK_SIZE].bits(0..=5),
+            _ => (bank_count - 1) as u8,
+        };
+        (bank as usize % bank_count) * PRG_BANK_SIZE + addr as usize % PRG_BANK_SIZE
+    }
+
+    fn get_chr_index(&self, addr: u16) -> usize {
+        let bank_count = (self.chr.len() / CHR_BANK_SIZE).max(1);
+        let bank = self.chr_banks[addr as usize / CHR_BANK_SIZE] as usize;
+        (bank % bank_count) * CHR_BANK_SIZE + addr as usize % CHR_BANK_SIZE
+    }
+
+    /// Returns the index into PRG-RAM if RAM is mapped and enabled at $6000.
+    fn get_ram_index(&self, addr: u16) -> Option<usize> {
+        let bank = self.prg_banks[0];
+        if bank.bit(6) && bank.bit(7) {
+            let bank_count = Fme7Mapper::RAM_SIZE / PRG_BANK_SIZE;
+            Some(
+                (bank.bits(0..=5) as usize % bank_count) * PRG_BANK_SIZE + (addr as usize - 0x6000),
+            )
+        } else {
+            None
+        }
+    }
+
+    fn write_parameter(&mut self, value: u8) {
+        match self.command {
+            0x0..=0x7 => self.chr_banks[self.command as usize] = value,
+            0x8..=0xB => self.prg_banks[self.command as usize - 0x8] = value,
+            0xC => self.mirroring_register = value,
+            0xD => {
+                self.irq_enabled = value.bit(0);
+                self.irq_counter_enabled = value.bit(7);
+                self.irq_pending = false;
+            }
+            0xE => self.irq_counter = (self.irq_counter & 0xFF00) | value as u16,
+            _ => self.irq_counter = (self.irq_counter & 0x00FF) | (value as u16) << 8,
+        }
+    }
+}
+
+impl Default for Fme7Mapper {
+    fn default() -> Self {
+        Self::new(&[], &[], None)
+    }
+}
+
+impl Mapper for Fme7Mapper {
+    fn cpu_bus_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7FFF => match self.get_ram_index(addr) {
+                Some(idx) => Some(self.ram[idx]),
+                None if self.prg_banks[0].bit(6) => None,
+                None => self.prg.get(self.get_prg_index(addr)).copied(),
+            },
+            0x8000..=0xFFFF => self.prg.get(self.get_prg_index(addr)).copied(),
+            _ => None,
+        }
+    }
+
+    fn cpu_bus_read(&mut self, addr: u16) -> CartridgeResult<u8> {
+        Ok(self.cpu_bus_peek(addr).unwrap_or_default())
+    }
+
+    fn cpu_bus_write(&mut self, addr: u16, value: u8) -> CartridgeResult<()> {
+        match addr {
+            0x6000..=0x7FFF => {
+                if let Some(idx) = self.get_ram_index(addr) {
+                    self.ram[idx] = value;
+                }
+            }
+            0x8000..=0x9FFF => self.command = value.bits(0..=3),
+            0xA000..=0xBFFF => self.write_parameter(value),
+            0xC000..=0xDFFF => self.audio.select_register(value),
+            0xE000..=0xFFFF => self.audio.write_register(value),
+            _ => return Err(CartridgeError::InvalidWrite(addr)),
+        }
+        Ok(())
+    }
+
+    fn ppu_bus_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x0000..=0x1FFF => Some(self.chr[self.get_chr_index(addr)]),
+            _ => None,
+        }
+    }
+
+    fn ppu_bus_read(&mut self, addr: u16) -> CartridgeResult<u8> {
+        self.ppu_bus_peek(addr)
+            .ok_or(CartridgeError::InvalidRead(addr))
+    }
+
+    fn ppu_bus_write(&mut self, addr: u16, value: u8) -> CartridgeResult<()> {
+        if let 0x0000..=0x1FFF = addr {
+            if self.chr_is_ram {
+                let idx = self.get_chr_index(addr);
+                self.chr[idx] = value;
+            }
+        }
+        Ok(())
+    }
+
+    fn irq_active(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn cpu_tick(&mut self) {
+        if self.irq_counter_enabled {
+            self.irq_counter = self.irq_counter.wrapping_sub(1);
+            if self.irq_counter == 0xFFFF && self.irq_enabled {
+                self.irq_pending = true;
+            }
+        }
+        self.audio.tick();
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    fn get_mirroring_mode(&self) -> MirroringMode {
+        match self.mirroring_register.bits(0..=1) {
+            0 => MirroringMode::Vertical,
+            1 => MirroringMode::Horizontal,
+            2 => MirroringMode::SingleLower,
+            _ => MirroringMode::SingleUpper,
+        }
+    }
+
+    fn persistent_data(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn load_persistent_data(&mut self, data: &[u8]) {
+        if data.len() == self.ram.len() {
+            self.ram.copy_from_slice(data);
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Sunsoft5b
+
+/// Amplitude of the 5 bit volume levels, which increase by 1.5dB per step.
+const VOLUME_TABLE: [f32; 32] = [
+    0.0000, 0.0056, 0.0067, 0.0079, 0.0094, 0.0112, 0.0133, 0.0158, 0.0188, 0.0224, 0.0266, 0.0316,
+    0.0376, 0.0447, 0.0531, 0.0631, 0.0750, 0.0891, 0.1059, 0.1259, 0.1496, 0.1778, 0.2113, 0.2512,
+    0.2985, 0.3548, 0.4217, 0.5012, 0.5957, 0.7079, 0.8414, 1.0000,
+];
+
+/// Audio of the Sunsoft 5B: Three square wave channels which can be mixed with noise and
+/// use a shared envelope for their volume. All units are clocked every 16 CPU cycles.
+#[derive(Encode, Decode, Clone, Default)]
+pub struct Sunsoft5b {
+    pub selected_register: u8,
+    pub registers: [u8; 14],
+    prescaler: u8,
+    tone_counters: [u16; 3],
+    tone_outputs: [bool; 3],
+    noise_counter: u8,
+    noise_shift: u32,
+    envelope_counter: u16,
+    envelope_step: u8,
+    envelope_attack: bool,
+    envelope_holding: bool,
+}
+
+impl Sunsoft5b {
+    fn select_register(&mut self, value: u8) {
+        self.selected_register = value.bits(0..=3);
+    }
+
+    fn write_register(&mut self, value: u8) {
+        let register = self.selected_register as usize;
+        if register < self.registers.len() {
+            self.registers[register] = value;
+        }
+        // Writing the envelope shape restarts the envelope.
+        if register == 13 {
+            self.envelope_counter = 0;
+            self.envelope_step = 0;
+            self.envelope_attack = value.bit(2);
+            self.envelope_holding = false;
+        }
+    }
+
+    fn tone_period(&self, channel: usize) -> u16 {
+        let period = self.registers[channel * 2] as u16
+            | (self.registers[channel * 2 + 1].bits(0..=3) as u16) << 8;
+        period.max(1)
+    }
+
+    fn tick(&mut self) {
+        self.prescaler = (self.prescaler + 1) % 16;
+        if self.prescaler != 0 {
+            return;
+        }
+        for channel in 0..3 {
+            self.tone_counters[channel] += 1;
+            if self.tone_counters[channel] >= self.tone_period(channel) {
+                self.tone_counters[channel] = 0;
+                self.tone_outputs[channel] = !self.tone_outputs[channel];
+            }
+        }
+
+        self.noise_counter += 1;
+        if self.noise_counter >= self.registers[6].bits(0..=4).max(1) {
+            self.noise_counter = 0;
+            if self.noise_shift == 0 {
+                self.noise_shift = 1;
+            }
+            // 17 bit LFSR with taps at bits 0 and 3.
+            let feedback = (self.noise_shift ^ (self.noise_shift >> 3)) & 1;
+            self.noise_shift = (self.noise_shift >> 1) | feedback << 16;
+        }
+
+        self.tick_envelope();
+    }
+
+    /// The envelope steps through 32 levels per period. The shape register selects whether it
+    /// counts up or down (attack), continues after the first cycle, alternates direction or
+    /// holds the last level.
+    fn tick_envelope(&mut self) {
+        if self.envelope_holding {
+            return;
+        }
+        let period = (self.registers[11] as u16 | (self.registers[12] as u16) << 8).max(1);
+        self.envelope_counter += 1;
+        if self.envelope_counter < period {
+            return;
+        }
+        self.envelope_counter = 0;
+        if self.envelope_step < 31 {
+            self.envelope_step += 1;
+            return;
+        }
+        let shape = self.registers[13];
+        let (continue_, attack, alternate, hold) =
+            (shape.bit(3), shape.bit(2), shape.bit(1), shape.bit(0));
+        if !continue_ {
+            self.envelope_holding = true;
+            self.envelope_attack = false;
+        } else if hold {
+            self.envelope_holding = true;
+            if alternate {
+                self.envelope_attack = !attack;
+            }
+        } else {
+            if alternate {
+                self.envelope_attack = !self.envelope_attack;
+            }
+            self.envelope_step = 0;
+        }
+    }
+
+    fn envelope_level(&self) -> u8 {
+        if self.envelope_attack {
+            self.envelope_step
+        } else {
+            31 - self.envelope_step
+        }
+    }
+
+    fn channel_output(&self, channel: usize) -> f32 {
+        let mixer = self.registers[7];
+        let tone = self.tone_outputs[channel] || mixer.bit(channel);
+        let noise = self.noise_shift.bit(0) || mixer.bit(channel + 3);
+        if !tone || !noise {
+            return 0.0;
+        }
+        let volume = self.registers[8 + channel];
+        let level = if volume.bit(4) {
+            self.envelope_level()
+        } else if volume.bits(0..=3) > 0 {
+            volume.bits(0..=3) * 2 + 1
+        } else {
+            0
+        };
+        VOLUME_TABLE[level as usize]
+    }
+
+    /// Each channel at full volume is about as loud as an APU pulse channel.
+    fn output(&self) -> f32 {
+        0.1128
+            * (0..3)
+                .map(|channel| self.channel_output(channel))
+                .sum::<f32>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Fme7Mapper;
+    use super::CHR_BANK_SIZE;
+    use super::PRG_BANK_SIZE;
+    use crate::cartridge::Mapper;
+    use crate::cartridge::MirroringMode;
+
+    fn test_mapper() -> Fme7Mapper {
+        // Create a mapper with the bank number in the first byte of each bank to allow us to
+        // identify them after mapping.
+        let mut prg = vec![0x00_u8; 16 * PRG_BANK_SIZE];
+        for bank in 0..16 {
+            prg[bank * PRG_BANK_SIZE] = bank as u8;
+        }
+        let mut chr = vec![0x00_u8; 16 * CHR_BANK_SIZE];
+        for bank in 0..16 {
+            chr[bank * CHR_BANK_SIZE] = bank as u8;
+        }
+        Fme7Mapper::new(&prg, &chr, None)
+    }
+
+    fn write_command(mapper: &mut Fme7Mapper, command: u8, value: u8) {
+        mapper.cpu_bus_write(0x8000, command).unwrap();
+        mapper.cpu_bus_write(0xA000, value).unwrap();
+    }
+
+    #[test]
+    pub fn test_persistent_data_size() {
+        // Saves of the wrong size are ignored instead of changing the size of the RAM.
+        for save in [&[][..], &[0x42; 16]] {
+            let mut mapper = Fme7Mapper::new(&[0; 16 * PRG_BANK_SIZE], &[], Some(save));
+            write_command(&mut mapper, 0x08, 0xC0);
+            mapper.cpu_bus_write(0x7FFF, 0x12).unwrap();
+            assert_eq!(mapper.cpu_bus_peek(0x6000), Some(0x00));
+            assert_eq!(mapper.cpu_bus_peek(0x7FFF), Some(0x12));
+            assert_eq!(mapper.persistent_data().len(), Fme7Mapper::RAM_SIZE);
+        }
+    }
+
+    #[test]
+    pub fn test_bank_mapping() {
+        let mut mapper = test_mapper();
+        for register in 0..8 {
+            write_command(&mut mapper, register, 8 + register);
+        }
+        for register in 0..3 {
+            write_command(&mut mapper, 9 + register, 3 + register);
+        }
+        assert_eq!(mapper.ppu_bus_peek(0x0000), Some(8));
+        assert_eq!(mapper.ppu_bus_peek(0x1C00), Some(15));
+        assert_eq!(mapper.cpu_bus_peek(0x8000), Some(3));
+        assert_eq!(mapper.cpu_bus_peek(0xA000), Some(4));
+        assert_eq!(mapper.cpu_bus_peek(0xC000), Some(5));
+        assert_eq!(mapper.cpu_bus_peek(0xE000), Some(15));
+
+        write_command(&mut mapper, 0xC, 1);
+        assert_eq!(mapper.get_mirroring_mode(), MirroringMode::Horizontal);
+    }
+
+    #[test]
+    pub fn test_ram_or_rom_at_6000() {
+        let mut mapper = test_mapper();
+        write_command(&mut mapper, 8, 7);
+        assert_eq!(mapper.cpu_bus_peek(0x6000), Some(7));
+
+        // RAM selected, but disabled
+        write_command(&mut mapper, 8, 0x40);
+        assert_eq!(mapper.cpu_bus_peek(0x6000), None);
+
+        write_command(&mut mapper, 8, 0xC0);
+        mapper.cpu_bus_write(0x6000, 0x42).unwrap();
+        assert_eq!(mapper.cpu_bus_peek(0x6000), Some(0x42));
+    }
+
+    #[test]
+    pub fn test_irq() {
+        let mut mapper = test_mapper();
+        write_command(&mut mapper, 0xE, 0x01);
+        write_command(&mut mapper, 0xF, 0x00);
+        write_command(&mut mapper, 0xD, 0x81);
+        mapper.cpu_tick();
+        assert!(!mapper.irq_active());
+        mapper.cpu_tick();
+        assert!(mapper.irq_active());
+
+        // Writing the IRQ control acknowledges the IRQ.
+        write_command(&mut mapper, 0xD, 0x81);
+        assert!(!mapper.irq_active());
+    }
+
+    #[test]
+    pub fn test_audio() {
+        let mut mapper = test_mapper();
+        let mut write_audio = |register: u8, value: u8| {
+            mapper.cpu_bus_write(0xC000, register).unwrap();
+            mapper.cpu_bus_write(0xE000, value).unwrap();
+        };
+        // Channel A tone only at full volume with a period of 1
+        write_audio(0, 1);
+        write_audio(7, 0b111_110);
+        write_audio(8, 0x0F);
+
+        let outputs: Vec<f32> = (0..64)
+            .map(|_| {
+                mapper.cpu_tick();
+                mapper.audio_output()
+            })
+            .collect();
+        assert!(outputs.iter().any(|&sample| (sample - 0.1128).abs() < 1e-6));
+        assert!(outputs.contains(&0.0));
+    }
+
+    #[test]
+    pub fn test_envelope() {
+        let mut mapper = test_mapper();
+        let audio = &mut mapper.audio;
+        audio.select_register(11);
+        audio.write_register(1);
+        // Attack, then hold at the highest level.
+        audio.select_register(13);
+        audio.write_register(0b1101);
+        assert_eq!(audio.envelope_level(), 0);
+        for _ in 0..16 {
+            audio.tick();
+        }
+        assert_eq!(audio.envelope_level(), 1);
+        for _ in 0..16 * 40 {
+            audio.tick();
+        }
+        assert_eq!(audio.envelope_level(), 31);
+
+        // Decay once, then stay silent.
+        audio.write_register(0b0000);
+        for _ in 0..16 * 40 {
+            audio.tick();
+        }
+        assert_eq!(audio.envelope_level(), 0);
+    }
+}
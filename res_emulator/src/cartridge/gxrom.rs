@@ -0,0 +1,140 @@
+use bincode::Decode;
+use bincode::Encode;
+use intbits::Bits;
+
+use super::CartridgeError;
+use super::CartridgeResult;
+use super::Mapper;
+use super::MirroringMode;
+
+const PRG_BANK_SIZE: usize = 32 * 1024;
+const CHR_BANK_SIZE: usize = 8 * 1024;
+
+/// GxROM (mapper 66): Switchable 32k PRG-ROM and 8k CHR-ROM banks selected by a single
+/// register.
+#[derive(Encode, Decode, Clone)]
+pub struct GxRomMapper {
+    pub prg: Vec<u8>,
+    pub chr: Vec<u8>,
+    pub bank_select: u8,
+    pub mirroring_mode: MirroringMode,
+}
+
+impl GxRomMapper {
+    pub fn new(prg: &[u8], chr: &[u8], mirroring_mode: MirroringMode) -> GxRomMapper {
+        GxRomMapper {
+            prg: prg.to_vec(),
+            chr: if chr.is_empty() {
+                vec![0; CHR_BANK_SIZE]
+            } else {
+                chr.to_vec()
+            },
+            bank_select: 0,
+            mirroring_mode,
+        }
+    }
+
+    fn get_prg_index(&self, addr: u16) -> usize {
+        let bank_count = (self.prg.len() / PRG_BANK_SIZE).max(1);
+        let bank = self.bank_select.bits(4..=5) as usize % bank_count;
+        bank * PRG_BANK_SIZE + (addr as usize - 0x8000)
+    }
+
+    fn get_chr_index(&self, addr: u16) -> usize {
+        let bank_count = (self.chr.len() / CHR_BANK_SIZE).max(1);
+        let bank = self.bank_select.bits(0..=1) as usize % bank_count;
+        bank * CHR_BANK_SIZE + addr as usize
+    }
+}
+
+impl Default for GxRomMapper {
+    fn default() -> Self {
+        Self::new(&[], &[], MirroringMode::Horizontal)
+    }
+}
+
+impl Mapper for GxRomMapper {
+    fn cpu_bus_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x8000..=0xFFFF => self.prg.get(self.get_prg_index(addr)).copied(),
+            _ => None,
+        }
+    }
+
+    fn cpu_bus_read(&mut self, addr: u16) -> CartridgeResult<u8> {
+        Ok(self.cpu_bus_peek(addr).unwrap_or_default())
+    }
+
+    fn cpu_bus_write(&mut self, addr: u16, value: u8) -> CartridgeResult<()> {
+        match addr {
+            0x8000..=0xFFFF => {
+                // The board has bus conflicts, the ROM drives the data bus at the same time
+                // as the CPU.
+                self.bank_select = value & self.cpu_bus_peek(addr).unwrap_or(0xFF);
+                Ok(())
+            }
+            _ => Err(CartridgeError::InvalidWrite(addr)),
+        }
+    }
+
+    fn ppu_bus_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x0000..=0x1FFF => Some(self.chr[self.get_chr_index(addr)]),
+            _ => None,
+        }
+    }
+
+    fn ppu_bus_read(&mut self, addr: u16) -> CartridgeResult<u8> {
+        self.ppu_bus_peek(addr)
+            .ok_or(CartridgeError::InvalidRead(addr))
+    }
+
+    fn ppu_bus_write(&mut self, _addr: u16, _value: u8) -> CartridgeResult<()> {
+        // Some games will try to write to character ROM and expect it to NOOP.
+        Ok(())
+    }
+
+    fn get_mirroring_mode(&self) -> MirroringMode {
+        self.mirroring_mode
+    }
+
+    fn persistent_data(&self) -> Vec<u8> {
+        Vec::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GxRomMapper;
+    use super::CHR_BANK_SIZE;
+    use super::PRG_BANK_SIZE;
+    use crate::cartridge::Mapper;
+    use crate::cartridge::MirroringMode;
+
+    fn test_mapper() -> GxRomMapper {
+        // Create a mapper with the bank number in the first byte of each bank to allow us to
+        // identify them after mapping. All other bytes are 0xFF to avoid bus conflicts.
+        let mut prg = vec![0xFF_u8; 4 * PRG_BANK_SIZE];
+        for bank in 0..4 {
+            prg[bank * PRG_BANK_SIZE] = bank as u8;
+        }
+        let mut chr = vec![0x00_u8; 4 * CHR_BANK_SIZE];
+        for bank in 0..4 {
+            chr[bank * CHR_BANK_SIZE] = bank as u8;
+        }
+        GxRomMapper::new(&prg, &chr, MirroringMode::Vertical)
+    }
+
+    #[test]
+    pub fn test_bank_mapping() {
+        let mut mapper = test_mapper();
+        mapper.cpu_bus_write(0x8001, 0x21).unwrap();
+        assert_eq!(mapper.cpu_bus_peek(0x8000), Some(2));
+        assert_eq!(mapper.ppu_bus_peek(0x0000), Some(1));
+
+        // Bus conflict with the 0x02 stored at the start of bank 2.
+        mapper.cpu_bus_write(0x8000, 0x33).unwrap();
+        assert_eq!(mapper.cpu_bus_peek(0x8000), Some(0));
+        assert_eq!(mapper.ppu_bus_peek(0x0000), Some(2));
+    }
+}
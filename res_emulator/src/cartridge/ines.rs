@@ -0,0 +1,371 @@
+use std::fmt::Display;
+use std::fmt::Formatter;
+
+use anyhow::anyhow;
+use anyhow::Result;
+use bincode::Decode;
+use bincode::Encode;
+use packed_struct::prelude::*;
+
+use super::MirroringMode;
+
+/// Byte layout of the iNES and NES 2.0 header. See https://www.nesdev.org/wiki/NES_2.0
+#[derive(PackedStruct, Default, Debug, Copy, Clone)]
+#[packed_struct(bit_numbering = "msb0", size_bytes = "16")]
+struct RawInesHeader {
+    magic: [u8; 4],
+    prg_size_lsb: u8,
+    chr_size_lsb: u8,
+    // Flags 6
+    #[packed_field(size_bits = "4")]
+    mapper_low: u8,
+    four_screen: bool,
+    trainer: bool,
+    has_battery_ram: bool,
+    mirroring: bool,
+    // Flags 7
+    #[packed_field(size_bits = "4")]
+    mapper_mid: u8,
+    #[packed_field(size_bits = "2")]
+    format: u8,
+    #[packed_field(size_bits = "2")]
+    console_type: u8,
+    // Flags 8
+    #[packed_field(size_bits = "4")]
+    submapper: u8,
+    #[packed_field(size_bits = "4")]
+    mapper_high: u8,
+    // Flags 9
+    #[packed_field(size_bits = "4")]
+    chr_size_msb: u8,
+    #[packed_field(size_bits = "4")]
+    prg_size_msb: u8,
+    // Flags 10
+    #[packed_field(size_bits = "4")]
+    prg_nvram_shift: u8,
+    #[packed_field(size_bits = "4")]
+    prg_ram_shift: u8,
+    // Flags 11
+    #[packed_field(size_bits = "4")]
+    chr_nvram_shift: u8,
+    #[packed_field(size_bits = "4")]
+    chr_ram_shift: u8,
+    // Flags 12
+    #[packed_field(size_bits = "6")]
+    _unused12: u8,
+    #[packed_field(size_bits = "2")]
+    timing: u8,
+    // Flags 13
+    #[packed_field(size_bits = "4")]
+    _vs_hardware_type: u8,
+    #[packed_field(size_bits = "4")]
+    extended_console_type: u8,
+    // Flags 14
+    #[packed_field(size_bits = "6")]
+    _unused14: u8,
+    #[packed_field(size_bits = "2")]
+    misc_roms: u8,
+    // Flags 15
+    #[packed_field(size_bits = "2")]
+    _unused15: u8,
+    #[packed_field(size_bits = "6")]
+    expansion_device: u8,
+}
+
+#[derive(Default, Debug, Encode, Decode, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderFormat {
+    /// Pre iNES 1.0 headers, which often contain garbage in bytes 7-15 (e.g. "DiskDude!").
+    ArchaicInes,
+    #[default]
+    Ines,
+    Nes20,
+    /// Header describing the board of a UNIF file.
+    Unif,
+}
+
+#[derive(Default, Debug, Encode, Decode, Clone, Copy, PartialEq, Eq)]
+pub enum ConsoleType {
+    #[default]
+    Nes,
+    VsSystem,
+    Playchoice10,
+    Extended(u8),
+}
+
+#[derive(Default, Debug, Encode, Decode, Clone, Copy, PartialEq, Eq)]
+pub enum TimingMode {
+    #[default]
+    Ntsc,
+    Pal,
+    MultiRegion,
+    Dendy,
+}
+
+/// Parsed iNES or NES 2.0 header. Sizes are in bytes. Fields that iNES 1.0 cannot describe are
+/// filled in with the commonly assumed defaults.
+#[derive(Default, Debug, Encode, Decode, Clone, Copy, PartialEq, Eq)]
+pub struct InesHeader {
+    pub format: HeaderFormat,
+    pub mapper: u16,
+    pub submapper: u8,
+    pub prg_rom_size: usize,
+    pub chr_rom_size: usize,
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+    pub mirroring: MirroringMode,
+    /// Mirroring bit of flags 6, which is ignored by `mirroring` in four-screen mode. Some
+    /// boards use it to select other nametable layouts (e.g. mapper 218).
+    pub mirroring_bit: bool,
+    pub has_battery: bool,
+    pub has_trainer: bool,
+    pub timing: TimingMode,
+    pub console_type: ConsoleType,
+    pub misc_roms: u8,
+    pub expansion_device: u8,
+    /// The board description was replaced by an entry of the game database.
+    pub from_database: bool,
+}
+
+impl InesHeader {
+    pub const SIZE: usize = 16;
+    pub const TRAINER_SIZE: usize = 512;
+    const MAGIC: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A];
+
+    pub fn parse(raw: &[u8]) -> Result<InesHeader> {
+        if raw.len() < InesHeader::SIZE {
+            return Err(anyhow!("File is too small for an iNES header."));
+        }
+        let header = RawInesHeader::unpack_from_slice(&raw[0..InesHeader::SIZE])?;
+        if header.magic != InesHeader::MAGIC {
+            return Err(anyhow!("Expected NES header."));
+        }
+
+        let format = match header.format {
+            2 => HeaderFormat::Nes20,
+            0 if raw[12..16].iter().all(|b| *b == 0) => HeaderFormat::Ines,
+            _ => HeaderFormat::ArchaicInes,
+        };
+        let mirroring = if header.four_screen {
+            MirroringMode::FourScreen
+        } else if header.mirroring {
+            MirroringMode::Vertical
+        } else {
+            MirroringMode::Horizontal
+        };
+        let console_type = match header.console_type {
+            0 => ConsoleType::Nes,
+            1 => ConsoleType::VsSystem,
+            2 => ConsoleType::Playchoice10,
+            _ => ConsoleType::Extended(header.extended_console_type),
+        };
+        let mut parsed = InesHeader {
+            format,
+            mapper: header.mapper_low as u16,
+            mirroring,
+            mirroring_bit: header.mirroring,
+            has_battery: header.has_battery_ram,
+            has_trainer: header.trainer,
+            console_type,
+            ..Default::default()
+        };
+
+        match format {
+            HeaderFormat::Nes20 => {
+                parsed.mapper |= (header.mapper_mid as u16) << 4 | (header.mapper_high as u16) << 8;
+                parsed.submapper = header.submapper;
+                parsed.prg_rom_size =
+                    rom_size(header.prg_size_lsb, header.prg_size_msb, 16 * 1024)?;
+                parsed.chr_rom_size = rom_size(header.chr_size_lsb, header.chr_size_msb, 8 * 1024)?;
+                parsed.prg_ram_size = ram_size(header.prg_ram_shift);
+                parsed.prg_nvram_size = ram_size(header.prg_nvram_shift);
+                parsed.chr_ram_size = ram_size(header.chr_ram_shift);
+                parsed.chr_nvram_size = ram_size(header.chr_nvram_shift);
+                parsed.timing = match header.timing {
+                    0 => TimingMode::Ntsc,
+                    1 => TimingMode::Pal,
+                    2 => TimingMode::MultiRegion,
+                    _ => TimingMode::Dendy,
+                };
+                parsed.misc_roms = header.misc_roms;
+                parsed.expansion_device = header.expansion_device;
+            }
+            _ => {
+                if format == HeaderFormat::Ines {
+                    parsed.mapper |= (header.mapper_mid as u16) << 4;
+                }
+                parsed.prg_rom_size = header.prg_size_lsb as usize * 16 * 1024;
+                parsed.chr_rom_size = header.chr_size_lsb as usize * 8 * 1024;
+                // Flags 8 contains the PRG-RAM size in 8k units, where 0 is used for 8k.
+                let prg_ram_banks = match format {
+                    HeaderFormat::Ines => raw[8].max(1),
+                    _ => 1,
+                };
+                let prg_ram_size = prg_ram_banks as usize * 8 * 1024;
+                if parsed.has_battery {
+                    parsed.prg_nvram_size = prg_ram_size;
+                } else {
+                    parsed.prg_ram_size = prg_ram_size;
+                }
+                if parsed.chr_rom_size == 0 {
+                    parsed.chr_ram_size = 8 * 1024;
+                }
+                if format == HeaderFormat::Ines && raw[9] & 0x01 != 0 {
+                    parsed.timing = TimingMode::Pal;
+                }
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// Offset of the PRG-ROM in the file, after the header and optional trainer.
+    pub fn prg_rom_offset(&self) -> usize {
+        if self.has_trainer {
+            InesHeader::SIZE + InesHeader::TRAINER_SIZE
+        } else {
+            InesHeader::SIZE
+        }
+    }
+
+    pub fn chr_rom_offset(&self) -> usize {
+        self.prg_rom_offset() + self.prg_rom_size
+    }
+
+    /// Size of header, trainer, PRG-ROM and CHR-ROM. Files may contain additional data at the
+    /// end, e.g. misc ROMs or a title.
+    pub fn expected_file_size(&self) -> usize {
+        self.chr_rom_offset() + self.chr_rom_size
+    }
+
+    /// Whether the RAM sizes describe the board. iNES 1.0 headers cannot tell if a board has
+    /// PRG-RAM, so their sizes are only the assumed 8k.
+    pub fn has_exact_ram_sizes(&self) -> bool {
+        self.format == HeaderFormat::Nes20 || self.from_database
+    }
+}
+
+impl Display for InesHeader {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:?} mapper {}.{}, PRG-ROM {}k, CHR-ROM {}k, PRG-RAM {}k, PRG-NVRAM {}k, \
+             CHR-RAM {}k, {:?} mirroring, {:?}",
+            self.format,
+            self.mapper,
+            self.submapper,
+            self.prg_rom_size / 1024,
+            self.chr_rom_size / 1024,
+            self.prg_ram_size / 1024,
+            self.prg_nvram_size / 1024,
+            self.chr_ram_size / 1024,
+            self.mirroring,
+            self.timing,
+        )
+    }
+}
+
+/// Decodes a NES 2.0 ROM size. If the MSB nibble is $F, the size is encoded as
+/// 2^exponent * (multiplier * 2 + 1) bytes in the LSB.
+fn rom_size(lsb: u8, msb: u8, unit: usize) -> Result<usize> {
+    if msb == 0xF {
+        let exponent = (lsb >> 2) as u32;
+        let multiplier = (lsb & 0x03) as usize * 2 + 1;
+        1_usize
+            .checked_shl(exponent)
+            .and_then(|size| size.checked_mul(multiplier))
+            .ok_or_else(|| anyhow!("Invalid ROM size 2^{exponent} * {multiplier}"))
+    } else {
+        Ok(((msb as usize) << 8 | lsb as usize) * unit)
+    }
+}
+
+/// Decodes a NES 2.0 RAM size, which is given as a shift count of 64 bytes.
+fn ram_size(shift: u8) -> usize {
+    if shift == 0 {
+        0
+    } else {
+        64 << shift
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(bytes: [u8; 12]) -> Vec<u8> {
+        let mut raw = InesHeader::MAGIC.to_vec();
+        raw.extend_from_slice(&bytes);
+        raw
+    }
+
+    #[test]
+    fn test_ines() {
+        let parsed =
+            InesHeader::parse(&header([2, 1, 0x43, 0x10, 0, 1, 0, 0, 0, 0, 0, 0])).unwrap();
+        assert_eq!(parsed.format, HeaderFormat::Ines);
+        assert_eq!(parsed.mapper, 0x14);
+        assert_eq!(parsed.prg_rom_size, 32 * 1024);
+        assert_eq!(parsed.chr_rom_size, 8 * 1024);
+        assert_eq!(parsed.prg_ram_size, 0);
+        assert_eq!(parsed.prg_nvram_size, 8 * 1024);
+        assert_eq!(parsed.chr_ram_size, 0);
+        assert_eq!(parsed.mirroring, MirroringMode::Vertical);
+        assert!(parsed.has_battery);
+        assert!(!parsed.has_trainer);
+        assert_eq!(parsed.timing, TimingMode::Pal);
+        assert_eq!(parsed.expected_file_size(), 16 + 40 * 1024);
+    }
+
+    #[test]
+    fn test_archaic_ines() {
+        // Bytes 7-15 contain "DiskDude!", so the upper mapper nibble has to be ignored.
+        let raw = header([
+            2, 0, 0x14, b'D', b'i', b's', b'k', b'D', b'u', b'd', b'e', b'!',
+        ]);
+        let parsed = InesHeader::parse(&raw).unwrap();
+        assert_eq!(parsed.format, HeaderFormat::ArchaicInes);
+        assert_eq!(parsed.mapper, 1);
+        assert!(parsed.has_trainer);
+        assert_eq!(parsed.chr_ram_size, 8 * 1024);
+        assert_eq!(parsed.prg_rom_offset(), 16 + 512);
+    }
+
+    #[test]
+    fn test_nes20() {
+        let parsed = InesHeader::parse(&header([
+            0x00, 0x01, 0x51, 0x4B, 0x21, 0x01, 0x70, 0x07, 0x03, 0x05, 0x01, 0x03,
+        ]))
+        .unwrap();
+        assert_eq!(parsed.format, HeaderFormat::Nes20);
+        assert_eq!(parsed.mapper, 0x145);
+        assert_eq!(parsed.submapper, 2);
+        assert_eq!(parsed.prg_rom_size, 256 * 16 * 1024);
+        assert_eq!(parsed.chr_rom_size, 8 * 1024);
+        assert_eq!(parsed.prg_ram_size, 0);
+        assert_eq!(parsed.prg_nvram_size, 8 * 1024);
+        assert_eq!(parsed.chr_ram_size, 8 * 1024);
+        assert_eq!(parsed.chr_nvram_size, 0);
+        assert_eq!(parsed.timing, TimingMode::Dendy);
+        assert_eq!(parsed.console_type, ConsoleType::Extended(5));
+        assert_eq!(parsed.misc_roms, 1);
+        assert_eq!(parsed.expansion_device, 3);
+    }
+
+    #[test]
+    fn test_nes20_exponent_size() {
+        // 2^10 * 3 bytes of PRG-ROM.
+        let parsed = InesHeader::parse(&header([
+            0x29, 0x00, 0x00, 0x08, 0x00, 0x0F, 0, 0, 0, 0, 0, 0,
+        ]))
+        .unwrap();
+        assert_eq!(parsed.prg_rom_size, 3 * 1024);
+        assert!(InesHeader::parse(&header([0xFF, 0, 0, 0x08, 0, 0x0F, 0, 0, 0, 0, 0, 0])).is_err());
+    }
+
+    #[test]
+    fn test_invalid_header() {
+        assert!(InesHeader::parse(&[0x4E, 0x45, 0x53, 0x1A]).is_err());
+        assert!(InesHeader::parse(&[0; 16]).is_err());
+    }
+}
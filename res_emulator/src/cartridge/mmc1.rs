@@ -170,6 +170,12 @@ impl Mapper for Mmc1Mapper {
     fn persistent_data(&self) -> Vec<u8> {
         self.ram.clone()
     }
+
+    fn load_persistent_data(&mut self, data: &[u8]) {
+        if data.len() == self.ram.len() {
+            self.ram.copy_from_slice(data);
+        }
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,247 @@
+use bincode::Decode;
+use bincode::Encode;
+use intbits::Bits;
+
+use super::persistent_ram;
+use super::CartridgeError;
+use super::CartridgeResult;
+use super::Mapper;
+use super::MirroringMode;
+
+const CHR_BANK_SIZE: usize = 4 * 1024;
+
+/// MMC2 and MMC4 only differ in the PRG-ROM layout and which addresses trigger latch 0.
+#[derive(Encode, Decode, Clone, Copy, PartialEq, Eq)]
+pub enum Mmc2Variant {
+    /// Mapper 9: Switchable 8k PRG-ROM bank at $8000, the last three 8k banks are fixed.
+    Mmc2,
+    /// Mapper 10: Switchable 16k PRG-ROM bank at $8000, the last 16k bank is fixed.
+    Mmc4,
+}
+
+/// MMC2 and MMC4 (mappers 9 and 10)
+///
+/// Each 4k CHR-ROM half has two bank registers. A latch selects which one is used and is
+/// switched when the PPU fetches the tiles $FD or $FE from the pattern table.
+#[derive(Encode, Decode, Clone)]
+pub struct Mmc2Mapper {
+    pub variant: Mmc2Variant,
+    pub prg: Vec<u8>,
+    pub chr: Vec<u8>,
+    pub ram: Vec<u8>,
+
+    pub prg_bank: u8,
+    /// CHR banks for $0000 and $1000, each for latch values $FD and $FE.
+    pub chr_banks: [[u8; 2]; 2],
+    /// Latches for $0000 and $1000. False if set to $FD, true if set to $FE.
+    pub latches: [bool; 2],
+    pub mirroring_register: u8,
+}
+
+impl Mmc2Mapper {
+    const RAM_SIZE: usize = 8 * 1024;
+
+    pub fn new(
+        variant: Mmc2Variant,
+        prg: &[u8],
+        chr: &[u8],
+        persistent_data: Option<&[u8]>,
+    ) -> Mmc2Mapper {
+        Mmc2Mapper {
+            variant,
+            prg: prg.to_vec(),
+            chr: if chr.is_empty() {
+                vec![0; 8 * 1024]
+            } else {
+                chr.to_vec()
+            },
+            ram: persistent_ram(persistent_data, Mmc2Mapper::RAM_SIZE),
+            prg_bank: 0,
+            chr_banks: [[0, 0], [0, 0]],
+            latches: [true, true],
+            mirroring_register: 0,
+        }
+    }
+
+    fn prg_bank_size(&self) -> usize {
+        match self.variant {
+            Mmc2Variant::Mmc2 => 8 * 1024,
+            Mmc2Variant::Mmc4 => 16 * 1024,
+        }
+    }
+
+    fn get_prg_index(&self, addr: u16) -> usize {
+        let bank_size = self.prg_bank_size();
+        let bank_count = (self.prg.len() / bank_size).max(1);
+        let offset = addr as usize - 0x8000;
+        // Only the first bank is switchable, the rest is fixed to the end of the ROM.
+        let slot = offset / bank_size;
+        let bank = if slot == 0 {
+            self.prg_bank as usize
+        } else {
+            bank_count.saturating_sub(0x8000 / bank_size - slot)
+        };
+        (bank % bank_count) * bank_size + offset % bank_size
+    }
+
+    fn get_chr_index(&self, addr: u16) -> usize {
+        let half = addr as usize / CHR_BANK_SIZE;
+        let bank = self.chr_banks[half][self.latches[half] as usize] as usize;
+        let bank_count = (self.chr.len() / CHR_BANK_SIZE).max(1);
+        (bank % bank_count) * CHR_BANK_SIZE + (addr as usize % CHR_BANK_SIZE)
+    }
+
+    /// Updates the latches after a fetch of the high bit plane of tile $FD or $FE. The MMC2
+    /// only checks the first row of the tile for latch 0.
+    fn update_latches(&mut self, addr: u16) {
+        let exact_match_only = self.variant == Mmc2Variant::Mmc2 && addr < 0x1000;
+        let row_mask = if exact_match_only { 0xFFFF } else { 0xFFF8 };
+        match addr & 0x0FFF & row_mask {
+            0x0FD8 => self.latches[addr as usize / CHR_BANK_SIZE] = false,
+            0x0FE8 => self.latches[addr as usize / CHR_BANK_SIZE] = true,
+            _ => (),
+        }
+    }
+}
+
+impl Default for Mmc2Mapper {
+    fn default() -> Self {
+        Self::new(Mmc2Variant::Mmc2, &[], &[], None)
+    }
+}
+
+impl Mapper for Mmc2Mapper {
+    fn cpu_bus_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7FFF => Some(self.ram[(addr as usize - 0x6000) % Mmc2Mapper::RAM_SIZE]),
+            0x8000..=0xFFFF => self.prg.get(self.get_prg_index(addr)).copied(),
+            _ => None,
+        }
+    }
+
+    fn cpu_bus_read(&mut self, addr: u16) -> CartridgeResult<u8> {
+        Ok(self.cpu_bus_peek(addr).unwrap_or_default())
+    }
+
+    fn cpu_bus_write(&mut self, addr: u16, value: u8) -> CartridgeResult<()> {
+        match addr {
+            0x6000..=0x7FFF => {
+                self.ram[(addr as usize - 0x6000) % Mmc2Mapper::RAM_SIZE] = value;
+            }
+            0x8000..=0x9FFF => (),
+            0xA000..=0xAFFF => self.prg_bank = value.bits(0..=3),
+            0xB000..=0xBFFF => self.chr_banks[0][0] = value.bits(0..=4),
+            0xC000..=0xCFFF => self.chr_banks[0][1] = value.bits(0..=4),
+            0xD000..=0xDFFF => self.chr_banks[1][0] = value.bits(0..=4),
+            0xE000..=0xEFFF => self.chr_banks[1][1] = value.bits(0..=4),
+            0xF000..=0xFFFF => self.mirroring_register = value,
+            _ => return Err(CartridgeError::InvalidWrite(addr)),
+        }
+        Ok(())
+    }
+
+    fn ppu_bus_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x0000..=0x1FFF => Some(self.chr[self.get_chr_index(addr)]),
+            _ => None,
+        }
+    }
+
+    fn ppu_bus_read(&mut self, addr: u16) -> CartridgeResult<u8> {
+        let value = self
+            .ppu_bus_peek(addr)
+            .ok_or(CartridgeError::InvalidRead(addr))?;
+        // The latch takes effect after the current fetch.
+        self.update_latches(addr);
+        Ok(value)
+    }
+
+    fn ppu_bus_write(&mut self, _addr: u16, _value: u8) -> CartridgeResult<()> {
+        // Some games will try to write to character ROM and expect it to NOOP.
+        Ok(())
+    }
+
+    fn get_mirroring_mode(&self) -> MirroringMode {
+        if self.mirroring_register.bit(0) {
+            MirroringMode::Horizontal
+        } else {
+            MirroringMode::Vertical
+        }
+    }
+
+    fn persistent_data(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn load_persistent_data(&mut self, data: &[u8]) {
+        if data.len() == self.ram.len() {
+            self.ram.copy_from_slice(data);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Mmc2Mapper;
+    use super::Mmc2Variant;
+    use super::CHR_BANK_SIZE;
+    use crate::cartridge::Mapper;
+
+    fn test_mapper(variant: Mmc2Variant) -> Mmc2Mapper {
+        // Create a mapper with the bank number in the first byte of each bank to allow us to
+        // identify them after mapping.
+        let mut prg = vec![0x00_u8; 128 * 1024];
+        for bank in 0..16 {
+            prg[bank * 8 * 1024] = bank as u8;
+        }
+        let mut chr = vec![0x00_u8; 8 * CHR_BANK_SIZE];
+        for bank in 0..8 {
+            chr[bank * CHR_BANK_SIZE] = bank as u8;
+        }
+        Mmc2Mapper::new(variant, &prg, &chr, None)
+    }
+
+    #[test]
+    pub fn test_prg_mapping() {
+        let mut mapper = test_mapper(Mmc2Variant::Mmc2);
+        mapper.cpu_bus_write(0xA000, 3).unwrap();
+        assert_eq!(mapper.cpu_bus_peek(0x8000), Some(3));
+        assert_eq!(mapper.cpu_bus_peek(0xA000), Some(13));
+        assert_eq!(mapper.cpu_bus_peek(0xC000), Some(14));
+        assert_eq!(mapper.cpu_bus_peek(0xE000), Some(15));
+
+        let mut mapper = test_mapper(Mmc2Variant::Mmc4);
+        mapper.cpu_bus_write(0xA000, 3).unwrap();
+        assert_eq!(mapper.cpu_bus_peek(0x8000), Some(6));
+        assert_eq!(mapper.cpu_bus_peek(0xC000), Some(14));
+    }
+
+    #[test]
+    pub fn test_chr_latches() {
+        let mut mapper = test_mapper(Mmc2Variant::Mmc2);
+        for (addr, bank) in [(0xB000, 1), (0xC000, 2), (0xD000, 3), (0xE000, 4)] {
+            mapper.cpu_bus_write(addr, bank).unwrap();
+        }
+        // Latches start out as $FE
+        assert_eq!(mapper.ppu_bus_peek(0x0000), Some(2));
+        assert_eq!(mapper.ppu_bus_peek(0x1000), Some(4));
+
+        // Fetching tile $FD switches the latch after the read.
+        assert_eq!(mapper.ppu_bus_read(0x0FD8).unwrap(), 0);
+        assert_eq!(mapper.ppu_bus_peek(0x0000), Some(1));
+        mapper.ppu_bus_read(0x1FDF).unwrap();
+        assert_eq!(mapper.ppu_bus_peek(0x1000), Some(3));
+
+        // Peeking does not affect the latches.
+        mapper.ppu_bus_peek(0x0FE8);
+        assert_eq!(mapper.ppu_bus_peek(0x0000), Some(1));
+
+        // The MMC2 only reacts to the first row of the tile for latch 0, the MMC4 to all rows.
+        mapper.ppu_bus_read(0x0FE9).unwrap();
+        assert_eq!(mapper.ppu_bus_peek(0x0000), Some(1));
+        let mut mapper = test_mapper(Mmc2Variant::Mmc4);
+        mapper.cpu_bus_write(0xB000, 1).unwrap();
+        mapper.ppu_bus_read(0x0FDC).unwrap();
+        assert_eq!(mapper.ppu_bus_peek(0x0000), Some(1));
+    }
+}
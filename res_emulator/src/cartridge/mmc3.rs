@@ -0,0 +1,366 @@
+use bincode::Decode;
+use bincode::Encode;
+use intbits::Bits;
+
+use super::persistent_ram;
+use super::CartridgeError;
+use super::CartridgeResult;
+use super::Mapper;
+use super::MirroringMode;
+
+const PRG_BANK_SIZE: usize = 8 * 1024;
+const CHR_BANK_SIZE: usize = 1024;
+
+#[derive(Encode, Decode, Clone)]
+pub struct Mmc3Mapper {
+    pub prg: Vec<u8>,
+    pub chr: Vec<u8>,
+    pub ram: Vec<u8>,
+    pub chr_is_ram: bool,
+    pub four_screen: bool,
+
+    pub bank_select: u8,
+    pub bank_registers: [u8; 8],
+    pub mirroring_register: u8,
+    pub ram_protect_register: u8,
+
+    pub irq_latch: u8,
+    pub irq_counter: u8,
+    pub irq_reload: bool,
+    pub irq_enabled: bool,
+    pub irq_pending: bool,
+}
+
+impl Mmc3Mapper {
+    const RAM_SIZE: usize = 8 * 1024;
+
+    pub fn new(
+        prg: &[u8],
+        chr: &[u8],
+        mirroring_mode: MirroringMode,
+        persistent_data: Option<&[u8]>,
+    ) -> Mmc3Mapper {
+        Mmc3Mapper {
+            prg: prg.to_vec(),
+            chr: if chr.is_empty() {
+                vec![0; 8 * 1024]
+            } else {
+                chr.to_vec()
+            },
+            ram: persistent_ram(persistent_data, Mmc3Mapper::RAM_SIZE),
+            chr_is_ram: chr.is_empty(),
+            four_screen: matches!(mirroring_mode, MirroringMode::FourScreen),
+            bank_select: 0,
+            bank_registers: [0, 2, 4, 5, 6, 7, 0, 1],
+            mirroring_register: 0,
+            ram_protect_register: 0x80,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+        }
+    }
+
+    fn prg_bank_count(&self) -> usize {
+        (self.prg.len() / PRG_BANK_SIZE).max(1)
+    }
+
+    fn get_prg_index(&self, addr: u16) -> usize {
+        let last_bank = self.prg_bank_count() - 1;
+        let r6 = self.bank_registers[6].bits(0..=5) as usize;
+        let r7 = self.bank_registers[7].bits(0..=5) as usize;
+        let prg_mode = self.bank_select.bit(6);
+        let bank = match (addr, prg_mode) {
+            (0x8000..=0x9FFF, false) => r6,
+            (0x8000..=0x9FFF, true) => last_bank.saturating_sub(1),
+            (0xA000..=0xBFFF, _) => r7,
+            (0xC000..=0xDFFF, false) => last_bank.saturating_sub(1),
+            (0xC000..=0xDFFF, true) => r6,
+            _ => last_bank,
+        };
+        (bank % self.prg_bank_count()) * PRG_BANK_SIZE + (addr as usize % PRG_BANK_SIZE)
+    }
+
+    fn get_chr_index(&self, addr: u16) -> usize {
+        // CHR A12 inversion swaps the 2kB and 1kB bank halves.
+        let addr = if self.bank_select.bit(7) {
+            addr ^ 0x1000
+        } else {
+            addr
+        };
+        let bank = match addr {
+            0x0000..=0x07FF => (self.bank_registers[0] & 0xFE) as usize + (addr as usize >> 10),
+            0x0800..=0x0FFF => {
+                (self.bank_registers[1] & 0xFE) as usize + ((addr as usize - 0x0800) >> 10)
+            }
+            0x1000..=0x13FF => self.bank_registers[2] as usize,
+            0x1400..=0x17FF => self.bank_registers[3] as usize,
+            0x1800..=0x1BFF => self.bank_registers[4] as usize,
+            _ => self.bank_registers[5] as usize,
+        };
+        let bank_count = (self.chr.len() / CHR_BANK_SIZE).max(1);
+        (bank % bank_count) * CHR_BANK_SIZE + (addr as usize % CHR_BANK_SIZE)
+    }
+
+    fn ram_enabled(&self) -> bool {
+        self.ram_protect_register.bit(7)
+    }
+
+    fn ram_writable(&self) -> bool {
+        self.ram_enabled() && !self.ram_protect_register.bit(6)
+    }
+
+    fn write_register(&mut self, addr: u16, value: u8) {
+        let even = addr & 1 == 0;
+        match (addr, even) {
+            (0x8000..=0x9FFF, true) => self.bank_select = value,
+            (0x8000..=0x9FFF, false) => {
+                self.bank_registers[self.bank_select.bits(0..=2) as usize] = value
+            }
+            (0xA000..=0xBFFF, true) => self.mirroring_register = value,
+            (0xA000..=0xBFFF, false) => self.ram_protect_register = value,
+            (0xC000..=0xDFFF, true) => self.irq_latch = value,
+            (0xC000..=0xDFFF, false) => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            (0xE000..=0xFFFF, true) => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            }
+            (0xE000..=0xFFFF, false) => self.irq_enabled = true,
+            _ => (),
+        }
+    }
+}
+
+impl Default for Mmc3Mapper {
+    fn default() -> Self {
+        Self::new(&[], &[], MirroringMode::Horizontal, None)
+    }
+}
+
+impl Mapper for Mmc3Mapper {
+    fn cpu_bus_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7FFF => {
+                if self.ram_enabled() {
+                    Some(self.ram[(addr as usize - 0x6000) % Mmc3Mapper::RAM_SIZE])
+                } else {
+                    None
+                }
+            }
+            0x8000..=0xFFFF => self.prg.get(self.get_prg_index(addr)).copied(),
+            _ => None,
+        }
+    }
+
+    fn cpu_bus_read(&mut self, addr: u16) -> CartridgeResult<u8> {
+        Ok(self.cpu_bus_peek(addr).unwrap_or_default())
+    }
+
+    fn cpu_bus_write(&mut self, addr: u16, value: u8) -> CartridgeResult<()> {
+        match addr {
+            0x6000..=0x7FFF => {
+                if self.ram_writable() {
+                    self.ram[(addr as usize - 0x6000) % Mmc3Mapper::RAM_SIZE] = value;
+                }
+                Ok(())
+            }
+            0x8000..=0xFFFF => {
+                self.write_register(addr, value);
+                Ok(())
+            }
+            _ => Err(CartridgeError::InvalidWrite(addr)),
+        }
+    }
+
+    fn ppu_bus_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x0000..=0x1FFF => Some(self.chr[self.get_chr_index(addr)]),
+            _ => None,
+        }
+    }
+
+    fn ppu_bus_read(&mut self, addr: u16) -> CartridgeResult<u8> {
+        self.ppu_bus_peek(addr)
+            .ok_or(CartridgeError::InvalidRead(addr))
+    }
+
+    fn ppu_bus_write(&mut self, addr: u16, value: u8) -> CartridgeResult<()> {
+        if let 0x0000..=0x1FFF = addr {
+            if self.chr_is_ram {
+                let idx = self.get_chr_index(addr);
+                self.chr[idx] = value;
+            }
+        }
+        Ok(())
+    }
+
+    fn ppu_a12_rising_edge(&mut self) {
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+        if self.irq_counter == 0 && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+
+    fn irq_active(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn get_mirroring_mode(&self) -> MirroringMode {
+        if self.four_screen {
+            MirroringMode::FourScreen
+        } else if self.mirroring_register.bit(0) {
+            MirroringMode::Horizontal
+        } else {
+            MirroringMode::Vertical
+        }
+    }
+
+    fn persistent_data(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn load_persistent_data(&mut self, data: &[u8]) {
+        if data.len() == self.ram.len() {
+            self.ram.copy_from_slice(data);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Mmc3Mapper;
+    use super::CHR_BANK_SIZE;
+    use super::PRG_BANK_SIZE;
+    use crate::cartridge::Mapper;
+    use crate::cartridge::MirroringMode;
+
+    fn test_mapper() -> Mmc3Mapper {
+        // Create a mapper with the bank number in the first byte of each bank to allow us to
+        // identify them after mapping.
+        let mut prg = vec![0x00_u8; 8 * PRG_BANK_SIZE];
+        for bank in 0..8 {
+            prg[bank * PRG_BANK_SIZE] = bank as u8;
+        }
+        let mut chr = vec![0x00_u8; 16 * CHR_BANK_SIZE];
+        for bank in 0..16 {
+            chr[bank * CHR_BANK_SIZE] = bank as u8;
+        }
+        Mmc3Mapper::new(&prg, &chr, MirroringMode::Vertical, None)
+    }
+
+    #[test]
+    pub fn test_persistent_data_size() {
+        // Saves of the wrong size are ignored instead of changing the size of the RAM.
+        for save in [&[][..], &[0x42; 16]] {
+            let mut mapper = Mmc3Mapper::new(
+                &[0; 8 * PRG_BANK_SIZE],
+                &[],
+                MirroringMode::Vertical,
+                Some(save),
+            );
+            mapper.cpu_bus_write(0x7FFF, 0x12).unwrap();
+            assert_eq!(mapper.cpu_bus_peek(0x6000), Some(0x00));
+            assert_eq!(mapper.cpu_bus_peek(0x7FFF), Some(0x12));
+            assert_eq!(mapper.persistent_data().len(), 8 * 1024);
+        }
+    }
+
+    #[test]
+    pub fn test_prg_mapping() {
+        let mut mapper = test_mapper();
+        mapper.cpu_bus_write(0x8000, 6).unwrap();
+        mapper.cpu_bus_write(0x8001, 2).unwrap();
+        mapper.cpu_bus_write(0x8000, 7).unwrap();
+        mapper.cpu_bus_write(0x8001, 3).unwrap();
+
+        // PRG mode 0: R6 at 0x8000, second to last bank fixed at 0xC000.
+        assert_eq!(mapper.cpu_bus_peek(0x8000), Some(2));
+        assert_eq!(mapper.cpu_bus_peek(0xA000), Some(3));
+        assert_eq!(mapper.cpu_bus_peek(0xC000), Some(6));
+        assert_eq!(mapper.cpu_bus_peek(0xE000), Some(7));
+
+        // PRG mode 1: Second to last bank fixed at 0x8000, R6 at 0xC000.
+        mapper.cpu_bus_write(0x8000, 0b0100_0000).unwrap();
+        assert_eq!(mapper.cpu_bus_peek(0x8000), Some(6));
+        assert_eq!(mapper.cpu_bus_peek(0xA000), Some(3));
+        assert_eq!(mapper.cpu_bus_peek(0xC000), Some(2));
+        assert_eq!(mapper.cpu_bus_peek(0xE000), Some(7));
+    }
+
+    #[test]
+    pub fn test_chr_mapping() {
+        let mut mapper = test_mapper();
+        for (register, bank) in [(0, 4), (1, 8), (2, 1), (3, 2), (4, 3), (5, 15)] {
+            mapper.cpu_bus_write(0x8000, register).unwrap();
+            mapper.cpu_bus_write(0x8001, bank).unwrap();
+        }
+        assert_eq!(mapper.ppu_bus_peek(0x0000), Some(4));
+        assert_eq!(mapper.ppu_bus_peek(0x0400), Some(5));
+        assert_eq!(mapper.ppu_bus_peek(0x0800), Some(8));
+        assert_eq!(mapper.ppu_bus_peek(0x0C00), Some(9));
+        assert_eq!(mapper.ppu_bus_peek(0x1000), Some(1));
+        assert_eq!(mapper.ppu_bus_peek(0x1C00), Some(15));
+
+        // With CHR A12 inversion the 1kB banks move to 0x0000.
+        mapper.cpu_bus_write(0x8000, 0b1000_0000).unwrap();
+        assert_eq!(mapper.ppu_bus_peek(0x0000), Some(1));
+        assert_eq!(mapper.ppu_bus_peek(0x0C00), Some(15));
+        assert_eq!(mapper.ppu_bus_peek(0x1000), Some(4));
+        assert_eq!(mapper.ppu_bus_peek(0x1800), Some(8));
+    }
+
+    #[test]
+    pub fn test_mirroring_and_ram_protect() {
+        let mut mapper = test_mapper();
+        mapper.cpu_bus_write(0xA000, 1).unwrap();
+        assert!(matches!(
+            mapper.get_mirroring_mode(),
+            MirroringMode::Horizontal
+        ));
+
+        mapper.cpu_bus_write(0x6000, 0x42).unwrap();
+        assert_eq!(mapper.cpu_bus_peek(0x6000), Some(0x42));
+
+        // Write protected RAM ignores writes.
+        mapper.cpu_bus_write(0xA001, 0b1100_0000).unwrap();
+        mapper.cpu_bus_write(0x6000, 0x24).unwrap();
+        assert_eq!(mapper.cpu_bus_peek(0x6000), Some(0x42));
+
+        // Disabled RAM is not readable.
+        mapper.cpu_bus_write(0xA001, 0).unwrap();
+        assert_eq!(mapper.cpu_bus_peek(0x6000), None);
+    }
+
+    #[test]
+    pub fn test_scanline_irq() {
+        let mut mapper = test_mapper();
+        mapper.cpu_bus_write(0xC000, 2).unwrap();
+        mapper.cpu_bus_write(0xC001, 0).unwrap();
+        mapper.cpu_bus_write(0xE001, 0).unwrap();
+
+        // First clock reloads the counter, then counts down to 0.
+        mapper.ppu_a12_rising_edge();
+        assert!(!mapper.irq_active());
+        mapper.ppu_a12_rising_edge();
+        assert!(!mapper.irq_active());
+        mapper.ppu_a12_rising_edge();
+        assert!(mapper.irq_active());
+
+        // Writing to 0xE000 acknowledges and disables the IRQ.
+        mapper.cpu_bus_write(0xE000, 0).unwrap();
+        assert!(!mapper.irq_active());
+        mapper.ppu_a12_rising_edge();
+        mapper.ppu_a12_rising_edge();
+        mapper.ppu_a12_rising_edge();
+        assert!(!mapper.irq_active());
+    }
+}
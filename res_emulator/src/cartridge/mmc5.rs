@@ -0,0 +1,786 @@
+use bincode::Decode;
+use bincode::Encode;
+use intbits::Bits;
+
+use super::CartridgeError;
+use super::CartridgeResult;
+use super::Mapper;
+use super::MirroringMode;
+use super::PpuFetchPhase;
+use crate::apu::PulseChannel;
+
+const PRG_BANK_SIZE: usize = 8 * 1024;
+const CHR_BANK_SIZE: usize = 1024;
+const EXRAM_SIZE: usize = 1024;
+/// The pulse length counters and envelopes are clocked at a fixed rate of about 240Hz.
+const AUDIO_FRAME_CYCLES: usize = 7458;
+
+/// MMC5 (mapper 5)
+///
+/// Provides flexible PRG and CHR banking with separate CHR banks for background and 8x16
+/// sprites, 1k of extra RAM (ExRAM) that can be used as nametable, for extended attributes
+/// or as general purpose RAM, a scanline IRQ, a multiplier and expansion audio.
+#[derive(Encode, Decode, Clone)]
+pub struct Mmc5Mapper {
+    pub prg: Vec<u8>,
+    pub chr: Vec<u8>,
+    pub ram: Vec<u8>,
+    pub exram: Vec<u8>,
+    pub chr_is_ram: bool,
+
+    pub prg_mode: u8,
+    pub chr_mode: u8,
+    pub ram_protect: [u8; 2],
+    pub exram_mode: u8,
+    pub nametable_mapping: u8,
+    pub fill_tile: u8,
+    pub fill_color: u8,
+    /// PRG banks for $6000, $8000, $A000, $C000 and $E000 ($5113-$5117).
+    pub prg_banks: [u8; 5],
+    /// CHR banks $5120-$5127 (set A) followed by $5128-$512B (set B), including the upper
+    /// bits from $5130.
+    pub chr_banks: [u16; 12],
+    pub chr_upper: u8,
+    /// In 8x8 sprite mode, the last written CHR bank set is used for all fetches.
+    pub last_chr_set_b: bool,
+
+    pub split_control: u8,
+    pub split_scroll: u8,
+    pub split_bank: u8,
+
+    pub irq_target: u8,
+    pub irq_enabled: bool,
+    pub irq_pending: bool,
+    pub in_frame: bool,
+    pub scanline_counter: u8,
+    pub multiplicand: u8,
+    pub multiplier: u8,
+
+    /// Rendering state observed through the PPU fetches.
+    pub fetch_phase: PpuFetchPhase,
+    pub large_sprites: bool,
+    pub last_nametable_fetch: Option<u16>,
+    pub next_tile: usize,
+    pub split_tile: bool,
+    pub split_line: usize,
+    pub ext_attribute: u8,
+
+    pub pulse0: PulseChannel,
+    pub pulse1: PulseChannel,
+    pub audio_status: u8,
+    pub audio_cycle: usize,
+    pub pcm_control: u8,
+    pub pcm_value: u8,
+    pub pcm_irq_pending: bool,
+}
+
+impl Mmc5Mapper {
+    const RAM_SIZE: usize = 64 * 1024;
+
+    pub fn new(prg: &[u8], chr: &[u8], persistent_data: Option<&[u8]>) -> Mmc5Mapper {
+        // Saves of smaller RAM chips fill the first banks.
+        let mut ram = vec![0; Mmc5Mapper::RAM_SIZE];
+        if let Some(data) = persistent_data {
+            let len = data.len().min(ram.len());
+            ram[..len].copy_from_slice(&data[..len]);
+        }
+        Mmc5Mapper {
+            prg: prg.to_vec(),
+            chr: if chr.is_empty() {
+                vec![0; 8 * 1024]
+            } else {
+                chr.to_vec()
+            },
+            ram,
+            exram: vec![0; EXRAM_SIZE],
+            chr_is_ram: chr.is_empty(),
+            prg_mode: 3,
+            chr_mode: 0,
+            ram_protect: [0, 0],
+            exram_mode: 0,
+            nametable_mapping: 0,
+            fill_tile: 0,
+            fill_color: 0,
+            prg_banks: [0xFF; 5],
+            chr_banks: [0; 12],
+            chr_upper: 0,
+            last_chr_set_b: false,
+            split_control: 0,
+            split_scroll: 0,
+            split_bank: 0,
+            irq_target: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            in_frame: false,
+            scanline_counter: 0,
+            multiplicand: 0xFF,
+            multiplier: 0xFF,
+            fetch_phase: PpuFetchPhase::Sprites,
+            large_sprites: false,
+            last_nametable_fetch: None,
+            next_tile: 0,
+            split_tile: false,
+            split_line: 0,
+            ext_attribute: 0,
+            pulse0: PulseChannel::default(),
+            pulse1: PulseChannel::default(),
+            audio_status: 0,
+            audio_cycle: 0,
+            pcm_control: 0,
+            pcm_value: 0,
+            pcm_irq_pending: false,
+        }
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////
+    // PRG banking
+
+    /// Returns the 8k bank mapped at the CPU address $8000-$FFFF and whether it is PRG-ROM.
+    fn prg_bank(&self, addr: u16) -> (usize, bool) {
+        let slot = (addr as usize - 0x8000) / PRG_BANK_SIZE;
+        // (register, number of 8k banks combined into one)
+        let (register, size) = match (self.prg_mode, slot) {
+            (0, _) => (4, 4),
+            (1, 0..=1) => (2, 2),
+            (1, _) => (4, 2),
+            (2, 0..=1) => (2, 2),
+            (2, 2) => (3, 1),
+            (2, _) => (4, 1),
+            (_, slot) => (slot + 1, 1),
+        };
+        let value = self.prg_banks[register];
+        let is_rom = register == 4 || value.bit(7);
+        let bank = (value.bits(0..=6) as usize & !(size - 1)) | (slot & (size - 1));
+        (bank, is_rom)
+    }
+
+    fn prg_ram_index(&self, bank: usize, addr: u16) -> usize {
+        (bank * PRG_BANK_SIZE + addr as usize % PRG_BANK_SIZE) % self.ram.len()
+    }
+
+    fn prg_rom_index(&self, bank: usize, addr: u16) -> usize {
+        let bank_count = (self.prg.len() / PRG_BANK_SIZE).max(1);
+        (bank % bank_count) * PRG_BANK_SIZE + addr as usize % PRG_BANK_SIZE
+    }
+
+    /// PRG-RAM is only writable after writing $02 to $5102 and $01 to $5103.
+    fn ram_writable(&self) -> bool {
+        self.ram_protect[0].bits(0..=1) == 0b10 && self.ram_protect[1].bits(0..=1) == 0b01
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////
+    // CHR banking
+
+    /// In 8x16 sprite mode, set A is used for sprites and set B for the background.
+    fn use_chr_set_b(&self) -> bool {
+        if self.large_sprites {
+            self.fetch_phase != PpuFetchPhase::Sprites
+        } else {
+            self.last_chr_set_b
+        }
+    }
+
+    fn get_chr_index(&self, addr: u16) -> usize {
+        let bank_size = CHR_BANK_SIZE << (3 - self.chr_mode);
+        let slot = addr as usize / bank_size;
+        let register = if self.use_chr_set_b() {
+            // Set B only has registers for 4k, which are used for both pattern tables.
+            let banks = (bank_size / CHR_BANK_SIZE).min(4);
+            8 + (addr as usize % (4 * CHR_BANK_SIZE) / bank_size + 1) * banks - 1
+        } else {
+            (slot + 1) * bank_size / CHR_BANK_SIZE - 1
+        };
+        self.chr_index(self.chr_banks[register] as usize, bank_size, addr)
+    }
+
+    fn chr_index(&self, bank: usize, bank_size: usize, addr: u16) -> usize {
+        let bank_count = (self.chr.len() / bank_size).max(1);
+        (bank % bank_count) * bank_size + addr as usize % bank_size
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////
+    // Nametables
+
+    /// Nametable source of the address: 0-1 for CIRAM pages, 2 for ExRAM and 3 for fill mode.
+    fn nametable_source(&self, addr: u16) -> u8 {
+        let quadrant = (addr as usize - 0x2000) / 0x400 % 4;
+        self.nametable_mapping.bits(quadrant * 2..=quadrant * 2 + 1)
+    }
+
+    fn split_enabled(&self) -> bool {
+        self.split_control.bit(7) && self.exram_mode <= 1
+    }
+
+    fn in_split_region(&self, tile: usize) -> bool {
+        let threshold = self.split_control.bits(0..=4) as usize;
+        if self.split_control.bit(6) {
+            tile >= threshold
+        } else {
+            tile < threshold
+        }
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////
+    // Registers
+
+    fn read_register(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            0x5010 => {
+                let value = (self.pcm_irq_pending as u8) << 7 | self.pcm_control.bit(0) as u8;
+                self.pcm_irq_pending = false;
+                Some(value)
+            }
+            0x5204 => {
+                let value = self.peek_register(addr);
+                self.irq_pending = false;
+                value
+            }
+            _ => self.peek_register(addr),
+        }
+    }
+
+    fn peek_register(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x5015 => Some((self.pulse1.active() as u8) << 1 | self.pulse0.active() as u8),
+            0x5204 => Some((self.irq_pending as u8) << 7 | (self.in_frame as u8) << 6),
+            0x5205 => Some(self.product().bits(0..=7) as u8),
+            0x5206 => Some(self.product().bits(8..=15) as u8),
+            0x5C00..=0x5FFF if self.exram_mode >= 2 => Some(self.exram[addr as usize - 0x5C00]),
+            _ => None,
+        }
+    }
+
+    fn write_register(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x5000 => self.pulse0.write_register(0, value),
+            0x5002 => self.pulse0.write_register(2, value),
+            0x5003 => self.pulse0.write_register(3, value),
+            0x5004 => self.pulse1.write_register(0, value),
+            0x5006 => self.pulse1.write_register(2, value),
+            0x5007 => self.pulse1.write_register(3, value),
+            0x5010 => self.pcm_control = value,
+            0x5011 => {
+                // In read mode the PCM value is taken from reads of $8000-$BFFF instead.
+                if !self.pcm_control.bit(0) && value != 0 {
+                    self.pcm_value = value;
+                }
+            }
+            0x5015 => {
+                self.audio_status = value;
+                self.pulse0.set_enabled(value.bit(0));
+                self.pulse1.set_enabled(value.bit(1));
+            }
+            0x5100 => self.prg_mode = value.bits(0..=1),
+            0x5101 => self.chr_mode = value.bits(0..=1),
+            0x5102 => self.ram_protect[0] = value,
+            0x5103 => self.ram_protect[1] = value,
+            0x5104 => self.exram_mode = value.bits(0..=1),
+            0x5105 => self.nametable_mapping = value,
+            0x5106 => self.fill_tile = value,
+            0x5107 => self.fill_color = value.bits(0..=1),
+            0x5113..=0x5117 => self.prg_banks[addr as usize - 0x5113] = value,
+            0x5120..=0x512B => {
+                self.chr_banks[addr as usize - 0x5120] =
+                    (self.chr_upper as u16) << 8 | value as u16;
+                self.last_chr_set_b = addr >= 0x5128;
+            }
+            0x5130 => self.chr_upper = value.bits(0..=1),
+            0x5200 => self.split_control = value,
+            0x5201 => self.split_scroll = value,
+            0x5202 => self.split_bank = value,
+            0x5203 => self.irq_target = value,
+            0x5204 => self.irq_enabled = value.bit(7),
+            0x5205 => self.multiplicand = value,
+            0x5206 => self.multiplier = value,
+            0x5C00..=0x5FFF => {
+                let offset = addr as usize - 0x5C00;
+                match self.exram_mode {
+                    // ExRAM can only be written while rendering when it is used by the PPU.
+                    0 | 1 => self.exram[offset] = if self.in_frame { value } else { 0 },
+                    2 => self.exram[offset] = value,
+                    _ => (),
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn product(&self) -> u16 {
+        self.multiplicand as u16 * self.multiplier as u16
+    }
+}
+
+impl Default for Mmc5Mapper {
+    fn default() -> Self {
+        Self::new(&[], &[], None)
+    }
+}
+
+impl Mapper for Mmc5Mapper {
+    fn cpu_bus_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x5000..=0x5FFF => self.peek_register(addr),
+            0x6000..=0x7FFF => {
+                let bank = self.prg_banks[0].bits(0..=3) as usize;
+                Some(self.ram[self.prg_ram_index(bank, addr)])
+            }
+            0x8000..=0xFFFF => match self.prg_bank(addr) {
+                (bank, true) => self.prg.get(self.prg_rom_index(bank, addr)).copied(),
+                (bank, false) => Some(self.ram[self.prg_ram_index(bank & 0x0F, addr)]),
+            },
+            _ => None,
+        }
+    }
+
+    fn cpu_bus_read(&mut self, addr: u16) -> CartridgeResult<u8> {
+        match addr {
+            0x5000..=0x5FFF => Ok(self.read_register(addr).unwrap_or_default()),
+            _ => {
+                let value = self.cpu_bus_peek(addr).unwrap_or_default();
+                if self.pcm_control.bit(0) && (0x8000..=0xBFFF).contains(&addr) {
+                    // A value of 0 stops playback and raises the PCM IRQ.
+                    if value == 0 {
+                        self.pcm_irq_pending = true;
+                    } else {
+                        self.pcm_value = value;
+                    }
+                }
+                Ok(value)
+            }
+        }
+    }
+
+    fn cpu_bus_write(&mut self, addr: u16, value: u8) -> CartridgeResult<()> {
+        match addr {
+            0x5000..=0x5FFF => self.write_register(addr, value),
+            0x6000..=0x7FFF => {
+                if self.ram_writable() {
+                    let idx = self.prg_ram_index(self.prg_banks[0].bits(0..=3) as usize, addr);
+                    self.ram[idx] = value;
+                }
+            }
+            0x8000..=0xFFFF => {
+                if let (bank, false) = self.prg_bank(addr) {
+                    if self.ram_writable() {
+                        let idx = self.prg_ram_index(bank & 0x0F, addr);
+                        self.ram[idx] = value;
+                    }
+                }
+            }
+            _ => return Err(CartridgeError::InvalidWrite(addr)),
+        }
+        Ok(())
+    }
+
+    fn ppu_bus_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x0000..=0x1FFF => Some(self.chr[self.get_chr_index(addr)]),
+            _ => None,
+        }
+    }
+
+    fn ppu_bus_read(&mut self, addr: u16) -> CartridgeResult<u8> {
+        if !matches!(addr, 0x0000..=0x1FFF) {
+            return Err(CartridgeError::InvalidRead(addr));
+        }
+        let idx = if self.fetch_phase == PpuFetchPhase::Sprites {
+            self.get_chr_index(addr)
+        } else if self.split_tile {
+            // The split region uses its own 4k bank and vertical scroll.
+            let addr = (addr & 0x0FF8) | (self.split_line % 8) as u16;
+            self.chr_index(self.split_bank as usize, 4 * CHR_BANK_SIZE, addr)
+        } else if self.exram_mode == 1 {
+            // Extended attributes select a 4k bank for each tile.
+            let bank = (self.chr_upper as usize) << 6 | self.ext_attribute.bits(0..=5) as usize;
+            self.chr_index(bank, 4 * CHR_BANK_SIZE, addr)
+        } else {
+            self.get_chr_index(addr)
+        };
+        Ok(self.chr[idx])
+    }
+
+    fn ppu_bus_write(&mut self, addr: u16, value: u8) -> CartridgeResult<()> {
+        if let 0x0000..=0x1FFF = addr {
+            if self.chr_is_ram {
+                let idx = self.get_chr_index(addr);
+                self.chr[idx] = value;
+            }
+        }
+        Ok(())
+    }
+
+    fn nametable_peek(&self, addr: u16) -> Option<u8> {
+        let offset = addr as usize % 0x400;
+        match self.nametable_source(addr) {
+            2 if self.exram_mode <= 1 => Some(self.exram[offset]),
+            2 => Some(0),
+            3 if offset < 0x3C0 => Some(self.fill_tile),
+            3 => Some(self.fill_color * 0b0101_0101),
+            _ => None,
+        }
+    }
+
+    fn nametable_fetch(&mut self, addr: u16) -> Option<u8> {
+        let offset = addr as usize % 0x400;
+        if offset < 0x3C0 {
+            // The PPU fetches the same nametable address multiple times at the end of each
+            // scanline. Only count the first fetch as a new tile.
+            if self.last_nametable_fetch != Some(addr) {
+                self.last_nametable_fetch = Some(addr);
+                self.split_tile = self.split_enabled() && self.in_split_region(self.next_tile);
+                self.next_tile += 1;
+            }
+            self.ext_attribute = self.exram[offset];
+            if self.split_tile {
+                let row = self.split_line / 8 % 30;
+                let column = (self.next_tile - 1) % 32;
+                return Some(self.exram[row * 32 + column]);
+            }
+        } else if self.split_tile {
+            let row = self.split_line / 8 % 30;
+            let column = (self.next_tile - 1) % 32;
+            let attribute = self.exram[0x3C0 + row / 4 * 8 + column / 4];
+            let shift = (row & 0b10) << 1 | (column & 0b10);
+            return Some(((attribute >> shift) & 0b11) * 0b0101_0101);
+        } else if self.exram_mode == 1 {
+            return Some(self.ext_attribute.bits(6..=7) * 0b0101_0101);
+        }
+        self.nametable_peek(addr)
+    }
+
+    fn nametable_write(&mut self, addr: u16, value: u8) -> bool {
+        match self.nametable_source(addr) {
+            2 => {
+                if self.exram_mode <= 1 {
+                    self.exram[addr as usize % 0x400] = value;
+                }
+                true
+            }
+            3 => true,
+            _ => false,
+        }
+    }
+
+    fn ppu_fetch_phase(&mut self, phase: PpuFetchPhase, large_sprites: bool) {
+        self.fetch_phase = phase;
+        self.large_sprites = large_sprites;
+        if let PpuFetchPhase::Background { scanline } = phase {
+            self.next_tile = 0;
+            self.last_nametable_fetch = None;
+            self.split_tile = false;
+            self.split_line = (self.split_scroll as usize + scanline) % 240;
+        }
+    }
+
+    fn ppu_scanline_start(&mut self, scanline: usize, rendering: bool) {
+        if !rendering || scanline >= 240 {
+            self.in_frame = false;
+        } else if !self.in_frame {
+            self.in_frame = true;
+            self.scanline_counter = 0;
+            self.irq_pending = false;
+        } else {
+            self.scanline_counter = self.scanline_counter.wrapping_add(1);
+            if self.scanline_counter == self.irq_target {
+                self.irq_pending = true;
+            }
+        }
+    }
+
+    fn irq_active(&self) -> bool {
+        (self.irq_enabled && self.irq_pending) || (self.pcm_control.bit(7) && self.pcm_irq_pending)
+    }
+
+    fn cpu_tick(&mut self) {
+        self.audio_cycle += 1;
+        if self.audio_cycle % 2 == 0 {
+            let frame = self.audio_cycle % AUDIO_FRAME_CYCLES == 0;
+            self.pulse0.tick(frame, frame);
+            self.pulse1.tick(frame, frame);
+        }
+    }
+
+    fn audio_output(&self) -> f32 {
+        let pulse0 = if self.audio_status.bit(0) {
+            self.pulse0.value()
+        } else {
+            0.0
+        };
+        let pulse1 = if self.audio_status.bit(1) {
+            self.pulse1.value()
+        } else {
+            0.0
+        };
+        0.1128 * (pulse0 + pulse1) + 0.42545 * self.pcm_value as f32 / 255.0
+    }
+
+    fn get_mirroring_mode(&self) -> MirroringMode {
+        // ExRAM and fill mode nametables are handled by `nametable_peek`.
+        MirroringMode::Custom(
+            [0, 1, 2, 3].map(|quadrant| self.nametable_mapping.bit(quadrant * 2) as u8),
+        )
+    }
+
+    fn persistent_data(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn load_persistent_data(&mut self, data: &[u8]) {
+        if data.len() == self.ram.len() {
+            self.ram.copy_from_slice(data);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Mmc5Mapper;
+    use super::CHR_BANK_SIZE;
+    use super::PRG_BANK_SIZE;
+    use crate::cartridge::Mapper;
+    use crate::cartridge::MirroringMode;
+    use crate::cartridge::PpuFetchPhase;
+
+    fn test_mapper() -> Mmc5Mapper {
+        // Create a mapper with the bank number in the first byte of each bank to allow us to
+        // identify them after mapping.
+        let mut prg = vec![0x00_u8; 16 * PRG_BANK_SIZE];
+        for bank in 0..16 {
+            prg[bank * PRG_BANK_SIZE] = bank as u8;
+        }
+        let mut chr = vec![0x00_u8; 256 * CHR_BANK_SIZE];
+        for bank in 0..256 {
+            chr[bank * CHR_BANK_SIZE] = bank as u8;
+        }
+        Mmc5Mapper::new(&prg, &chr, None)
+    }
+
+    #[test]
+    pub fn test_prg_mapping() {
+        let mut mapper = test_mapper();
+        // Power on state maps the last bank in all slots.
+        assert_eq!(mapper.cpu_bus_peek(0xE000), Some(15));
+
+        for (addr, value) in [
+            (0x5114, 0x81),
+            (0x5115, 0x83),
+            (0x5116, 0x85),
+            (0x5117, 0x07),
+        ] {
+            mapper.cpu_bus_write(addr, value).unwrap();
+        }
+        // Mode 3: 4 8k banks
+        assert_eq!(mapper.cpu_bus_peek(0x8000), Some(1));
+        assert_eq!(mapper.cpu_bus_peek(0xA000), Some(3));
+        assert_eq!(mapper.cpu_bus_peek(0xC000), Some(5));
+        assert_eq!(mapper.cpu_bus_peek(0xE000), Some(7));
+
+        // Mode 2: 16k bank at $8000 ignoring the low bit, two 8k banks.
+        mapper.cpu_bus_write(0x5100, 2).unwrap();
+        assert_eq!(mapper.cpu_bus_peek(0x8000), Some(2));
+        assert_eq!(mapper.cpu_bus_peek(0xA000), Some(3));
+        assert_eq!(mapper.cpu_bus_peek(0xC000), Some(5));
+        assert_eq!(mapper.cpu_bus_peek(0xE000), Some(7));
+
+        // Mode 1: two 16k banks
+        mapper.cpu_bus_write(0x5100, 1).unwrap();
+        assert_eq!(mapper.cpu_bus_peek(0xC000), Some(6));
+        assert_eq!(mapper.cpu_bus_peek(0xE000), Some(7));
+
+        // Mode 0: one 32k bank
+        mapper.cpu_bus_write(0x5100, 0).unwrap();
+        assert_eq!(mapper.cpu_bus_peek(0x8000), Some(4));
+        assert_eq!(mapper.cpu_bus_peek(0xE000), Some(7));
+    }
+
+    #[test]
+    pub fn test_prg_ram() {
+        let mut mapper = test_mapper();
+        // RAM is write protected by default.
+        mapper.cpu_bus_write(0x6000, 0x42).unwrap();
+        assert_eq!(mapper.cpu_bus_peek(0x6000), Some(0));
+
+        mapper.cpu_bus_write(0x5102, 0x02).unwrap();
+        mapper.cpu_bus_write(0x5103, 0x01).unwrap();
+        mapper.cpu_bus_write(0x5113, 0x01).unwrap();
+        mapper.cpu_bus_write(0x6000, 0x42).unwrap();
+        assert_eq!(mapper.cpu_bus_peek(0x6000), Some(0x42));
+
+        // The same RAM bank can be mapped into $8000-$DFFF.
+        mapper.cpu_bus_write(0x5114, 0x01).unwrap();
+        assert_eq!(mapper.cpu_bus_peek(0x8000), Some(0x42));
+        mapper.cpu_bus_write(0x8001, 0x43).unwrap();
+        assert_eq!(mapper.cpu_bus_peek(0x6001), Some(0x43));
+    }
+
+    #[test]
+    pub fn test_persistent_data_size() {
+        // An empty save leaves the RAM empty instead of removing it.
+        let mut mapper = Mmc5Mapper::new(&[0; 16 * PRG_BANK_SIZE], &[], Some(&[]));
+        mapper.cpu_bus_write(0x5102, 0x02).unwrap();
+        mapper.cpu_bus_write(0x5103, 0x01).unwrap();
+        mapper.cpu_bus_write(0x6000, 0x42).unwrap();
+        assert_eq!(mapper.cpu_bus_peek(0x6000), Some(0x42));
+        assert_eq!(mapper.persistent_data().len(), 64 * 1024);
+
+        let mut mapper = Mmc5Mapper::new(&[0; 16 * PRG_BANK_SIZE], &[], Some(&[0x12, 0x34]));
+        mapper.cpu_bus_write(0x5113, 0x00).unwrap();
+        assert_eq!(mapper.cpu_bus_peek(0x6001), Some(0x34));
+    }
+
+    #[test]
+    pub fn test_chr_mapping() {
+        let mut mapper = test_mapper();
+        mapper.cpu_bus_write(0x5101, 3).unwrap();
+        for register in 0..12 {
+            mapper
+                .cpu_bus_write(0x5120 + register, 0x10 + register as u8)
+                .unwrap();
+        }
+        // In 8x8 sprite mode the last written set (B) is used for everything.
+        assert_eq!(mapper.ppu_bus_peek(0x0000), Some(0x18));
+        assert_eq!(mapper.ppu_bus_peek(0x1C00), Some(0x1B));
+
+        // In 8x16 sprite mode, set A is used for sprites.
+        mapper.ppu_fetch_phase(PpuFetchPhase::Sprites, true);
+        assert_eq!(mapper.ppu_bus_peek(0x0000), Some(0x10));
+        assert_eq!(mapper.ppu_bus_peek(0x1C00), Some(0x17));
+        mapper.ppu_fetch_phase(PpuFetchPhase::Background { scanline: 0 }, true);
+        assert_eq!(mapper.ppu_bus_peek(0x1400), Some(0x19));
+
+        // 2k banks
+        mapper.cpu_bus_write(0x5101, 2).unwrap();
+        assert_eq!(mapper.ppu_bus_peek(0x1000), Some(0x19 * 2));
+        mapper.ppu_fetch_phase(PpuFetchPhase::Sprites, true);
+        assert_eq!(mapper.ppu_bus_peek(0x1000), Some(0x15 * 2));
+
+        // 8k banks with upper bits
+        mapper.cpu_bus_write(0x5101, 0).unwrap();
+        mapper.cpu_bus_write(0x5130, 0x01).unwrap();
+        mapper.cpu_bus_write(0x5127, 0x00).unwrap();
+        assert_eq!(mapper.chr_banks[7], 0x100);
+    }
+
+    #[test]
+    pub fn test_nametables() {
+        let mut mapper = test_mapper();
+        // CIRAM page 1, ExRAM, fill mode, CIRAM page 0
+        mapper.cpu_bus_write(0x5105, 0b00_11_10_01).unwrap();
+        mapper.cpu_bus_write(0x5106, 0x42).unwrap();
+        mapper.cpu_bus_write(0x5107, 0x02).unwrap();
+        assert_eq!(
+            mapper.get_mirroring_mode(),
+            MirroringMode::Custom([1, 0, 1, 0])
+        );
+        assert_eq!(mapper.nametable_peek(0x2000), None);
+        assert!(mapper.nametable_write(0x2401, 0x12));
+        assert_eq!(mapper.nametable_peek(0x2401), Some(0x12));
+        assert_eq!(mapper.nametable_peek(0x2800), Some(0x42));
+        assert_eq!(mapper.nametable_peek(0x2BC0), Some(0b1010_1010));
+        assert!(!mapper.nametable_write(0x2C00, 0x12));
+
+        // ExRAM as general purpose RAM
+        mapper.cpu_bus_write(0x5104, 2).unwrap();
+        mapper.cpu_bus_write(0x5C01, 0x34).unwrap();
+        assert_eq!(mapper.cpu_bus_peek(0x5C01), Some(0x34));
+        assert_eq!(mapper.nametable_peek(0x2401), Some(0x00));
+    }
+
+    #[test]
+    pub fn test_extended_attributes() {
+        let mut mapper = test_mapper();
+        mapper.cpu_bus_write(0x5104, 2).unwrap();
+        mapper.cpu_bus_write(0x5C21, 0b11_000101).unwrap();
+        mapper.cpu_bus_write(0x5104, 1).unwrap();
+
+        mapper.ppu_fetch_phase(PpuFetchPhase::Background { scanline: 8 }, false);
+        mapper.nametable_fetch(0x2021);
+        assert_eq!(mapper.nametable_fetch(0x23C8), Some(0xFF));
+        assert_eq!(mapper.ppu_bus_read(0x0000).unwrap(), 5 * 4);
+        assert_eq!(mapper.ppu_bus_read(0x1000).unwrap(), 5 * 4);
+
+        // Sprites use the regular banks.
+        mapper.ppu_fetch_phase(PpuFetchPhase::Sprites, false);
+        assert_eq!(mapper.ppu_bus_read(0x0000).unwrap(), 0);
+    }
+
+    #[test]
+    pub fn test_vertical_split() {
+        let mut mapper = test_mapper();
+        // Split on the left 2 tiles, scrolled down by 8 lines, using CHR bank 3.
+        mapper.cpu_bus_write(0x5104, 2).unwrap();
+        mapper.cpu_bus_write(0x5C21, 0x77).unwrap();
+        mapper.cpu_bus_write(0x5104, 0).unwrap();
+        mapper.cpu_bus_write(0x5200, 0x82).unwrap();
+        mapper.cpu_bus_write(0x5201, 8).unwrap();
+        mapper.cpu_bus_write(0x5202, 3).unwrap();
+
+        mapper.ppu_fetch_phase(PpuFetchPhase::Background { scanline: 0 }, false);
+        mapper.nametable_fetch(0x2000);
+        assert_eq!(mapper.nametable_fetch(0x2000), Some(0));
+        mapper.nametable_fetch(0x2001);
+        assert_eq!(mapper.nametable_fetch(0x2001), Some(0x77));
+        assert_eq!(mapper.ppu_bus_read(0x0000).unwrap(), 3 * 4);
+
+        // The third tile is outside of the split.
+        assert_eq!(mapper.nametable_fetch(0x2002), None);
+        assert_eq!(mapper.ppu_bus_read(0x0000).unwrap(), 0);
+    }
+
+    #[test]
+    pub fn test_scanline_irq() {
+        let mut mapper = test_mapper();
+        mapper.cpu_bus_write(0x5203, 3).unwrap();
+        mapper.cpu_bus_write(0x5204, 0x80).unwrap();
+
+        for scanline in 0..3 {
+            mapper.ppu_scanline_start(scanline, true);
+            assert!(!mapper.irq_active());
+        }
+        assert_eq!(mapper.cpu_bus_read(0x5204).unwrap(), 0x40);
+        mapper.ppu_scanline_start(3, true);
+        assert!(mapper.irq_active());
+
+        // Reading the status acknowledges the IRQ.
+        assert_eq!(mapper.cpu_bus_read(0x5204).unwrap(), 0xC0);
+        assert!(!mapper.irq_active());
+
+        mapper.ppu_scanline_start(240, true);
+        assert_eq!(mapper.cpu_bus_read(0x5204).unwrap(), 0x00);
+    }
+
+    #[test]
+    pub fn test_multiplier() {
+        let mut mapper = test_mapper();
+        mapper.cpu_bus_write(0x5205, 0xAB).unwrap();
+        mapper.cpu_bus_write(0x5206, 0xCD).unwrap();
+        assert_eq!(mapper.cpu_bus_read(0x5205).unwrap(), 0xEF);
+        assert_eq!(mapper.cpu_bus_read(0x5206).unwrap(), 0x88);
+    }
+
+    #[test]
+    pub fn test_audio() {
+        let mut mapper = test_mapper();
+        assert_eq!(mapper.audio_output(), 0.0);
+
+        // Constant volume pulse with 50% duty cycle
+        mapper.cpu_bus_write(0x5015, 0x01).unwrap();
+        mapper.cpu_bus_write(0x5000, 0b1011_1111).unwrap();
+        mapper.cpu_bus_write(0x5002, 0x40).unwrap();
+        mapper.cpu_bus_write(0x5003, 0x08).unwrap();
+        assert_eq!(mapper.cpu_bus_read(0x5015).unwrap(), 0x01);
+        let outputs: Vec<f32> = (0..1000)
+            .map(|_| {
+                mapper.cpu_tick();
+                mapper.audio_output()
+            })
+            .collect();
+        assert!(outputs.iter().any(|&sample| sample > 0.0));
+        assert!(outputs.contains(&0.0));
+
+        // Raw PCM
+        mapper.cpu_bus_write(0x5015, 0x00).unwrap();
+        mapper.cpu_bus_write(0x5011, 0xFF).unwrap();
+        assert_eq!(mapper.audio_output(), 0.42545);
+    }
+}
@@ -0,0 +1,481 @@
+use bincode::Decode;
+use bincode::Encode;
+use intbits::Bits;
+
+use super::CartridgeError;
+use super::CartridgeResult;
+use super::Mapper;
+use super::MirroringMode;
+
+const PRG_BANK_SIZE: usize = 8 * 1024;
+const CHR_BANK_SIZE: usize = 1024;
+const INTERNAL_RAM_SIZE: usize = 128;
+/// The chip updates one of the enabled channels every 15 CPU cycles.
+const CHANNEL_UPDATE_CYCLES: u8 = 15;
+
+/// Namco 163 (mapper 19)
+///
+/// Switchable 8k PRG-ROM banks and 1k CHR banks. Banks $E0-$FF select a page of the
+/// nametable RAM (CIRAM) instead of CHR-ROM, both for the pattern tables and for the
+/// nametables. Since the chip controls all accesses to CIRAM, it is emulated here instead of
+/// using the internal VRAM of the PPU. The 128 byte internal RAM holds the registers and
+/// waveforms of up to 8 wavetable channels.
+#[derive(Encode, Decode, Clone)]
+pub struct Namco163Mapper {
+    pub prg: Vec<u8>,
+    pub chr: Vec<u8>,
+    pub ram: Vec<u8>,
+    pub ciram: Vec<u8>,
+    pub internal_ram: Vec<u8>,
+
+    pub prg_banks: [u8; 3],
+    pub chr_banks: [u8; 8],
+    pub nametable_banks: [u8; 4],
+    pub address_port: u8,
+
+    pub irq_counter: u16,
+    pub irq_enabled: bool,
+    pub irq_pending: bool,
+
+    pub update_cycle: u8,
+    pub current_channel: u8,
+    pub channel_output: f32,
+}
+
+impl Namco163Mapper {
+    const RAM_SIZE: usize = 8 * 1024;
+
+    /// The persistent data contains the 8k PRG-RAM followed by the internal RAM.
+    pub fn new(prg: &[u8], chr: &[u8], persistent_data: Option<&[u8]>) -> Namco163Mapper {
+        let mut mapper = Namco163Mapper {
+            prg: prg.to_vec(),
+            chr: if chr.is_empty() {
+                vec![0; 8 * 1024]
+            } else {
+                chr.to_vec()
+            },
+            ram: vec![0; Namco163Mapper::RAM_SIZE],
+            ciram: vec![0; 2 * 1024],
+            internal_ram: vec![0; INTERNAL_RAM_SIZE],
+            prg_banks: [0; 3],
+            chr_banks: [0; 8],
+            nametable_banks: [0xE0; 4],
+            address_port: 0,
+            irq_counter: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            update_cycle: 0,
+            current_channel: 7,
+            channel_output: 0.0,
+        };
+        // Saves of the wrong size are ignored.
+        if let Some(data) = persistent_data {
+            mapper.load_persistent_data(data);
+        }
+        mapper
+    }
+
+    fn get_prg_index(&self, addr: u16) -> usize {
+        let bank_count = (self.prg.len() / PRG_BANK_SIZE).max(1);
+        let bank = match addr {
+            0x8000..=0xDFFF => self.prg_banks[(addr as usize - 0x8000) / PRG_BANK_SIZE].bits(0..=5),
+            _ => (bank_count - 1) as u8,
+        };
+        (bank as usize % bank_count) * PRG_BANK_SIZE + addr as usize % PRG_BANK_SIZE
+    }
+
+    fn get_chr_rom_index(&self, bank: u8, addr: u16) -> usize {
+        let bank_count = (self.chr.len() / CHR_BANK_SIZE).max(1);
+        (bank as usize % bank_count) * CHR_BANK_SIZE + addr as usize % CHR_BANK_SIZE
+    }
+
+    fn get_ciram_index(&self, bank: u8, addr: u16) -> usize {
+        (bank as usize & 1) * 0x400 + addr as usize % 0x400
+    }
+
+    /// Returns true if the pattern table bank is mapped to CIRAM. This can be disabled for
+    /// each pattern table by bits 6 and 7 of $E800.
+    fn chr_bank_is_ciram(&self, addr: u16) -> bool {
+        let bank = self.chr_banks[addr as usize / CHR_BANK_SIZE];
+        let ciram_disabled = self.prg_banks[1].bit(6 + addr as usize / 0x1000);
+        bank >= 0xE0 && !ciram_disabled
+    }
+
+    /// PRG-RAM is writable in 2k blocks, if the upper bits of $F800 are set to 0b0100 and the
+    /// bit for the block is cleared.
+    fn ram_writable(&self, addr: u16) -> bool {
+        let block = (addr as usize - 0x6000) / 0x800;
+        self.address_port.bits(4..=7) == 0b0100 && !self.address_port.bit(block)
+    }
+
+    fn sound_enabled(&self) -> bool {
+        !self.prg_banks[0].bit(6)
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////
+    // Audio
+
+    /// Number of enabled channels. The channels are enabled from the last one downwards.
+    fn enabled_channels(&self) -> u8 {
+        self.internal_ram[0x7F].bits(4..=6) + 1
+    }
+
+    /// Advances the phase of a channel and returns its output.
+    fn update_channel(&mut self, channel: u8) -> f32 {
+        let base = 0x40 + channel as usize * 8;
+        let registers = &self.internal_ram[base..base + 8];
+        let frequency = registers[0] as u32
+            | (registers[2] as u32) << 8
+            | (registers[4].bits(0..=1) as u32) << 16;
+        let length = 256 - (registers[4] & 0xFC) as u32;
+        let wave_address = registers[6] as u32;
+        let volume = registers[7].bits(0..=3);
+        let mut phase =
+            registers[1] as u32 | (registers[3] as u32) << 8 | (registers[5] as u32) << 16;
+
+        phase = (phase + frequency) % (length << 16);
+        self.internal_ram[base + 1] = phase.bits(0..=7) as u8;
+        self.internal_ram[base + 3] = phase.bits(8..=15) as u8;
+        self.internal_ram[base + 5] = phase.bits(16..=23) as u8;
+
+        // Samples are stored as 4 bit values, the low nibble first.
+        let sample_index = ((phase >> 16) + wave_address) as usize % (INTERNAL_RAM_SIZE * 2);
+        let sample_byte = self.internal_ram[sample_index / 2];
+        let sample = if sample_index % 2 == 0 {
+            sample_byte.bits(0..=3)
+        } else {
+            sample_byte.bits(4..=7)
+        };
+        (sample as f32 - 8.0) * volume as f32
+    }
+}
+
+impl Default for Namco163Mapper {
+    fn default() -> Self {
+        Self::new(&[], &[], None)
+    }
+}
+
+impl Mapper for Namco163Mapper {
+    fn cpu_bus_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x4800..=0x4FFF => Some(self.internal_ram[self.address_port.bits(0..=6) as usize]),
+            0x5000..=0x57FF => Some(self.irq_counter.bits(0..=7) as u8),
+            0x5800..=0x5FFF => {
+                Some(self.irq_counter.bits(8..=14) as u8 | (self.irq_enabled as u8) << 7)
+            }
+            0x6000..=0x7FFF => Some(self.ram[(addr as usize - 0x6000) % Namco163Mapper::RAM_SIZE]),
+            0x8000..=0xFFFF => self.prg.get(self.get_prg_index(addr)).copied(),
+            _ => None,
+        }
+    }
+
+    fn cpu_bus_read(&mut self, addr: u16) -> CartridgeResult<u8> {
+        let value = self.cpu_bus_peek(addr).unwrap_or_default();
+        if let 0x4800..=0x4FFF = addr {
+            if self.address_port.bit(7) {
+                self.address_port =
+                    (self.address_port & 0x80) | self.address_port.wrapping_add(1) & 0x7F;
+            }
+        }
+        Ok(value)
+    }
+
+    fn cpu_bus_write(&mut self, addr: u16, value: u8) -> CartridgeResult<()> {
+        match addr {
+            0x4800..=0x4FFF => {
+                self.internal_ram[self.address_port.bits(0..=6) as usize] = value;
+                if self.address_port.bit(7) {
+                    self.address_port =
+                        (self.address_port & 0x80) | self.address_port.wrapping_add(1) & 0x7F;
+                }
+            }
+            0x5000..=0x57FF => {
+                self.irq_counter = (self.irq_counter & 0x7F00) | value as u16;
+                self.irq_pending = false;
+            }
+            0x5800..=0x5FFF => {
+                self.irq_counter = (self.irq_counter & 0x00FF) | (value.bits(0..=6) as u16) << 8;
+                self.irq_enabled = value.bit(7);
+                self.irq_pending = false;
+            }
+            0x6000..=0x7FFF => {
+                if self.ram_writable(addr) {
+                    self.ram[(addr as usize - 0x6000) % Namco163Mapper::RAM_SIZE] = value;
+                }
+            }
+            0x8000..=0xBFFF => self.chr_banks[(addr as usize - 0x8000) / 0x800] = value,
+            0xC000..=0xDFFF => self.nametable_banks[(addr as usize - 0xC000) / 0x800] = value,
+            0xE000..=0xF7FF => self.prg_banks[(addr as usize - 0xE000) / 0x800] = value,
+            0xF800..=0xFFFF => self.address_port = value,
+            _ => return Err(CartridgeError::InvalidWrite(addr)),
+        }
+        Ok(())
+    }
+
+    fn ppu_bus_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x0000..=0x1FFF => {
+                let bank = self.chr_banks[addr as usize / CHR_BANK_SIZE];
+                if self.chr_bank_is_ciram(addr) {
+                    Some(self.ciram[self.get_ciram_index(bank, addr)])
+                } else {
+                    Some(self.chr[self.get_chr_rom_index(bank, addr)])
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn ppu_bus_read(&mut self, addr: u16) -> CartridgeResult<u8> {
+        self.ppu_bus_peek(addr)
+            .ok_or(CartridgeError::InvalidRead(addr))
+    }
+
+    fn ppu_bus_write(&mut self, addr: u16, value: u8) -> CartridgeResult<()> {
+        if let 0x0000..=0x1FFF = addr {
+            if self.chr_bank_is_ciram(addr) {
+                let bank = self.chr_banks[addr as usize / CHR_BANK_SIZE];
+                let idx = self.get_ciram_index(bank, addr);
+                self.ciram[idx] = value;
+            }
+        }
+        Ok(())
+    }
+
+    fn nametable_peek(&self, addr: u16) -> Option<u8> {
+        let bank = self.nametable_banks[(addr as usize - 0x2000) / 0x400 % 4];
+        if bank >= 0xE0 {
+            Some(self.ciram[self.get_ciram_index(bank, addr)])
+        } else {
+            Some(self.chr[self.get_chr_rom_index(bank, addr)])
+        }
+    }
+
+    fn nametable_write(&mut self, addr: u16, value: u8) -> bool {
+        let bank = self.nametable_banks[(addr as usize - 0x2000) / 0x400 % 4];
+        if bank >= 0xE0 {
+            let idx = self.get_ciram_index(bank, addr);
+            self.ciram[idx] = value;
+        }
+        true
+    }
+
+    fn irq_active(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn cpu_tick(&mut self) {
+        if self.irq_enabled && self.irq_counter < 0x7FFF {
+            self.irq_counter += 1;
+            if self.irq_counter == 0x7FFF {
+                self.irq_pending = true;
+            }
+        }
+
+        // Only one channel is updated and output at a time, cycling through the enabled
+        // channels from channel 7 downwards.
+        self.update_cycle += 1;
+        if self.update_cycle == CHANNEL_UPDATE_CYCLES {
+            self.update_cycle = 0;
+            let first_channel = 8 - self.enabled_channels();
+            self.current_channel = if self.current_channel <= first_channel {
+                7
+            } else {
+                self.current_channel - 1
+            };
+            self.channel_output = self.update_channel(self.current_channel);
+        }
+    }
+
+    /// A single channel at full volume is about twice as loud as an APU pulse channel.
+    fn audio_output(&self) -> f32 {
+        if self.sound_enabled() {
+            0.1128 * self.channel_output / 60.0
+        } else {
+            0.0
+        }
+    }
+
+    fn get_mirroring_mode(&self) -> MirroringMode {
+        // All nametable accesses are handled by `nametable_peek` and `nametable_write`.
+        MirroringMode::Vertical
+    }
+
+    fn persistent_data(&self) -> Vec<u8> {
+        [self.ram.as_slice(), self.internal_ram.as_slice()].concat()
+    }
+
+    fn load_persistent_data(&mut self, data: &[u8]) {
+        if data.len() == self.ram.len() + self.internal_ram.len() {
+            let (ram, internal_ram) = data.split_at(self.ram.len());
+            self.ram.copy_from_slice(ram);
+            self.internal_ram.copy_from_slice(internal_ram);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Namco163Mapper;
+    use super::CHR_BANK_SIZE;
+    use super::INTERNAL_RAM_SIZE;
+    use super::PRG_BANK_SIZE;
+    use crate::cartridge::Mapper;
+
+    fn test_mapper() -> Namco163Mapper {
+        // Create a mapper with the bank number in the first byte of each bank to allow us to
+        // identify them after mapping.
+        let mut prg = vec![0x00_u8; 16 * PRG_BANK_SIZE];
+        for bank in 0..16 {
+            prg[bank * PRG_BANK_SIZE] = bank as u8;
+        }
+        let mut chr = vec![0x00_u8; 32 * CHR_BANK_SIZE];
+        for bank in 0..32 {
+            chr[bank * CHR_BANK_SIZE] = bank as u8;
+        }
+        Namco163Mapper::new(&prg, &chr, None)
+    }
+
+    #[test]
+    pub fn test_prg_mapping() {
+        let mut mapper = test_mapper();
+        mapper.cpu_bus_write(0xE000, 1).unwrap();
+        mapper.cpu_bus_write(0xE800, 2).unwrap();
+        mapper.cpu_bus_write(0xF000, 3).unwrap();
+        assert_eq!(mapper.cpu_bus_peek(0x8000), Some(1));
+        assert_eq!(mapper.cpu_bus_peek(0xA000), Some(2));
+        assert_eq!(mapper.cpu_bus_peek(0xC000), Some(3));
+        assert_eq!(mapper.cpu_bus_peek(0xE000), Some(15));
+
+        // RAM is write protected unless enabled through $F800.
+        mapper.cpu_bus_write(0x6800, 0x42).unwrap();
+        assert_eq!(mapper.cpu_bus_peek(0x6800), Some(0x00));
+        mapper.cpu_bus_write(0xF800, 0x41).unwrap();
+        mapper.cpu_bus_write(0x6000, 0x42).unwrap();
+        mapper.cpu_bus_write(0x6800, 0x42).unwrap();
+        assert_eq!(mapper.cpu_bus_peek(0x6000), Some(0x00));
+        assert_eq!(mapper.cpu_bus_peek(0x6800), Some(0x42));
+    }
+
+    #[test]
+    pub fn test_chr_and_nametable_mapping() {
+        let mut mapper = test_mapper();
+        mapper.cpu_bus_write(0x8000, 5).unwrap();
+        mapper.cpu_bus_write(0x8800, 0xE1).unwrap();
+        assert_eq!(mapper.ppu_bus_peek(0x0000), Some(5));
+
+        // Pattern table bank $E1 and nametable bank $E1 share the same CIRAM page.
+        mapper.cpu_bus_write(0xC000, 0xE1).unwrap();
+        mapper.ppu_bus_write(0x0400, 0x42).unwrap();
+        assert_eq!(mapper.nametable_peek(0x2000), Some(0x42));
+        assert!(mapper.nametable_write(0x2001, 0x43));
+        assert_eq!(mapper.ppu_bus_peek(0x0401), Some(0x43));
+
+        // CIRAM can be disabled for the lower pattern table.
+        mapper.cpu_bus_write(0xE800, 0x40).unwrap();
+        assert_eq!(mapper.ppu_bus_peek(0x0400), Some(0xE1 % 32));
+
+        // Nametables can be mapped to CHR-ROM
+        mapper.cpu_bus_write(0xC800, 7).unwrap();
+        assert_eq!(mapper.nametable_peek(0x2400), Some(7));
+    }
+
+    #[test]
+    pub fn test_irq() {
+        let mut mapper = test_mapper();
+        mapper.cpu_bus_write(0x5000, 0xFE).unwrap();
+        mapper.cpu_bus_write(0x5800, 0xFF).unwrap();
+        assert_eq!(mapper.cpu_bus_read(0x5800).unwrap(), 0xFF);
+        mapper.cpu_tick();
+        assert!(mapper.irq_active());
+        // The counter stops at $7FFF.
+        mapper.cpu_tick();
+        assert_eq!(mapper.cpu_bus_read(0x5000).unwrap(), 0xFF);
+        mapper.cpu_bus_write(0x5000, 0x00).unwrap();
+        assert!(!mapper.irq_active());
+    }
+
+    #[test]
+    pub fn test_persistent_data_size() {
+        // Saves of the wrong size are ignored instead of changing the size of the RAM.
+        for save in [&[][..], &[0x42; 16]] {
+            let mut mapper = Namco163Mapper::new(&[0; 8 * PRG_BANK_SIZE], &[], Some(save));
+            mapper.cpu_bus_write(0xF800, 0x40).unwrap();
+            mapper.cpu_bus_write(0x7FFF, 0x12).unwrap();
+            assert_eq!(mapper.cpu_bus_peek(0x6000), Some(0x00));
+            assert_eq!(mapper.cpu_bus_peek(0x7FFF), Some(0x12));
+            assert_eq!(mapper.persistent_data().len(), 8 * 1024 + 128);
+        }
+    }
+
+    #[test]
+    pub fn test_address_port_wraps() {
+        let mut mapper = test_mapper();
+        // Auto increment wraps from 0x7F to 0x00 and stays enabled.
+        mapper.cpu_bus_write(0xF800, 0x80 | 0x7F).unwrap();
+        mapper.cpu_bus_write(0x4800, 0x12).unwrap();
+        assert_eq!(mapper.address_port, 0x80);
+        assert_eq!(mapper.internal_ram[0x7F], 0x12);
+
+        mapper.cpu_bus_write(0xF800, 0x80 | 0x7F).unwrap();
+        assert_eq!(mapper.cpu_bus_read(0x4800).unwrap(), 0x12);
+        assert_eq!(mapper.address_port, 0x80);
+    }
+
+    #[test]
+    pub fn test_internal_ram_and_persistence() {
+        let mut mapper = test_mapper();
+        // Write with auto increment
+        mapper.cpu_bus_write(0xF800, 0x80 | 0x7E).unwrap();
+        mapper.cpu_bus_write(0x4800, 0x12).unwrap();
+        mapper.cpu_bus_write(0x4800, 0x34).unwrap();
+        mapper.cpu_bus_write(0xF800, 0x7E).unwrap();
+        assert_eq!(mapper.cpu_bus_read(0x4800).unwrap(), 0x12);
+        assert_eq!(mapper.cpu_bus_read(0x4800).unwrap(), 0x12);
+
+        let data = mapper.persistent_data();
+        assert_eq!(data.len(), 8 * 1024 + INTERNAL_RAM_SIZE);
+        let restored = Namco163Mapper::new(&[], &[], Some(&data));
+        assert_eq!(restored.internal_ram[0x7F], 0x34);
+    }
+
+    #[test]
+    pub fn test_wavetable_channels() {
+        let mut mapper = test_mapper();
+        let mut write_internal = |addr: u8, values: &[u8]| {
+            mapper.cpu_bus_write(0xF800, 0x80 | addr).unwrap();
+            for value in values {
+                mapper.cpu_bus_write(0x4800, *value).unwrap();
+            }
+        };
+        // Waveform of 4 samples at address 0: 15, 15, 0, 0
+        write_internal(0x00, &[0xFF, 0x00]);
+        // Channel 7 with length 4, full volume and advancing one sample per update. One
+        // channel enabled.
+        write_internal(0x78, &[0x00, 0x00, 0x00, 0x00, 0xFD, 0x00, 0x00, 0x0F]);
+
+        let outputs: Vec<f32> = (0..15 * 8)
+            .map(|_| {
+                mapper.cpu_tick();
+                mapper.audio_output()
+            })
+            .collect();
+        assert!(outputs.iter().any(|&sample| sample > 0.0));
+        assert!(outputs.iter().any(|&sample| sample < 0.0));
+
+        // With two channels enabled, they take turns.
+        mapper.internal_ram[0x7F] = 0x1F;
+        mapper.cpu_tick();
+        let channels: Vec<u8> = (0..4)
+            .map(|_| {
+                for _ in 0..15 {
+                    mapper.cpu_tick();
+                }
+                mapper.current_channel
+            })
+            .collect();
+        assert!(channels.contains(&6));
+        assert!(channels.contains(&7));
+    }
+}
@@ -97,4 +97,10 @@ impl Mapper for NromMapper {
     fn persistent_data(&self) -> Vec<u8> {
         self.ram.clone()
     }
+
+    fn load_persistent_data(&mut self, data: &[u8]) {
+        if data.len() == self.ram.len() {
+            self.ram.copy_from_slice(data);
+        }
+    }
 }
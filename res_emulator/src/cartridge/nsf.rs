@@ -0,0 +1,852 @@
+use std::fmt::Display;
+use std::fmt::Formatter;
+
+use anyhow::anyhow;
+use anyhow::Result;
+use bincode::Decode;
+use bincode::Encode;
+use intbits::Bits;
+
+use super::fds::FdsAudio;
+use super::fme7::Fme7Mapper;
+use super::mmc5::Mmc5Mapper;
+use super::namco163::Namco163Mapper;
+use super::vrc6::Vrc6Mapper;
+use super::vrc6::Vrc6Variant;
+use super::CartridgeError;
+use super::CartridgeResult;
+use super::Mapper;
+use super::MirroringMode;
+
+const NSF_MAGIC: &[u8] = b"NESM\x1A";
+const NSFE_MAGIC: &[u8] = b"NSFE";
+const NSF_HEADER_SIZE: usize = 0x80;
+const BANK_SIZE: usize = 0x1000;
+const RAM_SIZE: usize = 8 * 1024;
+/// FDS tunes have RAM at $6000-$FFFF.
+const FDS_RAM_SIZE: usize = 40 * 1024;
+
+/// Default duration between PLAY calls in microseconds, matching the frame rate of the PPU.
+const DEFAULT_NTSC_PLAY_SPEED: u16 = 16639;
+const DEFAULT_PAL_PLAY_SPEED: u16 = 19997;
+const CPU_FREQUENCY: u64 = 1_789_773;
+
+/// Returns true if the file is in NSF or NSFe format.
+pub fn is_nsf(raw: &[u8]) -> bool {
+    raw.starts_with(NSF_MAGIC) || raw.starts_with(NSFE_MAGIC)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// NsfHeader
+
+#[derive(Default, Debug, Encode, Decode, Clone, Copy, PartialEq, Eq)]
+pub enum NsfFormat {
+    #[default]
+    Nsf,
+    Nsfe,
+}
+
+/// Expansion audio chips used by a tune.
+#[derive(Default, Debug, Encode, Decode, Clone, Copy, PartialEq, Eq)]
+pub struct NsfExpansion {
+    pub vrc6: bool,
+    /// Not emulated, tunes play without the VRC7 channels.
+    pub vrc7: bool,
+    pub fds: bool,
+    pub mmc5: bool,
+    pub namco163: bool,
+    pub sunsoft5b: bool,
+}
+
+impl NsfExpansion {
+    fn from_bits(bits: u8) -> NsfExpansion {
+        NsfExpansion {
+            vrc6: bits.bit(0),
+            vrc7: bits.bit(1),
+            fds: bits.bit(2),
+            mmc5: bits.bit(3),
+            namco163: bits.bit(4),
+            sunsoft5b: bits.bit(5),
+        }
+    }
+}
+
+impl Display for NsfExpansion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let chips: Vec<&str> = [
+            (self.vrc6, "VRC6"),
+            (self.vrc7, "VRC7"),
+            (self.fds, "FDS"),
+            (self.mmc5, "MMC5"),
+            (self.namco163, "N163"),
+            (self.sunsoft5b, "5B"),
+        ]
+        .iter()
+        .filter(|(used, _)| *used)
+        .map(|(_, name)| *name)
+        .collect();
+        if chips.is_empty() {
+            write!(f, "no expansion audio")
+        } else {
+            write!(f, "{} expansion audio", chips.join(", "))
+        }
+    }
+}
+
+/// Metadata and entry points of an NSF or NSFe file.
+/// See https://www.nesdev.org/wiki/NSF and https://www.nesdev.org/wiki/NSFe
+#[derive(Default, Debug, Encode, Decode, Clone, PartialEq, Eq)]
+pub struct NsfHeader {
+    pub format: NsfFormat,
+    pub track_count: usize,
+    /// Track to play first, starting at 0.
+    pub starting_track: usize,
+    pub load_addr: u16,
+    pub init_addr: u16,
+    pub play_addr: u16,
+    pub title: String,
+    pub artist: String,
+    pub copyright: String,
+    /// Names of the tracks, which are only available in NSFe files and NSF2 metadata.
+    pub track_names: Vec<String>,
+    /// Durations between PLAY calls in microseconds.
+    pub ntsc_play_speed: u16,
+    pub pal_play_speed: u16,
+    /// Banks of $8000-$FFFF after INIT. The tune is not bankswitched if all are 0.
+    pub initial_banks: [u8; 8],
+    pub pal_only: bool,
+    pub expansion: NsfExpansion,
+}
+
+impl NsfHeader {
+    pub fn is_bankswitched(&self) -> bool {
+        self.initial_banks.iter().any(|bank| *bank != 0)
+    }
+
+    pub fn track_name(&self, track: usize) -> Option<&str> {
+        self.track_names
+            .get(track)
+            .map(|name| name.as_str())
+            .filter(|name| !name.is_empty())
+    }
+
+    /// CPU cycles between PLAY calls. The emulator runs at NTSC speed, but PAL only tunes are
+    /// still played at their PAL rate.
+    fn play_period(&self) -> u32 {
+        let speed = if self.pal_only {
+            self.pal_play_speed
+        } else {
+            self.ntsc_play_speed
+        };
+        ((speed as u64 * CPU_FREQUENCY / 1_000_000) as u32).max(1)
+    }
+
+    fn set_region(&mut self, region: u8) {
+        self.pal_only = region.bit(0) && !region.bit(1);
+    }
+}
+
+impl Display for NsfHeader {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:?} \"{}\" by {} ({}), {} tracks, {}",
+            self.format, self.title, self.artist, self.copyright, self.track_count, self.expansion,
+        )
+    }
+}
+
+/// Parses an NSF or NSFe file into its header and program data.
+pub fn parse_nsf(raw: &[u8]) -> Result<(NsfHeader, Vec<u8>)> {
+    if raw.starts_with(NSFE_MAGIC) {
+        let mut header = NsfHeader {
+            format: NsfFormat::Nsfe,
+            track_count: 1,
+            ntsc_play_speed: DEFAULT_NTSC_PLAY_SPEED,
+            pal_play_speed: DEFAULT_PAL_PLAY_SPEED,
+            ..Default::default()
+        };
+        let mut data = None;
+        parse_nsfe_chunks(&raw[NSFE_MAGIC.len()..], &mut header, &mut data)?;
+        let data = data.ok_or_else(|| anyhow!("NSFe file is missing the DATA chunk"))?;
+        return Ok((header, data));
+    }
+
+    if !raw.starts_with(NSF_MAGIC) || raw.len() < NSF_HEADER_SIZE {
+        return Err(anyhow!("Expected NSF or NSFe file."));
+    }
+    let u16_at = |offset: usize| u16::from_le_bytes([raw[offset], raw[offset + 1]]);
+    let mut header = NsfHeader {
+        format: NsfFormat::Nsf,
+        track_count: raw[0x06] as usize,
+        starting_track: (raw[0x07] as usize).saturating_sub(1),
+        load_addr: u16_at(0x08),
+        init_addr: u16_at(0x0A),
+        play_addr: u16_at(0x0C),
+        title: parse_string(&raw[0x0E..0x2E]),
+        artist: parse_string(&raw[0x2E..0x4E]),
+        copyright: parse_string(&raw[0x4E..0x6E]),
+        track_names: vec![],
+        ntsc_play_speed: u16_at(0x6E),
+        pal_play_speed: u16_at(0x78),
+        initial_banks: raw[0x70..0x78].try_into().unwrap(),
+        pal_only: false,
+        expansion: NsfExpansion::from_bits(raw[0x7B]),
+    };
+    header.set_region(raw[0x7A]);
+    if header.ntsc_play_speed == 0 {
+        header.ntsc_play_speed = DEFAULT_NTSC_PLAY_SPEED;
+    }
+    if header.pal_play_speed == 0 {
+        header.pal_play_speed = DEFAULT_PAL_PLAY_SPEED;
+    }
+
+    // NSF2 files may store the length of the program data, followed by NSFe metadata chunks.
+    let data = &raw[NSF_HEADER_SIZE..];
+    let data_length = u32::from_le_bytes([raw[0x7D], raw[0x7E], raw[0x7F], 0]) as usize;
+    if raw[0x05] >= 2 && data_length > 0 && data_length < data.len() {
+        parse_nsfe_chunks(&data[data_length..], &mut header, &mut None)?;
+        return Ok((header, data[..data_length].to_vec()));
+    }
+    Ok((header, data.to_vec()))
+}
+
+/// Parses the chunks of an NSFe file. Chunks with an upper case first letter are required to
+/// play the file, so unknown ones are an error. Unknown optional chunks are skipped.
+fn parse_nsfe_chunks(
+    mut raw: &[u8],
+    header: &mut NsfHeader,
+    data: &mut Option<Vec<u8>>,
+) -> Result<()> {
+    while raw.len() >= 8 {
+        let length = u32::from_le_bytes(raw[0..4].try_into().unwrap()) as usize;
+        let id = &raw[4..8];
+        let chunk = raw
+            .get(8..8 + length)
+            .ok_or_else(|| anyhow!("NSFe chunk {} is truncated", String::from_utf8_lossy(id)))?;
+        match id {
+            b"INFO" => {
+                if chunk.len() < 8 {
+                    return Err(anyhow!("NSFe INFO chunk is too short"));
+                }
+                header.load_addr = u16::from_le_bytes([chunk[0], chunk[1]]);
+                header.init_addr = u16::from_le_bytes([chunk[2], chunk[3]]);
+                header.play_addr = u16::from_le_bytes([chunk[4], chunk[5]]);
+                header.set_region(chunk[6]);
+                header.expansion = NsfExpansion::from_bits(chunk[7]);
+                header.track_count = chunk.get(8).copied().unwrap_or(1) as usize;
+                header.starting_track = chunk.get(9).copied().unwrap_or(0) as usize;
+            }
+            b"DATA" => *data = Some(chunk.to_vec()),
+            b"BANK" => {
+                header.initial_banks = [0; 8];
+                let length = chunk.len().min(8);
+                header.initial_banks[..length].copy_from_slice(&chunk[..length]);
+            }
+            b"RATE" => {
+                if chunk.len() >= 2 {
+                    header.ntsc_play_speed = u16::from_le_bytes([chunk[0], chunk[1]]);
+                }
+                if chunk.len() >= 4 {
+                    header.pal_play_speed = u16::from_le_bytes([chunk[2], chunk[3]]);
+                }
+            }
+            b"auth" => {
+                let mut strings = chunk.split(|b| *b == 0).map(parse_string);
+                header.title = strings.next().unwrap_or_default();
+                header.artist = strings.next().unwrap_or_default();
+                header.copyright = strings.next().unwrap_or_default();
+            }
+            b"tlbl" => {
+                let names = chunk.strip_suffix(&[0]).unwrap_or(chunk);
+                header.track_names = names.split(|b| *b == 0).map(parse_string).collect();
+            }
+            b"NEND" => break,
+            _ => {
+                if id[0].is_ascii_uppercase() {
+                    return Err(anyhow!(
+                        "Unsupported NSFe chunk {}",
+                        String::from_utf8_lossy(id)
+                    ));
+                }
+            }
+        }
+        raw = &raw[8 + length..];
+    }
+    Ok(())
+}
+
+/// Strings are null terminated, or fill the whole field.
+fn parse_string(raw: &[u8]) -> String {
+    let end = raw.iter().position(|b| *b == 0).unwrap_or(raw.len());
+    String::from_utf8_lossy(&raw[..end]).to_string()
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Driver
+
+/// The NSF player maps a small driver program at $4100, which initializes the console and
+/// calls INIT. PLAY is called from the IRQ handler of the timer.
+const DRIVER_ADDR: u16 = 0x4100;
+const DRIVER_IRQ_ADDR: u16 = 0x4144;
+const DRIVER_NMI_ADDR: u16 = 0x414B;
+/// Writes start the timer and acknowledge its IRQ.
+const TIMER_REGISTER: u16 = 0x4150;
+
+fn build_driver(header: &NsfHeader, track: usize) -> Vec<u8> {
+    let [init_low, init_high] = header.init_addr.to_le_bytes();
+    let [play_low, play_high] = header.play_addr.to_le_bytes();
+    let [timer_low, timer_high] = TIMER_REGISTER.to_le_bytes();
+    vec![
+        0x78, // $4100: SEI
+        0xD8, // CLD
+        0xA2,
+        0xFF, // LDX #$FF
+        0x9A, // TXS
+        0xE8, // INX
+        0x8A, // TXA
+        // $4107: Clear RAM
+        0x95,
+        0x00, // STA $00,X
+        0x9D,
+        0x00,
+        0x01, // STA $0100,X
+        0x9D,
+        0x00,
+        0x02, // STA $0200,X
+        0x9D,
+        0x00,
+        0x03, // STA $0300,X
+        0x9D,
+        0x00,
+        0x04, // STA $0400,X
+        0x9D,
+        0x00,
+        0x05, // STA $0500,X
+        0x9D,
+        0x00,
+        0x06, // STA $0600,X
+        0x9D,
+        0x00,
+        0x07, // STA $0700,X
+        0xE8, // INX
+        0xD0,
+        0xE6, // BNE $4107
+        // $4121: Silence the APU
+        0xA2,
+        0x13, // LDX #$13
+        0x9D,
+        0x00,
+        0x40, // STA $4000,X
+        0xCA, // DEX
+        0x10,
+        0xFA, // BPL $4123
+        0x8D,
+        0x15,
+        0x40, // STA $4015
+        0xA9,
+        0x0F, // LDA #$0F
+        0x8D,
+        0x15,
+        0x40, // STA $4015
+        0xA9,
+        0x40, // LDA #$40
+        0x8D,
+        0x17,
+        0x40, // STA $4017
+        // $4136: Call INIT with the track and region
+        0xA9,
+        track as u8, // LDA #track
+        0xA2,
+        header.pal_only as u8, // LDX #region
+        0x20,
+        init_low,
+        init_high, // JSR init
+        0x8D,
+        timer_low,
+        timer_high, // STA timer
+        0x58,       // CLI
+        0x4C,
+        0x41,
+        0x41, // $4141: JMP $4141
+        // $4144: IRQ handler
+        0x8D,
+        timer_low,
+        timer_high, // STA timer
+        0x20,
+        play_low,
+        play_high, // JSR play
+        0x40,      // RTI
+        // $414B: NMI handler
+        0x40, // RTI
+    ]
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// NsfMapper
+
+/// Music player for NSF files
+///
+/// The program data is mapped in 4k banks at $8000-$FFFF, which are switched through
+/// $5FF8-$5FFF. FDS tunes use RAM at $6000-$FFFF instead, into which the banks are copied.
+/// The interrupt vectors point to a driver that runs INIT and calls PLAY on a timer, so the
+/// PPU is not needed. Writes to unmapped addresses are ignored since many rips write to
+/// registers of chips they do not use.
+#[derive(Encode, Decode, Clone)]
+pub struct NsfMapper {
+    pub header: NsfHeader,
+    /// Program data, padded to start at a bank boundary.
+    pub prg: Vec<u8>,
+    pub ram: Vec<u8>,
+    /// Banks of the 4k windows at $6000-$FFFF. Only FDS tunes can switch $6000-$7FFF.
+    pub banks: [u8; 10],
+    pub track: usize,
+    pub driver: Vec<u8>,
+
+    pub play_period: u32,
+    pub timer_counter: u32,
+    pub timer_running: bool,
+    pub timer_irq: bool,
+
+    pub vrc6: Option<Vrc6Mapper>,
+    pub mmc5: Option<Mmc5Mapper>,
+    pub namco163: Option<Namco163Mapper>,
+    pub sunsoft5b: Option<Fme7Mapper>,
+    pub fds: Option<FdsAudio>,
+}
+
+impl NsfMapper {
+    pub fn new(header: &NsfHeader, data: &[u8]) -> Result<NsfMapper> {
+        // Bankswitched data is aligned to the banks by the lower bits of the load address,
+        // other tunes are placed at the load address in the address space at $6000.
+        let padding = if header.is_bankswitched() {
+            header.load_addr as usize % BANK_SIZE
+        } else if header.load_addr >= 0x6000 {
+            header.load_addr as usize - 0x6000
+        } else {
+            return Err(anyhow!("Invalid NSF load address {:04X}", header.load_addr));
+        };
+        let mut prg = vec![0; padding];
+        prg.extend_from_slice(data);
+        prg.resize((prg.len() + BANK_SIZE - 1) / BANK_SIZE * BANK_SIZE, 0);
+
+        let mut mapper = NsfMapper {
+            header: header.clone(),
+            prg,
+            ram: vec![],
+            banks: [0; 10],
+            track: 0,
+            driver: vec![],
+            play_period: header.play_period(),
+            timer_counter: 0,
+            timer_running: false,
+            timer_irq: false,
+            vrc6: None,
+            mmc5: None,
+            namco163: None,
+            sunsoft5b: None,
+            fds: None,
+        };
+        mapper.select_track(header.starting_track.min(header.track_count.max(1) - 1));
+        Ok(mapper)
+    }
+
+    fn initial_banks(&self) -> [u8; 10] {
+        if self.header.is_bankswitched() {
+            let banks = self.header.initial_banks;
+            [
+                banks[6], banks[7], banks[0], banks[1], banks[2], banks[3], banks[4], banks[5],
+                banks[6], banks[7],
+            ]
+        } else {
+            [0, 1, 2, 3, 4, 5, 6, 7, 8, 9]
+        }
+    }
+
+    fn write_bank(&mut self, window: usize, bank: u8) {
+        self.banks[window] = bank;
+        if self.header.expansion.fds {
+            let start = bank as usize * BANK_SIZE;
+            for offset in 0..BANK_SIZE {
+                self.ram[window * BANK_SIZE + offset] =
+                    self.prg.get(start + offset).copied().unwrap_or_default();
+            }
+        }
+    }
+
+    fn prg_peek(&self, addr: u16) -> u8 {
+        let window = (addr as usize - 0x6000) / BANK_SIZE;
+        let index = self.banks[window] as usize * BANK_SIZE + addr as usize % BANK_SIZE;
+        self.prg.get(index).copied().unwrap_or_default()
+    }
+
+    fn vector_peek(&self, addr: u16) -> u8 {
+        let vector = match addr {
+            0xFFFA..=0xFFFB => DRIVER_NMI_ADDR,
+            0xFFFC..=0xFFFD => DRIVER_ADDR,
+            _ => DRIVER_IRQ_ADDR,
+        };
+        vector.to_le_bytes()[addr as usize % 2]
+    }
+}
+
+impl Default for NsfMapper {
+    fn default() -> Self {
+        let header = NsfHeader {
+            load_addr: 0x8000,
+            ..Default::default()
+        };
+        Self::new(&header, &[]).unwrap()
+    }
+}
+
+impl Mapper for NsfMapper {
+    fn cpu_bus_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x4040..=0x4092 => self.fds.as_ref()?.peek(addr),
+            0x4100..=0x41FF => self.driver.get((addr - DRIVER_ADDR) as usize).copied(),
+            0x4800..=0x4FFF => self.namco163.as_ref()?.cpu_bus_peek(addr),
+            0x5000..=0x5015 | 0x5205..=0x5206 | 0x5C00..=0x5FF5 => {
+                self.mmc5.as_ref()?.cpu_bus_peek(addr)
+            }
+            0xFFFA..=0xFFFF => Some(self.vector_peek(addr)),
+            0x6000..=0xFFFF if self.header.expansion.fds => Some(self.ram[addr as usize - 0x6000]),
+            0x6000..=0x7FFF => Some(self.ram[addr as usize - 0x6000]),
+            0x8000..=0xFFFF => Some(self.prg_peek(addr)),
+            _ => None,
+        }
+    }
+
+    fn cpu_bus_read(&mut self, addr: u16) -> CartridgeResult<u8> {
+        match addr {
+            0x4800..=0x4FFF => match &mut self.namco163 {
+                Some(namco163) => namco163.cpu_bus_read(addr),
+                None => Ok(0),
+            },
+            0x5000..=0x5015 | 0x5205..=0x5206 | 0x5C00..=0x5FF5 => match &mut self.mmc5 {
+                Some(mmc5) => mmc5.cpu_bus_read(addr),
+                None => Ok(0),
+            },
+            _ => Ok(self.cpu_bus_peek(addr).unwrap_or_default()),
+        }
+    }
+
+    fn cpu_bus_write(&mut self, addr: u16, value: u8) -> CartridgeResult<()> {
+        match addr {
+            0x4040..=0x408A => {
+                if let Some(fds) = &mut self.fds {
+                    fds.write(addr, value);
+                }
+            }
+            TIMER_REGISTER => {
+                self.timer_irq = false;
+                if !self.timer_running {
+                    self.timer_running = true;
+                    self.timer_counter = self.play_period;
+                }
+            }
+            0x4800..=0x4FFF => {
+                if let Some(namco163) = &mut self.namco163 {
+                    namco163.cpu_bus_write(addr, value)?;
+                }
+            }
+            0x5000..=0x5015 | 0x5205..=0x5206 | 0x5C00..=0x5FF5 => {
+                if let Some(mmc5) = &mut self.mmc5 {
+                    mmc5.cpu_bus_write(addr, value)?;
+                }
+            }
+            0x5FF6..=0x5FFF => self.write_bank((addr - 0x5FF6) as usize, value),
+            0x6000..=0x7FFF => self.ram[addr as usize - 0x6000] = value,
+            0x8000..=0xFFFF => {
+                if self.header.expansion.fds {
+                    self.ram[addr as usize - 0x6000] = value;
+                }
+                if let (0x9000..=0xB002, Some(vrc6)) = (addr, &mut self.vrc6) {
+                    vrc6.cpu_bus_write(addr, value)?;
+                }
+                if let (0xC000..=0xFFFF, Some(sunsoft5b)) = (addr, &mut self.sunsoft5b) {
+                    sunsoft5b.cpu_bus_write(addr, value)?;
+                }
+                if let (0xF800..=0xFFFF, Some(namco163)) = (addr, &mut self.namco163) {
+                    namco163.cpu_bus_write(addr, value)?;
+                }
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn ppu_bus_peek(&self, _addr: u16) -> Option<u8> {
+        Some(0)
+    }
+
+    fn ppu_bus_read(&mut self, addr: u16) -> CartridgeResult<u8> {
+        self.ppu_bus_peek(addr)
+            .ok_or(CartridgeError::InvalidRead(addr))
+    }
+
+    fn ppu_bus_write(&mut self, _addr: u16, _value: u8) -> CartridgeResult<()> {
+        Ok(())
+    }
+
+    fn irq_active(&self) -> bool {
+        self.timer_irq
+    }
+
+    fn cpu_tick(&mut self) {
+        if self.timer_running {
+            self.timer_counter -= 1;
+            if self.timer_counter == 0 {
+                self.timer_counter = self.play_period;
+                self.timer_irq = true;
+            }
+        }
+        if let Some(vrc6) = &mut self.vrc6 {
+            vrc6.cpu_tick();
+        }
+        if let Some(mmc5) = &mut self.mmc5 {
+            mmc5.cpu_tick();
+        }
+        if let Some(namco163) = &mut self.namco163 {
+            namco163.cpu_tick();
+        }
+        if let Some(sunsoft5b) = &mut self.sunsoft5b {
+            sunsoft5b.cpu_tick();
+        }
+        if let Some(fds) = &mut self.fds {
+            fds.tick();
+        }
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.vrc6.as_ref().map_or(0.0, |vrc6| vrc6.audio_output())
+            + self.mmc5.as_ref().map_or(0.0, |mmc5| mmc5.audio_output())
+            + self
+                .namco163
+                .as_ref()
+                .map_or(0.0, |namco163| namco163.audio_output())
+            + self
+                .sunsoft5b
+                .as_ref()
+                .map_or(0.0, |sunsoft5b| sunsoft5b.audio_output())
+            + self.fds.as_ref().map_or(0.0, |fds| fds.output())
+    }
+
+    fn track_count(&self) -> usize {
+        self.header.track_count
+    }
+
+    fn current_track(&self) -> Option<usize> {
+        Some(self.track)
+    }
+
+    /// Resets the memory, banks and expansion chips for the track and stops the timer until
+    /// the driver has called INIT again.
+    fn select_track(&mut self, track: usize) {
+        let expansion = self.header.expansion;
+        self.track = track;
+        self.driver = build_driver(&self.header, track);
+        self.ram = vec![
+            0;
+            if expansion.fds {
+                FDS_RAM_SIZE
+            } else {
+                RAM_SIZE
+            }
+        ];
+        for (window, bank) in self.initial_banks().into_iter().enumerate() {
+            self.write_bank(window, bank);
+        }
+        self.timer_running = false;
+        self.timer_irq = false;
+
+        self.vrc6 = expansion
+            .vrc6
+            .then(|| Vrc6Mapper::new(Vrc6Variant::Vrc6a, &[], &[], None));
+        self.mmc5 = expansion.mmc5.then(|| {
+            // ExRAM is used as general purpose RAM.
+            let mut mmc5 = Mmc5Mapper::new(&[], &[], None);
+            mmc5.exram_mode = 2;
+            mmc5
+        });
+        self.namco163 = expansion
+            .namco163
+            .then(|| Namco163Mapper::new(&[], &[], None));
+        self.sunsoft5b = expansion.sunsoft5b.then(|| Fme7Mapper::new(&[], &[], None));
+        self.fds = expansion.fds.then(FdsAudio::default);
+    }
+
+    fn get_mirroring_mode(&self) -> MirroringMode {
+        MirroringMode::Horizontal
+    }
+
+    fn persistent_data(&self) -> Vec<u8> {
+        vec![]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates an NSF file with the given bank registers and expansion chips.
+    fn test_nsf(load_addr: u16, banks: [u8; 8], expansion: u8, data: &[u8]) -> Vec<u8> {
+        let mut raw = NSF_MAGIC.to_vec();
+        raw.extend_from_slice(&[0x01, 3, 2]);
+        raw.extend_from_slice(&load_addr.to_le_bytes());
+        raw.extend_from_slice(&0x8000_u16.to_le_bytes());
+        raw.extend_from_slice(&0x8003_u16.to_le_bytes());
+        for text in [&b"Title"[..], b"Artist", b"Copyright"] {
+            let mut field = text.to_vec();
+            field.resize(32, 0);
+            raw.extend_from_slice(&field);
+        }
+        raw.extend_from_slice(&0_u16.to_le_bytes());
+        raw.extend_from_slice(&banks);
+        raw.extend_from_slice(&20000_u16.to_le_bytes());
+        raw.extend_from_slice(&[0x01, expansion, 0, 0, 0, 0]);
+        assert_eq!(raw.len(), NSF_HEADER_SIZE);
+        raw.extend_from_slice(data);
+        raw
+    }
+
+    fn chunk(id: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut chunk = (data.len() as u32).to_le_bytes().to_vec();
+        chunk.extend_from_slice(id);
+        chunk.extend_from_slice(data);
+        chunk
+    }
+
+    #[test]
+    pub fn test_parse_nsf() {
+        let (header, data) = parse_nsf(&test_nsf(0x8000, [0; 8], 0x11, &[1, 2, 3])).unwrap();
+        assert_eq!(header.format, NsfFormat::Nsf);
+        assert_eq!(header.track_count, 3);
+        assert_eq!(header.starting_track, 1);
+        assert_eq!(header.load_addr, 0x8000);
+        assert_eq!(header.init_addr, 0x8000);
+        assert_eq!(header.play_addr, 0x8003);
+        assert_eq!(header.title, "Title");
+        assert_eq!(header.artist, "Artist");
+        assert_eq!(header.copyright, "Copyright");
+        assert_eq!(header.ntsc_play_speed, DEFAULT_NTSC_PLAY_SPEED);
+        assert_eq!(header.pal_play_speed, 20000);
+        assert!(header.pal_only);
+        assert!(header.expansion.vrc6 && header.expansion.namco163 && !header.expansion.fds);
+        assert!(!header.is_bankswitched());
+        assert_eq!(header.play_period(), 35795);
+        assert_eq!(data, vec![1, 2, 3]);
+        assert!(parse_nsf(&[0; 16]).is_err());
+    }
+
+    #[test]
+    pub fn test_parse_nsfe() {
+        let mut raw = NSFE_MAGIC.to_vec();
+        raw.extend(chunk(
+            b"INFO",
+            &[0x00, 0x80, 0x00, 0x80, 0x03, 0x80, 0, 0x20, 2, 1],
+        ));
+        raw.extend(chunk(b"BANK", &[0, 1]));
+        raw.extend(chunk(b"RATE", &[0x1A, 0x41]));
+        raw.extend(chunk(b"DATA", &[1, 2, 3]));
+        raw.extend(chunk(b"auth", b"Title\0Artist\0Copyright\0Ripper\0"));
+        raw.extend(chunk(b"tlbl", b"Intro\0\0"));
+        raw.extend(chunk(b"plst", &[1, 0]));
+        raw.extend(chunk(b"NEND", &[]));
+
+        let (header, data) = parse_nsf(&raw).unwrap();
+        assert_eq!(header.format, NsfFormat::Nsfe);
+        assert_eq!(header.track_count, 2);
+        assert_eq!(header.starting_track, 1);
+        assert_eq!(header.play_addr, 0x8003);
+        assert_eq!(header.initial_banks, [0, 1, 0, 0, 0, 0, 0, 0]);
+        assert!(header.expansion.sunsoft5b);
+        assert_eq!(header.ntsc_play_speed, 16666);
+        assert_eq!(header.pal_play_speed, DEFAULT_PAL_PLAY_SPEED);
+        assert_eq!(header.title, "Title");
+        assert_eq!(header.copyright, "Copyright");
+        assert_eq!(header.track_name(0), Some("Intro"));
+        assert_eq!(header.track_name(1), None);
+        assert_eq!(data, vec![1, 2, 3]);
+
+        // Unknown required chunks cannot be skipped.
+        let mut raw = NSFE_MAGIC.to_vec();
+        raw.extend(chunk(b"ABCD", &[]));
+        assert!(parse_nsf(&raw).is_err());
+    }
+
+    #[test]
+    pub fn test_bankswitching() {
+        // Create a mapper with the bank number in the first byte of each bank. The data is
+        // loaded at $8100, so it is preceded by $100 bytes of padding.
+        let mut data = vec![0; 4 * BANK_SIZE - 0x100];
+        for bank in 1..4 {
+            data[bank * BANK_SIZE - 0x100] = bank as u8;
+        }
+        let (header, data) =
+            parse_nsf(&test_nsf(0x8100, [3, 2, 1, 0, 0, 0, 0, 0], 0, &data)).unwrap();
+        let mut mapper = NsfMapper::new(&header, &data).unwrap();
+        assert_eq!(mapper.current_track(), Some(1));
+        assert_eq!(mapper.cpu_bus_peek(0x8000), Some(3));
+        assert_eq!(mapper.cpu_bus_peek(0x9000), Some(2));
+        assert_eq!(mapper.cpu_bus_peek(0xA000), Some(1));
+        mapper.cpu_bus_write(0x5FFA, 2).unwrap();
+        assert_eq!(mapper.cpu_bus_peek(0xA000), Some(2));
+
+        // The interrupt vectors point to the driver.
+        assert_eq!(mapper.cpu_bus_peek(0xFFFC), Some(0x00));
+        assert_eq!(mapper.cpu_bus_peek(0xFFFD), Some(0x41));
+        assert_eq!(mapper.cpu_bus_peek(DRIVER_ADDR), Some(0x78));
+
+        // Selecting a track restores the initial banks and clears the RAM.
+        mapper.cpu_bus_write(0x6000, 0x42).unwrap();
+        mapper.select_track(2);
+        assert_eq!(mapper.cpu_bus_peek(0xA000), Some(1));
+        assert_eq!(mapper.cpu_bus_peek(0x6000), Some(0));
+        assert_eq!(mapper.cpu_bus_peek(DRIVER_ADDR + 0x37), Some(2));
+    }
+
+    #[test]
+    pub fn test_fds_ram() {
+        let (header, data) = parse_nsf(&test_nsf(0x6000, [0; 8], 0x04, &[0x42])).unwrap();
+        let mut mapper = NsfMapper::new(&header, &data).unwrap();
+        assert_eq!(mapper.cpu_bus_peek(0x6000), Some(0x42));
+        mapper.cpu_bus_write(0x8000, 0x17).unwrap();
+        assert_eq!(mapper.cpu_bus_peek(0x8000), Some(0x17));
+
+        // Switching banks copies them into RAM.
+        mapper.cpu_bus_write(0x5FF8, 0).unwrap();
+        assert_eq!(mapper.cpu_bus_peek(0x8000), Some(0x42));
+    }
+
+    #[test]
+    pub fn test_timer_irq() {
+        let (header, data) = parse_nsf(&test_nsf(0x8000, [0; 8], 0, &[])).unwrap();
+        let mut mapper = NsfMapper::new(&header, &data).unwrap();
+        for _ in 0..header.play_period() * 2 {
+            mapper.cpu_tick();
+        }
+        assert!(!mapper.irq_active());
+
+        mapper.cpu_bus_write(TIMER_REGISTER, 0).unwrap();
+        for _ in 0..header.play_period() - 1 {
+            mapper.cpu_tick();
+        }
+        assert!(!mapper.irq_active());
+        mapper.cpu_tick();
+        assert!(mapper.irq_active());
+        mapper.cpu_bus_write(TIMER_REGISTER, 0).unwrap();
+        assert!(!mapper.irq_active());
+    }
+
+    #[test]
+    pub fn test_expansion_audio() {
+        let (header, data) = parse_nsf(&test_nsf(0x8000, [0; 8], 0x01, &[])).unwrap();
+        let mut mapper = NsfMapper::new(&header, &data).unwrap();
+        assert_eq!(mapper.audio_output(), 0.0);
+        // VRC6 pulse 1 at full volume with a duty cycle of 100%.
+        mapper.cpu_bus_write(0x9000, 0x8F).unwrap();
+        mapper.cpu_bus_write(0x9002, 0x80).unwrap();
+        assert!((mapper.audio_output() - 0.1128).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,218 @@
+use std::sync::RwLock;
+
+use anyhow::anyhow;
+use anyhow::Result;
+use bincode::error::DecodeError;
+use bincode::error::EncodeError;
+use bincode::Decode;
+use bincode::Encode;
+use lazy_static::lazy_static;
+
+use super::InesHeader;
+use super::Mapper;
+
+/// The contents of a ROM file that a mapper is created from.
+pub struct RomData<'a> {
+    pub header: &'a InesHeader,
+    pub prg: &'a [u8],
+    pub chr: &'a [u8],
+    /// Battery backed RAM saved from a previous session, or the trainer.
+    pub persistent_data: Option<&'a [u8]>,
+}
+
+pub type DecodeMapperResult = std::result::Result<Box<dyn Mapper>, DecodeError>;
+
+/// Describes how to create a mapper and how to restore it from a save state.
+#[derive(Clone)]
+pub struct MapperRegistration {
+    /// Unique name of the mapper, which identifies its state in save states.
+    pub tag: &'static str,
+    /// iNES mapper numbers handled by this mapper.
+    pub mapper_numbers: &'static [u16],
+    pub create: fn(&RomData) -> Result<Box<dyn Mapper>>,
+    /// Restores a mapper from the state encoded by `MapperState::encode_state`. Usually
+    /// `decode_mapper::<T>`.
+    pub decode: fn(&[u8]) -> DecodeMapperResult,
+}
+
+lazy_static! {
+    static ref REGISTRY: RwLock<Vec<MapperRegistration>> = RwLock::new(super::builtin_mappers());
+}
+
+/// Registers a mapper that can be used by all cartridges loaded afterwards. Registrations
+/// take precedence over earlier ones for the same mapper number or tag, which allows
+/// replacing the built-in mappers.
+pub fn register_mapper(registration: MapperRegistration) {
+    REGISTRY.write().unwrap().push(registration);
+}
+
+pub(super) fn find_by_number(mapper: u16) -> Result<MapperRegistration> {
+    REGISTRY
+        .read()
+        .unwrap()
+        .iter()
+        .rev()
+        .find(|registration| registration.mapper_numbers.contains(&mapper))
+        .cloned()
+        .ok_or_else(|| anyhow!("Unsupported mapper {mapper}"))
+}
+
+fn find_by_tag(tag: &str) -> Option<MapperRegistration> {
+    REGISTRY
+        .read()
+        .unwrap()
+        .iter()
+        .rev()
+        .find(|registration| registration.tag == tag)
+        .cloned()
+}
+
+/// Decodes a mapper of type `T` from the state encoded by `MapperState::encode_state`.
+pub fn decode_mapper<T: Mapper + Decode + 'static>(data: &[u8]) -> DecodeMapperResult {
+    let (mapper, _) = bincode::decode_from_slice::<T, _>(data, bincode::config::standard())?;
+    Ok(Box::new(mapper))
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// MapperState
+
+/// Object-safe cloning and encoding of mappers. This is implemented for all mappers that
+/// implement `Encode` and `Clone`.
+pub trait MapperState {
+    fn clone_mapper(&self) -> Box<dyn Mapper>;
+    fn encode_state(&self) -> std::result::Result<Vec<u8>, EncodeError>;
+}
+
+impl<T: Mapper + Encode + Clone + 'static> MapperState for T {
+    fn clone_mapper(&self) -> Box<dyn Mapper> {
+        Box::new(self.clone())
+    }
+
+    fn encode_state(&self) -> std::result::Result<Vec<u8>, EncodeError> {
+        bincode::encode_to_vec(self, bincode::config::standard())
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// TaggedMapper
+
+/// A mapper together with the tag of its registration. It is encoded as the tag followed by
+/// the state of the mapper, so it can be decoded by the registered mapper again.
+pub(super) struct TaggedMapper {
+    pub tag: String,
+    pub mapper: Box<dyn Mapper>,
+}
+
+impl Clone for TaggedMapper {
+    fn clone(&self) -> Self {
+        TaggedMapper {
+            tag: self.tag.clone(),
+            mapper: self.mapper.clone_mapper(),
+        }
+    }
+}
+
+impl Encode for TaggedMapper {
+    fn encode<E: bincode::enc::Encoder>(
+        &self,
+        encoder: &mut E,
+    ) -> std::result::Result<(), EncodeError> {
+        self.tag.encode(encoder)?;
+        self.mapper.encode_state()?.encode(encoder)
+    }
+}
+
+impl Decode for TaggedMapper {
+    fn decode<D: bincode::de::Decoder>(decoder: &mut D) -> std::result::Result<Self, DecodeError> {
+        let tag = String::decode(decoder)?;
+        let state = Vec::<u8>::decode(decoder)?;
+        let registration = find_by_tag(&tag)
+            .ok_or_else(|| DecodeError::OtherString(format!("Unknown mapper {tag}")))?;
+        Ok(TaggedMapper {
+            mapper: (registration.decode)(&state)?,
+            tag,
+        })
+    }
+}
+
+bincode::impl_borrow_decode!(TaggedMapper);
+
+#[cfg(test)]
+mod tests {
+    use bincode::Decode;
+    use bincode::Encode;
+
+    use super::*;
+    use crate::cartridge::Cartridge;
+    use crate::cartridge::CartridgeResult;
+    use crate::cartridge::MirroringMode;
+
+    /// A board with a single register at $5000 that is mirrored into PRG-ROM reads.
+    #[derive(Encode, Decode, Clone, Default)]
+    struct TestMapper {
+        register: u8,
+    }
+
+    impl Mapper for TestMapper {
+        fn get_mirroring_mode(&self) -> MirroringMode {
+            MirroringMode::Vertical
+        }
+
+        fn persistent_data(&self) -> Vec<u8> {
+            vec![]
+        }
+
+        fn cpu_bus_peek(&self, addr: u16) -> Option<u8> {
+            (addr >= 0x8000).then_some(self.register)
+        }
+
+        fn cpu_bus_read(&mut self, addr: u16) -> CartridgeResult<u8> {
+            Ok(self.cpu_bus_peek(addr).unwrap_or_default())
+        }
+
+        fn cpu_bus_write(&mut self, _addr: u16, value: u8) -> CartridgeResult<()> {
+            self.register = value;
+            Ok(())
+        }
+
+        fn ppu_bus_peek(&self, _addr: u16) -> Option<u8> {
+            Some(0)
+        }
+
+        fn ppu_bus_read(&mut self, _addr: u16) -> CartridgeResult<u8> {
+            Ok(0)
+        }
+
+        fn ppu_bus_write(&mut self, _addr: u16, _value: u8) -> CartridgeResult<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    pub fn test_register_custom_mapper() {
+        // NES 2.0 header for mapper 3840 with 16k PRG-ROM.
+        let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x00, 0x00, 0x08, 0x0F];
+        rom.resize(16 + 16 * 1024, 0);
+
+        let mut cartridge = Cartridge::new();
+        assert!(cartridge.load_ines(&rom, None).is_err());
+
+        register_mapper(MapperRegistration {
+            tag: "test",
+            mapper_numbers: &[3840],
+            create: |_| Ok(Box::new(TestMapper { register: 0x42 })),
+            decode: decode_mapper::<TestMapper>,
+        });
+        cartridge.load_ines(&rom, None).unwrap();
+        assert_eq!(cartridge.cpu_bus_peek(0x8000), Some(0x42));
+        assert_eq!(cartridge.get_mirroring_mode(), MirroringMode::Vertical);
+
+        // The state of the mapper is restored from save states.
+        cartridge.cpu_bus_write(0x5000, 0x17).unwrap();
+        let state = bincode::encode_to_vec(&cartridge, bincode::config::standard()).unwrap();
+        let (restored, _): (Cartridge, usize) =
+            bincode::decode_from_slice(&state, bincode::config::standard()).unwrap();
+        assert_eq!(restored.cpu_bus_peek(0x8000), Some(0x17));
+        assert_eq!(restored.clone().cpu_bus_peek(0x8000), Some(0x17));
+    }
+}
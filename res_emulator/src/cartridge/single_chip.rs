@@ -0,0 +1,144 @@
+use bincode::Decode;
+use bincode::Encode;
+use intbits::Bits;
+
+use super::CartridgeError;
+use super::CartridgeResult;
+use super::Mapper;
+use super::MirroringMode;
+
+/// Single chip cartridges (mapper 218)
+///
+/// The board has no CHR memory. Instead the nametable RAM (CIRAM) of the console is enabled
+/// for the whole PPU address space and used for pattern tables as well as nametables. One of
+/// the PPU address lines A10-A13 is connected to CIRAM A10, which is selected by the mirroring
+/// and four-screen bits of the header. Since the CIRAM is shared between pattern tables and
+/// nametables, it is emulated here instead of using the internal VRAM of the PPU.
+#[derive(Encode, Decode, Clone)]
+pub struct SingleChipMapper {
+    pub prg: Vec<u8>,
+    pub ciram: Vec<u8>,
+    /// PPU address line connected to CIRAM A10.
+    pub ciram_a10_line: usize,
+}
+
+impl SingleChipMapper {
+    pub fn new(prg: &[u8], four_screen_bit: bool, mirroring_bit: bool) -> SingleChipMapper {
+        let ciram_a10_line = match (four_screen_bit, mirroring_bit) {
+            (false, false) => 11,
+            (false, true) => 10,
+            (true, false) => 12,
+            (true, true) => 13,
+        };
+        SingleChipMapper {
+            prg: prg.to_vec(),
+            ciram: vec![0; 2 * 1024],
+            ciram_a10_line,
+        }
+    }
+
+    fn get_ciram_index(&self, addr: u16) -> usize {
+        ((addr.bit(self.ciram_a10_line) as usize) << 10) | (addr as usize % 0x400)
+    }
+}
+
+impl Default for SingleChipMapper {
+    fn default() -> Self {
+        Self::new(&[], false, false)
+    }
+}
+
+impl Mapper for SingleChipMapper {
+    fn cpu_bus_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x8000..=0xFFFF if !self.prg.is_empty() => {
+                Some(self.prg[addr as usize % self.prg.len()])
+            }
+            _ => None,
+        }
+    }
+
+    fn cpu_bus_read(&mut self, addr: u16) -> CartridgeResult<u8> {
+        Ok(self.cpu_bus_peek(addr).unwrap_or_default())
+    }
+
+    fn cpu_bus_write(&mut self, addr: u16, _value: u8) -> CartridgeResult<()> {
+        Err(CartridgeError::InvalidWrite(addr))
+    }
+
+    fn ppu_bus_peek(&self, addr: u16) -> Option<u8> {
+        Some(self.ciram[self.get_ciram_index(addr)])
+    }
+
+    fn ppu_bus_read(&mut self, addr: u16) -> CartridgeResult<u8> {
+        self.ppu_bus_peek(addr)
+            .ok_or(CartridgeError::InvalidRead(addr))
+    }
+
+    fn ppu_bus_write(&mut self, addr: u16, value: u8) -> CartridgeResult<()> {
+        let idx = self.get_ciram_index(addr);
+        self.ciram[idx] = value;
+        Ok(())
+    }
+
+    fn nametable_peek(&self, addr: u16) -> Option<u8> {
+        self.ppu_bus_peek(addr)
+    }
+
+    fn nametable_write(&mut self, addr: u16, value: u8) -> bool {
+        let idx = self.get_ciram_index(addr);
+        self.ciram[idx] = value;
+        true
+    }
+
+    fn get_mirroring_mode(&self) -> MirroringMode {
+        // Nametables are at $2000-$2FFF, so A12 is always low and A13 always high.
+        match self.ciram_a10_line {
+            10 => MirroringMode::Vertical,
+            11 => MirroringMode::Horizontal,
+            12 => MirroringMode::SingleLower,
+            _ => MirroringMode::SingleUpper,
+        }
+    }
+
+    fn persistent_data(&self) -> Vec<u8> {
+        vec![]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SingleChipMapper;
+    use crate::cartridge::Mapper;
+    use crate::cartridge::MirroringMode;
+
+    #[test]
+    pub fn test_ciram_layouts() {
+        // Vertical: The pattern tables overlap with the nametables at the same A10.
+        let mut mapper = SingleChipMapper::new(&[], false, true);
+        mapper.ppu_bus_write(0x0400, 0x42).unwrap();
+        assert_eq!(mapper.nametable_peek(0x2400), Some(0x42));
+        assert_eq!(mapper.nametable_peek(0x2C00), Some(0x42));
+        assert_eq!(mapper.nametable_peek(0x2000), Some(0x00));
+        assert_eq!(mapper.get_mirroring_mode(), MirroringMode::Vertical);
+
+        // Horizontal: A11 selects the page.
+        let mut mapper = SingleChipMapper::new(&[], false, false);
+        assert!(mapper.nametable_write(0x2800, 0x42));
+        assert_eq!(mapper.ppu_bus_peek(0x0800), Some(0x42));
+        assert_eq!(mapper.ppu_bus_peek(0x1C00), Some(0x42));
+
+        // A12: Nametables use the lower page, the upper pattern table the upper page.
+        let mut mapper = SingleChipMapper::new(&[], true, false);
+        assert!(mapper.nametable_write(0x2C00, 0x42));
+        assert_eq!(mapper.ppu_bus_peek(0x0000), Some(0x42));
+        assert_eq!(mapper.ppu_bus_peek(0x1000), Some(0x00));
+
+        // A13: Nametables use the upper page, the pattern tables the lower page.
+        let mut mapper = SingleChipMapper::new(&[], true, true);
+        assert!(mapper.nametable_write(0x2000, 0x42));
+        assert_eq!(mapper.ppu_bus_peek(0x0000), Some(0x00));
+        assert_eq!(mapper.ciram[0x400], 0x42);
+        assert_eq!(mapper.get_mirroring_mode(), MirroringMode::SingleUpper);
+    }
+}
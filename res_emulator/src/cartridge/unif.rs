@@ -0,0 +1,212 @@
+use anyhow::anyhow;
+use anyhow::Result;
+
+use super::HeaderFormat;
+use super::InesHeader;
+use super::MirroringMode;
+
+const UNIF_MAGIC: &[u8] = b"UNIF";
+const UNIF_HEADER_SIZE: usize = 32;
+
+/// Prefixes of board names, which are not needed to identify the board.
+const BOARD_PREFIXES: &[&str] = &["NES-", "HVC-", "UNL-", "BMC-", "BTL-", "AVE-"];
+
+/// iNES mapper and submapper of the supported UNIF boards.
+const UNIF_BOARDS: &[(&str, u16, u8)] = &[
+    ("NROM", 0, 0),
+    ("NROM-128", 0, 0),
+    ("NROM-256", 0, 0),
+    ("SAROM", 1, 0),
+    ("SBROM", 1, 0),
+    ("SCROM", 1, 0),
+    ("SEROM", 1, 0),
+    ("SGROM", 1, 0),
+    ("SKROM", 1, 0),
+    ("SLROM", 1, 0),
+    ("SL1ROM", 1, 0),
+    ("SNROM", 1, 0),
+    ("SOROM", 1, 0),
+    ("SUROM", 1, 0),
+    ("SXROM", 1, 0),
+    ("UNROM", 2, 0),
+    ("UOROM", 2, 0),
+    ("CNROM", 3, 0),
+    ("TBROM", 4, 0),
+    ("TEROM", 4, 0),
+    ("TFROM", 4, 0),
+    ("TGROM", 4, 0),
+    ("TKROM", 4, 0),
+    ("TLROM", 4, 0),
+    ("TL1ROM", 4, 0),
+    ("TNROM", 4, 0),
+    ("TR1ROM", 4, 0),
+    ("TSROM", 4, 0),
+    ("TVROM", 4, 0),
+    ("EKROM", 5, 0),
+    ("ELROM", 5, 0),
+    ("ETROM", 5, 0),
+    ("EWROM", 5, 0),
+    ("AMROM", 7, 2),
+    ("ANROM", 7, 1),
+    ("AN1ROM", 7, 1),
+    ("AOROM", 7, 1),
+    ("PNROM", 9, 0),
+    ("PEEOROM", 9, 0),
+    ("FJROM", 10, 0),
+    ("FKROM", 10, 0),
+    ("BNROM", 34, 2),
+    ("NINA-001", 34, 1),
+    ("NINA-01", 34, 1),
+    ("GNROM", 66, 0),
+    ("MHROM", 66, 0),
+    ("JLROM", 69, 0),
+    ("JSROM", 69, 0),
+    ("BTR", 69, 0),
+];
+
+/// Returns true if the file is in UNIF format.
+pub fn is_unif(raw: &[u8]) -> bool {
+    raw.starts_with(UNIF_MAGIC)
+}
+
+/// Returns the iNES mapper and submapper of a UNIF board name.
+fn board_mapper(board: &str) -> Option<(u16, u8)> {
+    let name = BOARD_PREFIXES
+        .iter()
+        .find_map(|prefix| board.strip_prefix(prefix))
+        .unwrap_or(board);
+    UNIF_BOARDS
+        .iter()
+        .find(|(board_name, _, _)| board_name.eq_ignore_ascii_case(name))
+        .map(|(_, mapper, submapper)| (*mapper, *submapper))
+}
+
+/// Contents of a UNIF file. The board is described by an iNES header for the mapper that
+/// implements it. See https://www.nesdev.org/wiki/UNIF
+pub struct UnifRom {
+    pub header: InesHeader,
+    pub prg: Vec<u8>,
+    pub chr: Vec<u8>,
+}
+
+/// Parses the chunks of a UNIF file. The PRG and CHR chunks are concatenated in the order of
+/// their numbers, chunks that are not needed for emulation are skipped.
+pub fn parse_unif(raw: &[u8]) -> Result<UnifRom> {
+    if !is_unif(raw) || raw.len() < UNIF_HEADER_SIZE {
+        return Err(anyhow!("Expected UNIF file."));
+    }
+    let mut board = None;
+    let mut prg_chunks: [&[u8]; 16] = Default::default();
+    let mut chr_chunks: [&[u8]; 16] = Default::default();
+    let mut mirroring = None;
+    let mut has_battery = false;
+
+    let mut data = &raw[UNIF_HEADER_SIZE..];
+    while data.len() >= 8 {
+        let id = &data[0..4];
+        let length = u32::from_le_bytes(data[4..8].try_into().unwrap()) as usize;
+        let chunk = data
+            .get(8..8 + length)
+            .ok_or_else(|| anyhow!("UNIF chunk {} is truncated", String::from_utf8_lossy(id)))?;
+        match id {
+            b"MAPR" => {
+                let end = chunk.iter().position(|b| *b == 0).unwrap_or(chunk.len());
+                board = Some(String::from_utf8_lossy(&chunk[..end]).to_string());
+            }
+            b"MIRR" => mirroring = chunk.first().copied(),
+            b"BATR" => has_battery = true,
+            [b'P', b'R', b'G', index] | [b'C', b'H', b'R', index] => {
+                if let Some(index) = (*index as char).to_digit(16) {
+                    let chunks = if id[0] == b'P' {
+                        &mut prg_chunks
+                    } else {
+                        &mut chr_chunks
+                    };
+                    chunks[index as usize] = chunk;
+                }
+            }
+            _ => (),
+        }
+        data = &data[8 + length..];
+    }
+
+    let board = board.ok_or_else(|| anyhow!("UNIF file is missing the MAPR chunk"))?;
+    let (mapper, submapper) =
+        board_mapper(&board).ok_or_else(|| anyhow!("Unsupported UNIF board {board}"))?;
+    let prg = prg_chunks.concat();
+    let chr = chr_chunks.concat();
+    if prg.is_empty() {
+        return Err(anyhow!("UNIF file does not contain PRG-ROM"));
+    }
+
+    // Mirroring 5 is controlled by the mapper.
+    let mirroring = match mirroring {
+        Some(1) => MirroringMode::Vertical,
+        Some(2) => MirroringMode::SingleLower,
+        Some(3) => MirroringMode::SingleUpper,
+        Some(4) => MirroringMode::FourScreen,
+        _ => MirroringMode::Horizontal,
+    };
+    // UNIF does not describe the RAM of the board, so the iNES 1.0 defaults are used.
+    let header = InesHeader {
+        format: HeaderFormat::Unif,
+        mapper,
+        submapper,
+        prg_rom_size: prg.len(),
+        chr_rom_size: chr.len(),
+        prg_ram_size: if has_battery { 0 } else { 8 * 1024 },
+        prg_nvram_size: if has_battery { 8 * 1024 } else { 0 },
+        chr_ram_size: if chr.is_empty() { 8 * 1024 } else { 0 },
+        mirroring,
+        mirroring_bit: mirroring == MirroringMode::Vertical,
+        has_battery,
+        ..Default::default()
+    };
+    Ok(UnifRom { header, prg, chr })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(id: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut chunk = id.to_vec();
+        chunk.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        chunk.extend_from_slice(data);
+        chunk
+    }
+
+    #[test]
+    pub fn test_parse_unif() {
+        let mut raw = UNIF_MAGIC.to_vec();
+        raw.extend_from_slice(&7_u32.to_le_bytes());
+        raw.resize(UNIF_HEADER_SIZE, 0);
+        raw.extend(chunk(b"MAPR", b"NES-TLROM\0"));
+        raw.extend(chunk(b"NAME", b"Test\0"));
+        raw.extend(chunk(b"PRG1", &[2; 16]));
+        raw.extend(chunk(b"PRG0", &[1; 16]));
+        raw.extend(chunk(b"CHR0", &[3; 8]));
+        raw.extend(chunk(b"MIRR", &[1]));
+        raw.extend(chunk(b"BATR", &[0]));
+
+        let rom = parse_unif(&raw).unwrap();
+        assert_eq!(rom.prg, [[1; 16], [2; 16]].concat());
+        assert_eq!(rom.chr, vec![3; 8]);
+        assert_eq!(rom.header.format, HeaderFormat::Unif);
+        assert_eq!(rom.header.mapper, 4);
+        assert_eq!(rom.header.prg_rom_size, 32);
+        assert_eq!(rom.header.chr_ram_size, 0);
+        assert_eq!(rom.header.prg_nvram_size, 8 * 1024);
+        assert_eq!(rom.header.mirroring, MirroringMode::Vertical);
+        assert!(rom.header.has_battery);
+    }
+
+    #[test]
+    pub fn test_board_names() {
+        assert_eq!(board_mapper("NES-NROM-256"), Some((0, 0)));
+        assert_eq!(board_mapper("HVC-SNROM"), Some((1, 0)));
+        assert_eq!(board_mapper("AVE-NINA-01"), Some((34, 1)));
+        assert_eq!(board_mapper("UNL-BNROM"), Some((34, 2)));
+        assert_eq!(board_mapper("BMC-UNKNOWN"), None);
+    }
+}
@@ -0,0 +1,396 @@
+use bincode::Decode;
+use bincode::Encode;
+use intbits::Bits;
+
+use super::persistent_ram;
+use super::vrc_irq::VrcIrq;
+use super::CartridgeError;
+use super::CartridgeResult;
+use super::Mapper;
+use super::MirroringMode;
+
+const PRG_BANK_SIZE: usize = 8 * 1024;
+const CHR_BANK_SIZE: usize = 1024;
+
+#[derive(Encode, Decode, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum VrcChip {
+    /// No IRQ, single bit mirroring and the microwire latch at $6000-$6FFF.
+    Vrc2,
+    /// IRQ counter, PRG swap mode and one-screen mirroring.
+    Vrc4,
+}
+
+/// The VRC2 and VRC4 boards connect different CPU address lines to the A0 and A1 register
+/// select pins of the chip. This is described by a mask of the CPU address lines that select
+/// each pin.
+#[derive(Encode, Decode, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Vrc24Variant {
+    pub chip: VrcChip,
+    pub a0_lines: u16,
+    pub a1_lines: u16,
+    /// VRC2a ignores the lowest bit of the CHR bank registers.
+    pub chr_bank_shift: u8,
+}
+
+impl Vrc24Variant {
+    const fn new(chip: VrcChip, a0_lines: u16, a1_lines: u16) -> Vrc24Variant {
+        Vrc24Variant {
+            chip,
+            a0_lines,
+            a1_lines,
+            chr_bank_shift: 0,
+        }
+    }
+
+    /// Selects the variant from the mapper and NES 2.0 submapper numbers. Without a
+    /// submapper, a VRC4 that responds to the address lines of both possible boards is used,
+    /// which is compatible with the games of either board.
+    pub fn from_header(mapper: u16, submapper: u8) -> Vrc24Variant {
+        use VrcChip::*;
+        match (mapper, submapper) {
+            // VRC2a
+            (22, _) => Vrc24Variant {
+                chr_bank_shift: 1,
+                ..Vrc24Variant::new(Vrc2, 1 << 1, 1 << 0)
+            },
+            // VRC4a
+            (21, 1) => Vrc24Variant::new(Vrc4, 1 << 1, 1 << 2),
+            // VRC4c
+            (21, 2) => Vrc24Variant::new(Vrc4, 1 << 6, 1 << 7),
+            (21, _) => Vrc24Variant::new(Vrc4, 1 << 1 | 1 << 6, 1 << 2 | 1 << 7),
+            // VRC4f
+            (23, 1) => Vrc24Variant::new(Vrc4, 1 << 0, 1 << 1),
+            // VRC4e
+            (23, 2) => Vrc24Variant::new(Vrc4, 1 << 2, 1 << 3),
+            // VRC2b
+            (23, 3) => Vrc24Variant::new(Vrc2, 1 << 0, 1 << 1),
+            (23, _) => Vrc24Variant::new(Vrc4, 1 << 0 | 1 << 2, 1 << 1 | 1 << 3),
+            // VRC4b
+            (25, 1) => Vrc24Variant::new(Vrc4, 1 << 1, 1 << 0),
+            // VRC4d
+            (25, 2) => Vrc24Variant::new(Vrc4, 1 << 3, 1 << 2),
+            // VRC2c
+            (25, 3) => Vrc24Variant::new(Vrc2, 1 << 1, 1 << 0),
+            _ => Vrc24Variant::new(Vrc4, 1 << 1 | 1 << 3, 1 << 0 | 1 << 2),
+        }
+    }
+}
+
+/// Konami VRC2 and VRC4 (mappers 21, 22, 23 and 25)
+///
+/// Two switchable 8k PRG-ROM banks and eight 1k CHR-ROM banks. VRC4 adds the VRC IRQ counter
+/// and a mode to swap the switchable bank at $8000 with the fixed bank at $C000.
+#[derive(Encode, Decode, Clone)]
+pub struct Vrc24Mapper {
+    pub variant: Vrc24Variant,
+    pub prg: Vec<u8>,
+    pub chr: Vec<u8>,
+    pub ram: Vec<u8>,
+    pub has_ram: bool,
+
+    pub prg_banks: [u8; 2],
+    pub chr_banks: [u16; 8],
+    pub mirroring: u8,
+    pub prg_swap_mode: bool,
+    pub microwire_latch: u8,
+    pub irq: VrcIrq,
+}
+
+impl Vrc24Mapper {
+    const RAM_SIZE: usize = 8 * 1024;
+
+    /// Boards without PRG-RAM have the VRC2 microwire latch at $6000-$6FFF instead.
+    pub fn new(
+        variant: Vrc24Variant,
+        prg: &[u8],
+        chr: &[u8],
+        has_ram: bool,
+        persistent_data: Option<&[u8]>,
+    ) -> Vrc24Mapper {
+        Vrc24Mapper {
+            variant,
+            prg: prg.to_vec(),
+            chr: if chr.is_empty() {
+                vec![0; 8 * 1024]
+            } else {
+                chr.to_vec()
+            },
+            ram: persistent_ram(persistent_data, Vrc24Mapper::RAM_SIZE),
+            has_ram,
+            prg_banks: [0, 0],
+            chr_banks: [0; 8],
+            mirroring: 0,
+            prg_swap_mode: false,
+            microwire_latch: 0,
+            irq: VrcIrq::default(),
+        }
+    }
+
+    fn get_prg_index(&self, addr: u16) -> usize {
+        let bank_count = (self.prg.len() / PRG_BANK_SIZE).max(1);
+        let bank = match (addr, self.prg_swap_mode) {
+            (0x8000..=0x9FFF, false) | (0xC000..=0xDFFF, true) => self.prg_banks[0] as usize,
+            (0xA000..=0xBFFF, _) => self.prg_banks[1] as usize,
+            (0x8000..=0x9FFF, true) | (0xC000..=0xDFFF, false) => bank_count - 2,
+            _ => bank_count - 1,
+        };
+        (bank % bank_count) * PRG_BANK_SIZE + addr as usize % PRG_BANK_SIZE
+    }
+
+    fn get_chr_index(&self, addr: u16) -> usize {
+        let bank_count = (self.chr.len() / CHR_BANK_SIZE).max(1);
+        let bank = self.chr_banks[addr as usize / CHR_BANK_SIZE] >> self.variant.chr_bank_shift;
+        (bank as usize % bank_count) * CHR_BANK_SIZE + addr as usize % CHR_BANK_SIZE
+    }
+
+    /// Maps the address to the register it selects, based on the address lines connected to
+    /// the A0 and A1 pins of the chip.
+    fn register_addr(&self, addr: u16) -> u16 {
+        let a0 = addr & self.variant.a0_lines != 0;
+        let a1 = addr & self.variant.a1_lines != 0;
+        (addr & 0xF000) | (a1 as u16) << 1 | a0 as u16
+    }
+
+    fn is_vrc4(&self) -> bool {
+        self.variant.chip == VrcChip::Vrc4
+    }
+
+    fn microwire_latch_enabled(&self, addr: u16) -> bool {
+        !self.is_vrc4() && !self.has_ram && addr < 0x7000
+    }
+}
+
+impl Default for Vrc24Mapper {
+    fn default() -> Self {
+        Self::new(Vrc24Variant::from_header(21, 0), &[], &[], true, None)
+    }
+}
+
+impl Mapper for Vrc24Mapper {
+    fn cpu_bus_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7FFF if self.microwire_latch_enabled(addr) => Some(self.microwire_latch),
+            0x6000..=0x7FFF if self.has_ram => {
+                Some(self.ram[(addr as usize - 0x6000) % Vrc24Mapper::RAM_SIZE])
+            }
+            0x8000..=0xFFFF => self.prg.get(self.get_prg_index(addr)).copied(),
+            _ => None,
+        }
+    }
+
+    fn cpu_bus_read(&mut self, addr: u16) -> CartridgeResult<u8> {
+        Ok(self.cpu_bus_peek(addr).unwrap_or_default())
+    }
+
+    fn cpu_bus_write(&mut self, addr: u16, value: u8) -> CartridgeResult<()> {
+        match addr {
+            0x6000..=0x7FFF => {
+                if self.microwire_latch_enabled(addr) {
+                    self.microwire_latch = value.bits(0..=0);
+                } else if self.has_ram {
+                    self.ram[(addr as usize - 0x6000) % Vrc24Mapper::RAM_SIZE] = value;
+                }
+            }
+            0x8000..=0xFFFF => {
+                let register = self.register_addr(addr);
+                match register {
+                    0x8000..=0x8003 => self.prg_banks[0] = value.bits(0..=4),
+                    0x9000..=0x9003 if !self.is_vrc4() => self.mirroring = value.bits(0..=0),
+                    0x9000..=0x9001 => self.mirroring = value.bits(0..=1),
+                    0x9002..=0x9003 => self.prg_swap_mode = value.bit(1),
+                    0xA000..=0xA003 => self.prg_banks[1] = value.bits(0..=4),
+                    0xB000..=0xE003 => {
+                        // Each bank is written in two halves, the low 4 bits first.
+                        let idx =
+                            (register as usize - 0xB000) / 0x1000 * 2 + register.bit(1) as usize;
+                        let bank = &mut self.chr_banks[idx];
+                        if register.bit(0) {
+                            *bank = (*bank & 0x00F) | (value.bits(0..=4) as u16) << 4;
+                        } else {
+                            *bank = (*bank & 0x1F0) | value.bits(0..=3) as u16;
+                        }
+                    }
+                    0xF000 if self.is_vrc4() => self.irq.write_latch_low(value),
+                    0xF001 if self.is_vrc4() => self.irq.write_latch_high(value),
+                    0xF002 if self.is_vrc4() => self.irq.write_control(value),
+                    0xF003 if self.is_vrc4() => self.irq.acknowledge(),
+                    _ => (),
+                }
+            }
+            _ => return Err(CartridgeError::InvalidWrite(addr)),
+        }
+        Ok(())
+    }
+
+    fn ppu_bus_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x0000..=0x1FFF => Some(self.chr[self.get_chr_index(addr)]),
+            _ => None,
+        }
+    }
+
+    fn ppu_bus_read(&mut self, addr: u16) -> CartridgeResult<u8> {
+        self.ppu_bus_peek(addr)
+            .ok_or(CartridgeError::InvalidRead(addr))
+    }
+
+    fn ppu_bus_write(&mut self, _addr: u16, _value: u8) -> CartridgeResult<()> {
+        // Some games will try to write to character ROM and expect it to NOOP.
+        Ok(())
+    }
+
+    fn irq_active(&self) -> bool {
+        self.irq.pending
+    }
+
+    fn cpu_tick(&mut self) {
+        self.irq.tick();
+    }
+
+    fn get_mirroring_mode(&self) -> MirroringMode {
+        match self.mirroring {
+            0 => MirroringMode::Vertical,
+            1 => MirroringMode::Horizontal,
+            2 => MirroringMode::SingleLower,
+            _ => MirroringMode::SingleUpper,
+        }
+    }
+
+    fn persistent_data(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn load_persistent_data(&mut self, data: &[u8]) {
+        if data.len() == self.ram.len() {
+            self.ram.copy_from_slice(data);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Vrc24Mapper;
+    use super::Vrc24Variant;
+    use super::VrcChip;
+    use super::CHR_BANK_SIZE;
+    use super::PRG_BANK_SIZE;
+    use crate::cartridge::Mapper;
+    use crate::cartridge::MirroringMode;
+
+    fn test_mapper(variant: Vrc24Variant, has_ram: bool) -> Vrc24Mapper {
+        // Create a mapper with the bank number in the first byte of each bank to allow us to
+        // identify them after mapping.
+        let mut prg = vec![0x00_u8; 16 * PRG_BANK_SIZE];
+        for bank in 0..16 {
+            prg[bank * PRG_BANK_SIZE] = bank as u8;
+        }
+        let mut chr = vec![0x00_u8; 32 * CHR_BANK_SIZE];
+        for bank in 0..32 {
+            chr[bank * CHR_BANK_SIZE] = bank as u8;
+        }
+        Vrc24Mapper::new(variant, &prg, &chr, has_ram, None)
+    }
+
+    #[test]
+    pub fn test_variant_selection() {
+        assert_eq!(Vrc24Variant::from_header(22, 0).chip, VrcChip::Vrc2);
+        assert_eq!(Vrc24Variant::from_header(23, 3).chip, VrcChip::Vrc2);
+        assert_eq!(Vrc24Variant::from_header(25, 3).chip, VrcChip::Vrc2);
+        assert_eq!(Vrc24Variant::from_header(21, 0).chip, VrcChip::Vrc4);
+        assert_eq!(Vrc24Variant::from_header(25, 1).chip, VrcChip::Vrc4);
+    }
+
+    #[test]
+    pub fn test_prg_mapping() {
+        let mut mapper = test_mapper(Vrc24Variant::from_header(23, 1), true);
+        mapper.cpu_bus_write(0x8000, 3).unwrap();
+        mapper.cpu_bus_write(0xA000, 5).unwrap();
+        assert_eq!(mapper.cpu_bus_peek(0x8000), Some(3));
+        assert_eq!(mapper.cpu_bus_peek(0xA000), Some(5));
+        assert_eq!(mapper.cpu_bus_peek(0xC000), Some(14));
+        assert_eq!(mapper.cpu_bus_peek(0xE000), Some(15));
+
+        // Swap mode exchanges the banks at $8000 and $C000.
+        mapper.cpu_bus_write(0x9002, 0x02).unwrap();
+        assert_eq!(mapper.cpu_bus_peek(0x8000), Some(14));
+        assert_eq!(mapper.cpu_bus_peek(0xC000), Some(3));
+
+        mapper.cpu_bus_write(0x6000, 0x42).unwrap();
+        assert_eq!(mapper.cpu_bus_peek(0x6000), Some(0x42));
+    }
+
+    #[test]
+    pub fn test_address_line_decoding() {
+        // Each variant selects CHR register $B001 (bank 0, high bits) with a different address.
+        for (mapper_number, submapper, addr) in [
+            (21, 1, 0xB002),
+            (21, 2, 0xB040),
+            (21, 0, 0xB040),
+            (23, 1, 0xB001),
+            (23, 2, 0xB004),
+            (25, 1, 0xB002),
+            (25, 2, 0xB008),
+            (25, 0, 0xB002),
+        ] {
+            let mut mapper = test_mapper(Vrc24Variant::from_header(mapper_number, submapper), true);
+            mapper.cpu_bus_write(0xB000, 0x03).unwrap();
+            mapper.cpu_bus_write(addr, 0x01).unwrap();
+            assert_eq!(
+                mapper.chr_banks[0], 0x13,
+                "mapper {mapper_number} submapper {submapper}"
+            );
+        }
+    }
+
+    #[test]
+    pub fn test_chr_mapping_and_mirroring() {
+        let mut mapper = test_mapper(Vrc24Variant::from_header(25, 1), true);
+        // VRC4b connects A1 to A0 and A0 to A1, so $C001 selects the low bits of bank 3.
+        mapper.cpu_bus_write(0xC001, 0x07).unwrap();
+        mapper.cpu_bus_write(0xE001, 0x09).unwrap();
+        assert_eq!(mapper.ppu_bus_peek(0x0C00), Some(7));
+        assert_eq!(mapper.ppu_bus_peek(0x1C00), Some(9));
+
+        mapper.cpu_bus_write(0x9000, 0x03).unwrap();
+        assert_eq!(mapper.get_mirroring_mode(), MirroringMode::SingleUpper);
+
+        // VRC2a ignores the lowest bit of the bank number and has a single mirroring bit.
+        let mut mapper = test_mapper(Vrc24Variant::from_header(22, 0), true);
+        mapper.cpu_bus_write(0xB000, 0x07).unwrap();
+        assert_eq!(mapper.ppu_bus_peek(0x0000), Some(3));
+        mapper.cpu_bus_write(0x9000, 0x03).unwrap();
+        assert_eq!(mapper.get_mirroring_mode(), MirroringMode::Horizontal);
+    }
+
+    #[test]
+    pub fn test_irq() {
+        let mut mapper = test_mapper(Vrc24Variant::from_header(23, 1), true);
+        mapper.cpu_bus_write(0xF000, 0x0E).unwrap();
+        mapper.cpu_bus_write(0xF001, 0x0F).unwrap();
+        mapper.cpu_bus_write(0xF002, 0b110).unwrap();
+        mapper.cpu_tick();
+        assert!(!mapper.irq_active());
+        mapper.cpu_tick();
+        assert!(mapper.irq_active());
+        mapper.cpu_bus_write(0xF003, 0).unwrap();
+        assert!(!mapper.irq_active());
+
+        // VRC2 has no IRQ
+        let mut mapper = test_mapper(Vrc24Variant::from_header(23, 3), true);
+        mapper.cpu_bus_write(0xF000, 0x0F).unwrap();
+        mapper.cpu_bus_write(0xF001, 0x0F).unwrap();
+        mapper.cpu_bus_write(0xF002, 0b110).unwrap();
+        mapper.cpu_tick();
+        assert!(!mapper.irq_active());
+    }
+
+    #[test]
+    pub fn test_microwire_latch() {
+        let mut mapper = test_mapper(Vrc24Variant::from_header(23, 3), false);
+        mapper.cpu_bus_write(0x6000, 0xFF).unwrap();
+        assert_eq!(mapper.cpu_bus_peek(0x6000), Some(0x01));
+        mapper.cpu_bus_write(0x6100, 0xFE).unwrap();
+        assert_eq!(mapper.cpu_bus_peek(0x6000), Some(0x00));
+        assert_eq!(mapper.cpu_bus_peek(0x7000), None);
+    }
+}
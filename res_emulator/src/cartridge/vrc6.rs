@@ -0,0 +1,426 @@
+use bincode::Decode;
+use bincode::Encode;
+use intbits::Bits;
+
+use super::persistent_ram;
+use super::vrc_irq::VrcIrq;
+use super::CartridgeError;
+use super::CartridgeResult;
+use super::Mapper;
+use super::MirroringMode;
+
+const PRG_BANK_SIZE: usize = 8 * 1024;
+const CHR_BANK_SIZE: usize = 1024;
+
+/// The two VRC6 boards only differ in how the address lines are connected to the registers.
+#[derive(Encode, Decode, Clone, Copy, PartialEq, Eq)]
+pub enum Vrc6Variant {
+    /// Mapper 24
+    Vrc6a,
+    /// Mapper 26: A0 and A1 are swapped.
+    Vrc6b,
+}
+
+/// Konami VRC6 (mappers 24 and 26)
+///
+/// Switchable 16k and 8k PRG-ROM banks, eight 1k CHR-ROM banks, the VRC IRQ counter and
+/// expansion audio with two pulse channels and a sawtooth channel.
+#[derive(Encode, Decode, Clone)]
+pub struct Vrc6Mapper {
+    pub variant: Vrc6Variant,
+    pub prg: Vec<u8>,
+    pub chr: Vec<u8>,
+    pub ram: Vec<u8>,
+
+    pub prg_banks: [u8; 2],
+    pub chr_banks: [u8; 8],
+    pub banking_control: u8,
+    pub irq: VrcIrq,
+
+    pub frequency_control: u8,
+    pub pulse0: Vrc6Pulse,
+    pub pulse1: Vrc6Pulse,
+    pub sawtooth: Vrc6Sawtooth,
+}
+
+impl Vrc6Mapper {
+    const RAM_SIZE: usize = 8 * 1024;
+
+    pub fn new(
+        variant: Vrc6Variant,
+        prg: &[u8],
+        chr: &[u8],
+        persistent_data: Option<&[u8]>,
+    ) -> Vrc6Mapper {
+        Vrc6Mapper {
+            variant,
+            prg: prg.to_vec(),
+            chr: if chr.is_empty() {
+                vec![0; 8 * 1024]
+            } else {
+                chr.to_vec()
+            },
+            ram: persistent_ram(persistent_data, Vrc6Mapper::RAM_SIZE),
+            prg_banks: [0, 0],
+            chr_banks: [0; 8],
+            banking_control: 0,
+            irq: VrcIrq::default(),
+            frequency_control: 0,
+            pulse0: Vrc6Pulse::default(),
+            pulse1: Vrc6Pulse::default(),
+            sawtooth: Vrc6Sawtooth::default(),
+        }
+    }
+
+    fn get_prg_index(&self, addr: u16) -> usize {
+        let bank_count = (self.prg.len() / PRG_BANK_SIZE).max(1);
+        let bank = match addr {
+            0x8000..=0xBFFF => self.prg_banks[0] as usize * 2 + (addr as usize - 0x8000) / 0x2000,
+            0xC000..=0xDFFF => self.prg_banks[1] as usize,
+            _ => bank_count - 1,
+        };
+        (bank % bank_count) * PRG_BANK_SIZE + addr as usize % PRG_BANK_SIZE
+    }
+
+    fn get_chr_index(&self, addr: u16) -> usize {
+        let bank_count = (self.chr.len() / CHR_BANK_SIZE).max(1);
+        let bank = self.chr_banks[addr as usize / CHR_BANK_SIZE] as usize;
+        (bank % bank_count) * CHR_BANK_SIZE + addr as usize % CHR_BANK_SIZE
+    }
+
+    /// Maps the address to the register it selects, swapping A0 and A1 on VRC6b.
+    fn register_addr(&self, addr: u16) -> u16 {
+        let addr = addr & 0xF003;
+        match self.variant {
+            Vrc6Variant::Vrc6a => addr,
+            Vrc6Variant::Vrc6b => (addr & 0xF000) | (addr & 1) << 1 | (addr & 2) >> 1,
+        }
+    }
+
+    /// Shift applied to all channel periods by the frequency control register.
+    fn frequency_shift(&self) -> u16 {
+        if self.frequency_control.bit(2) {
+            8
+        } else if self.frequency_control.bit(1) {
+            4
+        } else {
+            0
+        }
+    }
+}
+
+impl Default for Vrc6Mapper {
+    fn default() -> Self {
+        Self::new(Vrc6Variant::Vrc6a, &[], &[], None)
+    }
+}
+
+impl Mapper for Vrc6Mapper {
+    fn cpu_bus_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7FFF if self.banking_control.bit(7) => {
+                Some(self.ram[(addr as usize - 0x6000) % Vrc6Mapper::RAM_SIZE])
+            }
+            0x8000..=0xFFFF => self.prg.get(self.get_prg_index(addr)).copied(),
+            _ => None,
+        }
+    }
+
+    fn cpu_bus_read(&mut self, addr: u16) -> CartridgeResult<u8> {
+        Ok(self.cpu_bus_peek(addr).unwrap_or_default())
+    }
+
+    fn cpu_bus_write(&mut self, addr: u16, value: u8) -> CartridgeResult<()> {
+        match addr {
+            0x6000..=0x7FFF => {
+                if self.banking_control.bit(7) {
+                    self.ram[(addr as usize - 0x6000) % Vrc6Mapper::RAM_SIZE] = value;
+                }
+            }
+            0x8000..=0xFFFF => {
+                let register = self.register_addr(addr);
+                let idx = register as usize & 0x3;
+                match register {
+                    0x8000..=0x8003 => self.prg_banks[0] = value.bits(0..=3),
+                    0x9000..=0x9002 => self.pulse0.write_register(idx, value),
+                    0x9003 => self.frequency_control = value,
+                    0xA000..=0xA002 => self.pulse1.write_register(idx, value),
+                    0xB000..=0xB002 => self.sawtooth.write_register(idx, value),
+                    0xB003 => self.banking_control = value,
+                    0xC000..=0xC003 => self.prg_banks[1] = value.bits(0..=4),
+                    0xD000..=0xD003 => self.chr_banks[idx] = value,
+                    0xE000..=0xE003 => self.chr_banks[4 + idx] = value,
+                    0xF000 => self.irq.write_latch(value),
+                    0xF001 => self.irq.write_control(value),
+                    0xF002 => self.irq.acknowledge(),
+                    _ => (),
+                }
+            }
+            _ => return Err(CartridgeError::InvalidWrite(addr)),
+        }
+        Ok(())
+    }
+
+    fn ppu_bus_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x0000..=0x1FFF => Some(self.chr[self.get_chr_index(addr)]),
+            _ => None,
+        }
+    }
+
+    fn ppu_bus_read(&mut self, addr: u16) -> CartridgeResult<u8> {
+        self.ppu_bus_peek(addr)
+            .ok_or(CartridgeError::InvalidRead(addr))
+    }
+
+    fn ppu_bus_write(&mut self, _addr: u16, _value: u8) -> CartridgeResult<()> {
+        // Some games will try to write to character ROM and expect it to NOOP.
+        Ok(())
+    }
+
+    fn irq_active(&self) -> bool {
+        self.irq.pending
+    }
+
+    fn cpu_tick(&mut self) {
+        self.irq.tick();
+        if !self.frequency_control.bit(0) {
+            let shift = self.frequency_shift();
+            self.pulse0.tick(shift);
+            self.pulse1.tick(shift);
+            self.sawtooth.tick(shift);
+        }
+    }
+
+    fn audio_output(&self) -> f32 {
+        // A pulse channel at full volume is about as loud as an APU pulse channel.
+        let output = self.pulse0.value() + self.pulse1.value() + self.sawtooth.value();
+        0.1128 / 15.0 * output as f32
+    }
+
+    fn get_mirroring_mode(&self) -> MirroringMode {
+        match self.banking_control.bits(2..=3) {
+            0 => MirroringMode::Vertical,
+            1 => MirroringMode::Horizontal,
+            2 => MirroringMode::SingleLower,
+            _ => MirroringMode::SingleUpper,
+        }
+    }
+
+    fn persistent_data(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+
+    fn load_persistent_data(&mut self, data: &[u8]) {
+        if data.len() == self.ram.len() {
+            self.ram.copy_from_slice(data);
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Vrc6Pulse
+
+#[derive(Encode, Decode, Clone, Default)]
+pub struct Vrc6Pulse {
+    pub control: u8,
+    pub period: u16,
+    pub enabled: bool,
+    timer: u16,
+    /// Counts down from 15 to 0. The output is high while the step is at or below the duty.
+    step: u8,
+}
+
+impl Vrc6Pulse {
+    fn write_register(&mut self, idx: usize, value: u8) {
+        match idx {
+            0 => self.control = value,
+            1 => self.period = (self.period & 0x0F00) | value as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | (value.bits(0..=3) as u16) << 8;
+                self.enabled = value.bit(7);
+                if !self.enabled {
+                    self.step = 15;
+                }
+            }
+        }
+    }
+
+    fn tick(&mut self, shift: u16) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer == 0 {
+            self.timer = self.period >> shift;
+            self.step = self.step.wrapping_sub(1) % 16;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    /// Output level from 0 to 15.
+    fn value(&self) -> u8 {
+        let ignore_duty = self.control.bit(7);
+        if self.enabled && (ignore_duty || self.step <= self.control.bits(4..=6)) {
+            self.control.bits(0..=3)
+        } else {
+            0
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Vrc6Sawtooth
+
+#[derive(Encode, Decode, Clone, Default)]
+pub struct Vrc6Sawtooth {
+    pub rate: u8,
+    pub period: u16,
+    pub enabled: bool,
+    timer: u16,
+    step: u8,
+    accumulator: u8,
+}
+
+impl Vrc6Sawtooth {
+    fn write_register(&mut self, idx: usize, value: u8) {
+        match idx {
+            0 => self.rate = value.bits(0..=5),
+            1 => self.period = (self.period & 0x0F00) | value as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | (value.bits(0..=3) as u16) << 8;
+                self.enabled = value.bit(7);
+                if !self.enabled {
+                    self.step = 0;
+                    self.accumulator = 0;
+                }
+            }
+        }
+    }
+
+    /// The rate is added to the accumulator on every second step. The accumulator is reset
+    /// after 7 additions.
+    fn tick(&mut self, shift: u16) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer == 0 {
+            self.timer = self.period >> shift;
+            self.step += 1;
+            if self.step == 14 {
+                self.step = 0;
+                self.accumulator = 0;
+            } else if self.step % 2 == 0 {
+                self.accumulator = self.accumulator.wrapping_add(self.rate);
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    /// Output level from 0 to 31.
+    fn value(&self) -> u8 {
+        self.accumulator >> 3
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Vrc6Mapper;
+    use super::Vrc6Variant;
+    use super::CHR_BANK_SIZE;
+    use super::PRG_BANK_SIZE;
+    use crate::cartridge::Mapper;
+    use crate::cartridge::MirroringMode;
+
+    fn test_mapper(variant: Vrc6Variant) -> Vrc6Mapper {
+        // Create a mapper with the bank number in the first byte of each bank to allow us to
+        // identify them after mapping.
+        let mut prg = vec![0x00_u8; 16 * PRG_BANK_SIZE];
+        for bank in 0..16 {
+            prg[bank * PRG_BANK_SIZE] = bank as u8;
+        }
+        let mut chr = vec![0x00_u8; 32 * CHR_BANK_SIZE];
+        for bank in 0..32 {
+            chr[bank * CHR_BANK_SIZE] = bank as u8;
+        }
+        Vrc6Mapper::new(variant, &prg, &chr, None)
+    }
+
+    #[test]
+    pub fn test_prg_mapping() {
+        let mut mapper = test_mapper(Vrc6Variant::Vrc6a);
+        mapper.cpu_bus_write(0x8000, 2).unwrap();
+        mapper.cpu_bus_write(0xC000, 7).unwrap();
+        assert_eq!(mapper.cpu_bus_peek(0x8000), Some(4));
+        assert_eq!(mapper.cpu_bus_peek(0xA000), Some(5));
+        assert_eq!(mapper.cpu_bus_peek(0xC000), Some(7));
+        assert_eq!(mapper.cpu_bus_peek(0xE000), Some(15));
+
+        // PRG-RAM needs to be enabled
+        mapper.cpu_bus_write(0x6000, 0x42).unwrap();
+        assert_eq!(mapper.cpu_bus_peek(0x6000), None);
+        mapper.cpu_bus_write(0xB003, 0x80).unwrap();
+        mapper.cpu_bus_write(0x6000, 0x42).unwrap();
+        assert_eq!(mapper.cpu_bus_peek(0x6000), Some(0x42));
+    }
+
+    #[test]
+    pub fn test_chr_mapping_and_mirroring() {
+        for (variant, swap) in [(Vrc6Variant::Vrc6a, 0), (Vrc6Variant::Vrc6b, 3)] {
+            let mut mapper = test_mapper(variant);
+            for register in 0..4 {
+                mapper
+                    .cpu_bus_write(0xD000 + register, register as u8)
+                    .unwrap();
+                mapper
+                    .cpu_bus_write(0xE000 + register, 4 + register as u8)
+                    .unwrap();
+            }
+            // On VRC6b, A0 and A1 are swapped, which switches registers 1 and 2.
+            assert_eq!(mapper.ppu_bus_peek(0x0000), Some(0));
+            assert_eq!(mapper.ppu_bus_peek(0x0400), Some(1 ^ swap));
+            assert_eq!(mapper.ppu_bus_peek(0x0800), Some(2 ^ swap));
+            assert_eq!(mapper.ppu_bus_peek(0x1C00), Some(7));
+
+            mapper.cpu_bus_write(0xB003, 0b0000_0100).unwrap();
+            assert_eq!(mapper.get_mirroring_mode(), MirroringMode::Horizontal);
+        }
+    }
+
+    #[test]
+    pub fn test_irq() {
+        let mut mapper = test_mapper(Vrc6Variant::Vrc6a);
+        mapper.cpu_bus_write(0xF000, 0xFE).unwrap();
+        mapper.cpu_bus_write(0xF001, 0b110).unwrap();
+        mapper.cpu_tick();
+        assert!(!mapper.irq_active());
+        mapper.cpu_tick();
+        assert!(mapper.irq_active());
+        mapper.cpu_bus_write(0xF002, 0).unwrap();
+        assert!(!mapper.irq_active());
+    }
+
+    #[test]
+    pub fn test_audio() {
+        let mut mapper = test_mapper(Vrc6Variant::Vrc6a);
+        assert_eq!(mapper.audio_output(), 0.0);
+
+        // Pulse in constant mode at full volume.
+        mapper.cpu_bus_write(0x9000, 0x8F).unwrap();
+        mapper.cpu_bus_write(0x9002, 0x80).unwrap();
+        assert!((mapper.audio_output() - 0.1128).abs() < 1e-6);
+
+        // Sawtooth ramps up and resets after 14 steps.
+        mapper.cpu_bus_write(0x9002, 0x00).unwrap();
+        mapper.cpu_bus_write(0xB000, 0x20).unwrap();
+        mapper.cpu_bus_write(0xB002, 0x80).unwrap();
+        let levels: Vec<u8> = (0..14)
+            .map(|_| {
+                mapper.cpu_tick();
+                mapper.sawtooth.value()
+            })
+            .collect();
+        assert_eq!(levels, [0, 4, 4, 8, 8, 12, 12, 16, 16, 20, 20, 24, 24, 0]);
+    }
+}
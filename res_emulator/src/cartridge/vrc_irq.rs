@@ -0,0 +1,119 @@
+use bincode::Decode;
+use bincode::Encode;
+use intbits::Bits;
+
+/// Number of PPU dots per scanline, which the prescaler counts down in steps of 3 per CPU
+/// cycle.
+const PRESCALER_PERIOD: i16 = 341;
+
+/// IRQ counter shared by the Konami VRC mappers.
+///
+/// The 8 bit counter counts up either every CPU cycle or every scanline, which is emulated
+/// by a prescaler dividing the CPU clock by 113.667. When it overflows, it is reloaded from
+/// the latch and an IRQ is raised.
+#[derive(Encode, Decode, Clone, Default)]
+pub struct VrcIrq {
+    pub latch: u8,
+    pub counter: u8,
+    pub prescaler: i16,
+    pub enabled: bool,
+    pub enable_after_ack: bool,
+    pub cycle_mode: bool,
+    pub pending: bool,
+}
+
+impl VrcIrq {
+    pub fn write_latch(&mut self, value: u8) {
+        self.latch = value;
+    }
+
+    /// VRC4 writes the latch in two 4 bit halves.
+    pub fn write_latch_low(&mut self, value: u8) {
+        self.latch = (self.latch & 0xF0) | value.bits(0..=3);
+    }
+
+    pub fn write_latch_high(&mut self, value: u8) {
+        self.latch = (self.latch & 0x0F) | value.bits(0..=3) << 4;
+    }
+
+    pub fn write_control(&mut self, value: u8) {
+        self.enable_after_ack = value.bit(0);
+        self.enabled = value.bit(1);
+        self.cycle_mode = value.bit(2);
+        self.pending = false;
+        if self.enabled {
+            self.counter = self.latch;
+            self.prescaler = PRESCALER_PERIOD;
+        }
+    }
+
+    pub fn acknowledge(&mut self) {
+        self.pending = false;
+        self.enabled = self.enable_after_ack;
+    }
+
+    /// Called once per CPU cycle.
+    pub fn tick(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        if self.cycle_mode {
+            self.clock_counter();
+        } else {
+            self.prescaler -= 3;
+            if self.prescaler <= 0 {
+                self.prescaler += PRESCALER_PERIOD;
+                self.clock_counter();
+            }
+        }
+    }
+
+    fn clock_counter(&mut self) {
+        if self.counter == 0xFF {
+            self.counter = self.latch;
+            self.pending = true;
+        } else {
+            self.counter += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::VrcIrq;
+
+    #[test]
+    pub fn test_cycle_mode() {
+        let mut irq = VrcIrq::default();
+        irq.write_latch(0xFD);
+        irq.write_control(0b111);
+        irq.tick();
+        irq.tick();
+        assert!(!irq.pending);
+        irq.tick();
+        assert!(irq.pending);
+        assert_eq!(irq.counter, 0xFD);
+
+        // Acknowledging copies the enable-after-acknowledge bit.
+        irq.acknowledge();
+        assert!(!irq.pending);
+        assert!(irq.enabled);
+    }
+
+    #[test]
+    pub fn test_scanline_mode() {
+        let mut irq = VrcIrq::default();
+        irq.write_latch(0xFE);
+        irq.write_control(0b010);
+        // Two scanlines take 2 * 341 / 3 = 227.3 CPU cycles.
+        for _ in 0..227 {
+            irq.tick();
+        }
+        assert!(!irq.pending);
+        irq.tick();
+        assert!(irq.pending);
+
+        irq.acknowledge();
+        assert!(!irq.enabled);
+    }
+}
@@ -11,6 +11,7 @@ pub use operations::Operation;
 use packed_struct::prelude::*;
 
 use super::apu::Apu;
+use super::apu::DMA_STALL_CYCLES;
 use super::cartridge::Cartridge;
 use super::debugger::Debugger;
 use super::debugger::MemoryAccess;
@@ -23,6 +24,8 @@ use super::ppu::Ppu;
 pub trait CpuBus {
     fn advance_clock(&mut self, cpu_cycles: usize) -> Result<()>;
     fn poll_nmi_interrupt(&mut self) -> bool;
+    /// Returns the current state of the level-triggered IRQ line.
+    fn irq_line(&self) -> IrqLine;
     fn peek(&self, addr: u16) -> Option<u8>;
     fn read(&mut self, addr: u16) -> Result<u8>;
     fn write(&mut self, addr: u16, value: u8) -> Result<()>;
@@ -48,7 +51,10 @@ pub trait CpuBus {
     }
 
     fn read_u16(&mut self, addr: u16) -> Result<u16> {
-        Ok(u16::from_le_bytes([self.read(addr)?, self.read(addr + 1)?]))
+        Ok(u16::from_le_bytes([
+            self.read(addr)?,
+            self.read(addr.wrapping_add(1))?,
+        ]))
     }
 
     fn zero_page_read(&mut self, addr: u8) -> Result<u8> {
@@ -63,6 +69,45 @@ pub trait CpuBus {
     }
 }
 
+////////////////////////////////////////////////////////////////////////////////
+// IrqLine
+
+/// Devices that can pull the shared IRQ line low.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum IrqSource {
+    ApuFrameCounter = 0b0001,
+    ApuDmc = 0b0010,
+    Cartridge = 0b0100,
+}
+
+/// The IRQ line of the CPU is wired-or: It stays asserted as long as at least one source is
+/// asserting it.
+#[derive(Encode, Decode, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct IrqLine {
+    sources: u8,
+}
+
+impl IrqLine {
+    pub fn set(&mut self, source: IrqSource, asserted: bool) {
+        if asserted {
+            self.sources |= source as u8;
+        } else {
+            self.sources &= !(source as u8);
+        }
+    }
+
+    pub fn is_asserted(&self) -> bool {
+        self.sources != 0
+    }
+
+    pub fn is_asserted_by(&self, source: IrqSource) -> bool {
+        self.sources & source as u8 != 0
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// ResCpuBus
+
 #[derive(Encode, Decode, Clone)]
 pub struct ResCpuBus {
     pub ram: Vec<u8>,
@@ -73,6 +118,9 @@ pub struct ResCpuBus {
     pub joypad1: Joypad,
     pub debugger: Rc<RefCell<Debugger>>,
     pub cycle: usize,
+    pub irq: IrqLine,
+    /// CPU cycles spent on DMA that have not been accounted for by the CPU yet.
+    pub dma_stall_cycles: usize,
 }
 
 impl ResCpuBus {
@@ -87,20 +135,60 @@ impl ResCpuBus {
             joypad0: Joypad::default(),
             joypad1: Joypad::default(),
             cycle: 0,
+            irq: IrqLine::default(),
+            dma_stall_cycles: 0,
         }
     }
 
+    /// The DMC reads sample bytes from the CPU bus, halting the CPU while doing so.
+    fn dmc_dma(&mut self, addr: u16) -> Result<()> {
+        let value = self.read(addr)?;
+        self.apu.dmc.load_sample_byte(value);
+        // DMA requests are only checked by `advance_clock`, so the stall cannot start another.
+        for _ in 0..DMA_STALL_CYCLES {
+            self.tick()?;
+        }
+        self.dma_stall_cycles += DMA_STALL_CYCLES;
+        Ok(())
+    }
+
+    /// Clocks all devices on the bus by one CPU cycle.
+    fn tick(&mut self) -> Result<()> {
+        {
+            let mut cartridge = self.cartridge.borrow_mut();
+            cartridge.cpu_tick();
+            self.apu.expansion_audio = cartridge.audio_output();
+        }
+        self.apu.advance_clock(1)?;
+        self.ppu.advance_clock(3)?;
+        self.cycle += 1;
+        Ok(())
+    }
+
     /// Peeks at a range of bytes from the bus
     pub fn peek_slice(&self, addr: u16, length: u16) -> impl Iterator<Item = Option<u8>> + '_ {
         (addr..(addr + length)).map(|addr| self.peek(addr))
     }
 }
 
+/// The 8 PPU registers are mirrored throughout $2000-$3FFF.
+fn ppu_register(addr: u16) -> u16 {
+    0x2000 | (addr & 0x0007)
+}
+
 impl CpuBus for ResCpuBus {
     fn advance_clock(&mut self, cpu_cycles: usize) -> Result<()> {
-        self.apu.advance_clock(cpu_cycles)?;
-        self.ppu.advance_clock(cpu_cycles * 3)?;
-        self.cycle += cpu_cycles;
+        for _ in 0..cpu_cycles {
+            self.tick()?;
+            if let Some(addr) = self.apu.dmc.dma_request() {
+                self.dmc_dma(addr)?;
+            }
+        }
+        self.irq
+            .set(IrqSource::Cartridge, self.cartridge.borrow().irq_active());
+        self.irq
+            .set(IrqSource::ApuFrameCounter, self.apu.frame_counter.interrupt);
+        self.irq.set(IrqSource::ApuDmc, self.apu.dmc.irq_active());
         Ok(())
     }
 
@@ -108,11 +196,15 @@ impl CpuBus for ResCpuBus {
         self.ppu.poll_nmi_interrupt()
     }
 
+    fn irq_line(&self) -> IrqLine {
+        self.irq
+    }
+
     /// Allows immutable reads from the bus for display/debug purposes.
     fn peek(&self, addr: u16) -> Option<u8> {
         match addr {
             0x0000..=0x1FFF => Some(self.ram[addr as usize & 0b0000_0111_1111_1111]),
-            0x2000..=0x3FFF => Some(self.ppu.cpu_bus_peek(addr)?),
+            0x2000..=0x3FFF => Some(self.ppu.cpu_bus_peek(ppu_register(addr))?),
             0x4000..=0x4013 => Some(self.apu.cpu_bus_peek(addr)),
             0x4014 => Some(0),
             0x4015 => Some(self.apu.cpu_bus_peek(0x4015)),
@@ -130,7 +222,7 @@ impl CpuBus for ResCpuBus {
             .on_cpu_memory_access(self.cycle, MemoryAccess::Read(addr));
         match addr {
             0x0000..=0x1FFF => Ok(self.ram[addr as usize & 0b0000_0111_1111_1111]),
-            0x2000..=0x3FFF => Ok(self.ppu.cpu_bus_read(addr)?),
+            0x2000..=0x3FFF => Ok(self.ppu.cpu_bus_read(ppu_register(addr))?),
             0x4000..=0x4015 => Ok(self.apu.cpu_bus_read(addr)),
             0x4016 => Ok(self.joypad0.cpu_bus_read()),
             0x4017 => Ok(self.joypad1.cpu_bus_read()),
@@ -150,12 +242,15 @@ impl CpuBus for ResCpuBus {
             .on_cpu_memory_access(self.cycle, MemoryAccess::Write(addr, value));
         match addr {
             0x0000..=0x1FFF => self.ram[addr as usize & 0b0000_0111_1111_1111] = value,
-            0x2000..=0x3FFF => self.ppu.cpu_bus_write(addr, value)?,
+            0x2000..=0x3FFF => self.ppu.cpu_bus_write(ppu_register(addr), value)?,
             0x4000..=0x4013 => self.apu.cpu_bus_write(addr, value),
             0x4014 => self.oam_dma(value)?,
             0x4015 => self.apu.cpu_bus_write(0x4015, value),
-            0x4016 => self.joypad0.cpu_bus_write(value),
-            0x4017 => self.joypad1.cpu_bus_write(value),
+            0x4016 => {
+                self.joypad0.cpu_bus_write(value);
+                self.joypad1.cpu_bus_write(value);
+            }
+            0x4017 => self.apu.cpu_bus_write(0x4017, value),
             0x4020..=0xFFFF => self.cartridge.borrow_mut().cpu_bus_write(addr, value)?,
             _ => self
                 .debugger
@@ -165,12 +260,20 @@ impl CpuBus for ResCpuBus {
         Ok(())
     }
 
+    /// Copies a page to OAM while the CPU is halted. The DMA waits one cycle, plus one more if
+    /// it has to align to a read cycle, and then alternates between reading and writing.
     fn oam_dma(&mut self, memory_page: u8) -> Result<()> {
+        let alignment_cycles = if self.cycle % 2 == 1 { 2 } else { 1 };
+        self.advance_clock(alignment_cycles)?;
         let start_addr = (memory_page as u16) << 8;
         for i in 0x00..=0xFF_u8 {
+            self.advance_clock(1)?;
             let value = self.read(start_addr + i as u16)?;
+            self.advance_clock(1)?;
             self.ppu.oam_data[i as usize] = value;
         }
+        // DMC DMA cycles during the transfer have been added to the stall cycles already.
+        self.dma_stall_cycles += alignment_cycles + 512;
         Ok(())
     }
 }
@@ -217,9 +320,7 @@ impl StatusFlags {
 #[derive(Copy, Clone)]
 enum InterruptVector {
     Nmi = 0xFFFA,
-    #[allow(dead_code)]
     Reset = 0xFFFC,
-    #[allow(dead_code)]
     Irq = 0xFFFE,
 }
 
@@ -233,7 +334,6 @@ pub struct Cpu {
     pub y: u8,
     pub status_flags: StatusFlags,
     pub program_counter: u16,
-    pub halt: bool,
     pub sp: u8,
     pub cycle: usize,
 
@@ -257,7 +357,6 @@ impl Cpu {
             x: 0,
             y: 0,
             program_counter: 0,
-            halt: false,
             cycle: 0,
         }
     }
@@ -267,22 +366,69 @@ impl Cpu {
         self.bus.advance_clock(7)
     }
 
-    pub fn execute_one(&mut self) -> Result<bool> {
+    /// Runs the reset sequence of the 6502. Like on hardware, A, X and Y are kept and the
+    /// stack pointer is decremented by 3 without writing to the stack.
+    pub fn reset(&mut self) -> Result<()> {
+        self.read(self.program_counter)?;
+        self.read(self.program_counter)?;
+        for _ in 0..3 {
+            self.dummy_stack_read()?;
+            self.sp = self.sp.wrapping_sub(1);
+        }
+        self.status_flags.interrupt = true;
+        self.program_counter = self.read_u16(InterruptVector::Reset as u16)?;
+        Ok(())
+    }
+
+    pub fn execute_one(&mut self) -> Result<()> {
         let operation = self.next_operation()?;
+        // CLI, SEI and PLP poll for interrupts before changing the I flag, so their effect is
+        // delayed by one instruction.
+        let previous_interrupt_flag = self.status_flags.interrupt;
         operation.execute(self)?;
+        let irq_inhibit = match operation.table_entry.code {
+            0x28 | 0x58 | 0x78 => previous_interrupt_flag,
+            _ => self.status_flags.interrupt,
+        };
         if self.bus.poll_nmi_interrupt() {
-            self.stack_push_u16(self.program_counter)?;
-            self.stack_push(self.status_flags.bits())?;
-            self.status_flags.interrupt = true;
-            self.program_counter = self.bus.read_u16(InterruptVector::Nmi as u16).unwrap();
-            self.advance_clock(2)?;
+            self.hardware_interrupt(InterruptVector::Nmi)?;
+        } else if !irq_inhibit && self.bus.irq_line().is_asserted() {
+            self.hardware_interrupt(InterruptVector::Irq)?;
         }
-        Ok(!self.halt)
+        Ok(())
+    }
+
+    /// NMI and IRQ read the next opcode twice without executing it, and then push the return
+    /// address and status flags like BRK.
+    fn hardware_interrupt(&mut self, vector: InterruptVector) -> Result<()> {
+        self.read(self.program_counter)?;
+        self.read(self.program_counter)?;
+        self.enter_interrupt(vector, self.program_counter, false)
+    }
+
+    /// Pushes the return address and status flags to the stack and jumps to the handler
+    /// of the interrupt vector.
+    fn enter_interrupt(
+        &mut self,
+        vector: InterruptVector,
+        return_addr: u16,
+        break_flag: bool,
+    ) -> Result<()> {
+        self.stack_push_u16(return_addr)?;
+        let mut flags = self.status_flags;
+        flags.break_flag = break_flag;
+        flags.unused = true;
+        self.stack_push(flags.bits())?;
+        self.status_flags.interrupt = true;
+        self.program_counter = self.read_u16(vector as u16)?;
+        Ok(())
     }
 
     pub fn advance_clock(&mut self, cycles: usize) -> Result<()> {
         self.cycle += cycles;
-        self.bus.advance_clock(cycles)
+        self.bus.advance_clock(cycles)?;
+        self.cycle += std::mem::take(&mut self.bus.dma_stall_cycles);
+        Ok(())
     }
 
     pub fn next_operation(&mut self) -> Result<Operation> {
@@ -321,6 +467,13 @@ impl Cpu {
         Ok(())
     }
 
+    /// Reads the top of the stack without changing the stack pointer, which the 6502 does
+    /// while incrementing it or while waiting for other internal operations.
+    fn dummy_stack_read(&mut self) -> Result<()> {
+        self.read(Self::STACK_ADDR + self.sp as u16)?;
+        Ok(())
+    }
+
     fn stack_pop(&mut self) -> Result<u8> {
         if self.sp == 0xFF {
             return Err(anyhow!("Popping from empty stack."));
@@ -336,19 +489,25 @@ impl Cpu {
             .map(|s| s.unwrap_or(0x00))
     }
 
+    /// Reads from the bus, which takes one cycle. The access happens at the end of the cycle.
     pub fn read(&mut self, addr: u16) -> Result<u8> {
+        self.advance_clock(1)?;
         self.bus.read(addr)
     }
 
+    /// Writes to the bus, which takes one cycle. The access happens at the end of the cycle.
     pub fn write(&mut self, addr: u16, value: u8) -> Result<()> {
-        // Hack: Advance clock when doing oam_dma access
-        if addr == 0x4014 {
-            self.advance_clock(512)?
-        }
-        self.bus.write(addr, value)
+        self.advance_clock(1)?;
+        self.bus.write(addr, value)?;
+        // Writes to $4014 halt the CPU for the OAM DMA.
+        self.cycle += std::mem::take(&mut self.bus.dma_stall_cycles);
+        Ok(())
     }
 
     pub fn read_u16(&mut self, addr: u16) -> Result<u16> {
-        Ok(u16::from_le_bytes([self.read(addr)?, self.read(addr + 1)?]))
+        Ok(u16::from_le_bytes([
+            self.read(addr)?,
+            self.read(addr.wrapping_add(1))?,
+        ]))
     }
 }
@@ -4,6 +4,7 @@ use lazy_static::lazy_static;
 
 use super::Cpu;
 use super::CpuBus;
+use super::InterruptVector;
 use super::StatusFlags;
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -26,16 +27,17 @@ impl Operation {
         Some(Operation { addr, table_entry })
     }
 
+    /// Fetches the opcode, which takes the first cycle of the operation.
     pub fn load(cpu: &mut Cpu, addr: u16) -> Result<Operation> {
         let raw_opcode = cpu.read(addr)?;
         let table_entry = OPCODE_TABLE[raw_opcode as usize];
         Ok(Operation { addr, table_entry })
     }
 
+    /// Runs the remaining cycles of the operation. Each cycle is one access to the bus, so the
+    /// timing follows from the memory accesses of the address mode and the operation.
     pub fn execute(&self, cpu: &mut Cpu) -> Result<()> {
-        cpu.advance_clock(self.table_entry.cycle_count_before)?;
-        (self.table_entry.execute_fn)(cpu, self.addr)?;
-        cpu.advance_clock(self.table_entry.cycle_count_after)
+        (self.table_entry.execute_fn)(cpu, self.addr)
     }
 
     pub fn format(&self, cpu: &Cpu) -> String {
@@ -57,11 +59,11 @@ macro_rules! opcode {
             code: $code,
             operand_size: $address_mode::OPERAND_SIZE,
             execute_fn: |cpu, addr| {
-                let address_mode = $address_mode::load(cpu, addr)?;
+                let address_mode = $address_mode::fetch(cpu, addr)?;
                 $method::<$address_mode>(cpu, address_mode)
             },
             format_fn: |cpu, addr| {
-                if let Ok(address_mode) = $address_mode::load(cpu, addr) {
+                if let Ok(address_mode) = $address_mode::peek(cpu, addr) {
                     format!(
                         "{}{}",
                         stringify!($method).to_uppercase(),
@@ -71,306 +73,302 @@ macro_rules! opcode {
                     format!("INV")
                 }
             },
-            cycle_count_before: $cycle_count.0,
-            cycle_count_after: $cycle_count.1,
+            cycle_count: $cycle_count,
         }
     };
 }
 
-// TODOS:
-// 0x00 is BRK not HLT
-
 lazy_static! {
     static ref OPCODE_TABLE: [OpCodeTableEntry; 0x100] = {
         // Specify opcodes out of order to better organize them.
         const OPCODE_LIST: &[OpCodeTableEntry] = &[
             // Codes ending in 0
-            opcode!(0x00, hlt, Implicit, (2, 0)),
-            opcode!(0x10, bpl, Relative, (2, 0)),
-            opcode!(0x20, jsr, Absolute, (6, 0)),
-            opcode!(0x30, bmi, Relative, (2, 0)),
-            opcode!(0x40, rti, Implicit, (6, 0)),
-            opcode!(0x50, bvc, Relative, (2, 0)),
-            opcode!(0x60, rts, Implicit, (6, 0)),
-            opcode!(0x70, bvs, Relative, (2, 0)),
-            opcode!(0x80, ill, Immediate, (1, 0)),
-            opcode!(0x90, bcc, Relative, (2, 0)),
-            opcode!(0xA0, ldy, Immediate, (2, 0)),
-            opcode!(0xB0, bcs, Relative, (2, 0)),
-            opcode!(0xC0, cpy, Immediate, (2, 0)),
-            opcode!(0xD0, bne, Relative, (2, 0)),
-            opcode!(0xE0, cpx, Immediate, (2, 0)),
-            opcode!(0xF0, beq, Relative, (2, 0)),
+            opcode!(0x00, brk, Implicit, 7),
+            opcode!(0x10, bpl, Relative, 2),
+            opcode!(0x20, jsr, Absolute, 6),
+            opcode!(0x30, bmi, Relative, 2),
+            opcode!(0x40, rti, Implicit, 6),
+            opcode!(0x50, bvc, Relative, 2),
+            opcode!(0x60, rts, Implicit, 6),
+            opcode!(0x70, bvs, Relative, 2),
+            opcode!(0x80, ill, Immediate, 1),
+            opcode!(0x90, bcc, Relative, 2),
+            opcode!(0xA0, ldy, Immediate, 2),
+            opcode!(0xB0, bcs, Relative, 2),
+            opcode!(0xC0, cpy, Immediate, 2),
+            opcode!(0xD0, bne, Relative, 2),
+            opcode!(0xE0, cpx, Immediate, 2),
+            opcode!(0xF0, beq, Relative, 2),
 
             // Codes ending in 1
-            opcode!(0x01, ora, IndirectX, (6, 0)),
-            opcode!(0x11, ora, IndirectY, (5, 0)),
-            opcode!(0x21, and, IndirectX, (6, 0)),
-            opcode!(0x31, and, IndirectY, (5, 0)),
-            opcode!(0x41, eor, IndirectX, (6, 0)),
-            opcode!(0x51, eor, IndirectY, (5, 0)),
-            opcode!(0x61, adc, IndirectX, (6, 0)),
-            opcode!(0x71, adc, IndirectY, (5, 0)),
-            opcode!(0x81, sta, IndirectX, (6, 0)),
-            opcode!(0x91, sta, IndirectY, (6, 0)),
-            opcode!(0xA1, lda, IndirectX, (6, 0)),
-            opcode!(0xB1, lda, IndirectY, (5, 0)),
-            opcode!(0xC1, cmp, IndirectX, (6, 0)),
-            opcode!(0xD1, cmp, IndirectY, (5, 0)),
-            opcode!(0xE1, sbc, IndirectX, (6, 0)),
-            opcode!(0xF1, sbc, IndirectY, (5, 0)),
+            opcode!(0x01, ora, IndirectX, 6),
+            opcode!(0x11, ora, IndirectY, 5),
+            opcode!(0x21, and, IndirectX, 6),
+            opcode!(0x31, and, IndirectY, 5),
+            opcode!(0x41, eor, IndirectX, 6),
+            opcode!(0x51, eor, IndirectY, 5),
+            opcode!(0x61, adc, IndirectX, 6),
+            opcode!(0x71, adc, IndirectY, 5),
+            opcode!(0x81, sta, IndirectX, 6),
+            opcode!(0x91, sta, IndirectY, 6),
+            opcode!(0xA1, lda, IndirectX, 6),
+            opcode!(0xB1, lda, IndirectY, 5),
+            opcode!(0xC1, cmp, IndirectX, 6),
+            opcode!(0xD1, cmp, IndirectY, 5),
+            opcode!(0xE1, sbc, IndirectX, 6),
+            opcode!(0xF1, sbc, IndirectY, 5),
 
             // Codes ending in 2
-            opcode!(0x02, ill, Implicit, (1, 0)),
-            opcode!(0x12, ill, Implicit, (1, 0)),
-            opcode!(0x22, ill, Implicit, (1, 0)),
-            opcode!(0x32, ill, Implicit, (1, 0)),
-            opcode!(0x42, ill, Implicit, (1, 0)),
-            opcode!(0x52, ill, Implicit, (1, 0)),
-            opcode!(0x62, ill, Implicit, (1, 0)),
-            opcode!(0x72, ill, Implicit, (1, 0)),
-            opcode!(0x82, ill, Immediate, (1, 0)),
-            opcode!(0x92, ill, Implicit, (1, 0)),
-            opcode!(0xA2, ldx, Immediate, (2, 0)),
-            opcode!(0xB2, ill, Implicit, (1, 0)),
-            opcode!(0xC2, ill, Immediate, (1, 0)),
-            opcode!(0xD2, ill, Implicit, (1, 0)),
-            opcode!(0xE2, ill, Immediate, (1, 0)),
-            opcode!(0xF2, ill, Implicit, (1, 0)),
+            opcode!(0x02, ill, Implicit, 1),
+            opcode!(0x12, ill, Implicit, 1),
+            opcode!(0x22, ill, Implicit, 1),
+            opcode!(0x32, ill, Implicit, 1),
+            opcode!(0x42, ill, Implicit, 1),
+            opcode!(0x52, ill, Implicit, 1),
+            opcode!(0x62, ill, Implicit, 1),
+            opcode!(0x72, ill, Implicit, 1),
+            opcode!(0x82, ill, Immediate, 1),
+            opcode!(0x92, ill, Implicit, 1),
+            opcode!(0xA2, ldx, Immediate, 2),
+            opcode!(0xB2, ill, Implicit, 1),
+            opcode!(0xC2, ill, Immediate, 1),
+            opcode!(0xD2, ill, Implicit, 1),
+            opcode!(0xE2, ill, Immediate, 1),
+            opcode!(0xF2, ill, Implicit, 1),
 
             // Codes ending in 3
-            opcode!(0x03, ill, IndirectX, (1, 0)),
-            opcode!(0x13, ill, IndirectY, (1, 0)),
-            opcode!(0x23, ill, IndirectX, (1, 0)),
-            opcode!(0x33, ill, IndirectY, (1, 0)),
-            opcode!(0x43, ill, IndirectX, (1, 0)),
-            opcode!(0x53, ill, IndirectY, (1, 0)),
-            opcode!(0x63, ill, IndirectX, (1, 0)),
-            opcode!(0x73, ill, IndirectY, (1, 0)),
-            opcode!(0x83, ill, IndirectX, (1, 0)),
-            opcode!(0x93, ill, IndirectY, (1, 0)),
-            opcode!(0xA3, ill, IndirectX, (1, 0)),
-            opcode!(0xB3, ill, IndirectY, (1, 0)),
-            opcode!(0xC3, ill, IndirectX, (1, 0)),
-            opcode!(0xD3, ill, IndirectY, (1, 0)),
-            opcode!(0xE3, ill, IndirectX, (1, 0)),
-            opcode!(0xF3, ill, IndirectY, (1, 0)),
+            opcode!(0x03, ill, IndirectX, 1),
+            opcode!(0x13, ill, IndirectY, 1),
+            opcode!(0x23, ill, IndirectX, 1),
+            opcode!(0x33, ill, IndirectY, 1),
+            opcode!(0x43, ill, IndirectX, 1),
+            opcode!(0x53, ill, IndirectY, 1),
+            opcode!(0x63, ill, IndirectX, 1),
+            opcode!(0x73, ill, IndirectY, 1),
+            opcode!(0x83, ill, IndirectX, 1),
+            opcode!(0x93, ill, IndirectY, 1),
+            opcode!(0xA3, ill, IndirectX, 1),
+            opcode!(0xB3, ill, IndirectY, 1),
+            opcode!(0xC3, ill, IndirectX, 1),
+            opcode!(0xD3, ill, IndirectY, 1),
+            opcode!(0xE3, ill, IndirectX, 1),
+            opcode!(0xF3, ill, IndirectY, 1),
 
             // Codes ending in 4
-            opcode!(0x04, ill, ZeroPage, (1, 0)),
-            opcode!(0x14, ill, ZeroPageX, (1, 0)),
-            opcode!(0x24, bit, ZeroPage, (3, 0)),
-            opcode!(0x34, ill, ZeroPageX, (1, 0)),
-            opcode!(0x44, ill, ZeroPage, (1, 0)),
-            opcode!(0x54, ill, ZeroPageX, (1, 0)),
-            opcode!(0x64, ill, ZeroPage, (1, 0)),
-            opcode!(0x74, ill, ZeroPageX, (1, 0)),
-            opcode!(0x84, sty, ZeroPage, (3, 0)),
-            opcode!(0x94, sty, ZeroPageX, (4, 0)),
-            opcode!(0xA4, ldy, ZeroPage, (3, 0)),
-            opcode!(0xB4, ldy, ZeroPageX, (4, 0)),
-            opcode!(0xC4, cpy, ZeroPage, (3, 0)),
-            opcode!(0xD4, ill, ZeroPageX, (1, 0)),
-            opcode!(0xE4, cpx, ZeroPage, (3, 0)),
-            opcode!(0xF4, ill, ZeroPageX, (1, 0)),
+            opcode!(0x04, ill, ZeroPage, 1),
+            opcode!(0x14, ill, ZeroPageX, 1),
+            opcode!(0x24, bit, ZeroPage, 3),
+            opcode!(0x34, ill, ZeroPageX, 1),
+            opcode!(0x44, ill, ZeroPage, 1),
+            opcode!(0x54, ill, ZeroPageX, 1),
+            opcode!(0x64, ill, ZeroPage, 1),
+            opcode!(0x74, ill, ZeroPageX, 1),
+            opcode!(0x84, sty, ZeroPage, 3),
+            opcode!(0x94, sty, ZeroPageX, 4),
+            opcode!(0xA4, ldy, ZeroPage, 3),
+            opcode!(0xB4, ldy, ZeroPageX, 4),
+            opcode!(0xC4, cpy, ZeroPage, 3),
+            opcode!(0xD4, ill, ZeroPageX, 1),
+            opcode!(0xE4, cpx, ZeroPage, 3),
+            opcode!(0xF4, ill, ZeroPageX, 1),
 
             // Codes ending in 5
-            opcode!(0x05, ora, ZeroPage, (3, 0)),
-            opcode!(0x15, ora, ZeroPageX, (4, 0)),
-            opcode!(0x25, and, ZeroPage, (3, 0)),
-            opcode!(0x35, and, ZeroPageX, (4, 0)),
-            opcode!(0x45, eor, ZeroPage, (3, 0)),
-            opcode!(0x55, eor, ZeroPageX, (4, 0)),
-            opcode!(0x65, adc, ZeroPage, (3, 0)),
-            opcode!(0x75, adc, ZeroPageX, (4, 0)),
-            opcode!(0x85, sta, ZeroPage, (3, 0)),
-            opcode!(0x95, sta, ZeroPageX, (4, 0)),
-            opcode!(0xA5, lda, ZeroPage, (3, 0)),
-            opcode!(0xB5, lda, ZeroPageX, (4, 0)),
-            opcode!(0xC5, cmp, ZeroPage, (3, 0)),
-            opcode!(0xD5, cmp, ZeroPageX, (4, 0)),
-            opcode!(0xE5, sbc, ZeroPage, (3, 0)),
-            opcode!(0xF5, sbc, ZeroPageX, (4, 0)),
+            opcode!(0x05, ora, ZeroPage, 3),
+            opcode!(0x15, ora, ZeroPageX, 4),
+            opcode!(0x25, and, ZeroPage, 3),
+            opcode!(0x35, and, ZeroPageX, 4),
+            opcode!(0x45, eor, ZeroPage, 3),
+            opcode!(0x55, eor, ZeroPageX, 4),
+            opcode!(0x65, adc, ZeroPage, 3),
+            opcode!(0x75, adc, ZeroPageX, 4),
+            opcode!(0x85, sta, ZeroPage, 3),
+            opcode!(0x95, sta, ZeroPageX, 4),
+            opcode!(0xA5, lda, ZeroPage, 3),
+            opcode!(0xB5, lda, ZeroPageX, 4),
+            opcode!(0xC5, cmp, ZeroPage, 3),
+            opcode!(0xD5, cmp, ZeroPageX, 4),
+            opcode!(0xE5, sbc, ZeroPage, 3),
+            opcode!(0xF5, sbc, ZeroPageX, 4),
 
             // Codes ending in 6
-            opcode!(0x06, asl, ZeroPage, (5, 0)),
-            opcode!(0x16, asl, ZeroPageX, (6, 0)),
-            opcode!(0x26, rol, ZeroPage, (5, 0)),
-            opcode!(0x36, rol, ZeroPageX, (6, 0)),
-            opcode!(0x46, lsr, ZeroPage, (5, 0)),
-            opcode!(0x56, lsr, ZeroPageX, (6, 0)),
-            opcode!(0x66, ror, ZeroPage, (5, 0)),
-            opcode!(0x76, ror, ZeroPageX, (6, 0)),
-            opcode!(0x86, stx, ZeroPage, (3, 0)),
-            opcode!(0x96, stx, ZeroPageY, (4, 0)),
-            opcode!(0xA6, ldx, ZeroPage, (3, 0)),
-            opcode!(0xB6, ldx, ZeroPageY, (4, 0)),
-            opcode!(0xC6, dec, ZeroPage, (5, 0)),
-            opcode!(0xD6, dec, ZeroPageX, (6, 0)),
-            opcode!(0xE6, inc, ZeroPage, (5, 0)),
-            opcode!(0xF6, inc, ZeroPageX, (6, 0)),
+            opcode!(0x06, asl, ZeroPage, 5),
+            opcode!(0x16, asl, ZeroPageX, 6),
+            opcode!(0x26, rol, ZeroPage, 5),
+            opcode!(0x36, rol, ZeroPageX, 6),
+            opcode!(0x46, lsr, ZeroPage, 5),
+            opcode!(0x56, lsr, ZeroPageX, 6),
+            opcode!(0x66, ror, ZeroPage, 5),
+            opcode!(0x76, ror, ZeroPageX, 6),
+            opcode!(0x86, stx, ZeroPage, 3),
+            opcode!(0x96, stx, ZeroPageY, 4),
+            opcode!(0xA6, ldx, ZeroPage, 3),
+            opcode!(0xB6, ldx, ZeroPageY, 4),
+            opcode!(0xC6, dec, ZeroPage, 5),
+            opcode!(0xD6, dec, ZeroPageX, 6),
+            opcode!(0xE6, inc, ZeroPage, 5),
+            opcode!(0xF6, inc, ZeroPageX, 6),
 
             // Codes ending in 7
-            opcode!(0x07, ill, ZeroPage, (1, 0)),
-            opcode!(0x17, ill, ZeroPageX, (1, 0)),
-            opcode!(0x27, ill, ZeroPage, (1, 0)),
-            opcode!(0x37, ill, ZeroPageX, (1, 0)),
-            opcode!(0x47, ill, ZeroPage, (1, 0)),
-            opcode!(0x57, ill, ZeroPageX, (1, 0)),
-            opcode!(0x67, ill, ZeroPage, (1, 0)),
-            opcode!(0x77, ill, ZeroPageX, (1, 0)),
-            opcode!(0x87, ill, ZeroPage, (1, 0)),
-            opcode!(0x97, ill, ZeroPageY, (1, 0)),
-            opcode!(0xA7, ill, ZeroPage, (1, 0)),
-            opcode!(0xB7, ill, ZeroPageY, (1, 0)),
-            opcode!(0xC7, ill, ZeroPage, (1, 0)),
-            opcode!(0xD7, ill, ZeroPageX, (1, 0)),
-            opcode!(0xE7, ill, ZeroPage, (1, 0)),
-            opcode!(0xF7, ill, ZeroPageX, (1, 0)),
+            opcode!(0x07, ill, ZeroPage, 1),
+            opcode!(0x17, ill, ZeroPageX, 1),
+            opcode!(0x27, ill, ZeroPage, 1),
+            opcode!(0x37, ill, ZeroPageX, 1),
+            opcode!(0x47, ill, ZeroPage, 1),
+            opcode!(0x57, ill, ZeroPageX, 1),
+            opcode!(0x67, ill, ZeroPage, 1),
+            opcode!(0x77, ill, ZeroPageX, 1),
+            opcode!(0x87, ill, ZeroPage, 1),
+            opcode!(0x97, ill, ZeroPageY, 1),
+            opcode!(0xA7, ill, ZeroPage, 1),
+            opcode!(0xB7, ill, ZeroPageY, 1),
+            opcode!(0xC7, ill, ZeroPage, 1),
+            opcode!(0xD7, ill, ZeroPageX, 1),
+            opcode!(0xE7, ill, ZeroPage, 1),
+            opcode!(0xF7, ill, ZeroPageX, 1),
 
             // Codes ending in 8
-            opcode!(0x08, php, Implicit, (3, 0)),
-            opcode!(0x18, clc, Implicit, (2, 0)),
-            opcode!(0x28, plp, Implicit, (4, 0)),
-            opcode!(0x38, sec, Implicit, (2, 0)),
-            opcode!(0x48, pha, Implicit, (3, 0)),
-            opcode!(0x58, cli, Implicit, (2, 0)),
-            opcode!(0x68, pla, Implicit, (4, 0)),
-            opcode!(0x78, sei, Implicit, (2, 0)),
-            opcode!(0x88, dey, Implicit, (2, 0)),
-            opcode!(0x98, tya, Implicit, (2, 0)),
-            opcode!(0xA8, tay, Implicit, (2, 0)),
-            opcode!(0xB8, clv, Implicit, (2, 0)),
-            opcode!(0xC8, iny, Implicit, (2, 0)),
-            opcode!(0xD8, cld, Implicit, (2, 0)),
-            opcode!(0xE8, inx, Implicit, (2, 0)),
-            opcode!(0xF8, sed, Implicit, (2, 0)),
+            opcode!(0x08, php, Implicit, 3),
+            opcode!(0x18, clc, Implicit, 2),
+            opcode!(0x28, plp, Implicit, 4),
+            opcode!(0x38, sec, Implicit, 2),
+            opcode!(0x48, pha, Implicit, 3),
+            opcode!(0x58, cli, Implicit, 2),
+            opcode!(0x68, pla, Implicit, 4),
+            opcode!(0x78, sei, Implicit, 2),
+            opcode!(0x88, dey, Implicit, 2),
+            opcode!(0x98, tya, Implicit, 2),
+            opcode!(0xA8, tay, Implicit, 2),
+            opcode!(0xB8, clv, Implicit, 2),
+            opcode!(0xC8, iny, Implicit, 2),
+            opcode!(0xD8, cld, Implicit, 2),
+            opcode!(0xE8, inx, Implicit, 2),
+            opcode!(0xF8, sed, Implicit, 2),
 
             // Codes ending in 9
-            opcode!(0x09, ora, Immediate, (2, 0)),
-            opcode!(0x19, ora, AbsoluteY, (4, 0)),
-            opcode!(0x29, and, Immediate, (2, 0)),
-            opcode!(0x39, and, AbsoluteY, (4, 0)),
-            opcode!(0x49, eor, Immediate, (2, 0)),
-            opcode!(0x59, eor, AbsoluteY, (4, 0)),
-            opcode!(0x69, adc, Immediate, (2, 0)),
-            opcode!(0x79, adc, AbsoluteY, (4, 0)),
-            opcode!(0x89, ill, Immediate, (1, 0)),
-            opcode!(0x99, sta, AbsoluteY, (5, 0)),
-            opcode!(0xA9, lda, Immediate, (2, 0)),
-            opcode!(0xB9, lda, AbsoluteY, (4, 0)),
-            opcode!(0xC9, cmp, Immediate, (2, 0)),
-            opcode!(0xD9, cmp, AbsoluteY, (4, 0)),
-            opcode!(0xE9, sbc, Immediate, (2, 0)),
-            opcode!(0xF9, sbc, AbsoluteY, (4, 0)),
+            opcode!(0x09, ora, Immediate, 2),
+            opcode!(0x19, ora, AbsoluteY, 4),
+            opcode!(0x29, and, Immediate, 2),
+            opcode!(0x39, and, AbsoluteY, 4),
+            opcode!(0x49, eor, Immediate, 2),
+            opcode!(0x59, eor, AbsoluteY, 4),
+            opcode!(0x69, adc, Immediate, 2),
+            opcode!(0x79, adc, AbsoluteY, 4),
+            opcode!(0x89, ill, Immediate, 1),
+            opcode!(0x99, sta, AbsoluteY, 5),
+            opcode!(0xA9, lda, Immediate, 2),
+            opcode!(0xB9, lda, AbsoluteY, 4),
+            opcode!(0xC9, cmp, Immediate, 2),
+            opcode!(0xD9, cmp, AbsoluteY, 4),
+            opcode!(0xE9, sbc, Immediate, 2),
+            opcode!(0xF9, sbc, AbsoluteY, 4),
 
             // Codes ending in A
-            opcode!(0x0A, asl, Accumulator, (2, 0)),
-            opcode!(0x1A, ill, Implicit, (1, 0)),
-            opcode!(0x2A, rol, Accumulator, (2, 0)),
-            opcode!(0x3A, ill, Implicit, (1, 0)),
-            opcode!(0x4A, lsr, Accumulator, (2, 0)),
-            opcode!(0x5A, ill, Implicit, (1, 0)),
-            opcode!(0x6A, ror, Accumulator, (2, 0)),
-            opcode!(0x7A, ill, Implicit, (1, 0)),
-            opcode!(0x8A, txa, Implicit, (2, 0)),
-            opcode!(0x9A, txs, Implicit, (2, 0)),
-            opcode!(0xAA, tax, Implicit, (2, 0)),
-            opcode!(0xBA, tsx, Implicit, (2, 0)),
-            opcode!(0xCA, dex, Implicit, (2, 0)),
-            opcode!(0xDA, ill, Implicit, (1, 0)),
-            opcode!(0xEA, nop, Implicit, (2, 0)),
-            opcode!(0xFA, ill, Implicit, (1, 0)),
+            opcode!(0x0A, asl, Accumulator, 2),
+            opcode!(0x1A, ill, Implicit, 1),
+            opcode!(0x2A, rol, Accumulator, 2),
+            opcode!(0x3A, ill, Implicit, 1),
+            opcode!(0x4A, lsr, Accumulator, 2),
+            opcode!(0x5A, ill, Implicit, 1),
+            opcode!(0x6A, ror, Accumulator, 2),
+            opcode!(0x7A, ill, Implicit, 1),
+            opcode!(0x8A, txa, Implicit, 2),
+            opcode!(0x9A, txs, Implicit, 2),
+            opcode!(0xAA, tax, Implicit, 2),
+            opcode!(0xBA, tsx, Implicit, 2),
+            opcode!(0xCA, dex, Implicit, 2),
+            opcode!(0xDA, ill, Implicit, 1),
+            opcode!(0xEA, nop, Implicit, 2),
+            opcode!(0xFA, ill, Implicit, 1),
 
             // Codes ending in B
-            opcode!(0x0B, ill, Immediate, (1, 0)),
-            opcode!(0x1B, ill, AbsoluteY, (1, 0)),
-            opcode!(0x2B, ill, Immediate, (1, 0)),
-            opcode!(0x3B, ill, AbsoluteY, (1, 0)),
-            opcode!(0x4B, ill, Immediate, (1, 0)),
-            opcode!(0x5B, ill, AbsoluteY, (1, 0)),
-            opcode!(0x6B, ill, Immediate, (1, 0)),
-            opcode!(0x7B, ill, AbsoluteY, (1, 0)),
-            opcode!(0x8B, ill, Immediate, (1, 0)),
-            opcode!(0x9B, ill, AbsoluteY, (1, 0)),
-            opcode!(0xAB, ill, Immediate, (1, 0)),
-            opcode!(0xBB, ill, AbsoluteY, (1, 0)),
-            opcode!(0xCB, ill, Immediate, (1, 0)),
-            opcode!(0xDB, ill, AbsoluteY, (1, 0)),
-            opcode!(0xEB, ill, Immediate, (1, 0)),
-            opcode!(0xFB, ill, AbsoluteY, (1, 0)),
+            opcode!(0x0B, ill, Immediate, 1),
+            opcode!(0x1B, ill, AbsoluteY, 1),
+            opcode!(0x2B, ill, Immediate, 1),
+            opcode!(0x3B, ill, AbsoluteY, 1),
+            opcode!(0x4B, ill, Immediate, 1),
+            opcode!(0x5B, ill, AbsoluteY, 1),
+            opcode!(0x6B, ill, Immediate, 1),
+            opcode!(0x7B, ill, AbsoluteY, 1),
+            opcode!(0x8B, ill, Immediate, 1),
+            opcode!(0x9B, ill, AbsoluteY, 1),
+            opcode!(0xAB, ill, Immediate, 1),
+            opcode!(0xBB, ill, AbsoluteY, 1),
+            opcode!(0xCB, ill, Immediate, 1),
+            opcode!(0xDB, ill, AbsoluteY, 1),
+            opcode!(0xEB, ill, Immediate, 1),
+            opcode!(0xFB, ill, AbsoluteY, 1),
 
             // Codes ending in C
-            opcode!(0x0C, ill, Absolute, (1, 0)),
-            opcode!(0x1C, ill, AbsoluteX, (1, 0)),
-            opcode!(0x2C, bit, Absolute, (4, 0)),
-            opcode!(0x3C, ill, AbsoluteX, (1, 0)),
-            opcode!(0x4C, jmp, Absolute, (3, 0)),
-            opcode!(0x5C, ill, AbsoluteX, (1, 0)),
-            opcode!(0x6C, jmp, Indirect, (5, 0)),
-            opcode!(0x7C, ill, AbsoluteX, (1, 0)),
-            opcode!(0x8C, sty, Absolute, (4, 0)),
-            opcode!(0x9C, ill, AbsoluteX, (1, 0)),
-            opcode!(0xAC, ldy, Absolute, (4, 0)),
-            opcode!(0xBC, ldy, AbsoluteX, (4, 0)),
-            opcode!(0xCC, cpy, Absolute, (4, 0)),
-            opcode!(0xDC, ill, AbsoluteX, (1, 0)),
-            opcode!(0xEC, cpx, Absolute, (4, 0)),
-            opcode!(0xFC, ill, AbsoluteX, (1, 0)),
+            opcode!(0x0C, ill, Absolute, 1),
+            opcode!(0x1C, ill, AbsoluteX, 1),
+            opcode!(0x2C, bit, Absolute, 4),
+            opcode!(0x3C, ill, AbsoluteX, 1),
+            opcode!(0x4C, jmp, Absolute, 3),
+            opcode!(0x5C, ill, AbsoluteX, 1),
+            opcode!(0x6C, jmp, Indirect, 5),
+            opcode!(0x7C, ill, AbsoluteX, 1),
+            opcode!(0x8C, sty, Absolute, 4),
+            opcode!(0x9C, ill, AbsoluteX, 1),
+            opcode!(0xAC, ldy, Absolute, 4),
+            opcode!(0xBC, ldy, AbsoluteX, 4),
+            opcode!(0xCC, cpy, Absolute, 4),
+            opcode!(0xDC, ill, AbsoluteX, 1),
+            opcode!(0xEC, cpx, Absolute, 4),
+            opcode!(0xFC, ill, AbsoluteX, 1),
 
             // Codes ending in D
-            opcode!(0x0D, ora, Absolute, (4, 0)),
-            opcode!(0x1D, ora, AbsoluteX, (4, 0)),
-            opcode!(0x2D, and, Absolute, (4, 0)),
-            opcode!(0x3D, and, AbsoluteX, (4, 0)),
-            opcode!(0x4D, eor, Absolute, (4, 0)),
-            opcode!(0x5D, eor, AbsoluteX, (4, 0)),
-            opcode!(0x6D, adc, Absolute, (4, 0)),
-            opcode!(0x7D, adc, AbsoluteX, (4, 0)),
-            opcode!(0x8D, sta, Absolute, (4, 0)),
-            opcode!(0x9D, sta, AbsoluteX, (5, 0)),
-            opcode!(0xAD, lda, Absolute, (4, 0)),
-            opcode!(0xBD, lda, AbsoluteX, (4, 0)),
-            opcode!(0xCD, cmp, Absolute, (4, 0)),
-            opcode!(0xDD, cmp, AbsoluteX, (4, 0)),
-            opcode!(0xED, sbc, Absolute, (4, 0)),
-            opcode!(0xFD, sbc, AbsoluteX, (4, 0)),
+            opcode!(0x0D, ora, Absolute, 4),
+            opcode!(0x1D, ora, AbsoluteX, 4),
+            opcode!(0x2D, and, Absolute, 4),
+            opcode!(0x3D, and, AbsoluteX, 4),
+            opcode!(0x4D, eor, Absolute, 4),
+            opcode!(0x5D, eor, AbsoluteX, 4),
+            opcode!(0x6D, adc, Absolute, 4),
+            opcode!(0x7D, adc, AbsoluteX, 4),
+            opcode!(0x8D, sta, Absolute, 4),
+            opcode!(0x9D, sta, AbsoluteX, 5),
+            opcode!(0xAD, lda, Absolute, 4),
+            opcode!(0xBD, lda, AbsoluteX, 4),
+            opcode!(0xCD, cmp, Absolute, 4),
+            opcode!(0xDD, cmp, AbsoluteX, 4),
+            opcode!(0xED, sbc, Absolute, 4),
+            opcode!(0xFD, sbc, AbsoluteX, 4),
 
             // Codes endding in E
-            opcode!(0x0E, asl, Absolute, (6, 0)),
-            opcode!(0x1E, asl, AbsoluteX, (7, 0)),
-            opcode!(0x2E, rol, Absolute, (6, 0)),
-            opcode!(0x3E, rol, AbsoluteX, (7, 0)),
-            opcode!(0x4E, lsr, Absolute, (6, 0)),
-            opcode!(0x5E, lsr, AbsoluteX, (7, 0)),
-            opcode!(0x6E, ror, Absolute, (6, 0)),
-            opcode!(0x7E, ror, AbsoluteX, (7, 0)),
-            opcode!(0x8E, stx, Absolute, (4, 0)),
-            opcode!(0x9E, ill, AbsoluteX, (1, 0)),
-            opcode!(0xAE, ldx, Absolute, (4, 0)),
-            opcode!(0xBE, ldx, AbsoluteY, (4, 0)),
-            opcode!(0xCE, dec, Absolute, (6, 0)),
-            opcode!(0xDE, dec, AbsoluteX, (7, 0)),
-            opcode!(0xEE, inc, Absolute, (6, 0)),
-            opcode!(0xFE, inc, AbsoluteX, (7, 0)),
+            opcode!(0x0E, asl, Absolute, 6),
+            opcode!(0x1E, asl, AbsoluteX, 7),
+            opcode!(0x2E, rol, Absolute, 6),
+            opcode!(0x3E, rol, AbsoluteX, 7),
+            opcode!(0x4E, lsr, Absolute, 6),
+            opcode!(0x5E, lsr, AbsoluteX, 7),
+            opcode!(0x6E, ror, Absolute, 6),
+            opcode!(0x7E, ror, AbsoluteX, 7),
+            opcode!(0x8E, stx, Absolute, 4),
+            opcode!(0x9E, ill, AbsoluteX, 1),
+            opcode!(0xAE, ldx, Absolute, 4),
+            opcode!(0xBE, ldx, AbsoluteY, 4),
+            opcode!(0xCE, dec, Absolute, 6),
+            opcode!(0xDE, dec, AbsoluteX, 7),
+            opcode!(0xEE, inc, Absolute, 6),
+            opcode!(0xFE, inc, AbsoluteX, 7),
 
             // Codes endding in F
-            opcode!(0x0F, ill, Absolute, (1, 0)),
-            opcode!(0x1F, ill, AbsoluteX, (1, 0)),
-            opcode!(0x2F, ill, Absolute, (1, 0)),
-            opcode!(0x3F, ill, AbsoluteX, (1, 0)),
-            opcode!(0x4F, ill, Absolute, (1, 0)),
-            opcode!(0x5F, ill, AbsoluteX, (1, 0)),
-            opcode!(0x6F, ill, Absolute, (1, 0)),
-            opcode!(0x7F, ill, AbsoluteX, (1, 0)),
-            opcode!(0x8F, ill, Absolute, (1, 0)),
-            opcode!(0x9F, ill, AbsoluteX, (1, 0)),
-            opcode!(0xAF, ill, Absolute, (1, 0)),
-            opcode!(0xBF, ill, AbsoluteY, (1, 0)),
-            opcode!(0xCF, ill, Absolute, (1, 0)),
-            opcode!(0xDF, ill, AbsoluteX, (1, 0)),
-            opcode!(0xEF, ill, Absolute, (1, 0)),
-            opcode!(0xFF, ill, AbsoluteX, (1, 0)),
+            opcode!(0x0F, ill, Absolute, 1),
+            opcode!(0x1F, ill, AbsoluteX, 1),
+            opcode!(0x2F, ill, Absolute, 1),
+            opcode!(0x3F, ill, AbsoluteX, 1),
+            opcode!(0x4F, ill, Absolute, 1),
+            opcode!(0x5F, ill, AbsoluteX, 1),
+            opcode!(0x6F, ill, Absolute, 1),
+            opcode!(0x7F, ill, AbsoluteX, 1),
+            opcode!(0x8F, ill, Absolute, 1),
+            opcode!(0x9F, ill, AbsoluteX, 1),
+            opcode!(0xAF, ill, Absolute, 1),
+            opcode!(0xBF, ill, AbsoluteY, 1),
+            opcode!(0xCF, ill, Absolute, 1),
+            opcode!(0xDF, ill, AbsoluteX, 1),
+            opcode!(0xEF, ill, Absolute, 1),
+            opcode!(0xFF, ill, AbsoluteX, 1),
 
         ];
 
@@ -393,8 +391,9 @@ pub struct OpCodeTableEntry {
     pub operand_size: usize,
     pub execute_fn: fn(cpu: &mut Cpu, addr: u16) -> Result<()>,
     pub format_fn: fn(cpu: &Cpu, addr: u16) -> String,
-    pub cycle_count_before: usize,
-    pub cycle_count_after: usize,
+    /// Cycles of the operation without page crossings or taken branches. Execution takes as
+    /// many cycles as it accesses the bus, so this is only used to verify the operations.
+    pub cycle_count: usize,
 }
 
 impl Default for OpCodeTableEntry {
@@ -404,8 +403,7 @@ impl Default for OpCodeTableEntry {
             operand_size: 0,
             execute_fn: |_, _| unimplemented!(),
             format_fn: |_, _| "N/A".to_string(),
-            cycle_count_before: 0,
-            cycle_count_after: 0,
+            cycle_count: 0,
         }
     }
 }
@@ -413,10 +411,16 @@ impl Default for OpCodeTableEntry {
 ////////////////////////////////////////////////////////////////////////////////
 // Address Modes
 
+/// Decodes the operand following the opcode at `addr`. `fetch` reads it from the bus with
+/// the same accesses as the CPU, including dummy reads, while `peek` has no side effects.
 trait Operand {
     const OPERAND_SIZE: usize = 0;
 
-    fn load(_cpu: &Cpu, _addr: u16) -> Result<Self>
+    fn peek(_cpu: &Cpu, _addr: u16) -> Result<Self>
+    where
+        Self: Sized;
+
+    fn fetch(_cpu: &mut Cpu, _addr: u16) -> Result<Self>
     where
         Self: Sized;
 
@@ -426,17 +430,40 @@ trait Operand {
         unimplemented!()
     }
 
-    fn apply_page_cross_penality(&self, _cpu: &mut Cpu) -> Result<()> {
-        Ok(())
+    /// Indexed address modes read this address before the carry of the index is added to
+    /// the high byte of the operand address.
+    fn uncorrected_addr(&self) -> Option<u16> {
+        None
     }
 
+    /// Reads the operand. The uncorrected address is only read if the index crossed a page.
     fn load_operand(&self, cpu: &mut Cpu) -> Result<u8> {
+        if let Some(addr) = self.uncorrected_addr() {
+            if addr != self.operand_addr() {
+                cpu.read(addr)?;
+            }
+        }
         cpu.read(self.operand_addr())
     }
 
     fn store_operand(&self, cpu: &mut Cpu, value: u8) -> Result<()> {
+        if let Some(addr) = self.uncorrected_addr() {
+            cpu.read(addr)?;
+        }
         cpu.write(self.operand_addr(), value)
     }
+
+    /// Read-modify-write operations write back the unmodified value while calculating the
+    /// result, which is written in the following cycle.
+    fn modify_operand(&self, cpu: &mut Cpu, modify: impl FnOnce(&mut Cpu, u8) -> u8) -> Result<()> {
+        if let Some(addr) = self.uncorrected_addr() {
+            cpu.read(addr)?;
+        }
+        let value = cpu.read(self.operand_addr())?;
+        cpu.write(self.operand_addr(), value)?;
+        let result = modify(cpu, value);
+        cpu.write(self.operand_addr(), result)
+    }
 }
 
 struct Immediate {
@@ -445,9 +472,15 @@ struct Immediate {
 impl Operand for Immediate {
     const OPERAND_SIZE: usize = 1;
 
-    fn load(cpu: &Cpu, addr: u16) -> Result<Self> {
+    fn peek(cpu: &Cpu, addr: u16) -> Result<Self> {
+        Ok(Self {
+            operand: peek_to_result(cpu.bus.peek(addr.wrapping_add(1)))?,
+        })
+    }
+
+    fn fetch(cpu: &mut Cpu, addr: u16) -> Result<Self> {
         Ok(Self {
-            operand: peek_to_result(cpu.bus.peek(addr + 1))?,
+            operand: cpu.read(addr.wrapping_add(1))?,
         })
     }
 
@@ -464,7 +497,13 @@ struct Implicit {}
 impl Operand for Implicit {
     const OPERAND_SIZE: usize = 0;
 
-    fn load(_cpu: &Cpu, _addr: u16) -> Result<Self> {
+    fn peek(_cpu: &Cpu, _addr: u16) -> Result<Self> {
+        Ok(Self {})
+    }
+
+    /// The byte following the opcode is read and ignored.
+    fn fetch(cpu: &mut Cpu, addr: u16) -> Result<Self> {
+        cpu.read(addr.wrapping_add(1))?;
         Ok(Self {})
     }
 
@@ -477,7 +516,13 @@ struct Accumulator {}
 impl Operand for Accumulator {
     const OPERAND_SIZE: usize = 0;
 
-    fn load(_cpu: &Cpu, _addr: u16) -> Result<Self> {
+    fn peek(_cpu: &Cpu, _addr: u16) -> Result<Self> {
+        Ok(Self {})
+    }
+
+    /// The byte following the opcode is read and ignored.
+    fn fetch(cpu: &mut Cpu, addr: u16) -> Result<Self> {
+        cpu.read(addr.wrapping_add(1))?;
         Ok(Self {})
     }
 
@@ -490,6 +535,11 @@ impl Operand for Accumulator {
         Ok(())
     }
 
+    fn modify_operand(&self, cpu: &mut Cpu, modify: impl FnOnce(&mut Cpu, u8) -> u8) -> Result<()> {
+        cpu.a = modify(cpu, cpu.a);
+        Ok(())
+    }
+
     fn format(&self, _cpu: &Cpu) -> String {
         " A".to_string()
     }
@@ -501,9 +551,15 @@ struct Absolute {
 impl Operand for Absolute {
     const OPERAND_SIZE: usize = 2;
 
-    fn load(cpu: &Cpu, addr: u16) -> Result<Self> {
+    fn peek(cpu: &Cpu, addr: u16) -> Result<Self> {
+        Ok(Self {
+            operand_addr: peek_to_result(cpu.bus.peek_u16(addr.wrapping_add(1)))?,
+        })
+    }
+
+    fn fetch(cpu: &mut Cpu, addr: u16) -> Result<Self> {
         Ok(Self {
-            operand_addr: peek_to_result(cpu.bus.peek_u16(addr + 1))?,
+            operand_addr: cpu.read_u16(addr.wrapping_add(1))?,
         })
     }
 
@@ -516,36 +572,45 @@ impl Operand for Absolute {
     }
 }
 
+/// Returns the address that is accessed before the carry from adding the index to the low
+/// byte is added to the high byte.
+fn uncorrected_addr(base_addr: u16, operand_addr: u16) -> u16 {
+    (base_addr & 0xFF00) | (operand_addr & 0x00FF)
+}
+
 struct AbsoluteX {
     base_addr: u16,
     operand_addr: u16,
-    page_cross: bool,
+}
+
+impl AbsoluteX {
+    fn new(base_addr: u16, x: u8) -> Self {
+        Self {
+            base_addr,
+            operand_addr: base_addr.wrapping_add(x as u16),
+        }
+    }
 }
 
 impl Operand for AbsoluteX {
     const OPERAND_SIZE: usize = 2;
 
-    fn load(cpu: &Cpu, addr: u16) -> Result<Self> {
-        let base_addr = peek_to_result(cpu.bus.peek_u16(addr + 1))?;
-        let operand_addr = base_addr.wrapping_add(cpu.x as u16);
-        let page_cross = base_addr & 0xFF00 != operand_addr & 0xFF00;
-        Ok(Self {
-            base_addr,
-            operand_addr,
-            page_cross,
-        })
+    fn peek(cpu: &Cpu, addr: u16) -> Result<Self> {
+        let base_addr = peek_to_result(cpu.bus.peek_u16(addr.wrapping_add(1)))?;
+        Ok(Self::new(base_addr, cpu.x))
+    }
+
+    fn fetch(cpu: &mut Cpu, addr: u16) -> Result<Self> {
+        let base_addr = cpu.read_u16(addr.wrapping_add(1))?;
+        Ok(Self::new(base_addr, cpu.x))
     }
 
     fn operand_addr(&self) -> u16 {
         self.operand_addr
     }
 
-    fn apply_page_cross_penality(&self, cpu: &mut Cpu) -> Result<()> {
-        if self.page_cross {
-            cpu.advance_clock(1)
-        } else {
-            Ok(())
-        }
+    fn uncorrected_addr(&self) -> Option<u16> {
+        Some(uncorrected_addr(self.base_addr, self.operand_addr))
     }
 
     fn format(&self, _cpu: &Cpu) -> String {
@@ -556,32 +621,36 @@ impl Operand for AbsoluteX {
 struct AbsoluteY {
     base_addr: u16,
     operand_addr: u16,
-    page_cross: bool,
 }
+
+impl AbsoluteY {
+    fn new(base_addr: u16, y: u8) -> Self {
+        Self {
+            base_addr,
+            operand_addr: base_addr.wrapping_add(y as u16),
+        }
+    }
+}
+
 impl Operand for AbsoluteY {
     const OPERAND_SIZE: usize = 2;
 
-    fn load(cpu: &Cpu, addr: u16) -> Result<Self> {
-        let base_addr = peek_to_result(cpu.bus.peek_u16(addr + 1))?;
-        let operand_addr = base_addr.wrapping_add(cpu.y as u16);
-        let page_cross = base_addr & 0xFF00 != operand_addr & 0xFF00;
-        Ok(Self {
-            base_addr,
-            operand_addr,
-            page_cross,
-        })
+    fn peek(cpu: &Cpu, addr: u16) -> Result<Self> {
+        let base_addr = peek_to_result(cpu.bus.peek_u16(addr.wrapping_add(1)))?;
+        Ok(Self::new(base_addr, cpu.y))
+    }
+
+    fn fetch(cpu: &mut Cpu, addr: u16) -> Result<Self> {
+        let base_addr = cpu.read_u16(addr.wrapping_add(1))?;
+        Ok(Self::new(base_addr, cpu.y))
     }
 
     fn operand_addr(&self) -> u16 {
         self.operand_addr
     }
 
-    fn apply_page_cross_penality(&self, cpu: &mut Cpu) -> Result<()> {
-        if self.page_cross {
-            cpu.advance_clock(1)
-        } else {
-            Ok(())
-        }
+    fn uncorrected_addr(&self) -> Option<u16> {
+        Some(uncorrected_addr(self.base_addr, self.operand_addr))
     }
 
     fn format(&self, _cpu: &Cpu) -> String {
@@ -599,9 +668,15 @@ struct ZeroPage {
 impl Operand for ZeroPage {
     const OPERAND_SIZE: usize = 1;
 
-    fn load(cpu: &Cpu, addr: u16) -> Result<Self> {
+    fn peek(cpu: &Cpu, addr: u16) -> Result<Self> {
         Ok(Self {
-            operand_addr: peek_to_result(cpu.bus.peek(addr + 1))?,
+            operand_addr: peek_to_result(cpu.bus.peek(addr.wrapping_add(1)))?,
+        })
+    }
+
+    fn fetch(cpu: &mut Cpu, addr: u16) -> Result<Self> {
+        Ok(Self {
+            operand_addr: cpu.read(addr.wrapping_add(1))?,
         })
     }
 
@@ -620,8 +695,19 @@ struct ZeroPageX {
 impl Operand for ZeroPageX {
     const OPERAND_SIZE: usize = 1;
 
-    fn load(cpu: &Cpu, addr: u16) -> Result<Self> {
-        let base_addr = peek_to_result(cpu.bus.peek(addr + 1))?;
+    fn peek(cpu: &Cpu, addr: u16) -> Result<Self> {
+        let base_addr = peek_to_result(cpu.bus.peek(addr.wrapping_add(1)))?;
+        let operand_addr = base_addr.wrapping_add(cpu.x) as u16;
+        Ok(Self {
+            base_addr,
+            operand_addr,
+        })
+    }
+
+    /// The base address is read while the index is added.
+    fn fetch(cpu: &mut Cpu, addr: u16) -> Result<Self> {
+        let base_addr = cpu.read(addr.wrapping_add(1))?;
+        cpu.read(base_addr as u16)?;
         let operand_addr = base_addr.wrapping_add(cpu.x) as u16;
         Ok(Self {
             base_addr,
@@ -645,10 +731,20 @@ struct ZeroPageY {
 impl Operand for ZeroPageY {
     const OPERAND_SIZE: usize = 1;
 
-    fn load(cpu: &Cpu, addr: u16) -> Result<Self> {
-        let base_addr = peek_to_result(cpu.bus.peek(addr + 1))?;
+    fn peek(cpu: &Cpu, addr: u16) -> Result<Self> {
+        let base_addr = peek_to_result(cpu.bus.peek(addr.wrapping_add(1)))?;
+        let operand_addr = base_addr.wrapping_add(cpu.y) as u16;
+        Ok(Self {
+            base_addr,
+            operand_addr,
+        })
+    }
+
+    /// The base address is read while the index is added.
+    fn fetch(cpu: &mut Cpu, addr: u16) -> Result<Self> {
+        let base_addr = cpu.read(addr.wrapping_add(1))?;
+        cpu.read(base_addr as u16)?;
         let operand_addr = base_addr.wrapping_add(cpu.y) as u16;
-        cpu.bus.peek(0x00).unwrap(); // Fake read for one extra cycle
         Ok(Self {
             base_addr,
             operand_addr,
@@ -668,21 +764,28 @@ struct Relative {
     relative_addr: i8,
     operand_addr: u16,
 }
+
+impl Relative {
+    fn new(addr: u16, relative_addr: i8) -> Self {
+        let base_addr = addr.wrapping_add(1 + Self::OPERAND_SIZE as u16);
+        Self {
+            relative_addr,
+            operand_addr: base_addr.wrapping_add(relative_addr as i16 as u16),
+        }
+    }
+}
+
 impl Operand for Relative {
     const OPERAND_SIZE: usize = 1;
 
-    fn load(cpu: &Cpu, addr: u16) -> Result<Self> {
-        let relative_addr = peek_to_result(cpu.bus.peek(addr + 1))? as i8;
-        let base_addr = addr + 1 + Self::OPERAND_SIZE as u16;
-        let operand_addr = if relative_addr > 0 {
-            base_addr.wrapping_add((relative_addr as i16).unsigned_abs())
-        } else {
-            base_addr.wrapping_sub((relative_addr as i16).unsigned_abs())
-        };
-        Ok(Self {
-            relative_addr,
-            operand_addr,
-        })
+    fn peek(cpu: &Cpu, addr: u16) -> Result<Self> {
+        let relative_addr = peek_to_result(cpu.bus.peek(addr.wrapping_add(1)))? as i8;
+        Ok(Self::new(addr, relative_addr))
+    }
+
+    fn fetch(cpu: &mut Cpu, addr: u16) -> Result<Self> {
+        let relative_addr = cpu.read(addr.wrapping_add(1))? as i8;
+        Ok(Self::new(addr, relative_addr))
     }
 
     fn operand_addr(&self) -> u16 {
@@ -694,6 +797,12 @@ impl Operand for Relative {
     }
 }
 
+/// Returns the address of the high byte of a pointer. Like on the 6502, the high byte is read
+/// from the same page if the low byte is at the end of a page.
+fn pointer_high_addr(pointer_addr: u16) -> u16 {
+    (pointer_addr & 0xFF00) | (pointer_addr.wrapping_add(1) & 0x00FF)
+}
+
 struct Indirect {
     indirect_addr: u16,
     operand_addr: u16,
@@ -701,22 +810,24 @@ struct Indirect {
 impl Operand for Indirect {
     const OPERAND_SIZE: usize = 2;
 
-    fn load(cpu: &Cpu, addr: u16) -> Result<Self> {
-        let indirect_addr = peek_to_result(cpu.bus.peek_u16(addr + 1))?;
-        let bytes = if indirect_addr & 0x00FF == 0x00FF {
-            // CPU Bug: Address wraps around inside page.
-            let page = indirect_addr & 0xFF00;
-            [
-                peek_to_result(cpu.bus.peek(indirect_addr))?,
-                peek_to_result(cpu.bus.peek(page))?,
-            ]
-        } else {
-            [
-                peek_to_result(cpu.bus.peek(indirect_addr))?,
-                peek_to_result(cpu.bus.peek(indirect_addr + 1))?,
-            ]
-        };
-        let operand_addr = u16::from_le_bytes(bytes);
+    fn peek(cpu: &Cpu, addr: u16) -> Result<Self> {
+        let indirect_addr = peek_to_result(cpu.bus.peek_u16(addr.wrapping_add(1)))?;
+        let operand_addr = u16::from_le_bytes([
+            peek_to_result(cpu.bus.peek(indirect_addr))?,
+            peek_to_result(cpu.bus.peek(pointer_high_addr(indirect_addr)))?,
+        ]);
+        Ok(Self {
+            indirect_addr,
+            operand_addr,
+        })
+    }
+
+    fn fetch(cpu: &mut Cpu, addr: u16) -> Result<Self> {
+        let indirect_addr = cpu.read_u16(addr.wrapping_add(1))?;
+        let operand_addr = u16::from_le_bytes([
+            cpu.read(indirect_addr)?,
+            cpu.read(pointer_high_addr(indirect_addr))?,
+        ]);
         Ok(Self {
             indirect_addr,
             operand_addr,
@@ -737,38 +848,44 @@ impl Operand for Indirect {
 
 struct IndirectY {
     indirect_addr: u8,
+    base_addr: u16,
     operand_addr: u16,
-    page_cross: bool,
 }
+
+impl IndirectY {
+    fn new(indirect_addr: u8, base_addr: u16, y: u8) -> Self {
+        Self {
+            indirect_addr,
+            base_addr,
+            operand_addr: base_addr.wrapping_add(y as u16),
+        }
+    }
+}
+
 impl Operand for IndirectY {
     const OPERAND_SIZE: usize = 1;
 
-    fn load(cpu: &Cpu, addr: u16) -> Result<Self> {
-        let indirect_addr = peek_to_result(cpu.bus.peek(addr + 1))?;
+    fn peek(cpu: &Cpu, addr: u16) -> Result<Self> {
+        let indirect_addr = peek_to_result(cpu.bus.peek(addr.wrapping_add(1)))?;
+        let base_addr = peek_to_result(cpu.bus.zero_page_peek_u16(indirect_addr))?;
+        Ok(Self::new(indirect_addr, base_addr, cpu.y))
+    }
+
+    fn fetch(cpu: &mut Cpu, addr: u16) -> Result<Self> {
+        let indirect_addr = cpu.read(addr.wrapping_add(1))?;
         let base_addr = u16::from_le_bytes([
-            cpu.bus.peek(indirect_addr as u16).unwrap(),
-            cpu.bus.peek(indirect_addr.wrapping_add(1) as u16).unwrap(),
+            cpu.read(indirect_addr as u16)?,
+            cpu.read(indirect_addr.wrapping_add(1) as u16)?,
         ]);
-        let operand_addr = base_addr.wrapping_add(cpu.y as u16);
-        let page_cross = base_addr & 0xFF00 != operand_addr & 0xFF00;
-
-        Ok(Self {
-            indirect_addr,
-            operand_addr,
-            page_cross,
-        })
+        Ok(Self::new(indirect_addr, base_addr, cpu.y))
     }
 
     fn operand_addr(&self) -> u16 {
         self.operand_addr
     }
 
-    fn apply_page_cross_penality(&self, cpu: &mut Cpu) -> Result<()> {
-        if self.page_cross {
-            cpu.advance_clock(1)
-        } else {
-            Ok(())
-        }
+    fn uncorrected_addr(&self) -> Option<u16> {
+        Some(uncorrected_addr(self.base_addr, self.operand_addr))
     }
 
     fn format(&self, _cpu: &Cpu) -> String {
@@ -786,13 +903,24 @@ struct IndirectX {
 impl Operand for IndirectX {
     const OPERAND_SIZE: usize = 1;
 
-    fn load(cpu: &Cpu, addr: u16) -> Result<Self> {
-        let indirect_addr = peek_to_result(cpu.bus.peek(addr + 1))?.wrapping_add(cpu.x);
+    fn peek(cpu: &Cpu, addr: u16) -> Result<Self> {
+        let indirect_addr = peek_to_result(cpu.bus.peek(addr.wrapping_add(1)))?.wrapping_add(cpu.x);
+        let operand_addr = peek_to_result(cpu.bus.zero_page_peek_u16(indirect_addr))?;
+        Ok(Self {
+            indirect_addr,
+            operand_addr,
+        })
+    }
+
+    /// The pointer is read while the index is added.
+    fn fetch(cpu: &mut Cpu, addr: u16) -> Result<Self> {
+        let pointer = cpu.read(addr.wrapping_add(1))?;
+        cpu.read(pointer as u16)?;
+        let indirect_addr = pointer.wrapping_add(cpu.x);
         let operand_addr = u16::from_le_bytes([
-            cpu.bus.peek(indirect_addr as u16).unwrap(),
-            cpu.bus.peek(indirect_addr.wrapping_add(1) as u16).unwrap(),
+            cpu.read(indirect_addr as u16)?,
+            cpu.read(indirect_addr.wrapping_add(1) as u16)?,
         ]);
-        cpu.bus.peek(0x00).unwrap(); // Fake read for extra cycle.
         Ok(Self {
             indirect_addr,
             operand_addr,
@@ -820,11 +948,11 @@ pub fn update_negative_zero_flags(cpu: &mut Cpu, value: u8) {
 }
 
 fn branch(cpu: &mut Cpu, target_addr: u16) -> Result<()> {
-    // Branch across pages take one more cycle
-    if target_addr & 0xFF00 == cpu.program_counter & 0xFF00 {
-        cpu.advance_clock(1)?;
-    } else {
-        cpu.advance_clock(2)?;
+    // The next opcode is read while the offset is added. Branches across pages read from the
+    // uncorrected address while fixing the high byte.
+    cpu.read(cpu.program_counter)?;
+    if target_addr & 0xFF00 != cpu.program_counter & 0xFF00 {
+        cpu.read(uncorrected_addr(cpu.program_counter, target_addr))?;
     }
     cpu.program_counter = target_addr;
     Ok(())
@@ -849,17 +977,23 @@ fn jmp<AM: Operand>(cpu: &mut Cpu, operand: AM) -> Result<()> {
 }
 
 fn jsr<AM: Operand>(cpu: &mut Cpu, operand: AM) -> Result<()> {
+    cpu.dummy_stack_read()?;
     cpu.stack_push_u16(cpu.program_counter - 1)?;
     cpu.program_counter = operand.operand_addr();
     Ok(())
 }
 
 fn rts<AM: Operand>(cpu: &mut Cpu, _operand: AM) -> Result<()> {
-    cpu.program_counter = cpu.stack_pop_u16()? + 1;
+    cpu.dummy_stack_read()?;
+    let return_addr = cpu.stack_pop_u16()?;
+    // The return address is read while it is incremented.
+    cpu.read(return_addr)?;
+    cpu.program_counter = return_addr.wrapping_add(1);
     Ok(())
 }
 
 fn rti<AM: Operand>(cpu: &mut Cpu, _operand: AM) -> Result<()> {
+    cpu.dummy_stack_read()?;
     pop_status_flags(cpu)?;
     cpu.program_counter = cpu.stack_pop_u16()?;
     Ok(())
@@ -884,31 +1018,29 @@ fn sty<AM: Operand>(cpu: &mut Cpu, operand: AM) -> Result<()> {
 fn lda<AM: Operand>(cpu: &mut Cpu, operand: AM) -> Result<()> {
     cpu.a = operand.load_operand(cpu)?;
     update_negative_zero_flags(cpu, cpu.a);
-    operand.apply_page_cross_penality(cpu)?;
     Ok(())
 }
 
 fn ldy<AM: Operand>(cpu: &mut Cpu, operand: AM) -> Result<()> {
     cpu.y = operand.load_operand(cpu)?;
     update_negative_zero_flags(cpu, cpu.y);
-    operand.apply_page_cross_penality(cpu)?;
     Ok(())
 }
 
 fn ldx<AM: Operand>(cpu: &mut Cpu, operand: AM) -> Result<()> {
     cpu.x = operand.load_operand(cpu)?;
     update_negative_zero_flags(cpu, cpu.x);
-    operand.apply_page_cross_penality(cpu)?;
     Ok(())
 }
 
 // IN* (Increment)
 
 fn inc<AM: Operand>(cpu: &mut Cpu, operand: AM) -> Result<()> {
-    let value = operand.load_operand(cpu)?.wrapping_add(1);
-    operand.store_operand(cpu, value)?;
-    update_negative_zero_flags(cpu, value);
-    Ok(())
+    operand.modify_operand(cpu, |cpu, value| {
+        let result = value.wrapping_add(1);
+        update_negative_zero_flags(cpu, result);
+        result
+    })
 }
 
 fn inx<AM: Operand>(cpu: &mut Cpu, _operand: AM) -> Result<()> {
@@ -926,10 +1058,11 @@ fn iny<AM: Operand>(cpu: &mut Cpu, _operand: AM) -> Result<()> {
 // DE* (Decrement)
 
 fn dec<AM: Operand>(cpu: &mut Cpu, operand: AM) -> Result<()> {
-    let value = operand.load_operand(cpu)?.wrapping_sub(1);
-    operand.store_operand(cpu, value)?;
-    update_negative_zero_flags(cpu, value);
-    Ok(())
+    operand.modify_operand(cpu, |cpu, value| {
+        let result = value.wrapping_sub(1);
+        update_negative_zero_flags(cpu, result);
+        result
+    })
 }
 
 fn dex<AM: Operand>(cpu: &mut Cpu, _operand: AM) -> Result<()> {
@@ -1047,12 +1180,14 @@ fn php<AM: Operand>(cpu: &mut Cpu, _operand: AM) -> Result<()> {
 }
 
 fn pla<AM: Operand>(cpu: &mut Cpu, _operand: AM) -> Result<()> {
+    cpu.dummy_stack_read()?;
     cpu.a = cpu.stack_pop()?;
     update_negative_zero_flags(cpu, cpu.a);
     Ok(())
 }
 
 fn plp<AM: Operand>(cpu: &mut Cpu, _operand: AM) -> Result<()> {
+    cpu.dummy_stack_read()?;
     pop_status_flags(cpu)?;
     Ok(())
 }
@@ -1132,45 +1267,45 @@ fn cpy<AM: Operand>(cpu: &mut Cpu, operand: AM) -> Result<()> {
 // Shifts
 
 fn lsr<AM: Operand>(cpu: &mut Cpu, operand: AM) -> Result<()> {
-    let value = operand.load_operand(cpu)?;
-    let (result, _) = value.overflowing_shr(1);
-    operand.store_operand(cpu, result)?;
-    update_negative_zero_flags(cpu, result);
-    cpu.status_flags.carry = (value & 0x01) != 0;
-    Ok(())
+    operand.modify_operand(cpu, |cpu, value| {
+        let result = value >> 1;
+        update_negative_zero_flags(cpu, result);
+        cpu.status_flags.carry = (value & 0x01) != 0;
+        result
+    })
 }
 
 fn asl<AM: Operand>(cpu: &mut Cpu, operand: AM) -> Result<()> {
-    let value = operand.load_operand(cpu)?;
-    let (result, _) = value.overflowing_shl(1);
-    operand.store_operand(cpu, result)?;
-    update_negative_zero_flags(cpu, result);
-    cpu.status_flags.carry = (value & 0x80) != 0;
-    Ok(())
+    operand.modify_operand(cpu, |cpu, value| {
+        let result = value << 1;
+        update_negative_zero_flags(cpu, result);
+        cpu.status_flags.carry = (value & 0x80) != 0;
+        result
+    })
 }
 
 fn ror<AM: Operand>(cpu: &mut Cpu, operand: AM) -> Result<()> {
-    let operand2 = operand.load_operand(cpu)?;
-    let (mut result, _) = operand2.overflowing_shr(1);
-    if cpu.status_flags.carry {
-        result |= 0b1000_0000;
-    }
-    operand.store_operand(cpu, result)?;
-    update_negative_zero_flags(cpu, result);
-    cpu.status_flags.carry = (operand2 & 0x01) != 0;
-    Ok(())
+    operand.modify_operand(cpu, |cpu, value| {
+        let mut result = value >> 1;
+        if cpu.status_flags.carry {
+            result |= 0b1000_0000;
+        }
+        update_negative_zero_flags(cpu, result);
+        cpu.status_flags.carry = (value & 0x01) != 0;
+        result
+    })
 }
 
 fn rol<AM: Operand>(cpu: &mut Cpu, operand: AM) -> Result<()> {
-    let operand2 = operand.load_operand(cpu)?;
-    let (mut result, _) = operand2.overflowing_shl(1);
-    if cpu.status_flags.carry {
-        result |= 0b0000_0001;
-    }
-    operand.store_operand(cpu, result)?;
-    update_negative_zero_flags(cpu, result);
-    cpu.status_flags.carry = (operand2 & 0x80) != 0;
-    Ok(())
+    operand.modify_operand(cpu, |cpu, value| {
+        let mut result = value << 1;
+        if cpu.status_flags.carry {
+            result |= 0b0000_0001;
+        }
+        update_negative_zero_flags(cpu, result);
+        cpu.status_flags.carry = (value & 0x80) != 0;
+        result
+    })
 }
 
 // Register Transfers
@@ -1221,12 +1356,13 @@ fn bit<AM: Operand>(cpu: &mut Cpu, operand: AM) -> Result<()> {
     Ok(())
 }
 
-fn hlt<AM: Operand>(cpu: &mut Cpu, _operand: AM) -> Result<()> {
-    cpu.halt = true;
-    Ok(())
+fn brk<AM: Operand>(cpu: &mut Cpu, _operand: AM) -> Result<()> {
+    // BRK skips the padding byte following the opcode.
+    cpu.enter_interrupt(InterruptVector::Irq, cpu.program_counter + 1, true)
 }
 
-fn sei<AM: Operand>(_cpu: &mut Cpu, _operand: AM) -> Result<()> {
+fn sei<AM: Operand>(cpu: &mut Cpu, _operand: AM) -> Result<()> {
+    cpu.status_flags.interrupt = true;
     Ok(())
 }
 
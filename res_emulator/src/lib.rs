@@ -65,7 +65,7 @@ impl System {
         &self.cpu.bus.cartridge
     }
 
-    pub fn tick(&mut self) -> Result<bool> {
+    pub fn tick(&mut self) -> Result<()> {
         self.cpu.execute_one()
     }
 
@@ -167,11 +167,62 @@ impl System {
         Ok(system)
     }
 
+    /// Loads a ROM in any of the formats supported by `Cartridge::load`, which is detected
+    /// from the contents. FDS images require the FDS BIOS.
+    pub fn with_rom_bytes(
+        bytes: &[u8],
+        persistent_data: Option<&[u8]>,
+        fds_bios: Option<&[u8]>,
+    ) -> Result<System> {
+        let mut system = System::new();
+        system
+            .cpu
+            .bus
+            .cartridge
+            .borrow_mut()
+            .load(bytes, persistent_data, fds_bios)?;
+        system.reset()?;
+        system.cpu.boot()?;
+        Ok(system)
+    }
+
+    /// Loads a Famicom Disk System image, which requires the FDS BIOS.
+    pub fn with_fds_bytes(
+        bytes: &[u8],
+        bios: &[u8],
+        persistent_data: Option<&[u8]>,
+    ) -> Result<System> {
+        let mut system = System::new();
+        system
+            .cpu
+            .bus
+            .cartridge
+            .borrow_mut()
+            .load_fds(bytes, bios, persistent_data)?;
+        system.reset()?;
+        system.cpu.boot()?;
+        Ok(system)
+    }
+
+    /// Loads an NSF or NSFe music file and starts playing its starting track.
+    pub fn with_nsf_bytes(bytes: &[u8]) -> Result<System> {
+        let mut system = System::new();
+        system.cpu.bus.cartridge.borrow_mut().load_nsf(bytes)?;
+        system.reset()?;
+        system.cpu.boot()?;
+        Ok(system)
+    }
+
     pub fn with_snapshot(snapshot: &[u8]) -> Result<System> {
+        let (mut cpu, _): (Cpu, usize) =
+            bincode::decode_from_slice(snapshot, bincode::config::standard())
+                .map_err(|e| anyhow!("Invalid snapshot: {e}"))?;
+        // Decoding creates a separate copy for each Rc. Re-link the cartridge and debugger so
+        // they are shared between the CPU, bus and PPU again.
+        cpu.bus.ppu.cartridge = cpu.bus.cartridge.clone();
+        cpu.bus.debugger = cpu.debugger.clone();
         Ok(System {
-            cpu: bincode::decode_from_slice(snapshot, bincode::config::standard())
-                .unwrap()
-                .0,
+            cpu,
             ..System::new()
         })
     }
@@ -182,10 +233,6 @@ impl System {
     {
         self.cpu.debugger.borrow_mut().start_execution();
         loop {
-            if self.cpu.halt {
-                return Err(anyhow!("CPU halted"));
-            }
-
             if let Err(e) = self.cpu.execute_one() {
                 return Err(anyhow!("Execution failed: {:?}", e));
             }
@@ -204,10 +251,6 @@ impl System {
         }
     }
 
-    pub fn execute_until_halt(&mut self) -> Result<()> {
-        self.execute_until(|cpu| cpu.halt)
-    }
-
     #[instrument(skip_all)]
     pub fn execute_one_frame(&mut self) -> Result<()> {
         let current_frame = self.ppu().frame;
@@ -234,4 +277,40 @@ impl System {
         self.cpu.program_counter = self.cpu.bus.read_u16(0xFFFC_u16)?;
         Ok(())
     }
+
+    /// Restarts music players (e.g. NSF files) with the given track, starting at 0.
+    pub fn select_track(&mut self, track: usize) -> Result<()> {
+        let track_count = self.cartridge().borrow().track_count();
+        if track >= track_count {
+            return Err(anyhow!(
+                "Invalid track {track}, the cartridge has {track_count} tracks"
+            ));
+        }
+        self.cartridge().borrow_mut().select_track(track);
+        self.soft_reset()
+    }
+
+    /// Plays a track of a music player for the given duration and returns the audio output.
+    /// Can be written to a WAV file with `util::write_wav`.
+    pub fn render_track(
+        &mut self,
+        track: usize,
+        seconds: f64,
+        sample_rate: usize,
+    ) -> Result<Vec<f32>> {
+        self.select_track(track)?;
+        self.cpu.bus.apu.audio_sample_rate = sample_rate;
+        self.cpu.bus.apu.audio_buffer.clear();
+        self.delta_t_accumulator = 0.0;
+        self.execute_for_duration(seconds)?;
+        Ok(std::mem::take(&mut self.cpu.bus.apu.audio_buffer))
+    }
+
+    /// Emulates pressing the reset button of the console. Unlike `reset`, which is used on
+    /// power up, this keeps RAM and runs the full CPU reset sequence.
+    pub fn soft_reset(&mut self) -> Result<()> {
+        self.cpu.bus.ppu.reset();
+        self.cpu.bus.apu.reset();
+        self.cpu.reset()
+    }
 }
@@ -1,3 +1,5 @@
+mod pipeline;
+
 use std::cell::RefCell;
 use std::fmt::Display;
 use std::fmt::Formatter;
@@ -13,9 +15,11 @@ use itertools::Itertools;
 use packed_struct::prelude::*;
 use thiserror::Error;
 
+use self::pipeline::RenderPipeline;
 use super::cartridge::Cartridge;
 use super::cartridge::CartridgeError;
 use super::cartridge::MirroringMode;
+use super::cartridge::PpuFetchPhase;
 
 #[derive(Error)]
 pub enum PpuError {
@@ -25,6 +29,8 @@ pub enum PpuError {
     InvalidBusWrite(u16),
     #[error("Invalid peek from 0x{0:04X}")]
     InvalidBusPeek(u16),
+    #[error("Address 0x{0:04X} is not mapped to VRAM")]
+    InvalidVramAddress(u16),
     #[error(transparent)]
     CartridgeError(#[from] CartridgeError),
 }
@@ -49,14 +55,28 @@ const DATA_REGISTER_ADDR: u16 = 0x2007;
 const FRAME_WIDTH: usize = 32 * 8;
 const FRAME_HEIGHT: usize = 30 * 8;
 
+// A12 has to be low for about 3 CPU cycles before a rising edge is reported to the cartridge.
+const A12_FILTER_CYCLES: usize = 9;
+
 ////////////////////////////////////////////////////////////////////////////////
 // PPU
 
+/// Selects how the PPU produces pixels.
+#[derive(Default, Encode, Decode, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RenderMode {
+    /// Cycle accurate renderer that fetches and outputs one pixel per dot.
+    Dot,
+    /// Faster renderer that draws a whole scanline at once. Mid-scanline changes to the
+    /// PPU state are not visible.
+    #[default]
+    Scanline,
+}
+
 #[derive(Encode, Decode, Clone)]
 pub struct Ppu {
     pub cartridge: Rc<RefCell<Cartridge>>,
     pub palette_table: [u8; 32],
-    pub vram: [u8; 0x2000],
+    pub vram: [u8; 0x0800],
     pub oam_data: [u8; 256],
     pub internal_data_buffer: u8,
     pub cycle: usize,
@@ -76,6 +96,18 @@ pub struct Ppu {
     pub nmi_interrupt: bool,
     pub vblank: bool,
 
+    /// Last address the PPU put on its address bus.
+    pub address_bus: u16,
+    pub a12_high: bool,
+    pub a12_low_cycles: usize,
+    pub sprite_a12_mask: u8,
+
+    pub render_mode: RenderMode,
+    /// Render all sprites on a scanline instead of at most 8. Avoids flickering, but is not
+    /// accurate to hardware.
+    pub disable_sprite_limit: bool,
+    pipeline: RenderPipeline,
+
     pub framebuffer: Framebuffer,
 }
 
@@ -83,7 +115,7 @@ impl Ppu {
     pub fn new(cartridge: Rc<RefCell<Cartridge>>) -> Self {
         Self {
             cartridge,
-            vram: [0; 0x2000],
+            vram: [0; 0x0800],
             oam_data: [0; 256],
             palette_table: [0; 32],
             internal_data_buffer: 0,
@@ -104,10 +136,30 @@ impl Ppu {
             nmi_interrupt: false,
             vblank: false,
 
+            address_bus: 0,
+            a12_high: false,
+            a12_low_cycles: 0,
+            sprite_a12_mask: 0,
+
+            render_mode: RenderMode::default(),
+            disable_sprite_limit: false,
+            pipeline: RenderPipeline::default(),
+
             framebuffer: Framebuffer::default(),
         }
     }
 
+    /// Called when the reset button is pressed. Clears PPUCTRL, PPUMASK, PPUSCROLL and the
+    /// read buffer, but keeps VRAM, OAM and the current position in the frame.
+    pub fn reset(&mut self) {
+        self.control_register = ControlRegister::default();
+        self.mask_register = MaskRegister::default();
+        self.t_register = VramAddress::default();
+        self.fine_scroll_x = 0;
+        self.register_latch = false;
+        self.internal_data_buffer = 0;
+    }
+
     pub fn advance_clock(&mut self, cycles: usize) -> PpuResult<()> {
         for _ in 0..cycles {
             self.tick()?;
@@ -117,6 +169,15 @@ impl Ppu {
 
     fn tick(&mut self) -> PpuResult<()> {
         self.cycle += 1;
+        // The last dot of the pre-render scanline is skipped on odd frames while rendering.
+        if self.render_mode == RenderMode::Dot
+            && self.scanline == 261
+            && self.cycle == 340
+            && self.frame % 2 == 1
+            && self.rendering_enabled()
+        {
+            self.cycle = 341;
+        }
         if self.cycle == 341 {
             self.cycle = 0;
             self.scanline += 1;
@@ -125,54 +186,84 @@ impl Ppu {
             self.scanline = 0;
             self.frame += 1;
         }
+        if !self.a12_high {
+            self.a12_low_cycles = self.a12_low_cycles.saturating_add(1);
+        }
 
-        match self.scanline {
-            // Visible scanlines
-            0..=239 => {
-                match self.cycle {
-                    2..=255 | 320.. => {
-                        // Increment x every 8 cycles during visible or pre-render cycles
-                        if self.cycle % 8 == 0 && self.mask_register.show_background {
-                            self.v_register.increment_x();
-                        }
-                    }
-                    256 => {
-                        // Increment y at end of visible cycles
-                        if self.mask_register.show_background {
-                            self.v_register.increment_y();
-                        }
-                    }
-                    257 => {
-                        // Reset x at end of visible cycles
-                        if self.mask_register.show_background {
-                            self.v_register.set_coarse_x(self.t_register.coarse_x());
-                            self.v_register
-                                .set_nametable_x(self.t_register.nametable_x());
-                        }
-                    }
-                    _ => (),
-                }
-            }
+        match (self.scanline, self.cycle) {
             // Start of vblank
-            241 => {
-                if self.cycle == 1 {
-                    self.status_register.vblank_started = true;
-                    self.vblank = true;
-                    if self.control_register.generate_nmi {
-                        self.nmi_interrupt = true;
-                    }
+            (241, 1) => {
+                self.status_register.vblank_started = true;
+                self.vblank = true;
+                if self.control_register.generate_nmi {
+                    self.nmi_interrupt = true;
                 }
             }
             // Start of pre-render
-            261 => {
+            (261, 1) => {
+                self.status_register.vblank_started = false;
+                self.status_register.sprite_zero_hit = false;
+                self.status_register.sprite_overflow = false;
+                self.vblank = false;
+            }
+            _ => (),
+        }
+        if self.cycle == 1 {
+            self.cartridge
+                .borrow_mut()
+                .ppu_scanline_start(self.scanline, self.rendering_enabled());
+        }
+
+        match self.render_mode {
+            RenderMode::Dot => self.tick_dot_pipeline(),
+            RenderMode::Scanline => self.tick_scanline_renderer(),
+        }
+    }
+
+    pub fn rendering_enabled(&self) -> bool {
+        self.mask_register.show_background || self.mask_register.show_sprites
+    }
+
+    fn update_address_bus(&mut self, addr: u16) {
+        if addr == self.address_bus {
+            return;
+        }
+        self.address_bus = addr;
+        self.cartridge.borrow_mut().ppu_address_changed(addr);
+        self.update_a12(addr.bit(12));
+    }
+
+    /// Follows A12 of the address bus and reports filtered rising edges to the cartridge.
+    fn update_a12(&mut self, a12: bool) {
+        if a12 && !self.a12_high {
+            if self.a12_low_cycles >= A12_FILTER_CYCLES {
+                self.cartridge.borrow_mut().ppu_a12_rising_edge();
+            }
+            self.a12_high = true;
+        } else if !a12 && self.a12_high {
+            self.a12_high = false;
+            self.a12_low_cycles = 0;
+        }
+    }
+
+    /// Tells the cartridge whether the following pattern fetches are for the background or
+    /// for sprites.
+    fn start_fetch_phase(&self, phase: PpuFetchPhase) {
+        self.cartridge
+            .borrow_mut()
+            .ppu_fetch_phase(phase, self.control_register.large_sprite_mode);
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////
+    // Scanline Rendering
+
+    fn tick_scanline_renderer(&mut self) -> PpuResult<()> {
+        match self.scanline {
+            // Visible and pre-render scanlines
+            0..=239 | 261 => {
                 match self.cycle {
-                    1 => {
-                        self.status_register.vblank_started = false;
-                        self.status_register.sprite_zero_hit = false;
-                        self.vblank = false;
-                    }
                     2..=255 | 320.. => {
-                        // Increment x every 8 cycles
+                        // Increment x every 8 cycles during visible or pre-render cycles
                         if self.cycle % 8 == 0 && self.mask_register.show_background {
                             self.v_register.increment_x();
                         }
@@ -191,7 +282,7 @@ impl Ppu {
                                 .set_nametable_x(self.t_register.nametable_x());
                         }
                     }
-                    280..=304 => {
+                    280..=304 if self.scanline == 261 => {
                         if self.mask_register.show_background {
                             self.v_register = self.t_register.clone();
                         }
@@ -202,6 +293,8 @@ impl Ppu {
             _ => (),
         }
 
+        self.emulate_pattern_fetches();
+
         // Shortcut: Render the whole scanline at once at cycle 255.
         if self.scanline < 240 && self.cycle == 255 {
             let sprite_0_hit = self.render_scanline()?;
@@ -213,23 +306,103 @@ impl Ppu {
         Ok(())
     }
 
-    ////////////////////////////////////////////////////////////////////////////////
-    // Scanline Rendering
+    /// The scanline renderer does not fetch pattern data at the right time. Emulate A12 of the
+    /// address the PPU would put on the bus at this cycle so the cartridge sees its edges. The
+    /// emulated addresses are not real fetches, so they are not reported to
+    /// `Mapper::ppu_address_changed`.
+    fn emulate_pattern_fetches(&mut self) {
+        if !self.rendering_enabled() || (self.scanline >= 240 && self.scanline != 261) {
+            return;
+        }
+        if self.cycle == 257 {
+            self.sprite_a12_mask = self.sprite_fetch_a12_mask();
+        }
+        // Nametable and attribute fetches ($2000-$2FFF) keep A12 low.
+        let a12 = match self.cycle {
+            1..=256 | 321..=336 => match (self.cycle - 1) % 8 {
+                4..=7 => self.control_register.background_pattern_addr,
+                _ => false,
+            },
+            257..=320 => match (self.cycle - 257) % 8 {
+                4..=7 => self.sprite_a12_mask.bit((self.cycle - 257) / 8),
+                _ => false,
+            },
+            337..=340 => false,
+            _ => return,
+        };
+        self.update_a12(a12);
+    }
+
+    /// Returns which pattern table each of the 8 sprite fetches of this scanline will access.
+    fn sprite_fetch_a12_mask(&self) -> u8 {
+        if !self.control_register.large_sprite_mode {
+            return if self.control_register.sprite_pattern_addr {
+                0xFF
+            } else {
+                0x00
+            };
+        }
+        // Unused slots fetch tile 0xFF, which is located in the upper pattern table in 8x16 mode.
+        // The first 8 sprites in OAM order are fetched, even if the sprite limit is disabled.
+        let mut mask = 0xFF_u8;
+        if self.scanline < 240 {
+            let sprites = (0..64)
+                .filter(|n| self.sprite_on_scanline(self.oam_data[n * 4], self.scanline))
+                .take(8);
+            for (slot, n) in sprites.enumerate() {
+                mask.set_bit(slot, self.oam_data[n * 4 + 1].bit(0));
+            }
+        }
+        mask
+    }
 
     fn collect_sprites_on_scanline(&self, scanline: usize) -> impl Iterator<Item = Sprite> + '_ {
-        (0..64)
-            .filter_map(move |i| {
-                let sprite = Sprite::new(self, i);
-                let delta_y = (scanline as i32 - sprite.data.y as i32) as usize;
-                if (0..self.get_sprite_height()).contains(&delta_y) {
-                    Some(sprite)
-                } else {
-                    None
-                }
-            })
+        let (sprite_ids, _) = self.evaluate_sprites(scanline);
+        sprite_ids
+            .into_iter()
+            .map(move |i| Sprite::new(self, i))
             .rev()
     }
 
+    /// Returns the ids of the sprites that are rendered on the scanline and whether the
+    /// sprite overflow flag is set while evaluating it.
+    fn evaluate_sprites(&self, scanline: usize) -> (Vec<usize>, bool) {
+        let mut sprite_ids = Vec::new();
+        let mut n = 0;
+        while n < 64 && sprite_ids.len() < 8 {
+            if self.sprite_on_scanline(self.oam_data[n * 4], scanline) {
+                sprite_ids.push(n);
+            }
+            n += 1;
+        }
+        let limit_index = n;
+
+        // Search for a 9th sprite. The hardware increments m along with n, which results in
+        // the overflow flag being set or missed incorrectly.
+        let mut overflow = false;
+        let mut m = 0;
+        while n < 64 {
+            if self.sprite_on_scanline(self.oam_data[n * 4 + m], scanline) {
+                overflow = true;
+                break;
+            }
+            n += 1;
+            m = (m + 1) % 4;
+        }
+
+        if self.disable_sprite_limit {
+            sprite_ids.extend(
+                (limit_index..64)
+                    .filter(|n| self.sprite_on_scanline(self.oam_data[n * 4], scanline)),
+            );
+        }
+        (sprite_ids, overflow)
+    }
+
+    fn sprite_on_scanline(&self, y: u8, scanline: usize) -> bool {
+        (0..self.get_sprite_height() as i32).contains(&(scanline as i32 - y as i32))
+    }
+
     pub fn get_nametable_entry(&self, coarse_x: usize, coarse_y: usize) -> PpuResult<usize> {
         let addr = 0x2000 + coarse_y * 0x20 + coarse_x;
         Ok(self.read_ppu_memory(addr as u16)? as usize)
@@ -243,6 +416,9 @@ impl Ppu {
         let mut pixels = [(0_u8, 0_u8); 32 * 8];
 
         // Write background pixels to buffer
+        self.start_fetch_phase(PpuFetchPhase::Background {
+            scanline: self.scanline,
+        });
         if self.mask_register.show_background {
             // Create a temporary copy of the v_register since we are drawing a whole scanline.
             // Make sure to reset the x location to the beginning of the scanline.
@@ -251,10 +427,10 @@ impl Ppu {
             addr.set_nametable_x(self.t_register.nametable_x());
 
             for coarse_x in 0..33 {
-                let background = NametableEntry::new(self, &addr)?;
+                let background = NametableEntry::fetch(self, &addr)?;
                 for (fine_x, pixel) in background
                     .pattern
-                    .row_pixels(self, addr.fine_y() as usize)?
+                    .fetch_row_pixels(self, addr.fine_y() as usize)?
                     .enumerate()
                 {
                     let screen_x = coarse_x * 8 + fine_x as usize;
@@ -270,7 +446,11 @@ impl Ppu {
         }
 
         // Add sprite pixels
+        if self.rendering_enabled() && self.evaluate_sprites(self.scanline).1 {
+            self.status_register.sprite_overflow = true;
+        }
         if self.mask_register.show_sprites {
+            self.start_fetch_phase(PpuFetchPhase::Sprites);
             for sprite in self.collect_sprites_on_scanline(self.scanline) {
                 let sprite_row = screen_y - sprite.data.y as usize;
                 for (fine_x, pixel) in sprite.row_pixels(self, sprite_row)?.enumerate() {
@@ -317,42 +497,28 @@ impl Ppu {
     ////////////////////////////////////////////////////////////////////////////////
     // PPU Bus
 
-    pub fn map_vram_addr_to_index(&self, addr: u16) -> u16 {
-        let addr = if addr >= 0x3000 { addr - 0x1000 } else { addr };
-        match self.cartridge.borrow().get_mirroring_mode() {
-            MirroringMode::Horizontal => match addr {
-                0x2000..=0x23FF => addr - 0x2000,
-                0x2400..=0x27FF => addr - 0x2400,
-                0x2800..=0x2BFF => addr - 0x2800 + 0x0400,
-                0x2C00..=0x2FFF => addr - 0x2C00 + 0x0400,
-                _ => panic!("Invalid vram addr: {addr:04X}"),
-            },
-            MirroringMode::Vertical => match addr {
-                0x2000..=0x23FF => addr - 0x2000,
-                0x2400..=0x27FF => addr - 0x2400 + 0x0400,
-                0x2800..=0x2BFF => addr - 0x2800,
-                0x2C00..=0x2FFF => addr - 0x2C00 + 0x0400,
-                _ => panic!("Invalid vram addr: {addr:04X}"),
-            },
-            MirroringMode::FourScreen => match addr {
-                0x2000..=0x2FFF => addr - 0x2000,
-                _ => panic!("Invalid vram addr: {addr:04X}"),
-            },
-            MirroringMode::SingleLower => match addr {
-                0x2000..=0x23FF => addr - 0x2000,
-                0x2400..=0x27FF => addr - 0x2400,
-                0x2800..=0x2BFF => addr - 0x2800,
-                0x2C00..=0x2FFF => addr - 0x2C00,
-                _ => panic!("Invalid vram addr: {addr:04X}"),
-            },
-            MirroringMode::SingleUpper => match addr {
-                0x2000..=0x23FF => addr - 0x2000 + 0x0400,
-                0x2400..=0x27FF => addr - 0x2400 + 0x0400,
-                0x2800..=0x2BFF => addr - 0x2800 + 0x0400,
-                0x2C00..=0x2FFF => addr - 0x2C00 + 0x0400,
-                _ => panic!("Invalid vram addr: {addr:04X}"),
-            },
+    /// Maps a nametable address to the internal 2k VRAM (CIRAM) of the PPU according to the
+    /// mirroring mode of the cartridge.
+    pub fn map_vram_addr_to_index(&self, addr: u16) -> PpuResult<usize> {
+        if !(0x2000..=0x3EFF).contains(&addr) {
+            return Err(PpuError::InvalidVramAddress(addr));
         }
+        let quadrant = (addr as usize & 0x0FFF) / 0x0400;
+        let page = match self.cartridge.borrow().get_mirroring_mode() {
+            MirroringMode::Horizontal => quadrant / 2,
+            MirroringMode::Vertical => quadrant % 2,
+            // The upper two nametables are provided by VRAM on the cartridge.
+            MirroringMode::FourScreen if quadrant < 2 => quadrant,
+            MirroringMode::FourScreen => return Err(PpuError::InvalidVramAddress(addr)),
+            MirroringMode::SingleLower => 0,
+            MirroringMode::SingleUpper => 1,
+            MirroringMode::Custom(pages) => pages[quadrant] as usize,
+        };
+        let index = page * 0x0400 + addr as usize % 0x0400;
+        if index >= self.vram.len() {
+            return Err(PpuError::InvalidVramAddress(addr));
+        }
+        Ok(index)
     }
 
     pub fn peek_slice(&self, addr: u16, length: u16) -> impl Iterator<Item = Option<u8>> + '_ {
@@ -362,7 +528,14 @@ impl Ppu {
     pub fn peek_ppu_memory(&self, addr: u16) -> Option<u8> {
         match addr {
             0..=0x1FFF => self.cartridge.borrow_mut().ppu_bus_peek(addr),
-            0x2000..=0x3EFF => Some(self.vram[self.map_vram_addr_to_index(addr) as usize]),
+            0x2000..=0x3EFF => self
+                .cartridge
+                .borrow()
+                .nametable_peek(addr & 0x2FFF)
+                .or_else(|| {
+                    let index = self.map_vram_addr_to_index(addr).ok()?;
+                    Some(self.vram[index])
+                }),
             0x3F00..=0xFFFF => Some(self.palette_table[(addr as usize - 0x3F00) % 0x20]),
         }
     }
@@ -372,6 +545,22 @@ impl Ppu {
             .ok_or(PpuError::InvalidBusRead(addr))
     }
 
+    /// Reads pattern data during rendering. Unlike `read_ppu_memory` the read is visible to the
+    /// cartridge, which allows mappers to switch banks based on the fetched tiles (e.g. MMC2).
+    pub fn fetch_pattern_memory(&self, addr: u16) -> PpuResult<u8> {
+        Ok(self.cartridge.borrow_mut().ppu_bus_read(addr)?)
+    }
+
+    /// Reads nametable data during rendering. Like `fetch_pattern_memory` the read is visible
+    /// to the cartridge (e.g. for the MMC5 extended attributes).
+    pub fn fetch_nametable_memory(&self, addr: u16) -> PpuResult<u8> {
+        let value = self.cartridge.borrow_mut().nametable_fetch(addr & 0x2FFF);
+        match value {
+            Some(value) => Ok(value),
+            None => self.read_ppu_memory(addr),
+        }
+    }
+
     pub fn write_ppu_memory(&mut self, addr: u16, value: u8) -> PpuResult<()> {
         // Map memory addresses
         let addr = match addr {
@@ -384,7 +573,14 @@ impl Ppu {
                 Ok(())
             }
             0x2000..=0x3EFF => {
-                self.vram[self.map_vram_addr_to_index(addr) as usize] = value;
+                let handled = self
+                    .cartridge
+                    .borrow_mut()
+                    .nametable_write(addr & 0x2FFF, value);
+                if !handled {
+                    let index = self.map_vram_addr_to_index(addr)?;
+                    self.vram[index] = value;
+                }
                 Ok(())
             }
             0x3F00..=0xFFFF => {
@@ -410,6 +606,7 @@ impl Ppu {
 
     pub fn read_data_register(&mut self) -> PpuResult<u8> {
         let addr = self.increment_address_register();
+        self.update_address_bus(addr);
         let buffer = self.internal_data_buffer;
         self.internal_data_buffer = self.read_ppu_memory(addr)?;
         Ok(buffer)
@@ -417,6 +614,7 @@ impl Ppu {
 
     pub fn write_data_register(&mut self, value: u8) -> PpuResult<()> {
         let addr = self.increment_address_register();
+        self.update_address_bus(addr);
         self.write_ppu_memory(addr, value)
     }
 
@@ -487,6 +685,10 @@ impl Ppu {
                 if self.register_latch {
                     self.t_register.set_low_byte(value as u16);
                     self.v_register.value = self.t_register.value;
+                    // Outside of rendering, the address bus follows the v register.
+                    if !self.rendering_enabled() {
+                        self.update_address_bus(self.v_register.value.bits(0..14));
+                    }
                 } else {
                     self.t_register.set_high_byte(value.bits(0..=5) as u16);
                 }
@@ -651,6 +853,32 @@ impl NametableEntry {
     pub fn new(ppu: &Ppu, addr: &VramAddress) -> PpuResult<NametableEntry> {
         let nametable_value = ppu.read_ppu_memory(addr.tile_addr())?;
         let attr_byte = ppu.read_ppu_memory(addr.attribute_addr())?;
+        Ok(NametableEntry::from_bytes(
+            ppu,
+            addr,
+            nametable_value,
+            attr_byte,
+        ))
+    }
+
+    /// Like `new`, but the reads are visible to the cartridge as rendering fetches.
+    pub fn fetch(ppu: &Ppu, addr: &VramAddress) -> PpuResult<NametableEntry> {
+        let nametable_value = ppu.fetch_nametable_memory(addr.tile_addr())?;
+        let attr_byte = ppu.fetch_nametable_memory(addr.attribute_addr())?;
+        Ok(NametableEntry::from_bytes(
+            ppu,
+            addr,
+            nametable_value,
+            attr_byte,
+        ))
+    }
+
+    fn from_bytes(
+        ppu: &Ppu,
+        addr: &VramAddress,
+        nametable_value: u8,
+        attr_byte: u8,
+    ) -> NametableEntry {
         let attribute = match (addr.coarse_x() % 4 / 2, addr.coarse_y() % 4 / 2) {
             (0, 0) => attr_byte & 0b11,
             (1, 0) => (attr_byte >> 2) & 0b11,
@@ -659,13 +887,13 @@ impl NametableEntry {
             (_, _) => panic!("should not happen"),
         };
 
-        Ok(NametableEntry {
+        NametableEntry {
             pattern: Pattern::new(
                 ppu.control_register.background_pattern_addr as u8,
                 nametable_value,
             ),
             palette_id: attribute,
-        })
+        }
     }
 
     pub fn from_coarse_x_y(
@@ -741,7 +969,7 @@ impl Sprite {
         };
 
         let pattern = Pattern::new(bank_id, index);
-        let mut row: Vec<u8> = pattern.row_pixels(ppu, y % 8)?.collect();
+        let mut row: Vec<u8> = pattern.fetch_row_pixels(ppu, y % 8)?.collect();
         if self.data.attr.flip_h {
             row.reverse();
         }
@@ -784,16 +1012,31 @@ impl Pattern {
     }
 
     pub fn row_pixels(&self, ppu: &Ppu, y: usize) -> PpuResult<impl Iterator<Item = u8> + '_> {
-        let mut low = ppu.read_ppu_memory(self.addr + y as u16)?;
-        let mut high = ppu.read_ppu_memory(self.addr + y as u16 + 8)?;
+        let low = ppu.read_ppu_memory(self.addr + y as u16)?;
+        let high = ppu.read_ppu_memory(self.addr + y as u16 + 8)?;
+        Ok(Pattern::pixels(low, high))
+    }
+
+    /// Like `row_pixels`, but the reads are visible to the cartridge as they are during
+    /// rendering.
+    pub fn fetch_row_pixels(
+        &self,
+        ppu: &Ppu,
+        y: usize,
+    ) -> PpuResult<impl Iterator<Item = u8> + '_> {
+        let low = ppu.fetch_pattern_memory(self.addr + y as u16)?;
+        let high = ppu.fetch_pattern_memory(self.addr + y as u16 + 8)?;
+        Ok(Pattern::pixels(low, high))
+    }
 
-        Ok((0..8).map(move |_| {
+    fn pixels(mut low: u8, mut high: u8) -> impl Iterator<Item = u8> {
+        (0..8).map(move |_| {
             let low_bit = low & 0b1000_0000 > 0;
             let high_bit = high & 0b1000_0000 > 0;
             low <<= 1;
             high <<= 1;
             (high_bit as u8) << 1 | (low_bit as u8)
-        }))
+        })
     }
 }
 
@@ -1078,6 +1321,45 @@ mod tests {
         assert_eq!(ppu.cpu_bus_read(DATA_REGISTER_ADDR).unwrap(), 0x34);
     }
 
+    #[test]
+    pub fn test_address_bus_notifies_cartridge() {
+        // MMC3 cartridge with 32k PRG-ROM and 8k CHR-ROM.
+        let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, 0x02, 0x01, 0x40, 0x00];
+        rom.resize(16 + 40 * 1024, 0);
+        let mut ppu = create_test_ppu();
+        {
+            let mut cartridge = ppu.cartridge.borrow_mut();
+            cartridge.load_ines(&rom, None).unwrap();
+            // Raise an IRQ on the next A12 rising edge.
+            cartridge.cpu_bus_write(0xC000, 0).unwrap();
+            cartridge.cpu_bus_write(0xC001, 0).unwrap();
+            cartridge.cpu_bus_write(0xE001, 0).unwrap();
+        }
+
+        // Setting the address via $2006 while rendering is disabled is visible on the bus.
+        ppu.a12_low_cycles = A12_FILTER_CYCLES;
+        ppu.cpu_bus_write(ADDRESS_REGISTER_ADDR, 0x10).unwrap();
+        ppu.cpu_bus_write(ADDRESS_REGISTER_ADDR, 0x00).unwrap();
+        assert_eq!(ppu.address_bus, 0x1000);
+        assert!(ppu.cartridge.borrow().irq_active());
+    }
+
+    #[test]
+    pub fn test_vram_mirroring() {
+        let mut ppu = create_test_ppu();
+        ppu.write_ppu_memory(0x2000, 0x12).unwrap();
+        ppu.write_ppu_memory(0x2C00, 0x34).unwrap();
+        // Horizontal mirroring, with $3000-$3EFF mirroring $2000-$2EFF.
+        assert_eq!(ppu.peek_ppu_memory(0x2400), Some(0x12));
+        assert_eq!(ppu.peek_ppu_memory(0x2800), Some(0x34));
+        assert_eq!(ppu.peek_ppu_memory(0x3000), Some(0x12));
+        assert_eq!(ppu.map_vram_addr_to_index(0x2C01).unwrap(), 0x0401);
+
+        // Addresses outside of the nametables are errors instead of panics.
+        assert!(ppu.map_vram_addr_to_index(0x1000).is_err());
+        assert!(ppu.map_vram_addr_to_index(0x3F00).is_err());
+    }
+
     #[test]
     pub fn test_addr_register_clipping() {
         let mut ppu = create_test_ppu();
@@ -1124,4 +1406,111 @@ mod tests {
         assert_eq!(ppu.v_register.value, ppu.t_register.value);
         assert!(!ppu.register_latch);
     }
+
+    fn create_sprite_test_ppu(render_mode: RenderMode) -> Ppu {
+        let mut ppu = create_test_ppu();
+        // Tile 1 is a solid block of color 1.
+        let mut chr = vec![0; 0x2000];
+        chr[0x10..0x18].fill(0xFF);
+        ppu.cartridge.borrow_mut().load_nrom_with_data(&[], &chr);
+        ppu.palette_table[0x00] = 0x0F;
+        ppu.palette_table[0x11] = 0x16;
+        ppu.render_mode = render_mode;
+        ppu.mask_register.show_sprites = true;
+        ppu.mask_register.mask_sprites = true;
+        // Move all sprites off screen.
+        ppu.oam_data.fill(0xFF);
+        ppu
+    }
+
+    fn place_sprite(ppu: &mut Ppu, id: usize, y: u8, x: u8) {
+        ppu.oam_data[id * 4..id * 4 + 4].copy_from_slice(&[y, 1, 0, x]);
+    }
+
+    #[test]
+    pub fn test_sprite_limit() {
+        for render_mode in [RenderMode::Scanline, RenderMode::Dot] {
+            let mut ppu = create_sprite_test_ppu(render_mode);
+            for id in 0..9 {
+                place_sprite(&mut ppu, id, 10, id as u8 * 8);
+            }
+            ppu.advance_clock(341 * 20).unwrap();
+            assert!(ppu.status_register.sprite_overflow);
+            assert_eq!(ppu.framebuffer[(7 * 8, 12)], 0x16);
+            assert_eq!(ppu.framebuffer[(8 * 8, 12)], 0x0F);
+
+            let mut ppu = create_sprite_test_ppu(render_mode);
+            ppu.disable_sprite_limit = true;
+            for id in 0..9 {
+                place_sprite(&mut ppu, id, 10, id as u8 * 8);
+            }
+            ppu.advance_clock(341 * 20).unwrap();
+            assert!(ppu.status_register.sprite_overflow);
+            assert_eq!(ppu.framebuffer[(8 * 8, 12)], 0x16);
+        }
+    }
+
+    #[test]
+    pub fn test_sprite_overflow_bug() {
+        for render_mode in [RenderMode::Scanline, RenderMode::Dot] {
+            // After 8 sprites are found, the 9th y coordinate is checked as expected, but the
+            // 10th sprite is checked using its tile index.
+            let mut ppu = create_sprite_test_ppu(render_mode);
+            for id in 0..8 {
+                place_sprite(&mut ppu, id, 10, 0);
+            }
+            place_sprite(&mut ppu, 9, 10, 0);
+            ppu.advance_clock(341 * 20).unwrap();
+            assert!(!ppu.status_register.sprite_overflow);
+
+            // A tile index that is in range of the scanline triggers the overflow flag.
+            let mut ppu = create_sprite_test_ppu(render_mode);
+            for id in 0..8 {
+                place_sprite(&mut ppu, id, 10, 0);
+            }
+            ppu.oam_data[9 * 4 + 1] = 12;
+            ppu.advance_clock(341 * 20).unwrap();
+            assert!(ppu.status_register.sprite_overflow);
+        }
+    }
+
+    #[test]
+    pub fn test_dot_renderer_split_scroll() {
+        // Like the status bar of Super Mario Bros, the X scroll is changed in the middle of a
+        // visible scanline. The PPU copies it to v at dot 257, so it only affects the
+        // following scanlines.
+        let mut ppu = create_test_ppu();
+        // Tile n is a solid block of color n.
+        let mut chr = vec![0; 0x2000];
+        chr[0x10..0x18].fill(0xFF);
+        chr[0x28..0x30].fill(0xFF);
+        chr[0x30..0x40].fill(0xFF);
+        ppu.cartridge.borrow_mut().load_nrom_with_data(&[], &chr);
+        ppu.palette_table[0..4].copy_from_slice(&[0x0F, 0x16, 0x2A, 0x12]);
+        // Column n of the nametable shows tile n % 4.
+        for addr in 0x2000..0x23C0 {
+            ppu.write_ppu_memory(addr, (addr % 4) as u8).unwrap();
+        }
+        ppu.render_mode = RenderMode::Dot;
+        ppu.mask_register.show_background = true;
+        ppu.mask_register.mask_background = true;
+
+        // Skip the first frame, which starts without prefetched tiles.
+        while (ppu.frame, ppu.scanline, ppu.cycle) != (1, 31, 100) {
+            ppu.advance_clock(1).unwrap();
+        }
+        ppu.cpu_bus_write(0x2005, 16).unwrap();
+        ppu.cpu_bus_write(0x2005, 0).unwrap();
+        while ppu.frame == 1 {
+            ppu.advance_clock(1).unwrap();
+        }
+
+        for y in 0..FRAME_HEIGHT {
+            let scroll_x = if y <= 31 { 0 } else { 16 };
+            for x in 0..FRAME_WIDTH {
+                let expected = ppu.palette_table[(x + scroll_x) / 8 % 4];
+                assert_eq!(ppu.framebuffer[(x, y)], expected, "Pixel ({x}, {y})");
+            }
+        }
+    }
 }
@@ -0,0 +1,424 @@
+use bincode::Decode;
+use bincode::Encode;
+use intbits::Bits;
+
+use super::Ppu;
+use super::PpuFetchPhase;
+use super::PpuResult;
+
+/// State of the dot based rendering pipeline.
+///
+/// The background is rendered through 16 bit shift registers which are loaded with a new
+/// tile every 8 dots. Sprites for the next scanline are evaluated into secondary OAM during
+/// dots 65-256 and their pattern data is fetched during dots 257-320.
+#[derive(Default, Encode, Decode, Clone)]
+pub struct RenderPipeline {
+    next_tile_id: u8,
+    next_tile_attribute: u8,
+    next_tile_low: u8,
+    next_tile_high: u8,
+    pattern_shifter_low: u16,
+    pattern_shifter_high: u16,
+    attribute_shifter_low: u16,
+    attribute_shifter_high: u16,
+
+    secondary_oam: [u8; 32],
+    /// Sprites found beyond the 8 sprite limit when the limit is disabled.
+    extra_oam: Vec<u8>,
+    eval_n: usize,
+    eval_m: usize,
+    eval_count: usize,
+    eval_done: bool,
+    eval_sprite_zero: bool,
+    eval_limit_index: usize,
+
+    sprite_zero_on_line: bool,
+    sprites: Vec<SpriteSlot>,
+}
+
+#[derive(Default, Encode, Decode, Clone, Copy)]
+struct SpriteSlot {
+    pattern_low: u8,
+    pattern_high: u8,
+    attributes: u8,
+    x: u8,
+}
+
+impl RenderPipeline {
+    fn shift_background(&mut self) {
+        self.pattern_shifter_low <<= 1;
+        self.pattern_shifter_high <<= 1;
+        self.attribute_shifter_low <<= 1;
+        self.attribute_shifter_high <<= 1;
+    }
+
+    fn load_background_shifters(&mut self) {
+        self.pattern_shifter_low = (self.pattern_shifter_low & 0xFF00) | self.next_tile_low as u16;
+        self.pattern_shifter_high =
+            (self.pattern_shifter_high & 0xFF00) | self.next_tile_high as u16;
+        self.attribute_shifter_low = (self.attribute_shifter_low & 0xFF00)
+            | if self.next_tile_attribute.bit(0) {
+                0xFF
+            } else {
+                0x00
+            };
+        self.attribute_shifter_high = (self.attribute_shifter_high & 0xFF00)
+            | if self.next_tile_attribute.bit(1) {
+                0xFF
+            } else {
+                0x00
+            };
+    }
+
+    fn background_pixel(&self, fine_x: u8) -> (u8, u8) {
+        let mux = 0x8000 >> fine_x;
+        let pixel = ((self.pattern_shifter_high & mux != 0) as u8) << 1
+            | (self.pattern_shifter_low & mux != 0) as u8;
+        let palette = ((self.attribute_shifter_high & mux != 0) as u8) << 1
+            | (self.attribute_shifter_low & mux != 0) as u8;
+        (pixel, palette)
+    }
+
+    /// Returns (pixel, palette, behind_background, is_sprite_zero) of the first opaque sprite
+    /// pixel at screen position x.
+    fn sprite_pixel(&self, x: usize) -> Option<(u8, u8, bool, bool)> {
+        for (slot, sprite) in self.sprites.iter().enumerate() {
+            let offset = x as i32 - sprite.x as i32;
+            if !(0..8).contains(&offset) {
+                continue;
+            }
+            let bit = 7 - offset as usize;
+            let pixel =
+                (sprite.pattern_high.bit(bit) as u8) << 1 | sprite.pattern_low.bit(bit) as u8;
+            if pixel != 0 {
+                return Some((
+                    pixel,
+                    sprite.attributes.bits(0..=1) + 4,
+                    sprite.attributes.bit(5),
+                    slot == 0 && self.sprite_zero_on_line,
+                ));
+            }
+        }
+        None
+    }
+
+    fn reset_sprite_evaluation(&mut self) {
+        self.secondary_oam = [0xFF; 32];
+        self.extra_oam.clear();
+        self.eval_n = 0;
+        self.eval_m = 0;
+        self.eval_count = 0;
+        self.eval_done = false;
+        self.eval_sprite_zero = false;
+    }
+
+    /// Returns the secondary OAM entry of a sprite slot, including slots beyond the limit.
+    fn oam_entry(&self, slot: usize) -> [u8; 4] {
+        let oam = if slot < 8 {
+            &self.secondary_oam[slot * 4..slot * 4 + 4]
+        } else {
+            &self.extra_oam[(slot - 8) * 4..(slot - 7) * 4]
+        };
+        oam.try_into().unwrap()
+    }
+
+    fn next_oam_entry(&mut self) {
+        self.eval_n += 1;
+        if self.eval_n == 64 {
+            self.eval_done = true;
+        }
+    }
+}
+
+impl Ppu {
+    /// Executes a single dot of the cycle accurate rendering pipeline.
+    pub(super) fn tick_dot_pipeline(&mut self) -> PpuResult<()> {
+        let visible_line = self.scanline < 240;
+        if !visible_line && self.scanline != 261 {
+            return Ok(());
+        }
+
+        if self.rendering_enabled() {
+            match self.cycle {
+                257 => self.start_fetch_phase(PpuFetchPhase::Sprites),
+                321 => self.start_fetch_phase(PpuFetchPhase::Background {
+                    scanline: (self.scanline + 1) % 262,
+                }),
+                _ => (),
+            }
+            self.tick_background_pipeline()?;
+            self.tick_sprite_pipeline(visible_line)?;
+        }
+
+        if visible_line && (1..=256).contains(&self.cycle) {
+            self.render_pixel()?;
+        }
+        Ok(())
+    }
+
+    fn tick_background_pipeline(&mut self) -> PpuResult<()> {
+        if matches!(self.cycle, 2..=257 | 322..=337) {
+            self.pipeline.shift_background();
+        }
+
+        if matches!(self.cycle, 2..=257 | 321..=337) {
+            match (self.cycle - 1) % 8 {
+                0 => {
+                    self.pipeline.load_background_shifters();
+                    self.pipeline.next_tile_id = self.fetch(self.v_register.tile_addr())?;
+                }
+                2 => {
+                    let attribute = self.fetch(self.v_register.attribute_addr())?;
+                    let shift = (self.v_register.coarse_y() & 0b10) << 1
+                        | (self.v_register.coarse_x() & 0b10);
+                    self.pipeline.next_tile_attribute = (attribute >> shift) & 0b11;
+                }
+                4 => {
+                    let addr = self.background_pattern_addr();
+                    self.pipeline.next_tile_low = self.fetch(addr)?;
+                }
+                6 => {
+                    let addr = self.background_pattern_addr() + 8;
+                    self.pipeline.next_tile_high = self.fetch(addr)?;
+                }
+                7 => self.v_register.increment_x(),
+                _ => (),
+            }
+        }
+
+        match self.cycle {
+            256 => self.v_register.increment_y(),
+            257 => {
+                self.v_register.set_coarse_x(self.t_register.coarse_x());
+                self.v_register
+                    .set_nametable_x(self.t_register.nametable_x());
+            }
+            280..=304 if self.scanline == 261 => {
+                self.v_register.set_coarse_y(self.t_register.coarse_y());
+                self.v_register
+                    .set_nametable_y(self.t_register.nametable_y());
+                self.v_register.set_fine_y(self.t_register.fine_y());
+            }
+            // Unused nametable fetches at the end of the scanline.
+            339 => {
+                self.fetch(self.v_register.tile_addr())?;
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn background_pattern_addr(&self) -> u16 {
+        self.control_register.background_pattern_addr as u16 * 0x1000
+            + self.pipeline.next_tile_id as u16 * 16
+            + self.v_register.fine_y()
+    }
+
+    fn tick_sprite_pipeline(&mut self, visible_line: bool) -> PpuResult<()> {
+        match self.cycle {
+            1 => self.pipeline.reset_sprite_evaluation(),
+            65..=256 if visible_line => {
+                if self.cycle % 2 == 0 {
+                    self.evaluate_sprites_step();
+                }
+                if self.cycle == 256 && self.disable_sprite_limit {
+                    self.evaluate_extra_sprites();
+                }
+            }
+            257..=320 => {
+                self.oam_addr = 0;
+                let slot = (self.cycle - 257) / 8;
+                match (self.cycle - 257) % 8 {
+                    0 => {
+                        if slot == 0 {
+                            self.pipeline.sprites.clear();
+                            self.pipeline.sprite_zero_on_line = self.pipeline.eval_sprite_zero;
+                        }
+                        // Garbage nametable fetch
+                        self.update_address_bus(self.v_register.tile_addr());
+                    }
+                    4 => self.fetch_sprite_pattern(slot, false)?,
+                    6 => self.fetch_sprite_pattern(slot, true)?,
+                    _ => (),
+                }
+                if self.cycle == 320 {
+                    self.fetch_extra_sprites()?;
+                }
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    /// Executes one read/write pair of the sprite evaluation, which copies the sprites that
+    /// are in range of the current scanline into secondary OAM.
+    fn evaluate_sprites_step(&mut self) {
+        if self.pipeline.eval_done {
+            return;
+        }
+        let value = self.oam_data[self.pipeline.eval_n * 4 + self.pipeline.eval_m];
+        let in_range = self.sprite_on_scanline(value, self.scanline);
+        let pipeline = &mut self.pipeline;
+
+        if pipeline.eval_count >= 8 {
+            // Once secondary OAM is full, the PPU keeps looking for a 9th sprite to set the
+            // overflow flag. Due to a hardware bug m is incremented along with n, so it
+            // ends up comparing tile, attribute and x bytes against the scanline as well.
+            if in_range {
+                self.status_register.sprite_overflow = true;
+                pipeline.eval_done = true;
+            } else {
+                pipeline.eval_m = (pipeline.eval_m + 1) % 4;
+                pipeline.next_oam_entry();
+            }
+            return;
+        }
+
+        pipeline.secondary_oam[pipeline.eval_count * 4 + pipeline.eval_m] = value;
+        if pipeline.eval_m == 0 {
+            if in_range {
+                pipeline.eval_m = 1;
+                if pipeline.eval_n == 0 {
+                    pipeline.eval_sprite_zero = true;
+                }
+            } else {
+                pipeline.next_oam_entry();
+            }
+        } else {
+            pipeline.eval_m += 1;
+            if pipeline.eval_m == 4 {
+                pipeline.eval_m = 0;
+                pipeline.eval_count += 1;
+                if pipeline.eval_count == 8 {
+                    pipeline.eval_limit_index = pipeline.eval_n + 1;
+                }
+                pipeline.next_oam_entry();
+            }
+        }
+    }
+
+    /// Appends the sprites beyond the 8 sprite limit to secondary OAM.
+    fn evaluate_extra_sprites(&mut self) {
+        if self.pipeline.eval_count < 8 {
+            return;
+        }
+        for n in self.pipeline.eval_limit_index..64 {
+            if self.sprite_on_scanline(self.oam_data[n * 4], self.scanline) {
+                self.pipeline
+                    .extra_oam
+                    .extend_from_slice(&self.oam_data[n * 4..n * 4 + 4]);
+                self.pipeline.eval_count += 1;
+            }
+        }
+    }
+
+    fn sprite_pattern_addr(&self, slot: usize) -> u16 {
+        let [y, tile, attributes, _] = self.pipeline.oam_entry(slot);
+        let sprite_height = self.get_sprite_height();
+
+        // Unused slots fetch the pattern of tile 0xFF.
+        let mut row = if slot < self.pipeline.eval_count {
+            self.scanline - y as usize
+        } else {
+            0
+        };
+        if attributes.bit(7) {
+            row = sprite_height - 1 - row;
+        }
+        if self.control_register.large_sprite_mode {
+            (tile as u16 & 1) * 0x1000
+                + (tile as u16 & 0xFE) * 16
+                + if row >= 8 { 16 } else { 0 }
+                + (row % 8) as u16
+        } else {
+            self.control_register.sprite_pattern_addr as u16 * 0x1000
+                + tile as u16 * 16
+                + row as u16
+        }
+    }
+
+    fn fetch_sprite_pattern(&mut self, slot: usize, high: bool) -> PpuResult<()> {
+        let addr = self.sprite_pattern_addr(slot);
+        let data = self.fetch(if high { addr + 8 } else { addr })?;
+        if slot < self.pipeline.eval_count {
+            self.load_sprite_slot(slot, high, data);
+        }
+        Ok(())
+    }
+
+    /// Sprites beyond the 8 sprite limit do not exist on hardware. Their patterns are read
+    /// without being visible on the PPU bus.
+    fn fetch_extra_sprites(&mut self) -> PpuResult<()> {
+        for slot in 8..self.pipeline.eval_count {
+            let addr = self.sprite_pattern_addr(slot);
+            let low = self.read_ppu_memory(addr)?;
+            let high = self.read_ppu_memory(addr + 8)?;
+            self.load_sprite_slot(slot, false, low);
+            self.load_sprite_slot(slot, true, high);
+        }
+        Ok(())
+    }
+
+    fn load_sprite_slot(&mut self, slot: usize, high: bool, data: u8) {
+        let [_, _, attributes, x] = self.pipeline.oam_entry(slot);
+        let data = if attributes.bit(6) {
+            data.reverse_bits()
+        } else {
+            data
+        };
+        if high {
+            self.pipeline.sprites[slot].pattern_high = data;
+        } else {
+            self.pipeline.sprites.push(SpriteSlot {
+                pattern_low: data,
+                pattern_high: 0,
+                attributes,
+                x,
+            });
+        }
+    }
+
+    /// Reads from the PPU bus during rendering. Unlike `read_ppu_memory` this is visible to
+    /// the cartridge.
+    fn fetch(&mut self, addr: u16) -> PpuResult<u8> {
+        self.update_address_bus(addr);
+        match addr {
+            0x0000..=0x1FFF => self.fetch_pattern_memory(addr),
+            _ => self.fetch_nametable_memory(addr),
+        }
+    }
+
+    fn render_pixel(&mut self) -> PpuResult<()> {
+        let x = self.cycle - 1;
+        let (background, background_palette) = if self.mask_register.show_background
+            && (x >= 8 || self.mask_register.mask_background)
+        {
+            self.pipeline.background_pixel(self.fine_scroll_x)
+        } else {
+            (0, 0)
+        };
+        let sprite =
+            if self.mask_register.show_sprites && (x >= 8 || self.mask_register.mask_sprites) {
+                self.pipeline.sprite_pixel(x)
+            } else {
+                None
+            };
+
+        let (pixel, palette) = match sprite {
+            Some((sprite, sprite_palette, behind_background, sprite_zero)) => {
+                if background != 0 && sprite_zero && x != 255 {
+                    self.status_register.sprite_zero_hit = true;
+                }
+                if background != 0 && behind_background {
+                    (background, background_palette)
+                } else {
+                    (sprite, sprite_palette)
+                }
+            }
+            None => (background, background_palette),
+        };
+        self.framebuffer[(x, self.scanline)] =
+            self.get_palette_entry(palette as usize, pixel as usize)?;
+        Ok(())
+    }
+}
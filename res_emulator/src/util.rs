@@ -1,6 +1,10 @@
 use std::collections::vec_deque::Iter;
 use std::collections::VecDeque;
+use std::fs::File;
+use std::path::Path;
 
+use anyhow::Context;
+use anyhow::Result;
 use bincode::Decode;
 use bincode::Encode;
 
@@ -34,3 +38,16 @@ impl<T, const N: usize> Default for RingBuffer<T, N> {
         }
     }
 }
+
+/// Writes mono audio samples, e.g. the output of the APU, to a WAV file.
+pub fn write_wav(path: &Path, samples: Vec<f32>, sample_rate: usize) -> Result<()> {
+    let mut file = File::create(path).with_context(|| format!("Cannot create {path:?}"))?;
+    let header = wav::header::Header::new(
+        wav::header::WAV_FORMAT_IEEE_FLOAT,
+        1,
+        sample_rate as u32,
+        32,
+    );
+    wav::write(header, &samples.into(), &mut file)?;
+    Ok(())
+}
@@ -81,3 +81,26 @@ fn read_wav(filename: &Path) -> Vec<f32> {
         panic!("Invalid wav format");
     }
 }
+
+#[test]
+pub fn test_length_counters() {
+    let mut apu = Apu::default();
+    // Loading length index 1 (254) into all channels while they are disabled has no effect.
+    for addr in [0x4003, 0x4007, 0x400B, 0x400F] {
+        apu.cpu_bus_write(addr, 0x08);
+    }
+    assert_eq!(apu.cpu_bus_read(0x4015) & 0x0F, 0x00);
+
+    apu.cpu_bus_write(0x4015, 0x0F);
+    for addr in [0x4003, 0x4007, 0x400B, 0x400F] {
+        apu.cpu_bus_write(addr, 0x08);
+    }
+    assert_eq!(apu.cpu_bus_read(0x4015) & 0x0F, 0x0F);
+
+    // Each frame of the 4-step sequence clocks the length counters twice, so all channels
+    // including the triangle run for 127 frames.
+    apu.advance_clock(126 * 29830).unwrap();
+    assert_eq!(apu.cpu_bus_read(0x4015) & 0x0F, 0x0F);
+    apu.advance_clock(2 * 29830).unwrap();
+    assert_eq!(apu.cpu_bus_read(0x4015) & 0x0F, 0x00);
+}
@@ -2,14 +2,16 @@ use std::fs::File;
 use std::io::BufRead;
 use std::io::{self};
 use std::path::Path;
+use std::rc::Rc;
 
 use res_emulator::cpu::CpuBus;
+use res_emulator::cpu::IrqSource;
 use res_emulator::trace::Trace;
 use res_emulator::System;
 
 #[test]
 pub fn test_basic_program() {
-    let mut system = System::with_program(&[
+    let mut program = vec![
         0xa9, 0x10, // LDA #$10     -> A = #$10
         0x85, 0x20, // STA $20      -> $20 = #$10
         0xa9, 0x01, // LDA #$1      -> A = #$1
@@ -18,17 +20,182 @@ pub fn test_basic_program() {
         0xe6, 0x21, // INC $21      -> $21=#$12
         0xa4, 0x21, // LDY $21      -> Y=#$12
         0xc8, // INY          -> Y=#$13
-        0x00, // BRK
-    ])
-    .unwrap();
+    ];
+    // NROM mirrors the PRG-ROM, so it has to fill a bank to start at $8000.
+    program.resize(0x4000, 0xea);
+    let mut system = System::with_program(&program).unwrap();
     system.cpu.program_counter = 0x8000;
-    system.execute_until_halt().unwrap();
+    system
+        .execute_until(|cpu| cpu.program_counter == 0x800F)
+        .unwrap();
     assert_eq!(system.cpu.bus.peek(0x20_u16).unwrap(), 0x10);
     assert_eq!(system.cpu.bus.peek(0x21_u16).unwrap(), 0x12);
     assert_eq!(system.cpu.a, 0x11);
     assert_eq!(system.cpu.y, 0x13);
 }
 
+#[test]
+pub fn test_brk() {
+    let mut system = System::with_ines_bytes(
+        &mmc3_test_rom(
+            &[
+                0x00, 0xFF, // BRK          -> Jump to IRQ handler
+            ],
+            &[
+                0xa9, 0x42, // LDA #$42     -> A = #$42
+                0x40, // RTI
+            ],
+        ),
+        None,
+    )
+    .unwrap();
+    system
+        .execute_until(|cpu| cpu.program_counter == 0xE002)
+        .unwrap();
+    assert_eq!(system.cpu.a, 0x42);
+    // BRK pushes the address after the padding byte and sets the B flag
+    assert_eq!(system.cpu.bus.peek_u16(0x01FC).unwrap(), 0xE002);
+    assert_eq!(system.cpu.bus.peek(0x01FB).unwrap() & 0x10, 0x10);
+}
+
+#[test]
+pub fn test_irq() {
+    let mut system = System::with_ines_bytes(
+        &mmc3_test_rom(
+            &[
+                0xea, // NOP
+                0x58, // CLI          -> Enable interrupts
+                0x4c, 0x02, 0xe0, // JMP $E002
+            ],
+            &[
+                0xa9, 0x42, // LDA #$42     -> A = #$42
+            ],
+        ),
+        None,
+    )
+    .unwrap();
+
+    // Trigger the IRQ via the MMC3 scanline counter.
+    {
+        let mut cartridge = system.cpu.bus.cartridge.borrow_mut();
+        cartridge.cpu_bus_write(0xC000, 0).unwrap();
+        cartridge.cpu_bus_write(0xC001, 0).unwrap();
+        cartridge.cpu_bus_write(0xE001, 0).unwrap();
+        cartridge.ppu_a12_rising_edge();
+        assert!(cartridge.irq_active());
+    }
+
+    // The IRQ is masked by the I flag until CLI is executed.
+    system.cpu.execute_one().unwrap();
+    assert_eq!(system.cpu.program_counter, 0xE001);
+    system
+        .execute_until(|cpu| cpu.program_counter == 0xE102)
+        .unwrap();
+    assert_eq!(system.cpu.a, 0x42);
+    assert!(system.cpu.bus.irq_line().is_asserted());
+}
+
+#[test]
+pub fn test_dmc_irq() {
+    let mut system = System::with_ines_bytes(
+        &mmc3_test_rom(
+            &[
+                0xa9, 0x80, // LDA #$80
+                0x8d, 0x10, 0x40, // STA $4010    -> Enable DMC IRQ
+                0xa9, 0x00, // LDA #$00
+                0x8d, 0x12, 0x40, // STA $4012    -> Sample at $C000
+                0x8d, 0x13, 0x40, // STA $4013    -> Sample length of 1 byte
+                0xa9, 0x10, // LDA #$10
+                0x8d, 0x15, 0x40, // STA $4015    -> Start sample playback
+                0x58, // CLI
+                0x4c, 0x13, 0xe0, // JMP $E013
+            ],
+            &[
+                0xa9, 0x42, // LDA #$42     -> A = #$42
+            ],
+        ),
+        None,
+    )
+    .unwrap();
+    system
+        .execute_until(|cpu| cpu.program_counter == 0xE102)
+        .unwrap();
+    assert_eq!(system.cpu.a, 0x42);
+    assert!(system.cpu.bus.irq_line().is_asserted_by(IrqSource::ApuDmc));
+    // Cycles the CPU was stalled by the sample fetch are included in the CPU cycle count.
+    assert_eq!(system.cpu.cycle, system.cpu.bus.cycle);
+}
+
+#[test]
+pub fn test_apu_frame_irq() {
+    // The sequence restarts 3 CPU cycles after a $4017 write during an APU cycle, or 4 cycles
+    // after a write between APU cycles. The 4th step raises the IRQ 29828 cycles later.
+    for (padding, restart_delay) in [(vec![], 4), (vec![0xa5, 0x00], 3)] {
+        let loop_addr = 0xE006 + padding.len() as u16;
+        let mut code = padding;
+        code.extend_from_slice(&[
+            0xa9,
+            0x00, // LDA #$00
+            0x8d,
+            0x17,
+            0x40, // STA $4017    -> 4-step mode with frame IRQ
+            0x58, // CLI
+            0x4c,
+            loop_addr as u8,
+            (loop_addr >> 8) as u8, // JMP to itself
+        ]);
+        let mut system = System::with_ines_bytes(
+            &mmc3_test_rom(
+                &code,
+                &[
+                    0xad, 0x15, 0x40, // LDA $4015    -> Read and acknowledge frame IRQ
+                    0xae, 0x15, 0x40, // LDX $4015
+                ],
+            ),
+            None,
+        )
+        .unwrap();
+        system
+            .execute_until(|cpu| cpu.program_counter == loop_addr - 1)
+            .unwrap();
+        let write_cycle = system.cpu.bus.cycle;
+        while !system
+            .cpu
+            .bus
+            .irq_line()
+            .is_asserted_by(IrqSource::ApuFrameCounter)
+        {
+            system.cpu.bus.advance_clock(1).unwrap();
+        }
+        assert_eq!(system.cpu.bus.cycle - write_cycle, restart_delay + 29828);
+
+        system
+            .execute_until(|cpu| cpu.program_counter == 0xE106)
+            .unwrap();
+        assert_eq!(system.cpu.a & 0x40, 0x40);
+        assert_eq!(system.cpu.x & 0x40, 0x00);
+        assert!(!system
+            .cpu
+            .bus
+            .irq_line()
+            .is_asserted_by(IrqSource::ApuFrameCounter));
+    }
+}
+
+/// Creates a MMC3 rom with `code` at the reset vector (0xE000) and `irq_handler` at the IRQ
+/// vector (0xE100).
+fn mmc3_test_rom(code: &[u8], irq_handler: &[u8]) -> Vec<u8> {
+    let mut rom = vec![0x4E, 0x45, 0x53, 0x1A, 0x02, 0x00, 0x40, 0x00];
+    rom.resize(16, 0);
+    let mut prg = vec![0xEA; 32 * 1024];
+    prg[0x6000..0x6000 + code.len()].copy_from_slice(code);
+    prg[0x6100..0x6100 + irq_handler.len()].copy_from_slice(irq_handler);
+    prg[0x7FFC..0x7FFE].copy_from_slice(&[0x00, 0xE0]);
+    prg[0x7FFE..0x8000].copy_from_slice(&[0x00, 0xE1]);
+    rom.append(&mut prg);
+    rom
+}
+
 #[test]
 #[ignore = "No support for MMC1 mapper yet."]
 pub fn test_gblargg_official_only() {
@@ -74,6 +241,42 @@ pub fn test_nestest_snapshot() {
     let snapshot = system.snapshot();
     let resumed_system = System::with_snapshot(&snapshot).unwrap();
     assert_eq!(system.trace(), resumed_system.trace());
+    // The cartridge is shared between the CPU bus and the PPU.
+    assert!(Rc::ptr_eq(
+        &resumed_system.cpu.bus.cartridge,
+        &resumed_system.cpu.bus.ppu.cartridge
+    ));
+    assert!(System::with_snapshot(&snapshot[..100]).is_err());
+}
+
+#[test]
+pub fn test_soft_reset() {
+    let mut system = System::with_ines_bytes(
+        &mmc3_test_rom(
+            &[
+                0xa9, 0x42, // LDA #$42
+                0x85, 0x20, // STA $20
+                0x58, // CLI
+            ],
+            &[],
+        ),
+        None,
+    )
+    .unwrap();
+    system
+        .execute_until(|cpu| cpu.program_counter == 0xE005)
+        .unwrap();
+    assert_eq!(system.cpu.status_flags.bits() & 0x04, 0x00);
+
+    let cycle = system.cpu.cycle;
+    system.soft_reset().unwrap();
+    assert_eq!(system.cpu.program_counter, 0xE000);
+    assert_eq!(system.cpu.sp, 0xFA);
+    assert_eq!(system.cpu.status_flags.bits() & 0x04, 0x04);
+    assert_eq!(system.cpu.cycle, cycle + 7);
+    // RAM and registers survive the reset.
+    assert_eq!(system.cpu.a, 0x42);
+    assert_eq!(system.cpu.bus.peek(0x20_u16).unwrap(), 0x42);
 }
 
 #[test]
@@ -93,6 +296,109 @@ pub fn test_ops_dont_panic() {
     }
 }
 
+#[test]
+pub fn test_operation_cycle_counts() {
+    // Operands address $0010 and the pointer there addresses $0300, so no page is crossed.
+    let mut system = System::new();
+    let cpu = &mut system.cpu;
+    for opcode in 0x00..=0xFF_u8 {
+        for (addr, value) in [(0x0200, opcode), (0x0201, 0x10), (0x0202, 0x00)] {
+            cpu.bus.write(addr, value).unwrap();
+        }
+        cpu.bus.write(0x0010, 0x00).unwrap();
+        cpu.bus.write(0x0011, 0x03).unwrap();
+        cpu.program_counter = 0x0200;
+        cpu.x = 0;
+        cpu.y = 0;
+        cpu.sp = 0xF0;
+
+        let start_cycle = cpu.cycle;
+        let operation = cpu.next_operation().unwrap();
+        let expected_cycles = operation.table_entry.cycle_count;
+        // Skip unofficial opcodes, which are not emulated.
+        if expected_cycles < 2 {
+            continue;
+        }
+        operation.execute(cpu).unwrap();
+        let cycles = cpu.cycle - start_cycle;
+        // Taken branches take one more cycle.
+        let is_branch = opcode & 0x1F == 0x10;
+        assert!(
+            cycles == expected_cycles || (is_branch && cycles == expected_cycles + 1),
+            "{} took {cycles} cycles but should take {expected_cycles}",
+            operation.format(cpu)
+        );
+        assert_eq!(cpu.cycle, cpu.bus.cycle);
+    }
+}
+
+#[test]
+pub fn test_dummy_accesses() {
+    let mut program = vec![
+        0xa9, 0x20, // LDA #$20
+        0x8d, 0x06, 0x20, // STA $2006
+        0xa9, 0x00, // LDA #$00
+        0x8d, 0x06, 0x20, // STA $2006    -> PPU address $2000
+        0xa9, 0x42, // LDA #$42
+        0x8d, 0x07, 0x20, // STA $2007    -> $2000 = #$42
+        0xa9, 0x20, // LDA #$20
+        0x8d, 0x06, 0x20, // STA $2006
+        0xa9, 0x00, // LDA #$00
+        0x8d, 0x06, 0x20, // STA $2006    -> PPU address $2000
+        0xa2, 0x10, // LDX #$10
+        0xbd, 0xf7, 0x20, // LDA $20F7,X  -> Reads $2007 before $2107
+        0xee, 0x07, 0x20, // INC $2007    -> Writes $2003 before $2004
+    ];
+    // NROM mirrors the PRG-ROM, so it has to fill a bank to start at $8000.
+    program.resize(0x4000, 0xea);
+    let mut system = System::with_program(&program).unwrap();
+    system.cpu.program_counter = 0x8000;
+    system
+        .execute_until(|cpu| cpu.program_counter == 0x8021)
+        .unwrap();
+    // The dummy read of $2007 filled the read buffer, so the second read returns the data.
+    assert_eq!(system.cpu.a, 0x42);
+    // INC reads from $2002, writes the unmodified value to $2003 and the result to $2004.
+    assert_eq!(system.cpu.bus.ppu.vram[0x003], 0x00);
+    assert_eq!(system.cpu.bus.ppu.vram[0x004], 0x01);
+}
+
+#[test]
+pub fn test_nmi_and_oam_dma_cycles() {
+    let mut program = vec![
+        0xea, // NOP
+        0xa9, 0x02, // LDA #$02
+        0x8d, 0x14, 0x40, // STA $4014
+        0x8d, 0x14, 0x40, // STA $4014
+    ];
+    program.resize(0x4000, 0xea);
+    // NMI vector to $8001.
+    program[0x3FFA] = 0x01;
+    program[0x3FFB] = 0x80;
+    let mut system = System::with_program(&program).unwrap();
+    let cpu = &mut system.cpu;
+    cpu.program_counter = 0x8000;
+    cpu.sp = 0xF0;
+
+    // NOP takes 2 cycles and entering the NMI handler 7 more.
+    cpu.bus.ppu.nmi_interrupt = true;
+    let start_cycle = cpu.cycle;
+    cpu.execute_one().unwrap();
+    assert_eq!(cpu.cycle - start_cycle, 9);
+    assert_eq!(cpu.program_counter, 0x8001);
+    cpu.execute_one().unwrap();
+
+    // STA takes 4 cycles and the DMA 513, plus one if the write happened on an odd cycle. The
+    // first DMA takes an odd number of cycles, so the second write is on an odd cycle.
+    for alignment in [0, 1] {
+        let start_cycle = cpu.cycle;
+        assert_eq!((start_cycle + 4) % 2, alignment);
+        cpu.execute_one().unwrap();
+        assert_eq!(cpu.cycle - start_cycle, 4 + 513 + alignment);
+        assert_eq!(cpu.cycle, cpu.bus.cycle);
+    }
+}
+
 #[test]
 pub fn test_snapshot_size() {
     let mut system = System::with_ines(Path::new("tests/cpu/nestest.nes")).unwrap();
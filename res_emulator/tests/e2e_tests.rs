@@ -1,6 +1,7 @@
 use std::path::PathBuf;
 
 use image::RgbaImage;
+use res_emulator::ppu::RenderMode;
 use res_emulator::System;
 
 #[test]
@@ -57,18 +58,50 @@ pub fn test_ice_climber() {
     test_playback("ice_climber", &[100, 1450, 1550, 1650]);
 }
 
+#[test]
+pub fn test_nestest_dot_renderer() {
+    test_playback_with_render_mode("nestest", RenderMode::Dot, &[60, 90]);
+}
+
+#[test]
+pub fn test_alter_ego_dot_renderer() {
+    test_playback_with_render_mode("alter_ego", RenderMode::Dot, &[100, 600, 1000, 1500]);
+}
+
+#[test]
+pub fn test_ice_climber_dot_renderer() {
+    test_playback_with_render_mode("ice_climber", RenderMode::Dot, &[100, 1450, 1550, 1650]);
+}
+
 fn test_playback(name: &str, frame_numbers: &[usize]) {
+    test_playback_with_render_mode(name, RenderMode::Scanline, frame_numbers);
+}
+
+fn test_playback_with_render_mode(name: &str, render_mode: RenderMode, frame_numbers: &[usize]) {
     let rom_path = PathBuf::from(&format!("tests/e2e/{name}.nes"));
     let recording_path = PathBuf::from(&format!("tests/e2e/{name}.recording.json"));
     if !rom_path.exists() {
         return;
     }
     let mut system = System::with_ines(&rom_path).unwrap();
+    system.cpu.bus.ppu.render_mode = render_mode;
     system.playback_from_file(&recording_path);
-    execute_and_compare_screenshots(name, &mut system, frame_numbers);
+    let golden_name = match render_mode {
+        RenderMode::Scanline => name.to_string(),
+        RenderMode::Dot => format!("{name}-dot"),
+    };
+    // Goldens of the dot renderer have to be captured with a reference emulator, so they are
+    // not created from its own output.
+    let create_missing = render_mode == RenderMode::Scanline;
+    execute_and_compare_screenshots(&golden_name, &mut system, frame_numbers, create_missing);
 }
 
-fn execute_and_compare_screenshots(name: &str, system: &mut System, frame_numbers: &[usize]) {
+fn execute_and_compare_screenshots(
+    name: &str,
+    system: &mut System,
+    frame_numbers: &[usize],
+    create_missing: bool,
+) {
     for frame_number in frame_numbers {
         while system.ppu().frame != *frame_number {
             system.update_buttons([false; 8]);
@@ -77,11 +110,12 @@ fn execute_and_compare_screenshots(name: &str, system: &mut System, frame_number
         compare_to_golden(
             &system.ppu().framebuffer.as_rgba_image(),
             &format!("{name}-{frame_number}"),
+            create_missing,
         );
     }
 }
 
-fn compare_to_golden(image: &RgbaImage, name: &str) {
+fn compare_to_golden(image: &RgbaImage, name: &str, create_missing: bool) {
     let path_prefix = PathBuf::from("tests/e2e").join(name);
     let golden_path = path_prefix.with_extension("png");
     if golden_path.exists() {
@@ -94,7 +128,11 @@ fn compare_to_golden(image: &RgbaImage, name: &str) {
                 name, actual_path
             );
         }
-    } else {
+    } else if create_missing {
         image.save(golden_path).unwrap();
+    } else {
+        let actual_path = golden_path.with_extension("actual.png");
+        image.save(&actual_path).unwrap();
+        panic!("Missing golden {:?}. See {:?}", golden_path, actual_path);
     }
 }
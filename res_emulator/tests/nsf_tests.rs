@@ -0,0 +1,66 @@
+use res_emulator::System;
+
+/// A tune with 3 tracks, starting at the second one. INIT stores the track and region in
+/// $00 and $01 and starts a square wave, PLAY counts its calls in $02.
+fn test_nsf() -> Vec<u8> {
+    let mut raw = b"NESM\x1A\x01\x03\x02".to_vec();
+    raw.extend_from_slice(&[0x00, 0x80, 0x00, 0x80, 0x19, 0x80]);
+    for text in [&b"Test Tune"[..], b"res", b"2022"] {
+        let mut field = text.to_vec();
+        field.resize(32, 0);
+        raw.extend_from_slice(&field);
+    }
+    raw.extend_from_slice(&[0x1A, 0x41]);
+    raw.extend_from_slice(&[0; 8]);
+    raw.extend_from_slice(&[0x20, 0x4E, 0x00, 0x00, 0, 0, 0, 0]);
+    raw.extend_from_slice(&[
+        0x85, 0x00, // $8000: STA $00
+        0x86, 0x01, // STX $01
+        0xA9, 0x01, // LDA #$01
+        0x8D, 0x15, 0x40, // STA $4015
+        0xA9, 0xBF, // LDA #$BF
+        0x8D, 0x00, 0x40, // STA $4000
+        0xA9, 0xFD, // LDA #$FD
+        0x8D, 0x02, 0x40, // STA $4002
+        0xA9, 0x00, // LDA #$00
+        0x8D, 0x03, 0x40, // STA $4003
+        0x60, // RTS
+        0xE6, 0x02, // $8019: INC $02
+        0x60, // RTS
+    ]);
+    raw
+}
+
+#[test]
+pub fn test_nsf_playback() {
+    let mut system = System::with_nsf_bytes(&test_nsf()).unwrap();
+    {
+        let cartridge = system.cartridge().borrow();
+        let header = cartridge.nsf_header.as_ref().unwrap();
+        assert_eq!(header.title, "Test Tune");
+        assert_eq!(header.track_count, 3);
+        assert_eq!(cartridge.current_track(), Some(1));
+    }
+
+    // PLAY is called at 60Hz, as set by the NTSC play speed of 16666us.
+    system.execute_frames(60).unwrap();
+    assert_eq!(system.cpu.bus.ram[0x00], 1);
+    assert_eq!(system.cpu.bus.ram[0x01], 0);
+    assert!((59..=61).contains(&system.cpu.bus.ram[0x02]));
+
+    // Selecting a track runs INIT again with cleared RAM.
+    system.select_track(2).unwrap();
+    system.execute_frames(10).unwrap();
+    assert_eq!(system.cpu.bus.ram[0x00], 2);
+    assert!((9..=11).contains(&system.cpu.bus.ram[0x02]));
+    assert!(system.select_track(3).is_err());
+}
+
+#[test]
+pub fn test_nsf_render_track() {
+    let mut system = System::with_nsf_bytes(&test_nsf()).unwrap();
+    let samples = system.render_track(0, 1.0, 44100).unwrap();
+    assert!((43900..=44100).contains(&samples.len()));
+    assert_eq!(system.cartridge().borrow().current_track(), Some(0));
+    assert!(samples.iter().any(|sample| *sample > 0.1));
+}
@@ -8,37 +8,69 @@ use res_emulator::System;
 struct ResCore {
     pixels: Vec<u8>,
     emulator: Option<System>,
-    counter: f32,
     audio_buffer: Vec<i16>,
     last_frame_time: Instant,
+    /// Battery backed RAM exposed to the frontend. The frontend loads the .srm file into this
+    /// buffer after `load_game`, so it is copied to the cartridge before the first frame.
+    save_ram: Vec<u8>,
+    save_ram_pending: bool,
 }
 
 libretro_core!(ResCore);
 
-const SAMPLE_RATE: f64 = 44_1000.0;
-const FRAME_RATE: f64 = 120.0;
+const SAMPLE_RATE: f64 = 44_100.0;
+const FRAME_RATE: f64 = 60.0988;
 const SAMPLES_PER_FRAME: usize = (SAMPLE_RATE / FRAME_RATE) as usize + 1;
 
+// Memory region ids from libretro.h
+const RETRO_MEMORY_SAVE_RAM: u32 = 0;
+const RETRO_MEMORY_SYSTEM_RAM: u32 = 2;
+
+/// Size of the internal RAM. The CPU bus allocates more to cover the mirrored region.
+const SYSTEM_RAM_SIZE: usize = 0x800;
+
+/// Snapshots are variable length. Reserve some room so the size reported to the frontend
+/// stays valid while the game is running.
+const SERIALIZE_SIZE_MARGIN: usize = 4096;
+
 impl RetroCore for ResCore {
     fn init(_env: &RetroEnvironment) -> Self {
         Self {
             pixels: vec![0; Framebuffer::SIZE[0] * Framebuffer::SIZE[1] * 4],
             emulator: None,
-            counter: 0.0,
-            audio_buffer: vec![0; SAMPLES_PER_FRAME * 2],
+            audio_buffer: Vec::with_capacity(SAMPLES_PER_FRAME * 2),
             last_frame_time: Instant::now(),
+            save_ram: Vec::new(),
+            save_ram_pending: false,
         }
     }
 
     fn get_system_info() -> RetroSystemInfo {
         RetroSystemInfo::new("Rust Entertainment System", env!("CARGO_PKG_VERSION"))
-            .with_valid_extensions(&["nes", "srm"])
+            .with_valid_extensions(&["nes", "unf", "nsf", "nsfe"])
     }
 
     fn load_game(&mut self, _env: &RetroEnvironment, game: RetroGame) -> RetroLoadGameResult {
-        if let RetroGame::Data { data, meta: _ } = game {
-            self.emulator = Some(System::with_ines_bytes(data, None).unwrap());
-        }
+        let RetroGame::Data { data, meta: _ } = game else {
+            return RetroLoadGameResult::Failure;
+        };
+        let mut emulator = match System::with_rom_bytes(data, None, None) {
+            Ok(emulator) => emulator,
+            Err(e) => {
+                eprintln!("Failed to load game: {e:?}");
+                return RetroLoadGameResult::Failure;
+            }
+        };
+        emulator.cpu.bus.apu.audio_sample_rate = SAMPLE_RATE as usize;
+        let cartridge = emulator.cartridge().borrow();
+        self.save_ram = if cartridge.has_persistent_data {
+            cartridge.persistent_data()
+        } else {
+            Vec::new()
+        };
+        drop(cartridge);
+        self.save_ram_pending = !self.save_ram.is_empty();
+        self.emulator = Some(emulator);
         RetroLoadGameResult::Success {
             audio: RetroAudioInfo::new(SAMPLE_RATE),
             video: RetroVideoInfo::new(
@@ -50,10 +82,87 @@ impl RetroCore for ResCore {
         }
     }
 
-    fn reset(&mut self, _env: &RetroEnvironment) {}
+    fn reset(&mut self, _env: &RetroEnvironment) {
+        if let Some(emulator) = self.emulator.as_mut() {
+            emulator.soft_reset().unwrap();
+        }
+    }
+
+    fn serialize_size(&self, _env: &RetroEnvironment) -> usize {
+        match &self.emulator {
+            Some(emulator) => emulator.snapshot().len() + SERIALIZE_SIZE_MARGIN,
+            None => 0,
+        }
+    }
+
+    fn serialize(&self, _env: &RetroEnvironment, data: *mut (), size: usize) -> bool {
+        if let Some(emulator) = &self.emulator {
+            let snapshot = emulator.snapshot();
+            if snapshot.len() > size {
+                return false;
+            }
+            let data = unsafe { std::slice::from_raw_parts_mut(data as *mut u8, size) };
+            data[..snapshot.len()].copy_from_slice(&snapshot);
+            // Trailing bytes are ignored when decoding the snapshot.
+            data[snapshot.len()..].fill(0);
+            true
+        } else {
+            false
+        }
+    }
+
+    fn unserialize(&mut self, _env: &RetroEnvironment, data: *const (), size: usize) -> bool {
+        if let Some(emulator) = self.emulator.as_mut() {
+            let data = unsafe { std::slice::from_raw_parts(data as *const u8, size) };
+            match System::with_snapshot(data) {
+                Ok(restored) => {
+                    // Keep the RAM allocation so pointers handed out by get_memory_data stay
+                    // valid.
+                    let mut cpu = restored.cpu;
+                    emulator.cpu.bus.ram.copy_from_slice(&cpu.bus.ram);
+                    std::mem::swap(&mut cpu.bus.ram, &mut emulator.cpu.bus.ram);
+                    cpu.bus.apu.audio_sample_rate = SAMPLE_RATE as usize;
+                    emulator.cpu = cpu;
+                    self.save_ram_pending = false;
+                    true
+                }
+                Err(_) => false,
+            }
+        } else {
+            false
+        }
+    }
+
+    fn get_memory_data(&mut self, _env: &RetroEnvironment, id: u32) -> *mut () {
+        match (id, self.emulator.as_mut()) {
+            (RETRO_MEMORY_SAVE_RAM, Some(_)) if !self.save_ram.is_empty() => {
+                self.save_ram.as_mut_ptr() as *mut ()
+            }
+            (RETRO_MEMORY_SYSTEM_RAM, Some(emulator)) => {
+                emulator.cpu.bus.ram.as_mut_ptr() as *mut ()
+            }
+            _ => std::ptr::null_mut(),
+        }
+    }
+
+    fn get_memory_size(&self, _env: &RetroEnvironment, id: u32) -> usize {
+        match (id, &self.emulator) {
+            (RETRO_MEMORY_SAVE_RAM, Some(_)) => self.save_ram.len(),
+            (RETRO_MEMORY_SYSTEM_RAM, Some(_)) => SYSTEM_RAM_SIZE,
+            _ => 0,
+        }
+    }
 
     fn run(&mut self, _env: &RetroEnvironment, runtime: &RetroRuntime) {
         if let Some(emulator) = self.emulator.as_mut() {
+            if self.save_ram_pending {
+                self.save_ram_pending = false;
+                emulator
+                    .cartridge()
+                    .borrow_mut()
+                    .load_persistent_data(&self.save_ram);
+            }
+
             let emu_start_time = Instant::now();
 
             let mut joypad0 = [false; 8];
@@ -76,14 +185,25 @@ impl RetroCore for ResCore {
 
             emulator.update_buttons(joypad0);
             emulator.execute_one_frame().unwrap();
+
+            // Mirror the battery backed RAM so the frontend can write it to the .srm file.
+            let cartridge = emulator.cartridge().borrow();
+            if cartridge.has_persistent_data {
+                let persistent_data = cartridge.persistent_data();
+                if persistent_data.len() == self.save_ram.len() {
+                    self.save_ram.copy_from_slice(&persistent_data);
+                }
+            }
+            drop(cartridge);
             // TODO: Verify if this should be rgba on little endian architectures.
             self.pixels = emulator.ppu().framebuffer.as_raw_bgra();
 
-            for i in 0..SAMPLES_PER_FRAME {
-                let sample = (f32::sin(self.counter) * 4096.0) as i16;
-                self.audio_buffer[i * 2] = sample;
-                self.audio_buffer[i * 2 + 1] = sample;
-                self.counter += 0.01;
+            // Convert mono f32 samples into interleaved i16 stereo frames.
+            self.audio_buffer.clear();
+            for sample in emulator.cpu.bus.apu.audio_buffer.drain(..) {
+                let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+                self.audio_buffer.push(sample);
+                self.audio_buffer.push(sample);
             }
 
             let audio_start_time = Instant::now();